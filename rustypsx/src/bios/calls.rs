@@ -0,0 +1,359 @@
+use crate::cpu::registers::Registers;
+use std::fmt;
+
+// The kernel's three function tables, reached by jumping to these addresses with the function number in t1
+pub const A0_VECTOR: u32 = 0xA0;
+pub const B0_VECTOR: u32 = 0xB0;
+pub const C0_VECTOR: u32 = 0xC0;
+
+const REGISTER_A0: usize = 4;
+const REGISTER_T1: usize = 9;
+const REGISTER_RA: usize = 31;
+
+// Strings in arguments are cut off after this many characters
+const MAX_STRING_LENGTH: usize = 48;
+
+// Function number, name, and how each argument is shown: x hex, d signed decimal, c character, s string pointer
+type Signature = (u8, &'static str, &'static str);
+
+const A0_FUNCTIONS: &[Signature] = &[
+    (0x00, "open", "sx"),
+    (0x01, "lseek", "dxd"),
+    (0x02, "read", "dxx"),
+    (0x03, "write", "dxx"),
+    (0x04, "close", "d"),
+    (0x05, "ioctl", "dxx"),
+    (0x06, "exit", "d"),
+    (0x07, "isatty", "d"),
+    (0x08, "getc", "d"),
+    (0x09, "putc", "cd"),
+    (0x0A, "todigit", "c"),
+    (0x0B, "atof", "s"),
+    (0x0C, "strtoul", "sxd"),
+    (0x0D, "strtol", "sxd"),
+    (0x0E, "abs", "d"),
+    (0x0F, "labs", "d"),
+    (0x10, "atoi", "s"),
+    (0x11, "atol", "s"),
+    (0x12, "atob", "sx"),
+    (0x13, "SaveState", "x"),
+    (0x14, "RestoreState", "xx"),
+    (0x15, "strcat", "xs"),
+    (0x16, "strncat", "xsd"),
+    (0x17, "strcmp", "ss"),
+    (0x18, "strncmp", "ssd"),
+    (0x19, "strcpy", "xs"),
+    (0x1A, "strncpy", "xsd"),
+    (0x1B, "strlen", "s"),
+    (0x1C, "index", "sc"),
+    (0x1D, "rindex", "sc"),
+    (0x1E, "strchr", "sc"),
+    (0x1F, "strrchr", "sc"),
+    (0x20, "strpbrk", "ss"),
+    (0x21, "strspn", "ss"),
+    (0x22, "strcspn", "ss"),
+    (0x23, "strtok", "xs"),
+    (0x24, "strstr", "ss"),
+    (0x25, "toupper", "c"),
+    (0x26, "tolower", "c"),
+    (0x27, "bcopy", "xxx"),
+    (0x28, "bzero", "xx"),
+    (0x29, "bcmp", "xxx"),
+    (0x2A, "memcpy", "xxx"),
+    (0x2B, "memset", "xxx"),
+    (0x2C, "memmove", "xxx"),
+    (0x2D, "memcmp", "xxx"),
+    (0x2E, "memchr", "xxx"),
+    (0x2F, "rand", ""),
+    (0x30, "srand", "x"),
+    (0x31, "qsort", "xddx"),
+    (0x32, "strtod", "sx"),
+    (0x33, "malloc", "x"),
+    (0x34, "free", "x"),
+    (0x35, "lsearch", "xxxx"),
+    (0x36, "bsearch", "xxxx"),
+    (0x37, "calloc", "xx"),
+    (0x38, "realloc", "xx"),
+    (0x39, "InitHeap", "xx"),
+    (0x3A, "SystemErrorExit", "d"),
+    (0x3B, "std_in_getchar", ""),
+    (0x3C, "std_out_putchar", "c"),
+    (0x3D, "std_in_gets", "x"),
+    (0x3E, "std_out_puts", "s"),
+    (0x3F, "printf", "sxxx"),
+    (0x40, "SystemErrorUnresolvedException", ""),
+    (0x41, "LoadTest", "sx"),
+    (0x42, "Load", "sx"),
+    (0x43, "Exec", "xxx"),
+    (0x44, "FlushCache", ""),
+    (0x45, "init_a0_b0_c0_vectors", ""),
+    (0x46, "GPU_dw", "dddd"),
+    (0x47, "gpu_send_dma", "dddd"),
+    (0x48, "SendGP1Command", "x"),
+    (0x49, "GPU_cw", "x"),
+    (0x4A, "GPU_cwp", "xd"),
+    (0x4B, "send_gpu_linked_list", "x"),
+    (0x4C, "gpu_abort_dma", ""),
+    (0x4D, "GetGPUStatus", ""),
+    (0x4E, "gpu_sync", ""),
+    (0x51, "LoadExec", "sxx"),
+    (0x52, "GetSysSp", ""),
+    (0x54, "CdInit", ""),
+    (0x55, "_bu_init", ""),
+    (0x56, "CdRemove", ""),
+    (0x5B, "dev_tty_init", ""),
+    (0x5C, "dev_tty_open", "xsx"),
+    (0x5D, "dev_tty_in_out", "xx"),
+    (0x5E, "dev_tty_ioctl", "xxx"),
+    (0x5F, "dev_cd_open", "xsx"),
+    (0x60, "dev_cd_read", "xxx"),
+    (0x61, "dev_cd_close", "x"),
+    (0x62, "dev_cd_firstfile", "xsx"),
+    (0x63, "dev_cd_nextfile", "xx"),
+    (0x64, "dev_cd_chdir", "xs"),
+    (0x65, "dev_card_open", "xsx"),
+    (0x66, "dev_card_read", "xxx"),
+    (0x67, "dev_card_write", "xxx"),
+    (0x68, "dev_card_close", "x"),
+    (0x69, "dev_card_firstfile", "xsx"),
+    (0x6A, "dev_card_nextfile", "xx"),
+    (0x6B, "dev_card_erase", "xs"),
+    (0x6C, "dev_card_undelete", "xs"),
+    (0x6D, "dev_card_format", "x"),
+    (0x6E, "dev_card_rename", "xsxs"),
+    (0x6F, "card_clear_error", "x"),
+    (0x70, "_bu_init", ""),
+    (0x71, "CdInit", ""),
+    (0x72, "CdRemove", ""),
+    (0x78, "CdAsyncSeekL", "x"),
+    (0x7C, "CdAsyncGetStatus", "x"),
+    (0x7E, "CdAsyncReadSector", "dxx"),
+    (0x81, "CdAsyncSetMode", "x"),
+    (0x90, "CdromIoIrqFunc1", ""),
+    (0x91, "CdromDmaIrqFunc1", ""),
+    (0x92, "CdromIoIrqFunc2", ""),
+    (0x93, "CdromDmaIrqFunc2", ""),
+    (0x94, "CdromGetInt5errCode", "xx"),
+    (0x95, "CdInitSubFunc", ""),
+    (0x96, "AddCDROMDevice", ""),
+    (0x97, "AddMemCardDevice", ""),
+    (0x98, "AddDuartTtyDevice", ""),
+    (0x99, "AddDummyTtyDevice", ""),
+    (0x9C, "SetConf", "ddx"),
+    (0x9D, "GetConf", "xxx"),
+    (0x9E, "SetCdromIrqAutoAbort", "dd"),
+    (0x9F, "SetMemSize", "d"),
+    (0xA0, "WarmBoot", ""),
+    (0xA1, "SystemErrorBootOrDiskFailure", "cx"),
+    (0xA2, "EnqueueCdIntr", ""),
+    (0xA3, "DequeueCdIntr", ""),
+    (0xA4, "CdGetLbn", "s"),
+    (0xA5, "CdReadSector", "ddx"),
+    (0xA6, "CdGetStatus", ""),
+    (0xA7, "bu_callback_okay", ""),
+    (0xA8, "bu_callback_err_write", ""),
+    (0xA9, "bu_callback_err_busy", ""),
+    (0xAA, "bu_callback_err_eject", ""),
+    (0xAB, "_card_info", "x"),
+    (0xAC, "_card_async_load_directory", "x"),
+    (0xAD, "set_card_auto_format", "x"),
+    (0xAE, "bu_callback_err_prev_write", ""),
+    (0xAF, "card_write_test", "x"),
+    (0xB2, "ioabort_raw", "x"),
+    (0xB4, "GetSystemInfo", "x"),
+];
+
+const B0_FUNCTIONS: &[Signature] = &[
+    (0x00, "alloc_kernel_memory", "x"),
+    (0x01, "free_kernel_memory", "x"),
+    (0x02, "init_timer", "dxx"),
+    (0x03, "get_timer", "d"),
+    (0x04, "enable_timer_irq", "d"),
+    (0x05, "disable_timer_irq", "d"),
+    (0x06, "restart_timer", "d"),
+    (0x07, "DeliverEvent", "xx"),
+    (0x08, "OpenEvent", "xxxx"),
+    (0x09, "CloseEvent", "x"),
+    (0x0A, "WaitEvent", "x"),
+    (0x0B, "TestEvent", "x"),
+    (0x0C, "EnableEvent", "x"),
+    (0x0D, "DisableEvent", "x"),
+    (0x0E, "OpenThread", "xxx"),
+    (0x0F, "CloseThread", "x"),
+    (0x10, "ChangeThread", "x"),
+    (0x11, "jump_to_00000000h", ""),
+    (0x12, "InitPad", "xdxd"),
+    (0x13, "StartPad", ""),
+    (0x14, "StopPad", ""),
+    (0x15, "OutdatedPadInitAndStart", "xxxx"),
+    (0x16, "OutdatedPadGetButtons", ""),
+    (0x17, "ReturnFromException", ""),
+    (0x18, "SetDefaultExitFromException", ""),
+    (0x19, "SetCustomExitFromException", "x"),
+    (0x20, "UnDeliverEvent", "xx"),
+    (0x32, "open", "sx"),
+    (0x33, "lseek", "dxd"),
+    (0x34, "read", "dxx"),
+    (0x35, "write", "dxx"),
+    (0x36, "close", "d"),
+    (0x37, "ioctl", "dxx"),
+    (0x38, "exit", "d"),
+    (0x39, "isatty", "d"),
+    (0x3A, "getc", "d"),
+    (0x3B, "putc", "cd"),
+    (0x3C, "std_in_getchar", ""),
+    (0x3D, "std_out_putchar", "c"),
+    (0x3E, "std_in_gets", "x"),
+    (0x3F, "std_out_puts", "s"),
+    (0x40, "chdir", "s"),
+    (0x41, "FormatDevice", "s"),
+    (0x42, "firstfile", "sx"),
+    (0x43, "nextfile", "x"),
+    (0x44, "rename", "ss"),
+    (0x45, "erase", "s"),
+    (0x46, "undelete", "s"),
+    (0x47, "AddDrv", "x"),
+    (0x48, "DelDrv", "s"),
+    (0x49, "PrintInstalledDevices", ""),
+    (0x4A, "InitCard", "x"),
+    (0x4B, "StartCard", ""),
+    (0x4C, "StopCard", ""),
+    (0x4D, "_card_info_subfunc", "x"),
+    (0x4E, "write_card_sector", "xdx"),
+    (0x4F, "read_card_sector", "xdx"),
+    (0x50, "allow_new_card", ""),
+    (0x51, "Krom2RawAdd", "x"),
+    (0x53, "Krom2Offset", "x"),
+    (0x54, "GetLastError", ""),
+    (0x55, "GetLastFileError", "d"),
+    (0x56, "GetC0Table", ""),
+    (0x57, "GetB0Table", ""),
+    (0x58, "get_bu_callback_port", ""),
+    (0x59, "testdevice", "s"),
+    (0x5B, "ChangeClearPad", "x"),
+    (0x5C, "get_card_status", "d"),
+    (0x5D, "wait_card_status", "d"),
+];
+
+const C0_FUNCTIONS: &[Signature] = &[
+    (0x00, "EnqueueTimerAndVblankIrqs", "d"),
+    (0x01, "EnqueueSyscallHandler", "d"),
+    (0x02, "SysEnqIntRP", "dx"),
+    (0x03, "SysDeqIntRP", "dx"),
+    (0x04, "get_free_EvCB_slot", ""),
+    (0x05, "get_free_TCB_slot", ""),
+    (0x06, "ExceptionHandler", ""),
+    (0x07, "InstallExceptionHandlers", ""),
+    (0x08, "SysInitMemory", "xx"),
+    (0x09, "SysInitKernelVariables", ""),
+    (0x0A, "ChangeClearRCnt", "dx"),
+    (0x0C, "InitDefInt", "d"),
+    (0x0D, "SetIrqAutoAck", "dx"),
+    (0x12, "InstallDevices", "x"),
+    (0x13, "FlushStdInOutPut", ""),
+    (0x15, "tty_cdevinput", "xc"),
+    (0x16, "tty_cdevscan", ""),
+    (0x17, "tty_circgetc", "x"),
+    (0x18, "tty_circputc", "cx"),
+    (0x19, "ioabort", "ss"),
+    (0x1A, "set_card_find_mode", "x"),
+    (0x1B, "KernelRedirect", "x"),
+    (0x1C, "AdjustA0Table", ""),
+    (0x1D, "get_card_find_mode", ""),
+];
+
+/// A call into one of the kernel's function tables
+#[derive(Clone, Debug)]
+pub struct KernelCall {
+    // 0xA0, 0xB0 or 0xC0
+    pub table: u32,
+    pub function: u32,
+    pub name: &'static str,
+    pub args: Vec<String>,
+    // Where the call will return to
+    pub caller: u32,
+}
+
+impl fmt::Display for KernelCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}:{:02X} {}({}) from {:08X}", self.table, self.function, self.name, self.args.join(", "), self.caller)
+    }
+}
+
+impl KernelCall {
+    /// The table and function, as `A0:3F`, for filtering
+    pub fn id(&self) -> String {
+        format!("{:02X}:{:02X}", self.table, self.function)
+    }
+
+    /// Character written by std_out_putchar, which the BIOS's printf goes through
+    pub fn putchar(&self, registers: &Registers) -> Option<u8> {
+        matches!((self.table, self.function), (A0_VECTOR, 0x3C) | (B0_VECTOR, 0x3D)).then_some(registers.gpr[REGISTER_A0] as u8)
+    }
+}
+
+fn lookup(functions: &[Signature], function: u32) -> (&'static str, &'static str) {
+    functions
+        .iter()
+        .find(|(number, _, _)| *number as u32 == function)
+        .map_or(("unknown", "xxxx"), |(_, name, signature)| (*name, *signature))
+}
+
+/// Name of a function in one of the tables, for messages
+pub fn function_name(table: u32, function: u32) -> &'static str {
+    match table {
+        A0_VECTOR => lookup(A0_FUNCTIONS, function).0,
+        B0_VECTOR => lookup(B0_FUNCTIONS, function).0,
+        C0_VECTOR => lookup(C0_FUNCTIONS, function).0,
+        _ => "unknown",
+    }
+}
+
+/// The kernel call being made when execution reaches `pc`, if it is one of the table vectors
+///
+/// `peek` reads memory without side effects, for string arguments.
+pub fn decode(pc: u32, registers: &Registers, peek: impl Fn(u32) -> Option<u8>) -> Option<KernelCall> {
+    let table = pc & 0x1FFFFFFF;
+    let functions = match table {
+        A0_VECTOR => A0_FUNCTIONS,
+        B0_VECTOR => B0_FUNCTIONS,
+        C0_VECTOR => C0_FUNCTIONS,
+        _ => return None,
+    };
+    let function = registers.gpr[REGISTER_T1] & 0xFF;
+    let (name, signature) = lookup(functions, function);
+
+    let args = signature
+        .chars()
+        .enumerate()
+        .map(|(index, kind)| {
+            let value = registers.gpr[REGISTER_A0 + index];
+            match kind {
+                's' => read_string(value, &peek),
+                'd' => (value as i32).to_string(),
+                'c' => format!("{:?}", value as u8 as char),
+                _ => format!("0x{:08X}", value),
+            }
+        })
+        .collect();
+
+    Some(KernelCall {
+        table,
+        function,
+        name,
+        args,
+        caller: registers.gpr[REGISTER_RA],
+    })
+}
+
+fn read_string(addr: u32, peek: &impl Fn(u32) -> Option<u8>) -> String {
+    let mut text = Vec::new();
+    for offset in 0..MAX_STRING_LENGTH as u32 {
+        match peek(addr.wrapping_add(offset)) {
+            Some(0) => return format!("{:?}", String::from_utf8_lossy(&text)),
+            Some(byte) => text.push(byte),
+            None => return format!("0x{:08X}", addr),
+        }
+    }
+    format!("{:?}...", String::from_utf8_lossy(&text))
+}
@@ -0,0 +1,110 @@
+use crate::bios::{calls, system_cnf};
+use crate::cdrom::{self, iso9660};
+use crate::cpu;
+use crate::memory::{mmio::Mmio, Addressable};
+
+const MAGIC: &[u8] = b"PS-X EXE";
+const HEADER_SIZE: usize = 0x800;
+
+const REGISTER_A0: usize = 4;
+const REGISTER_T1: usize = 9;
+const REGISTER_GP: usize = 28;
+const REGISTER_SP: usize = 29;
+const REGISTER_FP: usize = 30;
+const REGISTER_RA: usize = 31;
+
+const SET_CONF: u32 = 0x9C;
+
+// Where the kernel puts the stack when neither the executable nor SYSTEM.CNF say
+pub const DEFAULT_STACK: u32 = 0x801FFF00;
+
+/// A PS-X EXE: a 2 KB header followed by the text segment
+#[derive(Clone)]
+pub struct Executable {
+    pub pc: u32,
+    pub gp: u32,
+    pub text_address: u32,
+    pub bss_address: u32,
+    pub bss_size: u32,
+    // Zero to leave the stack where the loader put it
+    pub stack_address: u32,
+    pub stack_size: u32,
+    pub text: Vec<u8>,
+}
+
+fn header_word(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl Executable {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err("Not a PS-X EXE".to_string());
+        }
+        let text_size = header_word(data, 0x1C) as usize;
+        // Some tools pad the file less than the header claims
+        let text_end = (HEADER_SIZE + text_size).min(data.len());
+        Ok(Executable {
+            pc: header_word(data, 0x10),
+            gp: header_word(data, 0x14),
+            text_address: header_word(data, 0x18),
+            bss_address: header_word(data, 0x28),
+            bss_size: header_word(data, 0x2C),
+            stack_address: header_word(data, 0x30),
+            stack_size: header_word(data, 0x34),
+            text: data[HEADER_SIZE..text_end].to_vec(),
+        })
+    }
+
+    pub fn open(path: &std::path::Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The boot file named in SYSTEM.CNF, with SYSTEM.CNF's stack as the loader gives it
+    pub fn from_disc(disc: &mut cdrom::Disc, cnf: &system_cnf::SystemCnf) -> Result<Self, String> {
+        let file = iso9660::find(disc, &cnf.boot)?;
+        let mut executable = Self::parse(&iso9660::read_file(disc, &file)?).map_err(|e| format!("{}: {}", cnf.boot, e))?;
+        executable.stack_address = cnf.stack;
+        executable.stack_size = 0;
+        Ok(executable)
+    }
+
+    /// Copy the text into RAM, clear the BSS and point the CPU at the entry point
+    ///
+    /// `stack` is where the loader would put the stack, used when the header doesn't set one.
+    pub fn load(&self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, stack: u32) {
+        for (offset, &value) in self.text.iter().enumerate() {
+            mmio.write(self.text_address.wrapping_add(offset as u32), value);
+        }
+        for offset in 0..self.bss_size {
+            mmio.write(self.bss_address.wrapping_add(offset), 0);
+        }
+        // Code was just written behind the instruction cache's back
+        mmio.icache.invalidate_all();
+
+        let stack = if self.stack_address != 0 {
+            self.stack_address.wrapping_add(self.stack_size)
+        } else {
+            stack
+        };
+        let registers = &mut cpu.registers;
+        registers.pc = self.pc;
+        registers.gpr[REGISTER_GP] = self.gp;
+        registers.gpr[REGISTER_SP] = stack;
+        registers.gpr[REGISTER_FP] = stack;
+    }
+
+    /// Load as the BIOS shell would, but first have the real kernel's SetConf size its event and thread
+    /// tables, returning straight into the executable
+    pub fn load_with_set_conf(&self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, cnf: &system_cnf::SystemCnf) {
+        self.load(cpu, mmio, cnf.stack);
+        let registers = &mut cpu.registers;
+        registers.gpr[REGISTER_A0] = cnf.event;
+        registers.gpr[REGISTER_A0 + 1] = cnf.tcb;
+        registers.gpr[REGISTER_A0 + 2] = cnf.stack;
+        registers.gpr[REGISTER_T1] = SET_CONF;
+        registers.gpr[REGISTER_RA] = self.pc;
+        registers.pc = calls::A0_VECTOR;
+    }
+}
@@ -0,0 +1,1756 @@
+//! Built-in stand-in for the kernel in the BIOS ROM, so discs and executables boot without a firmware image.
+//!
+//! Calls through the A0/B0/C0 tables and exceptions are caught at their entry points and handled here
+//! instead of by MIPS code. Only callbacks into the game, such as event handlers, run on the CPU.
+
+use crate::bios::{calls, exe, system_cnf};
+use crate::cdrom::{self, iso9660};
+use crate::cpu::{self, cop0};
+use crate::interrupts::{self, Interrupt};
+use crate::memcard::directory;
+use crate::memory::{control, mmio::Mmio, Addressable};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+const REGISTER_V0: usize = 2;
+const REGISTER_A0: usize = 4;
+const REGISTER_T1: usize = 9;
+const REGISTER_S0: usize = 16;
+const REGISTER_GP: usize = 28;
+const REGISTER_SP: usize = 29;
+const REGISTER_FP: usize = 30;
+const REGISTER_RA: usize = 31;
+
+// Rough cost of a kernel call or exception, which would run some tens of instructions
+const KERNEL_CYCLES: u32 = 20;
+
+// Callbacks into the game return here, in the ROM area so it is never game code
+const CALLBACK_RETURN: u32 = 0xBFC0FF00;
+// Stack for callbacks run by the kernel, below its memory pool
+const KERNEL_STACK: u32 = 0x8000DF00;
+const KERNEL_HEAP_START: u32 = 0xA000E000;
+const KERNEL_HEAP_SIZE: u32 = 0x2000;
+// Where a real kernel keeps the B0 and C0 tables, for games that ask
+const B0_TABLE: u32 = 0x874;
+const C0_TABLE: u32 = 0x674;
+// Version date in GetSystemInfo
+const KERNEL_DATE: u32 = 0x19951204;
+
+// Scratchpad and code cache on, as the BIOS leaves them
+const BOOT_CACHE_CONTROL: u32 = 0x0001E988;
+// GTE usable, interrupts on with the interrupt controller's line unmasked
+const BOOT_SR: u32 = 0x40000000 | cop0::SR_INTERRUPT_MASK_HARDWARE | cop0::SR_INTERRUPT_ENABLE;
+// Enter/ExitCriticalSection work on the SR saved by the syscall, so the previous interrupt enable
+const CRITICAL_SECTION_BITS: u32 = cop0::SR_INTERRUPT_MASK_HARDWARE | cop0::SR_INTERRUPT_ENABLE << 2;
+
+const EXCEPTION_INTERRUPT: u32 = cop0::Exception::Interrupt as u32;
+const EXCEPTION_SYSCALL: u32 = cop0::Exception::Syscall as u32;
+
+const EVENT_HANDLE: u32 = 0xF1000000;
+const THREAD_HANDLE: u32 = 0xFF000000;
+const HANDLE_INDEX: u32 = 0xFFFF;
+
+const EVENT_DISABLED: u32 = 0x1000;
+const EVENT_ENABLED: u32 = 0x2000;
+const EVENT_READY: u32 = 0x4000;
+// Delivery either runs the event's function or marks it ready for WaitEvent/TestEvent
+const EVENT_MODE_CALLBACK: u32 = 0x1000;
+const EVENT_MODE_READY: u32 = 0x2000;
+
+const CLASS_ROOT_COUNTER: u32 = 0xF2000000;
+const CLASS_VBLANK: u32 = 0xF2000003;
+const CLASS_CARD_SOFTWARE: u32 = 0xF0000011;
+const CLASS_CARD_HARDWARE: u32 = 0xF4000001;
+const SPEC_INTERRUPT: u32 = 0x0002;
+const SPEC_DONE: u32 = 0x0004;
+const SPEC_TIMEOUT: u32 = 0x0100;
+
+// Lengths passed by programs are cut to the size of RAM, anything longer is garbage
+const MAX_BLOCK_LENGTH: u32 = 0x200000;
+
+const FILE_CREATE: u32 = 0x200;
+const MAX_FILES: usize = 16;
+// 0 and 1 are the TTY's stdin and stdout
+const FIRST_FILE: usize = 2;
+const STDOUT: u32 = 1;
+const DIRECTORY_NAME_LENGTH: usize = 20;
+
+// Error numbers for GetLastError
+const ERROR_NO_FILE: u32 = 2;
+const ERROR_BAD_FILE: u32 = 9;
+const ERROR_NO_DEVICE: u32 = 19;
+const ERROR_INVALID: u32 = 22;
+const ERROR_TOO_MANY_FILES: u32 = 24;
+const ERROR_NO_SPACE: u32 = 28;
+
+// Longest string the kernel will walk before giving up
+const MAX_STRING_LENGTH: u32 = 0x10000;
+// printf arguments are read up front, this many words past the format
+const MAX_PRINTF_ARGUMENTS: u32 = 32;
+
+const PAD_REPLY_LENGTH: usize = 34;
+const CARD_SECTORS: u32 = 1024;
+
+/// Memory handed out by malloc or alloc_kernel_memory
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Heap {
+    start: u32,
+    end: u32,
+    // Start and size of each allocation
+    blocks: BTreeMap<u32, u32>,
+}
+
+impl Heap {
+    fn new(start: u32, size: u32) -> Self {
+        Heap {
+            start,
+            end: start.wrapping_add(size),
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    /// First fit, 0 when nothing is big enough
+    fn alloc(&mut self, size: u32) -> u32 {
+        let Some(size) = size.max(1).checked_next_multiple_of(4) else {
+            return 0;
+        };
+        let mut candidate = self.start;
+        for (&start, &length) in &self.blocks {
+            if start - candidate >= size {
+                break;
+            }
+            candidate = start + length;
+        }
+        if candidate.checked_add(size).is_none_or(|end| end > self.end) {
+            return 0;
+        }
+        self.blocks.insert(candidate, size);
+        candidate
+    }
+
+    fn free(&mut self, addr: u32) {
+        self.blocks.remove(&addr);
+    }
+
+    fn size(&self, addr: u32) -> Option<u32> {
+        self.blocks.get(&addr).copied()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Event {
+    class: u32,
+    spec: u32,
+    mode: u32,
+    status: u32,
+    callback: u32,
+}
+
+/// CPU state of a thread, or of whatever the kernel interrupted
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Context {
+    gpr: [u32; 32],
+    hi: u32,
+    lo: u32,
+    pc: u32,
+    sr: u32,
+}
+
+impl Context {
+    fn save(cpu: &cpu::R3000A) -> Self {
+        Context {
+            gpr: cpu.registers.gpr,
+            hi: cpu.registers.hi,
+            lo: cpu.registers.lo,
+            pc: cpu.registers.pc,
+            sr: cpu.cop0.sr,
+        }
+    }
+
+    fn restore(&self, cpu: &mut cpu::R3000A) {
+        cpu.registers.gpr = self.gpr;
+        cpu.registers.hi = self.hi;
+        cpu.registers.lo = self.lo;
+        cpu.registers.pc = self.pc;
+        cpu.cop0.sr = self.sr;
+    }
+}
+
+/// A function in the game for the kernel to call
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Callback {
+    function: u32,
+    argument: u32,
+    // Called next with the return value if that is non-zero, as for SysEnqIntRP's second handler
+    then: u32,
+}
+
+/// Callbacks being run on behalf of an exception or kernel call
+#[derive(Serialize, Deserialize, Clone)]
+struct KernelRun {
+    interrupted: Context,
+    from_exception: bool,
+    current: Option<Callback>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+enum FileSource {
+    Disc { lba: u32 },
+    // Card blocks in chain order
+    Card { slot: usize, blocks: Vec<usize> },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct OpenFile {
+    source: FileSource,
+    size: u32,
+    position: u32,
+}
+
+/// A file found by firstfile, waiting to be handed out
+#[derive(Serialize, Deserialize, Clone)]
+struct SearchResult {
+    name: String,
+    size: u32,
+    // First sector on disc, or first block on a card
+    start: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HleBios {
+    heap: Heap,
+    kernel_heap: Heap,
+    events: Vec<Option<Event>>,
+    threads: Vec<Option<Context>>,
+    current_thread: usize,
+    stack_top: u32,
+    files: Vec<Option<OpenFile>>,
+    search: VecDeque<SearchResult>,
+    last_error: u32,
+    rand_seed: u32,
+    strtok_next: u32,
+    // Address and size of the buffer for each pad port
+    pad_buffers: [(u32, u32); 2],
+    pads_started: bool,
+    // Handler structures added by SysEnqIntRP, by priority
+    interrupt_handlers: [Vec<u32>; 4],
+    // Whether the kernel acknowledges each root counter's and VBlank's interrupt
+    auto_acknowledge: [bool; 4],
+    // Interrupts to acknowledge once the handlers have seen them
+    acknowledge_on_return: u16,
+    // SetCustomExitFromException's jump buffer, recorded but exceptions always return normally
+    exit_hook: u32,
+    pending_callbacks: VecDeque<Callback>,
+    kernel_run: Option<KernelRun>,
+    // Set while WaitEvent is spinning, so the call is only traced once
+    waiting: bool,
+    #[serde(skip)]
+    reported: HashSet<(u32, u32)>,
+}
+
+fn read_u32(mmio: &mut Mmio, addr: u32) -> u32 {
+    u32::from_le_bytes([0, 1, 2, 3].map(|byte| mmio.read(addr.wrapping_add(byte))))
+}
+
+fn write_u32(mmio: &mut Mmio, addr: u32, value: u32) {
+    write_bytes(mmio, addr, &value.to_le_bytes());
+}
+
+fn read_bytes(mmio: &mut Mmio, addr: u32, length: u32) -> Vec<u8> {
+    (0..length.min(MAX_BLOCK_LENGTH)).map(|offset| mmio.read(addr.wrapping_add(offset))).collect()
+}
+
+fn fill_bytes(mmio: &mut Mmio, addr: u32, value: u8, length: u32) {
+    for offset in 0..length.min(MAX_BLOCK_LENGTH) {
+        mmio.write(addr.wrapping_add(offset), value);
+    }
+}
+
+fn write_bytes(mmio: &mut Mmio, addr: u32, data: &[u8]) {
+    for (offset, &value) in data.iter().enumerate() {
+        mmio.write(addr.wrapping_add(offset as u32), value);
+    }
+}
+
+/// A NUL terminated string, without the NUL
+fn read_string(mmio: &mut Mmio, addr: u32) -> Vec<u8> {
+    let mut string = Vec::new();
+    for offset in 0..MAX_STRING_LENGTH {
+        match mmio.read(addr.wrapping_add(offset)) {
+            0 => break,
+            value => string.push(value),
+        }
+    }
+    string
+}
+
+fn write_string(mmio: &mut Mmio, addr: u32, string: &[u8]) {
+    write_bytes(mmio, addr, string);
+    mmio.write(addr.wrapping_add(string.len() as u32), 0);
+}
+
+/// The difference of the first mismatched bytes, as C's comparisons return
+fn compare(a: &[u8], b: &[u8]) -> u32 {
+    let mismatch = a.iter().zip(b).find(|(x, y)| x != y);
+    match mismatch {
+        Some((&x, &y)) => (x as i32 - y as i32) as u32,
+        None => (a.len() as i32 - b.len() as i32).signum() as u32,
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// strtol, returning the value and the address parsing stopped at
+fn parse_integer(mmio: &mut Mmio, addr: u32, base: u32) -> (u32, u32) {
+    let mut position = addr;
+    while matches!(mmio.read(position), b' ' | b'\t' | b'\n' | b'\r') {
+        position = position.wrapping_add(1);
+    }
+    let negative = mmio.read(position) == b'-';
+    if matches!(mmio.read(position), b'-' | b'+') {
+        position = position.wrapping_add(1);
+    }
+    let has_hex_prefix = mmio.read(position) == b'0' && matches!(mmio.read(position.wrapping_add(1)), b'x' | b'X');
+    let base = match base {
+        0 if has_hex_prefix => 16,
+        0 if mmio.read(position) == b'0' => 8,
+        0 => 10,
+        base => base,
+    };
+    if base == 16 && has_hex_prefix {
+        position = position.wrapping_add(2);
+    }
+    let mut value = 0u32;
+    while let Some(digit) = (mmio.read(position) as char).to_digit(36).filter(|&digit| digit < base) {
+        value = value.wrapping_mul(base).wrapping_add(digit);
+        position = position.wrapping_add(1);
+    }
+    (if negative { value.wrapping_neg() } else { value }, position)
+}
+
+/// printf formatting with arguments already read, `read_string` fetches the text for `%s`
+fn format(format: &[u8], arguments: &[u32], mut read_string: impl FnMut(u32) -> Vec<u8>) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut arguments = arguments.iter().copied().chain(std::iter::repeat(0));
+    let mut chars = format.iter().copied().peekable();
+    while let Some(char) = chars.next() {
+        if char != b'%' {
+            output.push(char);
+            continue;
+        }
+        let (mut left, mut zero, mut alternate, mut sign) = (false, false, false, None);
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                b'-' => left = true,
+                b'0' => zero = true,
+                b'#' => alternate = true,
+                b'+' => sign = Some(b'+'),
+                b' ' => sign = sign.or(Some(b' ')),
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut read_number = |chars: &mut std::iter::Peekable<std::iter::Copied<std::slice::Iter<u8>>>| {
+            if chars.peek() == Some(&b'*') {
+                chars.next();
+                return arguments.next().unwrap_or(0) as usize;
+            }
+            let mut number = 0;
+            while let Some(digit) = chars.peek().filter(|digit| digit.is_ascii_digit()) {
+                number = number * 10 + (digit - b'0') as usize;
+                chars.next();
+            }
+            number
+        };
+        let width = read_number(&mut chars);
+        let precision = (chars.peek() == Some(&b'.')).then(|| {
+            chars.next();
+            read_number(&mut chars)
+        });
+        while matches!(chars.peek(), Some(b'l' | b'h')) {
+            chars.next();
+        }
+        let Some(conversion) = chars.next() else {
+            break;
+        };
+
+        let mut argument = || arguments.next().unwrap_or(0);
+        let (prefix, mut body, numeric) = match conversion {
+            b'd' | b'i' => {
+                let value = argument() as i32;
+                let prefix = if value < 0 { Some(b'-') } else { sign };
+                (prefix.map(|sign| vec![sign]).unwrap_or_default(), value.unsigned_abs().to_string().into_bytes(), true)
+            }
+            b'u' => (Vec::new(), argument().to_string().into_bytes(), true),
+            b'x' | b'p' => {
+                let prefix = if alternate || conversion == b'p' { b"0x".to_vec() } else { Vec::new() };
+                (prefix, format!("{:x}", argument()).into_bytes(), true)
+            }
+            b'X' => (if alternate { b"0X".to_vec() } else { Vec::new() }, format!("{:X}", argument()).into_bytes(), true),
+            b'o' => (if alternate { b"0".to_vec() } else { Vec::new() }, format!("{:o}", argument()).into_bytes(), true),
+            b'c' => (Vec::new(), vec![argument() as u8], false),
+            b's' => {
+                let mut string = read_string(argument());
+                if let Some(precision) = precision {
+                    string.truncate(precision);
+                }
+                (Vec::new(), string, false)
+            }
+            b'%' => (Vec::new(), vec![b'%'], false),
+            other => (Vec::new(), vec![b'%', other], false),
+        };
+        if numeric && let Some(precision) = precision && body.len() < precision {
+            body.splice(0..0, std::iter::repeat_n(b'0', precision - body.len()));
+        }
+
+        let padding = width.saturating_sub(prefix.len() + body.len());
+        if left {
+            output.extend(prefix.iter().chain(&body));
+            output.extend(std::iter::repeat_n(b' ', padding));
+        } else if zero && numeric && precision.is_none() {
+            output.extend(&prefix);
+            output.extend(std::iter::repeat_n(b'0', padding));
+            output.extend(&body);
+        } else {
+            output.extend(std::iter::repeat_n(b' ', padding));
+            output.extend(prefix.iter().chain(&body));
+        }
+    }
+    output
+}
+
+/// Filename match for firstfile, `?` matches any character and `*` the rest of the name
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let mut name = name.chars();
+    for expected in pattern.chars() {
+        match expected {
+            '*' => return true,
+            '?' => {
+                if name.next().is_none() {
+                    return false;
+                }
+            }
+            _ => {
+                if !name.next().is_some_and(|char| char.eq_ignore_ascii_case(&expected)) {
+                    return false;
+                }
+            }
+        }
+    }
+    name.next().is_none()
+}
+
+/// Memory card slot for a `bu00:` or `bu10:` device
+fn card_slot(device: &str) -> Option<usize> {
+    match device.to_ascii_lowercase().as_str() {
+        "bu00" => Some(0),
+        "bu10" => Some(1),
+        _ => None,
+    }
+}
+
+fn disc_entry(lba: u32, size: u32) -> iso9660::Entry {
+    iso9660::Entry {
+        name: String::new(),
+        lba,
+        size,
+        is_directory: false,
+        xa: None,
+    }
+}
+
+/// Offset into a card image of a position in a save made of `blocks`
+fn card_offset(blocks: &[usize], position: u32) -> usize {
+    let position = position as usize;
+    blocks[position / directory::BLOCK_SIZE] * directory::BLOCK_SIZE + position % directory::BLOCK_SIZE
+}
+
+fn read_disc_file(disc: Option<&mut cdrom::Disc>, path: &str) -> Result<Vec<u8>, String> {
+    let disc = disc.ok_or_else(|| format!("No disc to load {} from", path))?;
+    let file = iso9660::find(disc, path)?;
+    iso9660::read_file(disc, &file)
+}
+
+impl HleBios {
+    pub fn new() -> Self {
+        let defaults = system_cnf::SystemCnf::default();
+        let mut bios = HleBios {
+            heap: Heap::default(),
+            kernel_heap: Heap::new(KERNEL_HEAP_START, KERNEL_HEAP_SIZE),
+            events: Vec::new(),
+            threads: Vec::new(),
+            current_thread: 0,
+            stack_top: defaults.stack,
+            files: vec![None; MAX_FILES],
+            search: VecDeque::new(),
+            last_error: 0,
+            rand_seed: 0,
+            strtok_next: 0,
+            pad_buffers: [(0, 0); 2],
+            pads_started: false,
+            interrupt_handlers: Default::default(),
+            auto_acknowledge: [true; 4],
+            acknowledge_on_return: 0,
+            exit_hook: 0,
+            pending_callbacks: VecDeque::new(),
+            kernel_run: None,
+            waiting: false,
+            reported: HashSet::new(),
+        };
+        bios.set_conf(defaults.event, defaults.tcb, defaults.stack);
+        bios
+    }
+
+    /// Set up the kernel as the BIOS would and start a side-loaded executable or the disc's boot file
+    pub fn boot(
+        &mut self,
+        cpu: &mut cpu::R3000A,
+        mmio: &mut Mmio,
+        disc: Option<&mut cdrom::Disc>,
+        executable: Option<&exe::Executable>,
+    ) -> Result<(), String> {
+        *self = HleBios::new();
+        write_u32(mmio, control::CACHE_CONTROL_START, BOOT_CACHE_CONTROL);
+        mmio.icache.invalidate_all();
+        cpu.cop0 = cop0::Cop0::new();
+        cpu.cop0.sr = BOOT_SR;
+
+        if let Some(executable) = executable {
+            executable.load(cpu, mmio, exe::DEFAULT_STACK);
+            return Ok(());
+        }
+        let disc = disc.ok_or("Nothing to boot, insert a disc or give an executable")?;
+        let cnf = system_cnf::SystemCnf::from_disc(disc)?;
+        self.set_conf(cnf.event, cnf.tcb, cnf.stack);
+        exe::Executable::from_disc(disc, &cnf)?.load(cpu, mmio, cnf.stack);
+        println!("HLE BIOS booting {}", cnf.boot);
+        Ok(())
+    }
+
+    /// Start rand() somewhere other than 0, as srand() would
+    pub fn seed_rand(&mut self, seed: u32) {
+        self.rand_seed = seed;
+    }
+
+    /// Whether the kernel handles execution at `pc` instead of the CPU
+    pub fn intercepts(&self, pc: u32) -> bool {
+        matches!(pc & 0x1FFFFFFF, calls::A0_VECTOR | calls::B0_VECTOR | calls::C0_VECTOR)
+            || pc == cop0::EXCEPTION_VECTOR
+            || pc == cop0::BOOT_EXCEPTION_VECTOR
+            || pc == CALLBACK_RETURN
+    }
+
+    /// Handle the kernel call, exception or callback return at the CPU's pc, returns the cycles taken
+    pub fn step(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>) -> u32 {
+        match cpu.registers.pc {
+            CALLBACK_RETURN => self.callback_returned(cpu),
+            cop0::EXCEPTION_VECTOR | cop0::BOOT_EXCEPTION_VECTOR => self.exception(cpu, mmio),
+            _ => self.kernel_call(cpu, mmio, disc),
+        }
+        if !cpu.stopped {
+            self.run_callbacks(cpu, mmio);
+        }
+        KERNEL_CYCLES
+    }
+
+    fn kernel_call(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>) {
+        if !self.waiting {
+            cpu.trace_kernel_call(mmio);
+        }
+        let table = cpu.registers.pc & 0x1FFFFFFF;
+        let function = cpu.registers.gpr[REGISTER_T1] & 0xFF;
+        let result = match table {
+            calls::A0_VECTOR => self.a0_call(function, cpu, mmio, disc),
+            calls::B0_VECTOR => self.b0_call(function, cpu, mmio, disc),
+            _ => self.c0_call(function, cpu),
+        };
+        // Otherwise the call has moved the CPU somewhere else
+        if let Some(value) = result {
+            cpu.registers.gpr[REGISTER_V0] = value;
+            cpu.registers.pc = cpu.registers.gpr[REGISTER_RA];
+        }
+    }
+
+    fn unimplemented(&mut self, table: u32, function: u32) -> u32 {
+        if self.reported.insert((table, function)) {
+            println!("HLE BIOS: {:02X}:{:02X} {} is not implemented", table, function, calls::function_name(table, function));
+        }
+        0
+    }
+
+    fn a0_call(&mut self, function: u32, cpu: &mut cpu::R3000A, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>) -> Option<u32> {
+        let [a0, a1, a2, _] = [0, 1, 2, 3].map(|index| cpu.registers.gpr[REGISTER_A0 + index]);
+        let value = match function {
+            0x00 => self.open(mmio, disc, a0, a1),
+            0x01 => self.lseek(a0, a1, a2),
+            0x02 => self.read(mmio, disc, a0, a1, a2),
+            0x03 => self.write(cpu, mmio, a0, a1, a2),
+            0x04 => self.close(a0),
+            0x05 => 0,
+            0x06 | 0x3A => {
+                println!("Program exited with code {}", a0 as i32);
+                cpu.stopped = true;
+                return None;
+            }
+            0x07 => (a0 < FIRST_FILE as u32) as u32,
+            // No keyboard behind the TTY
+            0x08 | 0x3B => u32::MAX,
+            0x09 => {
+                if a1 == STDOUT {
+                    cpu.write_tty(&[a0 as u8]);
+                }
+                a0
+            }
+            // Anything that isn't a digit gets a value too big for any base
+            0x0A => (a0 as u8 as char).to_digit(36).unwrap_or(9999999),
+            0x0C | 0x0D => {
+                let (value, end) = parse_integer(mmio, a0, a2);
+                if a1 != 0 {
+                    write_u32(mmio, a1, end);
+                }
+                value
+            }
+            0x0E | 0x0F => (a0 as i32).unsigned_abs(),
+            0x10 | 0x11 => parse_integer(mmio, a0, 10).0,
+            0x12 => {
+                let (value, end) = parse_integer(mmio, a0, 10);
+                write_u32(mmio, a1, value);
+                end
+            }
+            0x13 => {
+                // setjmp: ra, sp, fp, s0-s7 and gp
+                let registers = &cpu.registers.gpr;
+                let saved = [registers[REGISTER_RA], registers[REGISTER_SP], registers[REGISTER_FP]]
+                    .into_iter()
+                    .chain(registers[REGISTER_S0..REGISTER_S0 + 8].iter().copied())
+                    .chain([registers[REGISTER_GP]]);
+                for (index, value) in saved.enumerate() {
+                    write_u32(mmio, a0.wrapping_add(index as u32 * 4), value);
+                }
+                0
+            }
+            0x14 => {
+                // longjmp, returning a1 from the setjmp
+                let mut saved = (0..12).map(|index| read_u32(mmio, a0.wrapping_add(index * 4)));
+                let registers = &mut cpu.registers.gpr;
+                for register in [REGISTER_RA, REGISTER_SP, REGISTER_FP].into_iter().chain(REGISTER_S0..REGISTER_S0 + 8).chain([REGISTER_GP]) {
+                    registers[register] = saved.next().unwrap_or(0);
+                }
+                a1
+            }
+            0x15 | 0x16 => {
+                let mut source = read_string(mmio, a1);
+                if function == 0x16 {
+                    source.truncate(a2 as usize);
+                }
+                let end = a0.wrapping_add(read_string(mmio, a0).len() as u32);
+                write_string(mmio, end, &source);
+                a0
+            }
+            0x17 => compare(&read_string(mmio, a0), &read_string(mmio, a1)),
+            0x18 => {
+                let mut a = read_string(mmio, a0);
+                let mut b = read_string(mmio, a1);
+                a.truncate(a2 as usize);
+                b.truncate(a2 as usize);
+                compare(&a, &b)
+            }
+            0x19 => {
+                if a0 == 0 || a1 == 0 {
+                    return Some(0);
+                }
+                let source = read_string(mmio, a1);
+                write_string(mmio, a0, &source);
+                a0
+            }
+            0x1A => {
+                // Pad with NULs in guest memory rather than growing the host copy to a2
+                let mut source = read_string(mmio, a1);
+                source.truncate(a2 as usize);
+                write_bytes(mmio, a0, &source);
+                let written = source.len() as u32;
+                fill_bytes(mmio, a0.wrapping_add(written), 0, a2 - written);
+                a0
+            }
+            0x1B => read_string(mmio, a0).len() as u32,
+            0x1C | 0x1E => {
+                let string = read_string(mmio, a0);
+                match a1 as u8 {
+                    0 => a0.wrapping_add(string.len() as u32),
+                    char => string.iter().position(|&value| value == char).map_or(0, |index| a0.wrapping_add(index as u32)),
+                }
+            }
+            0x1D | 0x1F => {
+                let string = read_string(mmio, a0);
+                string.iter().rposition(|&value| value == a1 as u8).map_or(0, |index| a0.wrapping_add(index as u32))
+            }
+            0x20 => {
+                let set = read_string(mmio, a1);
+                let string = read_string(mmio, a0);
+                string.iter().position(|char| set.contains(char)).map_or(0, |index| a0.wrapping_add(index as u32))
+            }
+            0x21 | 0x22 => {
+                let set = read_string(mmio, a1);
+                let in_set = function == 0x21;
+                read_string(mmio, a0).iter().take_while(|char| set.contains(char) == in_set).count() as u32
+            }
+            0x23 => self.strtok(mmio, a0, a1),
+            0x24 => {
+                let haystack = read_string(mmio, a0);
+                let needle = read_string(mmio, a1);
+                find_bytes(&haystack, &needle).map_or(0, |index| a0.wrapping_add(index as u32))
+            }
+            0x25 => (a0 as u8).to_ascii_uppercase() as u32,
+            0x26 => (a0 as u8).to_ascii_lowercase() as u32,
+            0x27 => {
+                let data = read_bytes(mmio, a0, a2);
+                write_bytes(mmio, a1, &data);
+                a1
+            }
+            0x28 => {
+                fill_bytes(mmio, a0, 0, a1);
+                a0
+            }
+            0x29 | 0x2D => compare(&read_bytes(mmio, a0, a2), &read_bytes(mmio, a1, a2)),
+            0x2A | 0x2C => {
+                let data = read_bytes(mmio, a1, a2);
+                write_bytes(mmio, a0, &data);
+                a0
+            }
+            0x2B => {
+                fill_bytes(mmio, a0, a1 as u8, a2);
+                a0
+            }
+            0x2E => read_bytes(mmio, a0, a2).iter().position(|&value| value == a1 as u8).map_or(0, |index| a0.wrapping_add(index as u32)),
+            0x2F => {
+                self.rand_seed = self.rand_seed.wrapping_mul(0x41C64E6D).wrapping_add(0x3039);
+                (self.rand_seed >> 16) & 0x7FFF
+            }
+            0x30 => {
+                self.rand_seed = a0;
+                0
+            }
+            0x33 => self.heap.alloc(a0),
+            0x34 => {
+                self.heap.free(a0);
+                0
+            }
+            0x37 => {
+                let size = a0.wrapping_mul(a1);
+                let addr = self.heap.alloc(size);
+                if addr != 0 {
+                    fill_bytes(mmio, addr, 0, size);
+                }
+                addr
+            }
+            0x38 => self.realloc(mmio, a0, a1),
+            0x39 => {
+                self.heap = Heap::new(a0, a1);
+                0
+            }
+            // std_out_putchar, which the kernel call tracer already captured
+            0x3C => a0,
+            0x3D => 0,
+            0x3E => {
+                let mut string = read_string(mmio, a0);
+                string.push(b'\n');
+                cpu.write_tty(&string);
+                0
+            }
+            0x3F => {
+                let text = self.printf(cpu, mmio);
+                let length = text.len() as u32;
+                cpu.write_tty(&text);
+                length
+            }
+            0x42 => self.load(mmio, disc, a0, a1),
+            0x43 => {
+                self.exec(cpu, mmio, a0, a1, a2);
+                return None;
+            }
+            0x44 => {
+                mmio.icache.invalidate_all();
+                0
+            }
+            0x51 => {
+                if let Err(e) = self.load_exec(cpu, mmio, disc, a0, a1, a2) {
+                    println!("HLE BIOS: {}", e);
+                    cpu.stopped = true;
+                }
+                return None;
+            }
+            0x52 => KERNEL_STACK,
+            0x54 | 0x71 => 1,
+            0x55 | 0x56 | 0x70 | 0x72 => 0,
+            0x9C => {
+                self.set_conf(a0, a1, a2);
+                0
+            }
+            0x9D => {
+                write_u32(mmio, a0, self.events.len() as u32);
+                write_u32(mmio, a1, self.threads.len() as u32);
+                write_u32(mmio, a2, self.stack_top);
+                0
+            }
+            0xA4 => {
+                let path = String::from_utf8_lossy(&read_string(mmio, a0)).to_string();
+                disc.and_then(|disc| iso9660::find(disc, &path).ok()).map_or(u32::MAX, |file| file.lba)
+            }
+            0xA5 => self.cd_read_sector(mmio, disc, a0, a1, a2),
+            // Motor on
+            0xA6 => 0x02,
+            0xAB => {
+                self.finish_card_operation(mmio, a0);
+                1
+            }
+            0xAD => 0,
+            0xB4 => match a0 {
+                0 => KERNEL_DATE,
+                // RAM size in KB
+                2 => 2048,
+                _ => 0,
+            },
+            _ => self.unimplemented(calls::A0_VECTOR, function),
+        };
+        Some(value)
+    }
+
+    fn b0_call(&mut self, function: u32, cpu: &mut cpu::R3000A, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>) -> Option<u32> {
+        let [a0, a1, a2, a3] = [0, 1, 2, 3].map(|index| cpu.registers.gpr[REGISTER_A0 + index]);
+        let value = match function {
+            0x00 => self.kernel_heap.alloc(a0),
+            0x01 => {
+                self.kernel_heap.free(a0);
+                0
+            }
+            0x07 => {
+                self.deliver_event(a0, a1);
+                0
+            }
+            0x08 => self.open_event(a0, a1, a2, a3),
+            0x09 => {
+                if self.event_mut(a0).is_none() {
+                    return Some(0);
+                }
+                self.events[(a0 & HANDLE_INDEX) as usize] = None;
+                1
+            }
+            0x0A => {
+                let status = self.event_mut(a0).map(|event| event.status);
+                match status {
+                    Some(EVENT_READY) => {
+                        self.waiting = false;
+                        self.set_event_status(a0, EVENT_ENABLED);
+                        1
+                    }
+                    // Spin on the call until an interrupt delivers the event
+                    Some(EVENT_ENABLED) => {
+                        self.waiting = true;
+                        return None;
+                    }
+                    _ => {
+                        self.waiting = false;
+                        0
+                    }
+                }
+            }
+            0x0B => match self.event_mut(a0) {
+                Some(event) if event.status == EVENT_READY => {
+                    event.status = EVENT_ENABLED;
+                    1
+                }
+                _ => 0,
+            },
+            0x0C => self.set_event_status(a0, EVENT_ENABLED),
+            0x0D => self.set_event_status(a0, EVENT_DISABLED),
+            0x0E => self.open_thread(cpu, a0, a1, a2),
+            0x0F => match self.thread_index(a0) {
+                Some(index) if index != self.current_thread => {
+                    self.threads[index] = None;
+                    1
+                }
+                _ => 0,
+            },
+            0x10 => return self.change_thread(cpu, a0),
+            0x12 => {
+                self.pad_buffers = [(a0, a1), (a2, a3)];
+                for (buffer, size) in self.pad_buffers {
+                    fill_bytes(mmio, buffer, 0xFF, size);
+                }
+                2
+            }
+            0x13 => {
+                self.pads_started = true;
+                let mask = mmio.interrupts.read(interrupts::I_MASK_START);
+                mmio.interrupts.write(interrupts::I_MASK_START, mask | 1 << Interrupt::VBlank as u8);
+                1
+            }
+            0x14 => {
+                self.pads_started = false;
+                1
+            }
+            0x17 => {
+                // The game's own handler is done, skip anything still queued
+                self.pending_callbacks.clear();
+                if let Some(run) = self.kernel_run.take() {
+                    self.resume(cpu, mmio, run);
+                    return None;
+                }
+                0
+            }
+            0x18 => {
+                self.exit_hook = 0;
+                0
+            }
+            0x19 => {
+                self.exit_hook = a0;
+                0
+            }
+            0x20 => {
+                for event in self.events.iter_mut().flatten() {
+                    if event.class == a0 && event.spec == a1 && event.mode == EVENT_MODE_READY && event.status == EVENT_READY {
+                        event.status = EVENT_ENABLED;
+                    }
+                }
+                0
+            }
+            // File and TTY functions shared with the A0 table
+            0x32..=0x3B => return self.a0_call(function - 0x32, cpu, mmio, disc),
+            0x3C..=0x3F => return self.a0_call(function - 1, cpu, mmio, disc),
+            0x40 => 1,
+            0x41 => self.format_device(mmio, a0),
+            0x42 => self.firstfile(mmio, disc, a0, a1),
+            0x43 => self.nextfile(mmio, a0),
+            0x45 => self.erase(mmio, a0),
+            0x4A..=0x4C | 0x50 | 0x5B => 1,
+            0x4E => self.card_sector(mmio, a0, a1, a2, true),
+            0x4F => self.card_sector(mmio, a0, a1, a2, false),
+            0x54 | 0x55 => self.last_error,
+            0x56 => C0_TABLE,
+            0x57 => B0_TABLE,
+            // Card ready
+            0x5C | 0x5D => 1,
+            _ => self.unimplemented(calls::B0_VECTOR, function),
+        };
+        Some(value)
+    }
+
+    fn c0_call(&mut self, function: u32, cpu: &cpu::R3000A) -> Option<u32> {
+        let [a0, a1, _, _] = [0, 1, 2, 3].map(|index| cpu.registers.gpr[REGISTER_A0 + index]);
+        let value = match function {
+            0x02 => {
+                let handlers = &mut self.interrupt_handlers[(a0 & 3) as usize];
+                if !handlers.contains(&a1) {
+                    handlers.push(a1);
+                }
+                0
+            }
+            0x03 => {
+                self.interrupt_handlers[(a0 & 3) as usize].retain(|&handler| handler != a1);
+                0
+            }
+            0x0A => match self.auto_acknowledge.get_mut(a0 as usize) {
+                Some(acknowledge) => std::mem::replace(acknowledge, a1 != 0) as u32,
+                None => 0,
+            },
+            _ => self.unimplemented(calls::C0_VECTOR, function),
+        };
+        Some(value)
+    }
+
+    fn set_conf(&mut self, events: u32, threads: u32, stack: u32) {
+        // Handles only have room for an index this big
+        self.events = vec![None; events.min(HANDLE_INDEX + 1) as usize];
+        self.threads = vec![None; threads.clamp(1, HANDLE_INDEX + 1) as usize];
+        // The thread that booted
+        self.threads[0] = Some(Context::default());
+        self.current_thread = 0;
+        self.stack_top = stack;
+    }
+
+    fn realloc(&mut self, mmio: &mut Mmio, addr: u32, size: u32) -> u32 {
+        if addr == 0 {
+            return self.heap.alloc(size);
+        }
+        if size == 0 {
+            self.heap.free(addr);
+            return 0;
+        }
+        let old_size = self.heap.size(addr).unwrap_or(0);
+        let new_addr = self.heap.alloc(size);
+        if new_addr != 0 {
+            let data = read_bytes(mmio, addr, old_size.min(size));
+            write_bytes(mmio, new_addr, &data);
+            self.heap.free(addr);
+        }
+        new_addr
+    }
+
+    fn strtok(&mut self, mmio: &mut Mmio, string: u32, delimiters: u32) -> u32 {
+        let delimiters = read_string(mmio, delimiters);
+        let mut position = if string != 0 { string } else { self.strtok_next };
+        if position == 0 {
+            return 0;
+        }
+        while mmio.read(position) != 0 && delimiters.contains(&mmio.read(position)) {
+            position = position.wrapping_add(1);
+        }
+        if mmio.read(position) == 0 {
+            self.strtok_next = 0;
+            return 0;
+        }
+        let token = position;
+        while mmio.read(position) != 0 && !delimiters.contains(&mmio.read(position)) {
+            position = position.wrapping_add(1);
+        }
+        if mmio.read(position) == 0 {
+            self.strtok_next = 0;
+        } else {
+            mmio.write(position, 0);
+            self.strtok_next = position.wrapping_add(1);
+        }
+        token
+    }
+
+    /// Format from a0, with the arguments in a1-a3 and then on the stack past the caller's home area
+    fn printf(&mut self, cpu: &cpu::R3000A, mmio: &mut Mmio) -> Vec<u8> {
+        let registers = &cpu.registers.gpr;
+        let format_string = read_string(mmio, registers[REGISTER_A0]);
+        let mut arguments = registers[REGISTER_A0 + 1..REGISTER_A0 + 4].to_vec();
+        for index in 4..MAX_PRINTF_ARGUMENTS {
+            arguments.push(read_u32(mmio, registers[REGISTER_SP].wrapping_add(index * 4)));
+        }
+        format(&format_string, &arguments, |addr| read_string(mmio, addr))
+    }
+
+    fn load(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, filename: u32, header: u32) -> u32 {
+        let path = String::from_utf8_lossy(&read_string(mmio, filename)).to_string();
+        let loaded = read_disc_file(disc, &path).and_then(|data| {
+            let executable = exe::Executable::parse(&data)?;
+            // The header as Exec wants it, from the entry point on
+            write_bytes(mmio, header, &data[0x10..0x4C]);
+            for (offset, &value) in executable.text.iter().enumerate() {
+                mmio.write(executable.text_address.wrapping_add(offset as u32), value);
+            }
+            Ok(())
+        });
+        match loaded {
+            Ok(()) => 1,
+            Err(e) => {
+                println!("HLE BIOS: {}", e);
+                self.last_error = ERROR_NO_FILE;
+                0
+            }
+        }
+    }
+
+    /// Start a program whose header Load filled in, passing it two arguments
+    fn exec(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, header: u32, argument_1: u32, argument_2: u32) {
+        let word = |mmio: &mut Mmio, offset: u32| read_u32(mmio, header.wrapping_add(offset));
+        let executable = exe::Executable {
+            pc: word(mmio, 0x00),
+            gp: word(mmio, 0x04),
+            text_address: word(mmio, 0x08),
+            bss_address: word(mmio, 0x18),
+            bss_size: word(mmio, 0x1C),
+            stack_address: word(mmio, 0x20),
+            stack_size: word(mmio, 0x24),
+            text: Vec::new(),
+        };
+        let stack = cpu.registers.gpr[REGISTER_SP];
+        executable.load(cpu, mmio, stack);
+        cpu.registers.gpr[REGISTER_A0] = argument_1;
+        cpu.registers.gpr[REGISTER_A0 + 1] = argument_2;
+    }
+
+    fn load_exec(
+        &mut self,
+        cpu: &mut cpu::R3000A,
+        mmio: &mut Mmio,
+        disc: Option<&mut cdrom::Disc>,
+        filename: u32,
+        stack: u32,
+        stack_offset: u32,
+    ) -> Result<(), String> {
+        let path = String::from_utf8_lossy(&read_string(mmio, filename)).to_string();
+        let mut executable = exe::Executable::parse(&read_disc_file(disc, &path)?).map_err(|e| format!("{}: {}", path, e))?;
+        if stack != 0 {
+            executable.stack_address = stack;
+            executable.stack_size = stack_offset;
+        }
+        executable.load(cpu, mmio, self.stack_top);
+        Ok(())
+    }
+
+    fn cd_read_sector(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, count: u32, lba: u32, buffer: u32) -> u32 {
+        let Some(disc) = disc else {
+            return u32::MAX;
+        };
+        for index in 0..count {
+            match disc.read_sector(lba.wrapping_add(index)) {
+                Ok(sector) => write_bytes(mmio, buffer.wrapping_add(index.wrapping_mul(2048)), &sector.data()[..2048]),
+                Err(e) => {
+                    println!("HLE BIOS: {}", e);
+                    return u32::MAX;
+                }
+            }
+        }
+        count
+    }
+
+    fn open(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, filename: u32, mode: u32) -> u32 {
+        let name = String::from_utf8_lossy(&read_string(mmio, filename)).to_string();
+        let opened = self.open_file(mmio, disc, &name, mode);
+        let result = opened.and_then(|file| {
+            let fd = (FIRST_FILE..MAX_FILES).find(|&fd| self.files[fd].is_none()).ok_or(ERROR_TOO_MANY_FILES)?;
+            self.files[fd] = Some(file);
+            Ok(fd as u32)
+        });
+        result.unwrap_or_else(|error| {
+            self.last_error = error;
+            u32::MAX
+        })
+    }
+
+    fn open_file(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, name: &str, mode: u32) -> Result<OpenFile, u32> {
+        let (device, path) = name.split_once(':').unwrap_or(("", name));
+        if device.eq_ignore_ascii_case("cdrom") {
+            let disc = disc.ok_or(ERROR_NO_DEVICE)?;
+            let file = iso9660::find(disc, name).map_err(|_| ERROR_NO_FILE)?;
+            return Ok(OpenFile {
+                source: FileSource::Disc { lba: file.lba },
+                size: file.size,
+                position: 0,
+            });
+        }
+
+        let slot = card_slot(device).ok_or(ERROR_NO_DEVICE)?;
+        let card = mmio.sio0.ports[slot].memory_card_mut().ok_or(ERROR_NO_DEVICE)?;
+        let find = |card: &[u8]| directory::list_saves(card).into_iter().find(|save| save.filename == path);
+        let save = match find(card.data()) {
+            Some(save) => save,
+            None if mode & FILE_CREATE != 0 => {
+                let blocks = ((mode >> 16) as usize).max(1);
+                if blocks > directory::SAVE_BLOCKS {
+                    return Err(ERROR_NO_SPACE);
+                }
+                let file = directory::SaveFile::new(path, vec![0; blocks * directory::BLOCK_SIZE]);
+                directory::import_save(card.data_mut(), &file).map_err(|_| ERROR_NO_SPACE)?;
+                find(card.data()).ok_or(ERROR_NO_SPACE)?
+            }
+            None => return Err(ERROR_NO_FILE),
+        };
+        Ok(OpenFile {
+            size: (save.blocks.len() * directory::BLOCK_SIZE) as u32,
+            source: FileSource::Card { slot, blocks: save.blocks },
+            position: 0,
+        })
+    }
+
+    fn open_file_mut(&mut self, fd: u32) -> Option<&mut OpenFile> {
+        let file = self.files.get_mut(fd as usize).and_then(Option::as_mut);
+        if file.is_none() {
+            self.last_error = ERROR_BAD_FILE;
+        }
+        file
+    }
+
+    fn lseek(&mut self, fd: u32, offset: u32, whence: u32) -> u32 {
+        let Some(file) = self.open_file_mut(fd) else {
+            return u32::MAX;
+        };
+        file.position = match whence {
+            0 => offset,
+            1 => file.position.wrapping_add(offset),
+            _ => file.size.wrapping_add(offset),
+        };
+        file.position
+    }
+
+    fn read(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, fd: u32, buffer: u32, length: u32) -> u32 {
+        let Some(file) = self.open_file_mut(fd) else {
+            return u32::MAX;
+        };
+        let length = length.min(file.size.saturating_sub(file.position));
+        let data = match &file.source {
+            FileSource::Disc { lba } => {
+                let entry = disc_entry(*lba, file.size);
+                match disc.map(|disc| iso9660::read(disc, &entry, file.position, length)) {
+                    Some(Ok(data)) => data,
+                    _ => return u32::MAX,
+                }
+            }
+            FileSource::Card { slot, blocks } => {
+                let Some(card) = mmio.sio0.ports[*slot].memory_card() else {
+                    return u32::MAX;
+                };
+                (file.position..file.position + length).map(|position| card.data()[card_offset(blocks, position)]).collect()
+            }
+        };
+        file.position += data.len() as u32;
+        write_bytes(mmio, buffer, &data);
+        data.len() as u32
+    }
+
+    fn write(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, fd: u32, buffer: u32, length: u32) -> u32 {
+        let data = read_bytes(mmio, buffer, length);
+        if fd == STDOUT {
+            cpu.write_tty(&data);
+            return length;
+        }
+        let Some(file) = self.open_file_mut(fd) else {
+            return u32::MAX;
+        };
+        let FileSource::Card { slot, blocks } = &file.source else {
+            self.last_error = ERROR_INVALID;
+            return u32::MAX;
+        };
+        let Some(card) = mmio.sio0.ports[*slot].memory_card_mut() else {
+            return u32::MAX;
+        };
+        let length = length.min(file.size.saturating_sub(file.position));
+        for (index, &value) in data[..length as usize].iter().enumerate() {
+            card.data_mut()[card_offset(blocks, file.position + index as u32)] = value;
+        }
+        file.position += length;
+        length
+    }
+
+    fn close(&mut self, fd: u32) -> u32 {
+        match self.files.get_mut(fd as usize) {
+            Some(file @ Some(_)) => {
+                *file = None;
+                fd
+            }
+            _ => {
+                self.last_error = ERROR_BAD_FILE;
+                u32::MAX
+            }
+        }
+    }
+
+    fn firstfile(&mut self, mmio: &mut Mmio, disc: Option<&mut cdrom::Disc>, pattern: u32, entry: u32) -> u32 {
+        let pattern = String::from_utf8_lossy(&read_string(mmio, pattern)).to_string();
+        let (device, path) = pattern.split_once(':').unwrap_or(("", &pattern));
+        let (directory_path, name_pattern) = path.rsplit_once(['\\', '/']).unwrap_or(("", path));
+        let name_pattern = name_pattern.split(';').next().unwrap_or_default();
+
+        let found: Vec<SearchResult> = if device.eq_ignore_ascii_case("cdrom") {
+            disc.and_then(|disc| {
+                let directory = iso9660::find(disc, directory_path).ok()?;
+                iso9660::list(disc, &directory).ok()
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .map(|file| SearchResult { name: file.name, size: file.size, start: file.lba })
+            .collect()
+        } else {
+            card_slot(device)
+                .and_then(|slot| mmio.sio0.ports[slot].memory_card())
+                .map(|card| directory::list_saves(card.data()))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|save| SearchResult {
+                    size: (save.blocks.len() * directory::BLOCK_SIZE) as u32,
+                    start: save.first_block() as u32,
+                    name: save.filename,
+                })
+                .collect()
+        };
+        self.search = found.into_iter().filter(|file| matches_pattern(&file.name, name_pattern)).collect();
+        self.nextfile(mmio, entry)
+    }
+
+    /// Fill in the next directory entry from the last firstfile, or return 0 when there are no more
+    fn nextfile(&mut self, mmio: &mut Mmio, entry: u32) -> u32 {
+        let Some(file) = self.search.pop_front() else {
+            self.last_error = ERROR_NO_FILE;
+            return 0;
+        };
+        let mut name = file.name.into_bytes();
+        name.resize(DIRECTORY_NAME_LENGTH, 0);
+        write_bytes(mmio, entry, &name);
+        write_u32(mmio, entry.wrapping_add(0x14), 0);
+        write_u32(mmio, entry.wrapping_add(0x18), file.size);
+        write_u32(mmio, entry.wrapping_add(0x1C), 0);
+        write_u32(mmio, entry.wrapping_add(0x20), file.start);
+        entry
+    }
+
+    fn erase(&mut self, mmio: &mut Mmio, filename: u32) -> u32 {
+        let name = String::from_utf8_lossy(&read_string(mmio, filename)).to_string();
+        let (device, path) = name.split_once(':').unwrap_or(("", &name));
+        let Some(card) = card_slot(device).and_then(|slot| mmio.sio0.ports[slot].memory_card_mut()) else {
+            self.last_error = ERROR_NO_DEVICE;
+            return 0;
+        };
+        let Some(save) = directory::list_saves(card.data()).into_iter().find(|save| save.filename == path) else {
+            self.last_error = ERROR_NO_FILE;
+            return 0;
+        };
+        if directory::delete_save(card.data_mut(), save.first_block()).is_err() {
+            return 0;
+        }
+        1
+    }
+
+    fn format_device(&mut self, mmio: &mut Mmio, device: u32) -> u32 {
+        let name = String::from_utf8_lossy(&read_string(mmio, device)).to_string();
+        let device = name.split(':').next().unwrap_or_default();
+        let Some(card) = card_slot(device).and_then(|slot| mmio.sio0.ports[slot].memory_card_mut()) else {
+            self.last_error = ERROR_NO_DEVICE;
+            return 0;
+        };
+        card.format();
+        1
+    }
+
+    /// Read or write one 128 byte card sector, finishing straight away with the card events
+    fn card_sector(&mut self, mmio: &mut Mmio, port: u32, sector: u32, buffer: u32, write: bool) -> u32 {
+        if sector >= CARD_SECTORS {
+            return 0;
+        }
+        let offset = sector as usize * crate::memcard::FRAME_SIZE;
+        let data = read_bytes(mmio, buffer, crate::memcard::FRAME_SIZE as u32);
+        if let Some(card) = mmio.sio0.ports[(port >> 4) as usize & 1].memory_card_mut() {
+            if write {
+                card.data_mut()[offset..offset + data.len()].copy_from_slice(&data);
+            } else {
+                let frame = card.data()[offset..offset + crate::memcard::FRAME_SIZE].to_vec();
+                write_bytes(mmio, buffer, &frame);
+            }
+        }
+        self.finish_card_operation(mmio, port);
+        1
+    }
+
+    fn finish_card_operation(&mut self, mmio: &Mmio, port: u32) {
+        let present = mmio.sio0.ports[(port >> 4) as usize & 1].memory_card().is_some();
+        let spec = if present { SPEC_DONE } else { SPEC_TIMEOUT };
+        self.deliver_event(CLASS_CARD_HARDWARE, spec);
+        self.deliver_event(CLASS_CARD_SOFTWARE, spec);
+    }
+
+    fn event_mut(&mut self, handle: u32) -> Option<&mut Event> {
+        if handle & !HANDLE_INDEX != EVENT_HANDLE {
+            return None;
+        }
+        self.events.get_mut((handle & HANDLE_INDEX) as usize).and_then(Option::as_mut)
+    }
+
+    fn set_event_status(&mut self, handle: u32, status: u32) -> u32 {
+        match self.event_mut(handle) {
+            Some(event) => {
+                event.status = status;
+                1
+            }
+            None => 0,
+        }
+    }
+
+    fn open_event(&mut self, class: u32, spec: u32, mode: u32, callback: u32) -> u32 {
+        let Some(index) = self.events.iter().position(Option::is_none) else {
+            return u32::MAX;
+        };
+        self.events[index] = Some(Event {
+            class,
+            spec,
+            mode,
+            status: EVENT_DISABLED,
+            callback,
+        });
+        EVENT_HANDLE | index as u32
+    }
+
+    fn deliver_event(&mut self, class: u32, spec: u32) {
+        for event in self.events.iter_mut().flatten() {
+            if event.class != class || event.spec != spec || event.status != EVENT_ENABLED {
+                continue;
+            }
+            match event.mode {
+                EVENT_MODE_READY => event.status = EVENT_READY,
+                EVENT_MODE_CALLBACK if event.callback != 0 => self.pending_callbacks.push_back(Callback {
+                    function: event.callback,
+                    argument: 0,
+                    then: 0,
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    fn thread_index(&self, handle: u32) -> Option<usize> {
+        let index = (handle & HANDLE_INDEX) as usize;
+        (handle & !HANDLE_INDEX == THREAD_HANDLE && self.threads.get(index).is_some_and(Option::is_some)).then_some(index)
+    }
+
+    fn open_thread(&mut self, cpu: &cpu::R3000A, pc: u32, sp: u32, gp: u32) -> u32 {
+        let Some(index) = self.threads.iter().position(Option::is_none) else {
+            return u32::MAX;
+        };
+        let mut context = Context {
+            pc,
+            sr: cpu.cop0.sr,
+            ..Context::default()
+        };
+        context.gpr[REGISTER_SP] = sp;
+        context.gpr[REGISTER_FP] = sp;
+        context.gpr[REGISTER_GP] = gp;
+        self.threads[index] = Some(context);
+        THREAD_HANDLE | index as u32
+    }
+
+    /// Park the running thread, which sees ChangeThread return 1 when it next runs, and switch
+    fn change_thread(&mut self, cpu: &mut cpu::R3000A, handle: u32) -> Option<u32> {
+        let Some(index) = self.thread_index(handle) else {
+            return Some(0);
+        };
+        if index == self.current_thread {
+            return Some(1);
+        }
+        let mut current = Context::save(cpu);
+        current.pc = cpu.registers.gpr[REGISTER_RA];
+        current.gpr[REGISTER_V0] = 1;
+        self.threads[self.current_thread] = Some(current);
+        if let Some(target) = &self.threads[index] {
+            target.restore(cpu);
+        }
+        self.current_thread = index;
+        None
+    }
+
+    /// Fill each started pad buffer with a poll of its port: a status byte, the pad's ID, then its data
+    fn poll_pads(&mut self, mmio: &mut Mmio) {
+        for (port, (buffer, size)) in self.pad_buffers.into_iter().enumerate() {
+            if buffer == 0 {
+                continue;
+            }
+            let controller = &mut mmio.sio0.ports[port].controller;
+            let mut reply = Vec::new();
+            let (_, mut acknowledged) = controller.transfer(0x01);
+            while acknowledged && reply.len() < PAD_REPLY_LENGTH {
+                let (value, more) = controller.transfer(if reply.is_empty() { 0x42 } else { 0x00 });
+                reply.push(value);
+                acknowledged = more;
+            }
+            controller.deselect();
+
+            let mut data = if reply.len() >= 2 {
+                [0x00, reply[0]].into_iter().chain(reply[2..].iter().copied()).collect()
+            } else {
+                vec![0xFF]
+            };
+            data.truncate(size as usize);
+            write_bytes(mmio, buffer, &data);
+        }
+    }
+
+    fn exception(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio) {
+        let mut context = Context::save(cpu);
+        context.pc = cpu.cop0.epc;
+        match cpu.cop0.exception_code() {
+            EXCEPTION_INTERRUPT => self.interrupt(mmio),
+            EXCEPTION_SYSCALL => self.syscall(&mut context),
+            code => {
+                println!("HLE BIOS: unhandled exception {:02X} at {:08X}", code, cpu.cop0.epc);
+                cpu.stopped = true;
+                return;
+            }
+        }
+        // A syscall from a callback already being run returns to it, its queue picks up anything new
+        if self.kernel_run.is_some() || self.pending_callbacks.is_empty() {
+            self.resume(
+                cpu,
+                mmio,
+                KernelRun {
+                    interrupted: context,
+                    from_exception: true,
+                    current: None,
+                },
+            );
+        } else {
+            self.kernel_run = Some(KernelRun {
+                interrupted: context,
+                from_exception: true,
+                current: None,
+            });
+        }
+    }
+
+    fn syscall(&mut self, context: &mut Context) {
+        context.pc = context.pc.wrapping_add(4);
+        match context.gpr[REGISTER_A0] {
+            0 => {}
+            // EnterCriticalSection, returns whether interrupts were on
+            1 => {
+                context.gpr[REGISTER_V0] = (context.sr & CRITICAL_SECTION_BITS == CRITICAL_SECTION_BITS) as u32;
+                context.sr &= !CRITICAL_SECTION_BITS;
+            }
+            // ExitCriticalSection
+            2 => context.sr |= CRITICAL_SECTION_BITS,
+            number => println!("HLE BIOS: unhandled syscall {:X}", number),
+        }
+    }
+
+    /// Queue the game's interrupt handlers and deliver the kernel's own interrupt events
+    fn interrupt(&mut self, mmio: &mut Mmio) {
+        for priority in 0..self.interrupt_handlers.len() {
+            for index in 0..self.interrupt_handlers[priority].len() {
+                let entry = self.interrupt_handlers[priority][index];
+                let (second, first) = (read_u32(mmio, entry.wrapping_add(4)), read_u32(mmio, entry.wrapping_add(8)));
+                if first != 0 {
+                    self.pending_callbacks.push_back(Callback {
+                        function: first,
+                        argument: 0,
+                        then: second,
+                    });
+                }
+            }
+        }
+
+        let active = mmio.interrupts.active();
+        let counters = [Interrupt::Timer0, Interrupt::Timer1, Interrupt::Timer2, Interrupt::VBlank];
+        for (counter, interrupt) in counters.into_iter().enumerate() {
+            let bit = 1 << interrupt as u16;
+            if active & bit == 0 {
+                continue;
+            }
+            if matches!(interrupt, Interrupt::VBlank) && self.pads_started {
+                self.poll_pads(mmio);
+            }
+            let class = if matches!(interrupt, Interrupt::VBlank) { CLASS_VBLANK } else { CLASS_ROOT_COUNTER + counter as u32 };
+            self.deliver_event(class, SPEC_INTERRUPT);
+            if self.auto_acknowledge[counter] {
+                self.acknowledge_on_return |= bit;
+            }
+        }
+    }
+
+    /// Start the next queued callback, or go back to what was interrupted once they are all done
+    fn run_callbacks(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio) {
+        if self.kernel_run.is_none() {
+            if self.pending_callbacks.is_empty() {
+                return;
+            }
+            // Delivered by a kernel call, keep interrupts off until the callbacks are done
+            self.kernel_run = Some(KernelRun {
+                interrupted: Context::save(cpu),
+                from_exception: false,
+                current: None,
+            });
+            cpu.cop0.sr &= !cop0::SR_INTERRUPT_ENABLE;
+        }
+        let Some(run) = self.kernel_run.as_mut() else {
+            return;
+        };
+        if run.current.is_some() {
+            return;
+        }
+        match self.pending_callbacks.pop_front() {
+            Some(callback) => {
+                run.current = Some(callback);
+                let registers = &mut cpu.registers;
+                registers.gpr[REGISTER_A0] = callback.argument;
+                registers.gpr[REGISTER_SP] = KERNEL_STACK;
+                registers.gpr[REGISTER_RA] = CALLBACK_RETURN;
+                registers.pc = callback.function;
+            }
+            None => {
+                if let Some(run) = self.kernel_run.take() {
+                    self.resume(cpu, mmio, run);
+                }
+            }
+        }
+    }
+
+    fn callback_returned(&mut self, cpu: &mut cpu::R3000A) {
+        let Some(run) = self.kernel_run.as_mut() else {
+            println!("HLE BIOS: returned to the kernel with no callback running");
+            cpu.stopped = true;
+            return;
+        };
+        if let Some(callback) = run.current.take() {
+            let value = cpu.registers.gpr[REGISTER_V0];
+            if callback.then != 0 && value != 0 {
+                self.pending_callbacks.push_front(Callback {
+                    function: callback.then,
+                    argument: value,
+                    then: 0,
+                });
+            }
+        }
+    }
+
+    /// Go back to what the exception or kernel call interrupted
+    fn resume(&mut self, cpu: &mut cpu::R3000A, mmio: &mut Mmio, run: KernelRun) {
+        run.interrupted.restore(cpu);
+        if run.from_exception {
+            mmio.interrupts.acknowledge(std::mem::take(&mut self.acknowledge_on_return));
+            cpu.cop0.return_from_exception();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u32 = 0x80100000;
+    const SOURCE: u32 = 0x80010000;
+    const DESTINATION: u32 = 0x80020000;
+    const RETURN: u32 = 0x80030000;
+    // The last words of the address space, unmapped so accesses past them wrap into RAM at 0
+    const TOP: u32 = 0xFFFFFFF8;
+
+    /// Make an A0 call and return v0
+    fn call(bios: &mut HleBios, cpu: &mut cpu::R3000A, mmio: &mut Mmio, function: u32, arguments: [u32; 3]) -> u32 {
+        cpu.registers.pc = calls::A0_VECTOR;
+        cpu.registers.gpr[REGISTER_T1] = function;
+        cpu.registers.gpr[REGISTER_A0..REGISTER_A0 + 3].copy_from_slice(&arguments);
+        cpu.registers.gpr[REGISTER_RA] = RETURN;
+        bios.step(cpu, mmio, None);
+        cpu.registers.gpr[REGISTER_V0]
+    }
+
+    fn setup() -> (HleBios, cpu::R3000A, Mmio) {
+        (HleBios::new(), cpu::R3000A::new(), Mmio::new())
+    }
+
+    #[test]
+    fn allocations_are_word_aligned_and_packed() {
+        let mut heap = Heap::new(START, 0x100);
+        assert_eq!(heap.alloc(5), START);
+        assert_eq!(heap.size(START), Some(8));
+        assert_eq!(heap.alloc(0), START + 8);
+        assert_eq!(heap.alloc(4), START + 12);
+    }
+
+    #[test]
+    fn freed_blocks_are_reused_first_fit() {
+        let mut heap = Heap::new(START, 0x100);
+        let first = heap.alloc(0x10);
+        let second = heap.alloc(0x10);
+        let third = heap.alloc(0x10);
+        heap.free(second);
+        assert_eq!(heap.size(second), None);
+        // Too big for the hole, so it goes after the last block
+        assert_eq!(heap.alloc(0x14), third + 0x10);
+        assert_eq!(heap.alloc(0x8), first + 0x10);
+        assert_eq!(heap.alloc(0x8), first + 0x18);
+    }
+
+    #[test]
+    fn full_heaps_return_null() {
+        let mut heap = Heap::new(START, 0x20);
+        assert_eq!(heap.alloc(0x21), 0);
+        assert_eq!(heap.alloc(0x20), START);
+        assert_eq!(heap.alloc(1), 0);
+        heap.free(START);
+        assert_eq!(heap.alloc(0x10), START);
+    }
+
+    #[test]
+    fn sizes_past_the_address_space_return_null() {
+        let mut heap = Heap::new(0xFFFFFF00, 0xF0);
+        assert_eq!(heap.alloc(u32::MAX - 2), 0);
+        assert_eq!(heap.alloc(0x200), 0);
+        assert_eq!(heap.alloc(0xF0), 0xFFFFFF00);
+    }
+
+    #[test]
+    fn strncpy_pads_in_guest_memory() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        write_string(&mut mmio, SOURCE, b"abc");
+        fill_bytes(&mut mmio, DESTINATION, 0xAA, 0x10);
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x1A, [DESTINATION, SOURCE, 8]), DESTINATION);
+        assert_eq!(read_bytes(&mut mmio, DESTINATION, 9), b"abc\0\0\0\0\0\xAA");
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x1A, [DESTINATION, SOURCE, 2]), DESTINATION);
+        assert_eq!(read_bytes(&mut mmio, DESTINATION, 3), b"abc");
+    }
+
+    #[test]
+    fn strncpy_wraps_and_caps_oversized_lengths() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        write_string(&mut mmio, SOURCE, b"abc");
+        fill_bytes(&mut mmio, 0, 0xAA, 0x10);
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x1A, [u32::MAX - 1, SOURCE, 0x10]), u32::MAX - 1);
+        assert_eq!(read_bytes(&mut mmio, 0, 0x10), b"c\0\0\0\0\0\0\0\0\0\0\0\0\0\xAA\xAA");
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x1A, [DESTINATION, SOURCE, u32::MAX]), DESTINATION);
+        assert_eq!(mmio.read(DESTINATION + 3), 0);
+    }
+
+    #[test]
+    fn strcat_appends_past_the_top_of_memory() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        write_string(&mut mmio, SOURCE, b"xy");
+        mmio.write(0, 0);
+        // Unmapped bytes read as 0xFF, so the string ends at 0 once it wraps
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x15, [TOP, SOURCE, 0]), TOP);
+        assert_eq!(read_string(&mut mmio, 0), b"xy");
+    }
+
+    #[test]
+    fn memcpy_wraps_and_caps_oversized_lengths() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        write_bytes(&mut mmio, SOURCE, b"wxyz");
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x2A, [DESTINATION, SOURCE, 4]), DESTINATION);
+        assert_eq!(read_bytes(&mut mmio, DESTINATION, 4), b"wxyz");
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x2A, [u32::MAX - 1, SOURCE, 4]), u32::MAX - 1);
+        assert_eq!(read_bytes(&mut mmio, 0, 2), b"yz");
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x2A, [DESTINATION, SOURCE, u32::MAX]), DESTINATION);
+    }
+
+    #[test]
+    fn longjmp_restores_what_setjmp_saved() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        for (index, register) in [REGISTER_SP, REGISTER_FP, REGISTER_GP].into_iter().chain(REGISTER_S0..REGISTER_S0 + 8).enumerate() {
+            cpu.registers.gpr[register] = 0x1000 + index as u32;
+        }
+        let saved = cpu.registers.gpr;
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x13, [DESTINATION, 0, 0]), 0);
+        cpu.registers.gpr[REGISTER_S0..=REGISTER_FP].fill(0);
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x14, [DESTINATION, 5, 0]), 5);
+        assert_eq!(cpu.registers.gpr[REGISTER_S0..=REGISTER_FP], saved[REGISTER_S0..=REGISTER_FP]);
+        assert_eq!(cpu.registers.pc, RETURN);
+    }
+
+    #[test]
+    fn setjmp_buffers_wrap_past_the_top_of_memory() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        cpu.registers.gpr[REGISTER_FP] = 0x12345678;
+        call(&mut bios, &mut cpu, &mut mmio, 0x13, [TOP, 0, 0]);
+        // ra and sp fall in the unmapped words, fp is the first to wrap
+        assert_eq!(read_u32(&mut mmio, 0), 0x12345678);
+        cpu.registers.gpr[REGISTER_FP] = 0;
+        call(&mut bios, &mut cpu, &mut mmio, 0x14, [TOP, 1, 0]);
+        assert_eq!(cpu.registers.gpr[REGISTER_FP], 0x12345678);
+    }
+
+    #[test]
+    fn printf_reads_stack_arguments_past_the_top_of_memory() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        write_string(&mut mmio, SOURCE, b"%d %d %d %d");
+        // The fourth argument is past the home area, which wraps to 0
+        cpu.registers.gpr[REGISTER_SP] = 0xFFFFFFF0;
+        write_u32(&mut mmio, 0, 4);
+        call(&mut bios, &mut cpu, &mut mmio, 0x3F, [SOURCE, 1, 2]);
+        assert!(cpu.tty_output.iter().copied().collect::<Vec<u8>>().ends_with(b"1 2 0 4"));
+    }
+
+    #[test]
+    fn creating_files_bigger_than_a_card_runs_out_of_space() {
+        let (mut bios, mut cpu, mut mmio) = setup();
+        mmio.sio0.set_memory_card(0, Some(crate::memcard::MemoryCard::new()));
+        write_string(&mut mmio, SOURCE, b"bu00:BESLES-00000TEST");
+        for blocks in [directory::SAVE_BLOCKS as u32 + 1, HANDLE_INDEX] {
+            assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x00, [SOURCE, FILE_CREATE | blocks << 16, 0]), u32::MAX);
+            assert_eq!(bios.last_error, ERROR_NO_SPACE);
+        }
+        assert_eq!(call(&mut bios, &mut cpu, &mut mmio, 0x00, [SOURCE, FILE_CREATE | 1 << 16, 0]), FIRST_FILE as u32);
+    }
+}
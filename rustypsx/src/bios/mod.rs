@@ -0,0 +1,4 @@
+pub mod calls;
+pub mod exe;
+pub mod hle;
+pub mod system_cnf;
@@ -0,0 +1,55 @@
+use crate::bios::exe;
+use crate::cdrom::{self, iso9660};
+
+/// Boot settings from a disc's SYSTEM.CNF
+#[derive(Clone, Debug)]
+pub struct SystemCnf {
+    pub boot: String,
+    // Number of thread control blocks and event control blocks the kernel sets aside
+    pub tcb: u32,
+    pub event: u32,
+    pub stack: u32,
+}
+
+impl Default for SystemCnf {
+    // What the BIOS uses for discs without a SYSTEM.CNF
+    fn default() -> Self {
+        SystemCnf {
+            boot: "cdrom:PSX.EXE;1".to_string(),
+            tcb: 4,
+            event: 16,
+            stack: exe::DEFAULT_STACK,
+        }
+    }
+}
+
+impl SystemCnf {
+    /// Read `KEY = VALUE` lines, numbers are in hex, unknown keys and bad values are ignored
+    pub fn parse(text: &str) -> Self {
+        let mut cnf = SystemCnf::default();
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let number = u32::from_str_radix(value.trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+            match key.trim().to_ascii_uppercase().as_str() {
+                // Anything after the path is the executable's arguments
+                "BOOT" => cnf.boot = value.split_whitespace().next().unwrap_or_default().to_string(),
+                "TCB" => cnf.tcb = number.unwrap_or(cnf.tcb),
+                "EVENT" => cnf.event = number.unwrap_or(cnf.event),
+                "STACK" => cnf.stack = number.unwrap_or(cnf.stack),
+                _ => {}
+            }
+        }
+        cnf
+    }
+
+    /// The disc's SYSTEM.CNF, or the defaults if it has none
+    pub fn from_disc(disc: &mut cdrom::Disc) -> Result<Self, String> {
+        match iso9660::find(disc, "cdrom:SYSTEM.CNF;1") {
+            Ok(file) => Ok(Self::parse(&String::from_utf8_lossy(&iso9660::read_file(disc, &file)?))),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+pub const SECTOR_SIZE: usize = 2352;
+const MODE2_SECTOR_SIZE: usize = 2336;
+const COOKED_SECTOR_SIZE: usize = 2048;
+
+const SYNC: [u8; 12] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+const HEADER_SIZE: usize = 16;
+const SUBHEADER_SIZE: usize = 8;
+// Sector 0 sits after the two second lead-in
+const LEAD_IN_SECTORS: u32 = 150;
+
+pub const SUBMODE_AUDIO: u8 = 1 << 2;
+pub const SUBMODE_FORM2: u8 = 1 << 5;
+
+/// How sectors are stored in an image file
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SectorFormat {
+    // Whole 2352 byte sectors, as in .bin files
+    Raw,
+    // Mode 2 sectors without sync and header, as in raw .str rips
+    Mode2,
+    // 2048 bytes of user data per sector, as in .iso files
+    Cooked,
+}
+
+impl SectorFormat {
+    fn size(self) -> usize {
+        match self {
+            SectorFormat::Raw => SECTOR_SIZE,
+            SectorFormat::Mode2 => MODE2_SECTOR_SIZE,
+            SectorFormat::Cooked => COOKED_SECTOR_SIZE,
+        }
+    }
+}
+
+/// Mode 2 subheader, giving the interleaved channel and what the sector holds
+#[derive(Clone, Copy, Debug)]
+pub struct Subheader {
+    pub channel: u8,
+    pub submode: u8,
+    pub coding: u8,
+}
+
+/// A full 2352 byte sector, with sync and header filled in for images that leave them out
+#[derive(Clone)]
+pub struct Sector {
+    raw: Vec<u8>,
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+impl Sector {
+    fn synthesize(lba: u32, mode: u8, body: &[u8]) -> Self {
+        let position = lba + LEAD_IN_SECTORS;
+        let mut raw = vec![0; SECTOR_SIZE];
+        raw[..12].copy_from_slice(&SYNC);
+        raw[12] = to_bcd(position / 75 / 60);
+        raw[13] = to_bcd(position / 75 % 60);
+        raw[14] = to_bcd(position % 75);
+        raw[15] = mode;
+        raw[HEADER_SIZE..HEADER_SIZE + body.len()].copy_from_slice(body);
+        Sector { raw }
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.raw[15]
+    }
+
+    pub fn subheader(&self) -> Option<Subheader> {
+        (self.mode() == 2).then(|| Subheader {
+            channel: self.raw[17],
+            submode: self.raw[18],
+            coding: self.raw[19],
+        })
+    }
+
+    pub fn is_form2(&self) -> bool {
+        self.subheader().is_some_and(|subheader| subheader.submode & SUBMODE_FORM2 != 0)
+    }
+
+    /// User data: 2048 bytes for mode 1 and mode 2 form 1, 2324 for mode 2 form 2
+    pub fn data(&self) -> &[u8] {
+        match (self.mode(), self.is_form2()) {
+            (2, true) => &self.raw[HEADER_SIZE + SUBHEADER_SIZE..HEADER_SIZE + SUBHEADER_SIZE + 2324],
+            (2, false) => &self.raw[HEADER_SIZE + SUBHEADER_SIZE..HEADER_SIZE + SUBHEADER_SIZE + COOKED_SECTOR_SIZE],
+            _ => &self.raw[HEADER_SIZE..HEADER_SIZE + COOKED_SECTOR_SIZE],
+        }
+    }
+}
+
+/// Single track disc image, read a sector at a time
+///
+/// Clones share the open image file, every read seeks first so they don't get in each other's way.
+#[derive(Clone)]
+pub struct Disc {
+    file: Arc<Mutex<File>>,
+    path: PathBuf,
+    format: SectorFormat,
+    sector_count: u32,
+}
+
+/// The first data file referenced by a .cue sheet
+fn cue_image_path(cue: &Path) -> Result<PathBuf, String> {
+    let sheet = std::fs::read_to_string(cue).map_err(|e| format!("Failed to read {}: {}", cue.display(), e))?;
+    let name = sheet
+        .lines()
+        .map(str::trim)
+        .find_map(|line| {
+            let rest = line.strip_prefix("FILE ")?;
+            let name = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next()?,
+                None => rest.split_whitespace().next()?,
+            };
+            Some(name.to_string())
+        })
+        .ok_or_else(|| format!("No FILE entry in {}", cue.display()))?;
+    Ok(cue.with_file_name(name))
+}
+
+impl Disc {
+    /// Open a .bin/.img/.iso/.str image, or the first file of a .cue sheet
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let is_cue = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("cue"));
+        let path = if is_cue { cue_image_path(path)? } else { path.to_path_buf() };
+
+        let mut file = File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let length = file.metadata().map_err(|e| format!("Failed to read {}: {}", path.display(), e))?.len() as usize;
+        let mut start = [0u8; 12];
+        file.read_exact(&mut start).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        // Mode 2 sectors begin with their subheader twice over
+        let format = if start == SYNC && length.is_multiple_of(SECTOR_SIZE) {
+            SectorFormat::Raw
+        } else if start[..4] == start[4..8] && length.is_multiple_of(MODE2_SECTOR_SIZE) {
+            SectorFormat::Mode2
+        } else if length.is_multiple_of(COOKED_SECTOR_SIZE) {
+            SectorFormat::Cooked
+        } else {
+            return Err(format!("{} is not a whole number of 2352, 2336 or 2048 byte sectors", path.display()));
+        };
+
+        Ok(Disc {
+            file: Arc::new(Mutex::new(file)),
+            sector_count: (length / format.size()) as u32,
+            path,
+            format,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sector_count(&self) -> u32 {
+        self.sector_count
+    }
+
+    pub fn read_sector(&mut self, lba: u32) -> Result<Sector, String> {
+        if lba >= self.sector_count {
+            return Err(format!("Sector {} is past the end of the disc ({} sectors)", lba, self.sector_count));
+        }
+        let size = self.format.size();
+        let mut body = vec![0; size];
+        // A read that panicked elsewhere leaves nothing behind that the seek doesn't reset
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.seek(SeekFrom::Start(lba as u64 * size as u64))
+            .and_then(|_| file.read_exact(&mut body))
+            .map_err(|e| format!("Failed to read sector {} of {}: {}", lba, self.path.display(), e))?;
+
+        Ok(match self.format {
+            SectorFormat::Raw => Sector { raw: body },
+            SectorFormat::Mode2 => Sector::synthesize(lba, 2, &body),
+            SectorFormat::Cooked => Sector::synthesize(lba, 1, &body),
+        })
+    }
+}
@@ -0,0 +1,421 @@
+use crate::cdrom::Disc;
+
+// The primary volume descriptor follows the 16 sector system area
+const VOLUME_DESCRIPTOR_LBA: u32 = 16;
+const ROOT_RECORD_OFFSET: usize = 156;
+const DATA_SIZE: usize = 2048;
+// Subheader and data of a Mode 2 sector, what raw XA rips keep
+const MODE2_SIZE: usize = 2336;
+const MODE2_OFFSET: usize = 16;
+
+const FLAG_DIRECTORY: u8 = 1 << 1;
+
+// Attribute bits of the CD-ROM XA system use area, stored big endian
+pub const XA_FORM1: u16 = 1 << 11;
+pub const XA_FORM2: u16 = 1 << 12;
+pub const XA_INTERLEAVED: u16 = 1 << 13;
+pub const XA_CDDA: u16 = 1 << 14;
+pub const XA_DIRECTORY: u16 = 1 << 15;
+
+/// The CD-ROM XA extension recorded after a file's name
+#[derive(Clone, Copy, Debug)]
+pub struct XaAttributes {
+    pub attributes: u16,
+}
+
+impl XaAttributes {
+    /// Streams of Form 2 sectors, like STR video and XA audio, whose raw sectors are worth keeping
+    pub fn is_raw(&self) -> bool {
+        self.attributes & (XA_FORM2 | XA_INTERLEAVED) != 0
+    }
+
+    /// Short flags for listings, as in `F2 IL`
+    pub fn describe(&self) -> String {
+        [(XA_FORM1, "F1"), (XA_FORM2, "F2"), (XA_INTERLEAVED, "IL"), (XA_CDDA, "DA"), (XA_DIRECTORY, "DIR")]
+            .iter()
+            .filter(|(bit, _)| self.attributes & bit != 0)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A file or directory found in a directory's records
+#[derive(Clone, Debug)]
+pub struct Entry {
+    // Without the ;1 version suffix
+    pub name: String,
+    pub lba: u32,
+    pub size: u32,
+    pub is_directory: bool,
+    pub xa: Option<XaAttributes>,
+}
+
+impl Entry {
+    /// Whether extracting should keep whole Mode 2 sectors rather than 2048 bytes of each
+    pub fn is_raw(&self) -> bool {
+        self.xa.is_some_and(|xa| xa.is_raw())
+    }
+}
+
+/// The fields of the primary volume descriptor worth showing
+#[derive(Clone, Debug)]
+pub struct VolumeDescriptor {
+    pub system_id: String,
+    pub volume_id: String,
+    // In sectors
+    pub volume_size: u32,
+    pub path_table_size: u32,
+    pub path_table_lba: u32,
+    pub root: Entry,
+}
+
+/// A directory as listed in the path table, parents are indices into the table
+#[derive(Clone, Debug)]
+pub struct PathTableEntry {
+    pub name: String,
+    pub lba: u32,
+    pub parent: usize,
+}
+
+fn parse_xa(system_use: &[u8]) -> Option<XaAttributes> {
+    if system_use.len() < 14 || &system_use[6..8] != b"XA" {
+        return None;
+    }
+    // Owner group and user IDs come first, the file number for interleaved streams after the signature
+    Some(XaAttributes {
+        attributes: u16::from_be_bytes([system_use[4], system_use[5]]),
+    })
+}
+
+fn parse_record(record: &[u8]) -> Option<Entry> {
+    if record.len() < 34 {
+        return None;
+    }
+    let name_length = record[32] as usize;
+    let raw_name = record.get(33..33 + name_length)?;
+    // The system use area starts at an even offset after the name
+    let system_use_offset = 33 + name_length + (name_length + 1) % 2;
+    let name = match raw_name {
+        // The directory itself and its parent
+        [0] => ".".to_string(),
+        [1] => "..".to_string(),
+        _ => {
+            let name = String::from_utf8_lossy(raw_name);
+            name.split(';').next().unwrap_or_default().to_string()
+        }
+    };
+    Some(Entry {
+        name,
+        lba: u32::from_le_bytes(record[2..6].try_into().unwrap()),
+        size: u32::from_le_bytes(record[10..14].try_into().unwrap()),
+        is_directory: record[25] & FLAG_DIRECTORY != 0,
+        xa: record.get(system_use_offset..).and_then(parse_xa),
+    })
+}
+
+fn text_field(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end().to_string()
+}
+
+fn read_data(disc: &mut Disc, lba: u32) -> Result<Vec<u8>, String> {
+    let sector = disc.read_sector(lba)?;
+    Ok(sector.data()[..DATA_SIZE].to_vec())
+}
+
+/// The primary volume descriptor
+pub fn volume_descriptor(disc: &mut Disc) -> Result<VolumeDescriptor, String> {
+    let descriptor = read_data(disc, VOLUME_DESCRIPTOR_LBA)?;
+    if descriptor[0] != 1 || &descriptor[1..6] != b"CD001" {
+        return Err(format!("{} has no ISO9660 filesystem", disc.path().display()));
+    }
+    let record_length = descriptor[ROOT_RECORD_OFFSET] as usize;
+    let root = parse_record(&descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + record_length])
+        .map(|root| Entry { name: String::new(), ..root })
+        .ok_or_else(|| "Bad root directory record".to_string())?;
+    Ok(VolumeDescriptor {
+        system_id: text_field(&descriptor[8..40]),
+        volume_id: text_field(&descriptor[40..72]),
+        volume_size: u32::from_le_bytes(descriptor[80..84].try_into().unwrap()),
+        path_table_size: u32::from_le_bytes(descriptor[132..136].try_into().unwrap()),
+        // The little endian table
+        path_table_lba: u32::from_le_bytes(descriptor[140..144].try_into().unwrap()),
+        root,
+    })
+}
+
+/// The root directory, from the primary volume descriptor
+pub fn root(disc: &mut Disc) -> Result<Entry, String> {
+    Ok(volume_descriptor(disc)?.root)
+}
+
+/// Every directory on the disc, the root first with an empty name
+pub fn path_table(disc: &mut Disc, descriptor: &VolumeDescriptor) -> Result<Vec<PathTableEntry>, String> {
+    let mut table = Vec::new();
+    for index in 0..descriptor.path_table_size.div_ceil(DATA_SIZE as u32) {
+        table.extend(read_data(disc, descriptor.path_table_lba + index)?);
+    }
+    table.truncate(descriptor.path_table_size as usize);
+
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= table.len() {
+        let name_length = table[offset] as usize;
+        let Some(raw_name) = table.get(offset + 8..offset + 8 + name_length) else {
+            break;
+        };
+        let name = if raw_name == [0] { String::new() } else { String::from_utf8_lossy(raw_name).to_string() };
+        entries.push(PathTableEntry {
+            name,
+            lba: u32::from_le_bytes(table[offset + 2..offset + 6].try_into().unwrap()),
+            // Numbered from 1
+            parent: (u16::from_le_bytes([table[offset + 6], table[offset + 7]]) as usize).saturating_sub(1),
+        });
+        offset += 8 + name_length + name_length % 2;
+    }
+    Ok(entries)
+}
+
+/// The directory a path table entry points at, sized from its own . record
+pub fn path_table_directory(disc: &mut Disc, directory: &PathTableEntry) -> Result<Entry, String> {
+    let data = read_data(disc, directory.lba)?;
+    let length = data[0] as usize;
+    data.get(..length)
+        .and_then(parse_record)
+        .map(|record| Entry { name: directory.name.clone(), ..record })
+        .ok_or_else(|| format!("Bad directory record at sector {}", directory.lba))
+}
+
+/// The full path of each path table entry, as in `\MOVIE\INTRO`
+pub fn directory_paths(table: &[PathTableEntry]) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::with_capacity(table.len());
+    for (index, entry) in table.iter().enumerate() {
+        // Parents always come earlier in the table
+        let parent = if entry.parent < index { paths[entry.parent].as_str() } else { "" };
+        paths.push(if entry.name.is_empty() { String::new() } else { format!("{}\\{}", parent, entry.name) });
+    }
+    paths
+}
+
+/// Everything in a directory, without the . and .. entries
+pub fn list(disc: &mut Disc, directory: &Entry) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let sectors = directory.size.div_ceil(DATA_SIZE as u32);
+    for index in 0..sectors {
+        let data = read_data(disc, directory.lba + index)?;
+        let mut offset = 0;
+        // Records never cross sectors, a zero length pads out the rest of one
+        while offset < DATA_SIZE && data[offset] != 0 {
+            let length = data[offset] as usize;
+            if let Some(entry) = data.get(offset..offset + length).and_then(parse_record)
+                && entry.name != "."
+                && entry.name != ".."
+            {
+                entries.push(entry);
+            }
+            offset += length;
+        }
+    }
+    Ok(entries)
+}
+
+/// Find a file by path, as in `cdrom:\DIR\FILE.EXE;1`, ignoring case, the device and the version
+pub fn find(disc: &mut Disc, path: &str) -> Result<Entry, String> {
+    let trimmed = path.split_once(':').map_or(path, |(_, rest)| rest);
+    let trimmed = trimmed.split(';').next().unwrap_or_default();
+    let mut entry = root(disc)?;
+    for component in trimmed.split(['\\', '/']).filter(|component| !component.is_empty()) {
+        if !entry.is_directory {
+            return Err(format!("{} is not a directory", entry.name));
+        }
+        entry = list(disc, &entry)?
+            .into_iter()
+            .find(|child| child.name.eq_ignore_ascii_case(component))
+            .ok_or_else(|| format!("{} not found on disc", path))?;
+    }
+    Ok(entry)
+}
+
+/// Read `length` bytes of a file from `offset`, clipped to the end of the file
+pub fn read(disc: &mut Disc, file: &Entry, offset: u32, length: u32) -> Result<Vec<u8>, String> {
+    let end = file.size.min(offset.saturating_add(length));
+    let mut contents = Vec::with_capacity(end.saturating_sub(offset) as usize);
+    let mut position = offset;
+    while position < end {
+        let data = read_data(disc, file.lba + position / DATA_SIZE as u32)?;
+        let start = position as usize % DATA_SIZE;
+        let count = (DATA_SIZE - start).min((end - position) as usize);
+        contents.extend_from_slice(&data[start..start + count]);
+        position += count as u32;
+    }
+    Ok(contents)
+}
+
+/// A whole file
+pub fn read_file(disc: &mut Disc, file: &Entry) -> Result<Vec<u8>, String> {
+    read(disc, file, 0, file.size)
+}
+
+/// A file's sectors as stored, subheader included, for Form 2 streams that don't fit in 2048 bytes a sector
+pub fn read_raw_file(disc: &mut Disc, file: &Entry) -> Result<Vec<u8>, String> {
+    let sectors = file.size.div_ceil(DATA_SIZE as u32);
+    let mut contents = Vec::with_capacity(sectors as usize * MODE2_SIZE);
+    for index in 0..sectors {
+        let sector = disc.read_sector(file.lba + index)?;
+        contents.extend_from_slice(&sector.raw()[MODE2_OFFSET..MODE2_OFFSET + MODE2_SIZE]);
+    }
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const PATH_TABLE_LBA: u32 = 18;
+    const ROOT_LBA: u32 = 19;
+    const DATA_LBA: u32 = 20;
+    const CNF_LBA: u32 = 21;
+    const SECTORS: usize = 22;
+    const CNF: &[u8] = b"BOOT = cdrom:\\MAIN.EXE;1";
+
+    fn record(name: &[u8], lba: u32, size: u32, flags: u8, xa: Option<u16>) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[6..10].copy_from_slice(&lba.to_be_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[14..18].copy_from_slice(&size.to_be_bytes());
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if name.len().is_multiple_of(2) {
+            record.push(0);
+        }
+        if let Some(attributes) = xa {
+            let mut system_use = vec![0; 14];
+            system_use[4..6].copy_from_slice(&attributes.to_be_bytes());
+            system_use[6..8].copy_from_slice(b"XA");
+            record.extend(system_use);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn directory(parent: u32, own: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = record(&[0], own, DATA_SIZE as u32, FLAG_DIRECTORY, None);
+        data.extend(record(&[1], parent, DATA_SIZE as u32, FLAG_DIRECTORY, None));
+        for entry in entries {
+            data.extend(entry);
+        }
+        data
+    }
+
+    // A cooked image holding SYSTEM.CNF in the root and an empty DATA directory
+    fn image(name: &str) -> PathBuf {
+        let mut image = vec![0; SECTORS * DATA_SIZE];
+        let mut sector = |lba: u32, data: &[u8]| {
+            let start = lba as usize * DATA_SIZE;
+            image[start..start + data.len()].copy_from_slice(data);
+        };
+
+        let mut descriptor = vec![0; DATA_SIZE];
+        descriptor[0] = 1;
+        descriptor[1..6].copy_from_slice(b"CD001");
+        descriptor[8..40].copy_from_slice(&format!("{:32}", "PLAYSTATION").into_bytes());
+        descriptor[40..72].copy_from_slice(&format!("{:32}", "TEST").into_bytes());
+        descriptor[80..84].copy_from_slice(&(SECTORS as u32).to_le_bytes());
+        let root = record(&[0], ROOT_LBA, DATA_SIZE as u32, FLAG_DIRECTORY, None);
+        descriptor[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + root.len()].copy_from_slice(&root);
+
+        let mut path_table = vec![1, 0];
+        path_table.extend(ROOT_LBA.to_le_bytes());
+        path_table.extend([1, 0, 0, 0]);
+        path_table.extend([4, 0]);
+        path_table.extend(DATA_LBA.to_le_bytes());
+        path_table.extend([1, 0]);
+        path_table.extend(b"DATA");
+        descriptor[132..136].copy_from_slice(&(path_table.len() as u32).to_le_bytes());
+        descriptor[140..144].copy_from_slice(&PATH_TABLE_LBA.to_le_bytes());
+
+        sector(VOLUME_DESCRIPTOR_LBA, &descriptor);
+        sector(PATH_TABLE_LBA, &path_table);
+        sector(
+            ROOT_LBA,
+            &directory(
+                ROOT_LBA,
+                ROOT_LBA,
+                &[
+                    record(b"SYSTEM.CNF;1", CNF_LBA, CNF.len() as u32, 0, Some(XA_FORM1)),
+                    record(b"DATA", DATA_LBA, DATA_SIZE as u32, FLAG_DIRECTORY, Some(XA_DIRECTORY)),
+                ],
+            ),
+        );
+        sector(DATA_LBA, &directory(ROOT_LBA, DATA_LBA, &[]));
+        sector(CNF_LBA, CNF);
+
+        let path = std::env::temp_dir().join(format!("rustypsx-{}-{}.iso", std::process::id(), name));
+        std::fs::write(&path, image).unwrap();
+        path
+    }
+
+    fn open(name: &str) -> Disc {
+        let path = image(name);
+        let disc = Disc::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        disc
+    }
+
+    #[test]
+    fn volume_descriptor_fields() {
+        let mut disc = open("descriptor");
+        let descriptor = volume_descriptor(&mut disc).unwrap();
+        assert_eq!(descriptor.system_id, "PLAYSTATION");
+        assert_eq!(descriptor.volume_id, "TEST");
+        assert_eq!(descriptor.volume_size, SECTORS as u32);
+        assert_eq!(descriptor.root.lba, ROOT_LBA);
+        assert!(descriptor.root.is_directory);
+    }
+
+    #[test]
+    fn path_table_lists_every_directory() {
+        let mut disc = open("path-table");
+        let descriptor = volume_descriptor(&mut disc).unwrap();
+        let table = path_table(&mut disc, &descriptor).unwrap();
+        assert_eq!(directory_paths(&table), ["", "\\DATA"]);
+        assert_eq!(table[1].lba, DATA_LBA);
+        assert_eq!(table[1].parent, 0);
+        assert_eq!(path_table_directory(&mut disc, &table[1]).unwrap().lba, DATA_LBA);
+    }
+
+    #[test]
+    fn listing_skips_dot_entries() {
+        let mut disc = open("list");
+        let root = root(&mut disc).unwrap();
+        let entries = list(&mut disc, &root).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["SYSTEM.CNF", "DATA"]);
+        assert!(!entries[0].is_directory && entries[1].is_directory);
+        assert_eq!(entries[0].xa.unwrap().attributes, XA_FORM1);
+        assert!(!entries[0].is_raw());
+        assert!(list(&mut disc, &entries[1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_ignores_device_case_and_version() {
+        let mut disc = open("find");
+        let file = find(&mut disc, "cdrom:\\system.cnf;1").unwrap();
+        assert_eq!(read_file(&mut disc, &file).unwrap(), CNF);
+        assert_eq!(read(&mut disc, &file, 5, 100).unwrap(), &CNF[5..]);
+        assert!(find(&mut disc, "\\MISSING.EXE").is_err());
+        assert!(find(&mut disc, "\\SYSTEM.CNF\\MAIN.EXE").is_err());
+    }
+
+    #[test]
+    fn images_without_a_filesystem_are_refused() {
+        let path = std::env::temp_dir().join(format!("rustypsx-{}-blank.iso", std::process::id()));
+        std::fs::write(&path, vec![0; SECTORS * DATA_SIZE]).unwrap();
+        let mut disc = Disc::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(volume_descriptor(&mut disc).is_err());
+    }
+}
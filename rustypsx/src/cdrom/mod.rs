@@ -0,0 +1,5 @@
+pub mod disc;
+pub mod iso9660;
+pub mod xa;
+
+pub use disc::*;
@@ -0,0 +1,98 @@
+//! XA-ADPCM decoding of the audio sectors interleaved with game data and video.
+
+use crate::cdrom::{Sector, SUBMODE_AUDIO};
+use serde::{Deserialize, Serialize};
+
+const SOUND_GROUPS: usize = 18;
+const SOUND_GROUP_SIZE: usize = 128;
+const SOUND_GROUP_HEADER: usize = 16;
+const SAMPLES_PER_UNIT: usize = 28;
+
+const FILTER_POSITIVE: [i32; 4] = [0, 60, 115, 98];
+const FILTER_NEGATIVE: [i32; 4] = [0, 0, -52, -55];
+
+const CODING_STEREO: u8 = 1 << 0;
+const CODING_HALF_RATE: u8 = 1 << 2;
+const CODING_8BIT: u8 = 1 << 4;
+
+/// Decoded samples of one sector, interleaved left/right when stereo
+pub struct XaAudio {
+    pub sample_rate: u32,
+    pub stereo: bool,
+    pub samples: Vec<i16>,
+}
+
+/// ADPCM decoder keeping the last two samples of each channel between sectors
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct XaDecoder {
+    history: [[i32; 2]; 2],
+}
+
+impl XaDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn decode_unit(&mut self, channel: usize, parameter: u8, raw: impl Iterator<Item = i32>, sample_bits: u32, output: &mut Vec<i16>) {
+        let filter = ((parameter >> 4) & 3) as usize;
+        // Ranges past 12 behave as 9 on hardware
+        let range = match parameter & 0xF {
+            range if range > 12 => 9,
+            range => range as u32,
+        };
+        let [old, older] = &mut self.history[channel];
+        for value in raw {
+            let shifted = (value << (16 - sample_bits)) >> range;
+            let sample = (shifted + (*old * FILTER_POSITIVE[filter] + *older * FILTER_NEGATIVE[filter] + 32) / 64)
+                .clamp(i16::MIN as i32, i16::MAX as i32);
+            *older = *old;
+            *old = sample;
+            output.push(sample as i16);
+        }
+    }
+
+    /// Decode an audio sector, or None if it holds no XA-ADPCM
+    pub fn decode_sector(&mut self, sector: &Sector) -> Option<XaAudio> {
+        let subheader = sector.subheader()?;
+        if subheader.submode & SUBMODE_AUDIO == 0 || !sector.is_form2() {
+            return None;
+        }
+        let stereo = subheader.coding & CODING_STEREO != 0;
+        let sample_bits = if subheader.coding & CODING_8BIT != 0 { 8 } else { 4 };
+        let units = if sample_bits == 8 { 4 } else { 8 };
+        let mut samples = Vec::with_capacity(SOUND_GROUPS * units * SAMPLES_PER_UNIT);
+
+        for group in sector.data().chunks_exact(SOUND_GROUP_SIZE).take(SOUND_GROUPS) {
+            let data = &group[SOUND_GROUP_HEADER..];
+            let unit_samples = |unit: usize| {
+                data.chunks_exact(4).map(move |word| {
+                    if sample_bits == 8 {
+                        word[unit] as i8 as i32
+                    } else {
+                        (((word[unit / 2] >> ((unit & 1) * 4)) << 4) as i8 >> 4) as i32
+                    }
+                })
+            };
+
+            if stereo {
+                for pair in (0..units).step_by(2) {
+                    let mut left = Vec::with_capacity(SAMPLES_PER_UNIT);
+                    let mut right = Vec::with_capacity(SAMPLES_PER_UNIT);
+                    self.decode_unit(0, group[4 + pair], unit_samples(pair), sample_bits, &mut left);
+                    self.decode_unit(1, group[4 + pair + 1], unit_samples(pair + 1), sample_bits, &mut right);
+                    samples.extend(left.into_iter().zip(right).flat_map(|(l, r)| [l, r]));
+                }
+            } else {
+                for unit in 0..units {
+                    self.decode_unit(0, group[4 + unit], unit_samples(unit), sample_bits, &mut samples);
+                }
+            }
+        }
+
+        Some(XaAudio {
+            sample_rate: if subheader.coding & CODING_HALF_RATE != 0 { 18900 } else { 37800 },
+            stereo,
+            samples,
+        })
+    }
+}
@@ -1,4 +1,7 @@
+use crate::input::multitap::MULTITAP_SLOTS;
+use crate::{input, player, savestate, sio};
 use clap::Parser;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -6,17 +9,310 @@ pub struct RawConfig {
     /// Scale factor for GUI
     #[arg(short, long, default_value_t = 1)]
     scale: u8,
+
+    /// BIOS ROM image (512 KB), the built-in HLE BIOS is used without one
+    #[arg(long)]
+    bios: Option<PathBuf>,
+
+    /// Disc image (.bin, .cue or .iso) to insert
+    #[arg(long)]
+    disc: Option<PathBuf>,
+
+    /// PS-X EXE to boot instead of the disc, a BIOS image loads it in place of its shell
+    #[arg(long)]
+    exe: Option<PathBuf>,
+
+    /// Skip the BIOS logo, loading the disc's boot executable with its SYSTEM.CNF settings once the BIOS is up
+    #[arg(long, requires = "bios")]
+    fast_boot: bool,
+
+    /// Directory for save states, F1-F10 load a slot and Shift+F1-F10 save one
+    #[arg(long, default_value = "states")]
+    state_dir: PathBuf,
+
+    /// Frames between rewind snapshots, hold Backspace to rewind, 0 turns rewinding off
+    #[arg(long, default_value_t = 10)]
+    rewind_interval: u32,
+
+    /// Memory for rewinding in MiB, snapshots and the frames being rewound through, the oldest snapshots are dropped past it
+    #[arg(long, default_value_t = 64)]
+    rewind_budget: usize,
+
+    /// Show the frame this many frames ahead, hiding input lag at the cost of emulating each frame again
+    #[arg(long, default_value_t = 0)]
+    run_ahead: u32,
+
+    /// How the machine that runs ahead is copied each frame
+    #[arg(long, value_enum, default_value_t = savestate::run_ahead::RunAheadMode::State)]
+    run_ahead_mode: savestate::run_ahead::RunAheadMode,
+
+    /// Movie file the File menu records to and plays back from
+    #[arg(long)]
+    movie: Option<PathBuf>,
+
+    /// Play the movie back read-only from the start
+    #[arg(long, requires = "movie")]
+    play_movie: bool,
+
+    /// Seed for everything the machine would otherwise start at random, runs with the same seed and input match
+    #[arg(long, default_value_t = 0)]
+    seed: u32,
+
+    /// Print a hash of the whole machine state after every frame
+    #[arg(long)]
+    log_state_hash: bool,
+
+    /// Run this many frames twice without a window, with the movie's input if given, and report the first frame whose state differs
+    #[arg(long)]
+    check_determinism: Option<u64>,
+
+    /// Controller plugged into port 1
+    #[arg(long, value_enum, default_value_t = input::ControllerType::Digital)]
+    port1: input::ControllerType,
+
+    /// Controller plugged into port 2
+    #[arg(long, value_enum, default_value_t = input::ControllerType::None)]
+    port2: input::ControllerType,
+
+    /// Plug a multitap into port 1, with a port 1 type controller in each of its slots unless --multitap1-pads is given
+    #[arg(long)]
+    multitap1: bool,
+
+    /// Plug a multitap into port 2, with a port 2 type controller in each of its slots unless --multitap2-pads is given
+    #[arg(long)]
+    multitap2: bool,
+
+    /// Controllers in slots A-D of port 1's multitap, comma separated, e.g. digital,analog,none,mouse
+    #[arg(long, value_enum, value_delimiter = ',', requires = "multitap1")]
+    multitap1_pads: Vec<input::ControllerType>,
+
+    /// Controllers in slots A-D of port 2's multitap, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', requires = "multitap2")]
+    multitap2_pads: Vec<input::ControllerType>,
+
+    /// Raw memory card image (.mcr/.mcd) for slot 1, created if missing
+    #[arg(long)]
+    memcard1: Option<PathBuf>,
+
+    /// Raw memory card image (.mcr/.mcd) for slot 2, created if missing
+    #[arg(long)]
+    memcard2: Option<PathBuf>,
+
+    /// Memory card images for slots A-D of port 1's multitap, comma separated, with nothing between commas for an empty slot
+    #[arg(long, value_delimiter = ',', requires = "multitap1", conflicts_with = "memcard1")]
+    multitap1_memcards: Vec<String>,
+
+    /// Memory card images for slots A-D of port 2's multitap, comma separated
+    #[arg(long, value_delimiter = ',', requires = "multitap2", conflicts_with = "memcard2")]
+    multitap2_memcards: Vec<String>,
+
+    /// JSON file with keyboard bindings for each pad
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+
+    /// Play an STR movie (.str file or disc image) instead of running the emulator
+    #[arg(long)]
+    play_str: Option<PathBuf>,
+
+    /// Sector range to demux from the STR source, as FIRST-LAST
+    #[arg(long, requires = "play_str", value_parser = parse_sector_range)]
+    sectors: Option<(u32, u32)>,
+
+    /// XA audio channel to decode, defaults to the first one found
+    #[arg(long, requires = "play_str")]
+    xa_channel: Option<u8>,
+
+    /// Write the movie's frames as PNGs and its audio as a WAV to this directory instead of playing it
+    #[arg(long, requires = "play_str")]
+    export_dir: Option<PathBuf>,
+
+    /// Also print debug TTY output to stdout
+    #[arg(long)]
+    tty: bool,
+
+    /// Print BIOS kernel calls (A0/B0/C0 functions) to stdout
+    #[arg(long)]
+    trace_kernel: bool,
+
+    /// ROM image for a cartridge in the parallel port (Expansion 1), up to 128 KB
+    #[arg(long)]
+    exp1_rom: Option<PathBuf>,
+
+    /// Turn on the switch on top of the parallel port cartridge
+    #[arg(long, requires = "exp1_rom")]
+    exp1_switch: bool,
+
+    /// Bridge the parallel port cartridge's PC link to a pseudo-terminal
+    #[arg(long, requires = "exp1_rom")]
+    exp1_pty: Option<PathBuf>,
+
+    /// Wait for another instance to connect its SIO1 link cable to this address, e.g. 127.0.0.1:9001
+    #[arg(long, group = "sio1")]
+    sio1_listen: Option<String>,
+
+    /// Connect the SIO1 link cable to another instance listening on this address
+    #[arg(long, group = "sio1")]
+    sio1_connect: Option<String>,
+
+    /// Bridge SIO1 to a pseudo-terminal, e.g. one made with `socat -d -d pty,raw,echo=0 pty,raw,echo=0`
+    #[arg(long, group = "sio1")]
+    sio1_pty: Option<PathBuf>,
 }
 
 pub struct CleanConfig {
     // GUI scale factor
     pub scale: u8,
+    // BIOS image, None for the HLE BIOS
+    pub bios: Option<PathBuf>,
+    pub disc: Option<PathBuf>,
+    pub exe: Option<PathBuf>,
+    pub fast_boot: bool,
+    // Save state slots live here, one file per game and slot
+    pub state_dir: PathBuf,
+    // Frames between rewind snapshots and the bytes they may take
+    pub rewind_interval: u32,
+    pub rewind_budget: usize,
+    // Frames shown ahead and how the machine running them is copied
+    pub run_ahead: u32,
+    pub run_ahead_mode: savestate::run_ahead::RunAheadMode,
+    // Input movie to record or play, and whether to play it at launch
+    pub movie: Option<PathBuf>,
+    pub play_movie: bool,
+    pub seed: u32,
+    pub log_state_hash: bool,
+    // Frames to run twice for the determinism check
+    pub check_determinism: Option<u64>,
+    // Controller type for each port
+    pub controllers: [input::ControllerType; 2],
+    // Controller in each slot of each port's multitap, if it has one
+    pub multitaps: [Option<[input::ControllerType; MULTITAP_SLOTS]>; 2],
+    // Memory card image for each slot, numbered four to a port as sio0 numbers them
+    pub memory_cards: [Option<PathBuf>; sio::sio0::MEMORY_CARD_SLOTS],
+    // Keyboard bindings for each pad
+    pub input: input::bindings::InputConfig,
+    // Set when playing an STR movie instead of emulating
+    pub player: Option<player::PlayerConfig>,
+    // Whether TTY output goes to stdout as well as the console panel
+    pub tty: bool,
+    // Whether kernel calls are printed to stdout
+    pub trace_kernel: bool,
+    // Parallel port cartridge ROM, its switch, and where its PC link goes
+    pub expansion_rom: Option<PathBuf>,
+    pub expansion_switch: bool,
+    pub expansion_pty: Option<PathBuf>,
+    // Where the SIO1 link cable goes, if anywhere
+    pub sio1_link: Option<sio::link::LinkTarget>,
 }
 
 impl RawConfig {
     pub fn clean(self) -> CleanConfig {
+        let input = match &self.keymap {
+            Some(path) => load_keymap(path),
+            None => input::bindings::InputConfig::default(),
+        };
+
         CleanConfig {
             scale: self.scale,
+            bios: self.bios,
+            disc: self.disc,
+            exe: self.exe,
+            fast_boot: self.fast_boot,
+            state_dir: self.state_dir,
+            rewind_interval: self.rewind_interval,
+            rewind_budget: self.rewind_budget * 1024 * 1024,
+            run_ahead: self.run_ahead,
+            run_ahead_mode: self.run_ahead_mode,
+            movie: self.movie,
+            play_movie: self.play_movie,
+            seed: self.seed,
+            log_state_hash: self.log_state_hash,
+            check_determinism: self.check_determinism,
+            controllers: [self.port1, self.port2],
+            multitaps: [
+                multitap_pads(1, self.multitap1, self.port1, &self.multitap1_pads),
+                multitap_pads(2, self.multitap2, self.port2, &self.multitap2_pads),
+            ],
+            memory_cards: memory_cards([
+                port_memory_cards(1, self.memcard1, self.multitap1_memcards),
+                port_memory_cards(2, self.memcard2, self.multitap2_memcards),
+            ]),
+            input,
+            player: self.play_str.map(|path| player::PlayerConfig {
+                path,
+                sectors: self.sectors,
+                xa_channel: self.xa_channel,
+                output: self.export_dir,
+            }),
+            tty: self.tty,
+            trace_kernel: self.trace_kernel,
+            expansion_rom: self.exp1_rom,
+            expansion_switch: self.exp1_switch,
+            expansion_pty: self.exp1_pty,
+            sio1_link: self
+                .sio1_listen
+                .map(sio::link::LinkTarget::Listen)
+                .or(self.sio1_connect.map(sio::link::LinkTarget::Connect))
+                .or(self.sio1_pty.map(sio::link::LinkTarget::Pty)),
+        }
+    }
+}
+
+fn load_keymap(path: &PathBuf) -> input::bindings::InputConfig {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read keymap {}: {}", path.display(), e));
+    let config: input::bindings::InputConfig = serde_json::from_str(&contents)
+        .unwrap_or_else(|e| panic!("Failed to parse keymap {}: {}", path.display(), e));
+    if config.pads.len() > input::bindings::MAX_PADS {
+        panic!("Keymap {} binds {} pads, at most {} can be connected", path.display(), config.pads.len(), input::bindings::MAX_PADS);
+    }
+    config
+}
+
+// What's in each slot of a port's multitap, if it has one
+fn multitap_pads(
+    port: usize,
+    multitap: bool,
+    port_type: input::ControllerType,
+    pads: &[input::ControllerType],
+) -> Option<[input::ControllerType; MULTITAP_SLOTS]> {
+    if !multitap {
+        return None;
+    }
+    if pads.is_empty() {
+        return Some([port_type; MULTITAP_SLOTS]);
+    }
+    if pads.len() > MULTITAP_SLOTS {
+        panic!("Port {}'s multitap has {} slots, {} controllers were given", port, MULTITAP_SLOTS, pads.len());
+    }
+    Some(std::array::from_fn(|slot| pads.get(slot).copied().unwrap_or(input::ControllerType::None)))
+}
+
+// Card images for a port's slots, slot A alone without a multitap
+fn port_memory_cards(port: usize, memcard: Option<PathBuf>, multitap_memcards: Vec<String>) -> Vec<Option<PathBuf>> {
+    if multitap_memcards.is_empty() {
+        return vec![memcard];
+    }
+    if multitap_memcards.len() > MULTITAP_SLOTS {
+        panic!("Port {}'s multitap has {} card slots, {} cards were given", port, MULTITAP_SLOTS, multitap_memcards.len());
+    }
+    multitap_memcards
+        .into_iter()
+        .map(|path| (!path.is_empty()).then(|| PathBuf::from(path)))
+        .collect()
+}
+
+fn memory_cards(ports: [Vec<Option<PathBuf>>; 2]) -> [Option<PathBuf>; sio::sio0::MEMORY_CARD_SLOTS] {
+    let mut memory_cards: [Option<PathBuf>; sio::sio0::MEMORY_CARD_SLOTS] = Default::default();
+    for (port, cards) in ports.into_iter().enumerate() {
+        for (slot, path) in cards.into_iter().enumerate() {
+            memory_cards[port * MULTITAP_SLOTS + slot] = path;
         }
     }
+    memory_cards
+}
+
+fn parse_sector_range(value: &str) -> Result<(u32, u32), String> {
+    let (first, last) = value.split_once('-').ok_or("expected FIRST-LAST")?;
+    let parse = |sector: &str| sector.trim().parse::<u32>().map_err(|e| format!("bad sector {}: {}", sector, e));
+    Ok((parse(first)?, parse(last)?))
 }
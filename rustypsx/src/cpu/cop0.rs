@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+// Current interrupt enable, bit 2 and 4 hold the previous and old ones
+pub const SR_INTERRUPT_ENABLE: u32 = 1 << 0;
+// Interrupt mask bit for the interrupt controller's line
+pub const SR_INTERRUPT_MASK_HARDWARE: u32 = 1 << 10;
+// Loads and stores go to the cache instead of memory
+const SR_ISOLATE_CACHE: u32 = 1 << 16;
+// Exception vectors in the BIOS rather than RAM
+const SR_BOOT_EXCEPTION_VECTORS: u32 = 1 << 22;
+
+const CAUSE_INTERRUPT_PENDING_HARDWARE: u32 = 1 << 10;
+const CAUSE_BRANCH_DELAY: u32 = 1 << 31;
+
+pub const EXCEPTION_VECTOR: u32 = 0x80000080;
+pub const BOOT_EXCEPTION_VECTOR: u32 = 0xBFC00180;
+
+/// Exception codes, as found in bits 2-6 of CAUSE
+// Only the HLE BIOS raises any so far, the rest wait for the instructions that cause them
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Exception {
+    Interrupt = 0x00,
+    AddressErrorLoad = 0x04,
+    AddressErrorStore = 0x05,
+    BusErrorInstruction = 0x06,
+    BusErrorData = 0x07,
+    Syscall = 0x08,
+    Break = 0x09,
+    ReservedInstruction = 0x0A,
+    CoprocessorUnusable = 0x0B,
+    Overflow = 0x0C,
+}
+
+/// System control coprocessor
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cop0 {
+    pub sr: u32,
+    pub cause: u32,
+    pub epc: u32,
+}
+
+impl Cop0 {
+    pub fn new() -> Self {
+        Cop0 {
+            sr: SR_BOOT_EXCEPTION_VECTORS,
+            cause: 0,
+            epc: 0,
+        }
+    }
+
+    pub fn cache_isolated(&self) -> bool {
+        self.sr & SR_ISOLATE_CACHE != 0
+    }
+
+    pub fn exception_code(&self) -> u32 {
+        (self.cause >> 2) & 0x1F
+    }
+
+    /// Whether the interrupt controller's line is unmasked and interrupts are on
+    pub fn interrupts_enabled(&self) -> bool {
+        self.sr & (SR_INTERRUPT_ENABLE | SR_INTERRUPT_MASK_HARDWARE) == SR_INTERRUPT_ENABLE | SR_INTERRUPT_MASK_HARDWARE
+    }
+
+    /// Mirror the interrupt controller's output into CAUSE
+    pub fn set_interrupt_pending(&mut self, pending: bool) {
+        if pending {
+            self.cause |= CAUSE_INTERRUPT_PENDING_HARDWARE;
+        } else {
+            self.cause &= !CAUSE_INTERRUPT_PENDING_HARDWARE;
+        }
+    }
+
+    /// Record an exception at `pc` and push the mode stack, returns the vector to jump to
+    pub fn enter_exception(&mut self, exception: Exception, pc: u32, in_delay_slot: bool) -> u32 {
+        self.sr = self.sr & !0x3F | (self.sr << 2) & 0x3F;
+        self.cause = self.cause & !(0x1F << 2 | CAUSE_BRANCH_DELAY) | (exception as u32) << 2;
+        if in_delay_slot {
+            self.epc = pc.wrapping_sub(4);
+            self.cause |= CAUSE_BRANCH_DELAY;
+        } else {
+            self.epc = pc;
+        }
+        if self.sr & SR_BOOT_EXCEPTION_VECTORS != 0 {
+            BOOT_EXCEPTION_VECTOR
+        } else {
+            EXCEPTION_VECTOR
+        }
+    }
+
+    /// RFE, pop the mode stack
+    pub fn return_from_exception(&mut self) {
+        self.sr = self.sr & !0xF | (self.sr >> 2) & 0xF;
+    }
+}
@@ -1,3 +1,5 @@
+pub mod cop0;
+pub mod muldiv;
 pub mod r3000a;
 pub mod registers;
 
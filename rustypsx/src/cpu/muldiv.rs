@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+// Division always takes the full time, multiplication finishes early for small operands
+const DIVIDE_CYCLES: u32 = 36;
+const MULTIPLY_CYCLES_SMALL: u32 = 6;
+const MULTIPLY_CYCLES_MEDIUM: u32 = 9;
+const MULTIPLY_CYCLES_LARGE: u32 = 13;
+
+/// Tracks the multiply/divide unit, which runs alongside the pipeline until `hi`/`lo` are read
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct MulDivUnit {
+    // Cycles until hi and lo hold the result
+    busy: u32,
+}
+
+impl MulDivUnit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time passing as other instructions run
+    pub fn advance(&mut self, cycles: u32) {
+        self.busy = self.busy.saturating_sub(cycles);
+    }
+
+    /// MULT and MULTU, timed on the magnitude of rs
+    #[allow(dead_code)] // For the MULT/MULTU/DIV/DIVU and MFHI/MFLO instructions, which aren't decoded yet
+    pub fn start_multiply(&mut self, rs: u32, signed: bool) {
+        let magnitude = if signed && (rs as i32) < 0 { !rs } else { rs };
+        self.busy = match magnitude {
+            0..0x800 => MULTIPLY_CYCLES_SMALL,
+            0x800..0x100000 => MULTIPLY_CYCLES_MEDIUM,
+            _ => MULTIPLY_CYCLES_LARGE,
+        };
+    }
+
+    #[allow(dead_code)]
+    pub fn start_divide(&mut self) {
+        self.busy = DIVIDE_CYCLES;
+    }
+
+    /// MFHI/MFLO and anything else touching hi/lo wait for the result, returns the stall
+    #[allow(dead_code)]
+    pub fn wait(&mut self) -> u32 {
+        std::mem::take(&mut self.busy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplies_are_timed_on_the_magnitude_of_rs() {
+        let mut unit = MulDivUnit::new();
+        for (rs, signed, cycles) in [
+            (0x7FF, false, MULTIPLY_CYCLES_SMALL),
+            (0x800, false, MULTIPLY_CYCLES_MEDIUM),
+            (0x100000, false, MULTIPLY_CYCLES_LARGE),
+            (u32::MAX, true, MULTIPLY_CYCLES_SMALL),
+            (u32::MAX, false, MULTIPLY_CYCLES_LARGE),
+        ] {
+            unit.start_multiply(rs, signed);
+            assert_eq!(unit.wait(), cycles);
+        }
+    }
+
+    #[test]
+    fn waits_only_cover_what_is_left() {
+        let mut unit = MulDivUnit::new();
+        unit.start_divide();
+        unit.advance(30);
+        assert_eq!(unit.wait(), DIVIDE_CYCLES - 30);
+        assert_eq!(unit.wait(), 0);
+        unit.start_multiply(0, false);
+        unit.advance(100);
+        assert_eq!(unit.wait(), 0);
+    }
+}
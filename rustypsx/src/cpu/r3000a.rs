@@ -1,27 +1,93 @@
-use crate::{cpu::registers, memory, memory::Addressable};
+use crate::{bios, cpu::cop0, cpu::muldiv, cpu::registers, memory};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// Kernel calls and TTY bytes kept until they're taken, the oldest are dropped past these
+const MAX_KERNEL_CALLS: usize = 4096;
+const MAX_TTY_OUTPUT: usize = 0x10000;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct R3000A {
     pub registers: registers::Registers,
+    pub cop0: cop0::Cop0,
+    pub muldiv: muldiv::MulDivUnit,
     pub halted: bool,
     pub stopped: bool,
+    // Kernel calls made and std_out_putchar text since they were last taken
+    #[serde(skip)]
+    pub kernel_calls: VecDeque<bios::calls::KernelCall>,
+    #[serde(skip)]
+    pub tty_output: VecDeque<u8>,
 }
 
 impl R3000A {
     pub fn new() -> Self {
-        R3000A { registers: registers::Registers::new(), halted: false, stopped: false }
+        R3000A {
+            registers: registers::Registers::new(),
+            cop0: cop0::Cop0::new(),
+            muldiv: muldiv::MulDivUnit::new(),
+            halted: false,
+            stopped: false,
+            kernel_calls: VecDeque::new(),
+            tty_output: VecDeque::new(),
+        }
+    }
+
+    /// Run one instruction, returns the cycles it took
+    ///
+    /// That is the instruction's own cycle plus instruction cache refills, bus waits for loads and a
+    /// full write queue, `hi`/`lo` interlocks and any time DMA held the bus since the last instruction.
+    pub fn step(&mut self, mmio: &mut memory::mmio::Mmio) -> u32 {
+        mmio.cache_isolated = self.cop0.cache_isolated();
+        let dma_cycles = mmio.take_dma_cycles();
+        if self.take_interrupt(mmio) {
+            return dma_cycles + 1;
+        }
+        self.trace_kernel_call(mmio);
+        let (opcode, fetch_cycles) = mmio.fetch_instruction(self.registers.pc);
+        self.registers.pc = self.registers.pc.wrapping_add(4);
+        let cycles = dma_cycles + fetch_cycles + self.execute(opcode, mmio);
+        self.muldiv.advance(cycles);
+        cycles
     }
 
-    pub fn step(&mut self, mmio: &mut memory::mmio::Mmio) -> u8 {
-        let opcode = mmio.read(self.registers.pc);
-        self.registers.pc += 1;
-        self.execute(opcode, mmio)
+    /// Jump to the exception vector if the interrupt controller has an unmasked interrupt and the CPU
+    /// would take it
+    pub fn take_interrupt(&mut self, mmio: &memory::mmio::Mmio) -> bool {
+        let pending = mmio.interrupts.pending();
+        self.cop0.set_interrupt_pending(pending);
+        if !pending || !self.cop0.interrupts_enabled() {
+            return false;
+        }
+        self.registers.pc = self.cop0.enter_exception(cop0::Exception::Interrupt, self.registers.pc, false);
+        true
+    }
+
+    /// Text printed through the kernel, kept until it's taken
+    pub fn write_tty(&mut self, text: &[u8]) {
+        self.tty_output.extend(text);
+        let excess = self.tty_output.len().saturating_sub(MAX_TTY_OUTPUT);
+        self.tty_output.drain(..excess);
+    }
+
+    /// Note a call into the kernel's function tables if the CPU is about to make one
+    pub fn trace_kernel_call(&mut self, mmio: &memory::mmio::Mmio) {
+        if let Some(call) = bios::calls::decode(self.registers.pc, &self.registers, |addr| mmio.peek(addr)) {
+            if let Some(char) = call.putchar(&self.registers) {
+                self.write_tty(&[char]);
+            }
+            if self.kernel_calls.len() == MAX_KERNEL_CALLS {
+                self.kernel_calls.pop_front();
+            }
+            self.kernel_calls.push_back(call);
+        }
     }
 
-    fn execute(&mut self, opcode: u8, _mmio: &mut memory::mmio::Mmio) -> u8 {
+    /// Runs an instruction, returns 1 plus any stalls from `mmio.load`/`mmio.store` and `self.muldiv.wait`
+    #[allow(clippy::match_single_binding)]
+    fn execute(&mut self, opcode: u32, _mmio: &mut memory::mmio::Mmio) -> u32 {
         match opcode {
-            _ => panic!("Opcode {:02X} not implemented", opcode),
+            _ => panic!("Opcode {:08X} not implemented", opcode),
         }
     }
 }
@@ -0,0 +1,74 @@
+//! Checking a run plays out the same every time, by running it twice from scratch and
+//! comparing a hash of the machine state after every frame.
+
+use crate::movie::{self, Movie};
+use crate::psx::PS1;
+use crate::savestate;
+use std::path::Path;
+
+// Hashes after each frame, ending early if the movie runs out or the emulator panics
+fn run(ps1: &mut PS1, movie_path: Option<&Path>, settings: &movie::Settings, frames: u64) -> Result<Vec<u32>, String> {
+    // Both runs must start from the same card contents
+    ps1.detach_memory_cards();
+    let mut movie = match movie_path {
+        Some(path) => {
+            let mut movie = Movie::open(path)?;
+            movie.start_playback(ps1, settings, true)?;
+            Some(movie)
+        }
+        None => None,
+    };
+
+    let mut hashes = Vec::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        for _ in 0..frames {
+            if let Some(movie) = movie.as_mut() {
+                let Some(inputs) = movie.input(ps1, &[]) else {
+                    break;
+                };
+                for (pad, input) in inputs.into_iter().enumerate() {
+                    ps1.set_pad_input(pad, input);
+                }
+            }
+            ps1.run_until_frame(false);
+            hashes.push(savestate::state_hash(ps1));
+        }
+    }));
+    if result.is_err() {
+        println!("Emulator panicked after {} frames", hashes.len());
+    }
+    Ok(hashes)
+}
+
+/// Run two machines from `build` for `frames` frames, returning the first frame they differ after
+///
+/// A run that ends early, because the movie ran out or the emulator panicked, counts as diverging
+/// at the frame it didn't get to.
+pub fn check(build: impl Fn() -> PS1, movie_path: Option<&Path>, settings: &movie::Settings, frames: u64, log: bool) -> Result<Option<usize>, String> {
+    let first = run(&mut build(), movie_path, settings, frames)?;
+    let second = run(&mut build(), movie_path, settings, frames)?;
+    if log {
+        for (frame, (first, second)) in first.iter().zip(&second).enumerate() {
+            println!("frame {} {:08X} {:08X}", frame + 1, first, second);
+        }
+    }
+
+    let diverged = first
+        .iter()
+        .zip(&second)
+        .position(|(first, second)| first != second)
+        .or((first.len().min(second.len()) < frames as usize).then(|| first.len().min(second.len())));
+    match diverged {
+        Some(frame) if first.len() == frame && second.len() == frame => {
+            println!("Both runs stopped after {} of {} frames", frame, frames)
+        }
+        Some(frame) => println!(
+            "Runs diverge at frame {}: {} vs {}",
+            frame + 1,
+            first.get(frame).map_or("no frame".to_string(), |hash| format!("{:08X}", hash)),
+            second.get(frame).map_or("no frame".to_string(), |hash| format!("{:08X}", hash))
+        ),
+        None => println!("Runs match for all {} frames", first.len()),
+    }
+    Ok(diverged)
+}
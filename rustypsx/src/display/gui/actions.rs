@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 pub enum GuiAction {
     Exit,
     TogglePause,
@@ -7,4 +9,19 @@ pub enum GuiAction {
     StepFrames(u32),
     SetBreakpoint(u32),
     RemoveBreakpoint(u32),
+    // Memory card slot and first block of the save
+    DeleteSave(usize, usize),
+    CopySave(usize, usize, usize),
+    ExportSave(usize, usize, PathBuf),
+    ImportSave(usize, PathBuf),
+    ImportMemoryCard(usize, PathBuf),
+    ExportMemoryCard(usize, PathBuf),
+    // Save state slot
+    SaveState(usize),
+    LoadState(usize),
+    // From power on rather than from now
+    RecordMovie(bool),
+    // Read-only
+    PlayMovie(bool),
+    StopMovie,
 }
@@ -0,0 +1,144 @@
+use egui::Context;
+use std::path::{Path, PathBuf};
+
+use crate::cdrom::{Disc, iso9660};
+
+// A file and the directory it was found in
+struct File {
+    directory: String,
+    entry: iso9660::Entry,
+}
+
+/// The inserted disc's filesystem, listed once per disc and extracted to the host
+pub(crate) struct DiscBrowser {
+    output_input: String,
+    filter: String,
+    // Disc the listing was read from
+    listed: Option<PathBuf>,
+    volume: Option<iso9660::VolumeDescriptor>,
+    files: Vec<File>,
+    message: Option<String>,
+}
+
+impl DiscBrowser {
+    pub(crate) fn new() -> Self {
+        Self {
+            output_input: String::new(),
+            filter: String::new(),
+            listed: None,
+            volume: None,
+            files: Vec::new(),
+            message: None,
+        }
+    }
+
+    // Opened separately from the emulated drive so browsing never moves its read position
+    fn list(&mut self, path: &Path) {
+        self.listed = Some(path.to_path_buf());
+        self.volume = None;
+        self.files.clear();
+        self.message = None;
+        if let Err(e) = self.read_listing(path) {
+            self.message = Some(e);
+        }
+    }
+
+    fn read_listing(&mut self, path: &Path) -> Result<(), String> {
+        let mut disc = Disc::open(path)?;
+        let volume = iso9660::volume_descriptor(&mut disc)?;
+        let table = iso9660::path_table(&mut disc, &volume)?;
+        let paths = iso9660::directory_paths(&table);
+        for (directory, path) in table.iter().zip(paths) {
+            let entry = iso9660::path_table_directory(&mut disc, directory)?;
+            for child in iso9660::list(&mut disc, &entry)? {
+                if !child.is_directory {
+                    self.files.push(File { directory: path.clone(), entry: child });
+                }
+            }
+        }
+        self.volume = Some(volume);
+        Ok(())
+    }
+
+    fn extract(&self, file: &File) -> Result<String, String> {
+        let disc_path = self.listed.as_ref().ok_or("No disc")?;
+        let mut disc = Disc::open(disc_path)?;
+        let contents = if file.entry.is_raw() {
+            iso9660::read_raw_file(&mut disc, &file.entry)?
+        } else {
+            iso9660::read_file(&mut disc, &file.entry)?
+        };
+        let output = PathBuf::from(self.output_input.trim()).join(&file.entry.name);
+        std::fs::write(&output, &contents).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+        Ok(format!("Wrote {} bytes to {}", contents.len(), output.display()))
+    }
+
+    pub(crate) fn render(&mut self, ctx: &Context, ps1: Option<&crate::psx::PS1>, open: &mut bool) {
+        let disc_path = ps1.and_then(|ps1| ps1.disc_path()).map(Path::to_path_buf);
+        if let Some(path) = &disc_path
+            && self.listed.as_ref() != Some(path)
+        {
+            self.list(path);
+        }
+
+        egui::Window::new("Disc Browser")
+            .open(open)
+            .default_width(560.0)
+            .default_height(400.0)
+            .show(ctx, |ui| {
+                if disc_path.is_none() {
+                    ui.label("No disc inserted");
+                    return;
+                }
+                if let Some(volume) = &self.volume {
+                    ui.label(format!("{} ({}), {} sectors", volume.volume_id, volume.system_id, volume.volume_size));
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Extract to:");
+                    ui.add(egui::TextEdit::singleline(&mut self.output_input)
+                        .desired_width(280.0)
+                        .hint_text("host directory"));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Filter:");
+                    ui.add(egui::TextEdit::singleline(&mut self.filter).desired_width(200.0));
+                });
+                if let Some(message) = &self.message {
+                    ui.small(message);
+                }
+                ui.small("Form 2 and interleaved files are written as 2336 byte sectors, subheaders included");
+                ui.separator();
+
+                let filter = self.filter.to_lowercase();
+                let can_extract = !self.output_input.trim().is_empty();
+                let mut extracted = None;
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    egui::Grid::new("disc_files").striped(true).show(ui, |ui| {
+                        ui.strong("File");
+                        ui.strong("LBA");
+                        ui.strong("Size");
+                        ui.strong("XA");
+                        ui.label("");
+                        ui.end_row();
+                        for file in &self.files {
+                            let path = format!("{}\\{}", file.directory, file.entry.name);
+                            if !filter.is_empty() && !path.to_lowercase().contains(&filter) {
+                                continue;
+                            }
+                            ui.monospace(path);
+                            ui.monospace(file.entry.lba.to_string());
+                            ui.monospace(file.entry.size.to_string());
+                            ui.monospace(file.entry.xa.map(|xa| xa.describe()).unwrap_or_default());
+                            if ui.add_enabled(can_extract, egui::Button::new("Extract")).clicked() {
+                                extracted = Some(self.extract(file));
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+                if let Some(Ok(message) | Err(message)) = extracted {
+                    self.message = Some(message);
+                }
+            });
+    }
+}
@@ -25,6 +25,7 @@ impl Framework {
         height: u32,
         scale_factor: f32,
         pixels: &pixels::Pixels,
+        state_dir: std::path::PathBuf,
     ) -> Self {
         let max_texture_size = pixels.device().limits().max_texture_dimension_2d as usize;
 
@@ -42,7 +43,7 @@ impl Framework {
         };
         let renderer = Renderer::new(pixels.device(), pixels.render_texture_format(), None, 1);
         let textures = TexturesDelta::default();
-        let gui = Gui::new();
+        let gui = Gui::new(state_dir);
 
         Self {
             egui_ctx,
@@ -69,6 +70,15 @@ impl Framework {
         self.screen_descriptor.pixels_per_point = scale_factor as f32;
     }
 
+    /// Whether the mouse is over a window or menu, rather than the emulated screen
+    pub(crate) fn wants_pointer_input(&self) -> bool {
+        self.egui_ctx.wants_pointer_input()
+    }
+
+    pub(crate) fn wants_keyboard_input(&self) -> bool {
+        self.egui_ctx.wants_keyboard_input()
+    }
+
     pub(crate) fn set_error(&mut self, error_message: String) {
         self.gui.set_error(error_message);
     }
@@ -81,6 +91,18 @@ impl Framework {
         self.gui.set_status(status_message);
     }
 
+    pub(crate) fn save_states_changed(&mut self) {
+        self.gui.save_states_changed();
+    }
+
+    pub(crate) fn append_tty(&mut self, output: &[u8]) {
+        self.gui.append_tty(output);
+    }
+
+    pub(crate) fn append_kernel_calls(&mut self, calls: &[crate::bios::calls::KernelCall]) {
+        self.gui.append_kernel_calls(calls);
+    }
+
     pub(crate) fn prepare(&mut self, window: &Window, paused: bool, ps1: Option<&crate::psx::PS1>) -> (Option<GuiAction>, bool) {
         let raw_input = self.egui_state.take_egui_input(window);
         let mut ui_result = None;
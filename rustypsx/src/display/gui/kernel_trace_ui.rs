@@ -0,0 +1,83 @@
+use egui::Context;
+use std::collections::VecDeque;
+
+use crate::bios::calls::KernelCall;
+
+// Oldest calls are dropped past this many
+const MAX_CALLS: usize = 5000;
+
+/// Recent calls into the BIOS kernel, filtered by name or table:function
+pub(crate) struct KernelTrace {
+    calls: VecDeque<(String, String)>,
+    filter: String,
+    paused: bool,
+}
+
+impl KernelTrace {
+    pub(crate) fn new() -> Self {
+        Self {
+            calls: VecDeque::new(),
+            filter: String::new(),
+            paused: false,
+        }
+    }
+
+    pub(crate) fn append(&mut self, calls: &[KernelCall]) {
+        if self.paused {
+            return;
+        }
+        for call in calls {
+            self.calls.push_back((call.id(), call.to_string()));
+        }
+        let excess = self.calls.len().saturating_sub(MAX_CALLS);
+        self.calls.drain(..excess);
+    }
+
+    pub(crate) fn render(&mut self, ctx: &Context, open: &mut bool) {
+        egui::Window::new("Kernel Call Trace")
+            .open(open)
+            .default_width(560.0)
+            .default_height(320.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Filter:");
+                    ui.add(egui::TextEdit::singleline(&mut self.filter)
+                        .desired_width(200.0)
+                        .hint_text("printf, B0:, !TestEvent"));
+                    ui.checkbox(&mut self.paused, "Pause");
+                    if ui.button("Clear").clicked() {
+                        self.calls.clear();
+                    }
+                });
+                ui.small("Space separated terms, a leading ! hides matching calls");
+                ui.separator();
+
+                let terms: Vec<&str> = self.filter.split_whitespace().collect();
+                let shown: Vec<&String> = self
+                    .calls
+                    .iter()
+                    .filter(|(id, text)| matches_filter(&terms, id, text))
+                    .map(|(_, text)| text)
+                    .collect();
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, shown.len(), |ui, rows| {
+                        for text in &shown[rows] {
+                            ui.monospace(text.as_str());
+                        }
+                    });
+            });
+    }
+}
+
+// Calls must match one of the plain terms, if there are any, and none of the ! terms
+fn matches_filter(terms: &[&str], id: &str, text: &str) -> bool {
+    let matches = |term: &str| id.eq_ignore_ascii_case(term) || text.to_lowercase().contains(&term.to_lowercase());
+    let (excluded, included): (Vec<&str>, Vec<&str>) = terms.iter().partition(|term| term.starts_with('!'));
+    if excluded.iter().any(|term| term.len() > 1 && matches(&term[1..])) {
+        return false;
+    }
+    included.is_empty() || included.iter().any(|term| matches(term))
+}
@@ -1,20 +1,47 @@
 use egui::Context;
 use super::actions::GuiAction;
+use super::disc_browser_ui::DiscBrowser;
+use super::kernel_trace_ui::KernelTrace;
+use super::memory_card_ui::MemoryCardManager;
+use super::save_state_ui::SaveStateManager;
+use super::tty_ui::TtyConsole;
 
 pub(crate) struct Gui {
     error_message: Option<String>,
     status_message: Option<String>,
     show_breakpoint_panel: bool,
     breakpoint_address_input: String,
+    show_memory_card_panel: bool,
+    memory_card_manager: MemoryCardManager,
+    show_crosshair: bool,
+    show_tty_panel: bool,
+    tty_console: TtyConsole,
+    show_kernel_trace_panel: bool,
+    kernel_trace: KernelTrace,
+    show_disc_browser_panel: bool,
+    disc_browser: DiscBrowser,
+    show_save_state_panel: bool,
+    save_state_manager: SaveStateManager,
 }
 
 impl Gui {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(state_dir: std::path::PathBuf) -> Self {
         Self { 
             error_message: None,
             status_message: None,
             show_breakpoint_panel: false,
             breakpoint_address_input: String::from("0000"),
+            show_memory_card_panel: false,
+            memory_card_manager: MemoryCardManager::new(),
+            show_crosshair: true,
+            show_tty_panel: false,
+            tty_console: TtyConsole::new(),
+            show_kernel_trace_panel: false,
+            kernel_trace: KernelTrace::new(),
+            show_disc_browser_panel: false,
+            disc_browser: DiscBrowser::new(),
+            show_save_state_panel: false,
+            save_state_manager: SaveStateManager::new(state_dir),
         }
     }
 
@@ -27,6 +54,9 @@ impl Gui {
         self.render_menu_bar(ctx, &mut action, &mut any_menu_open, paused);
         self.render_status_panel(ctx);
         self.render_error_panel(ctx, &mut action);
+        if self.show_crosshair && ps1.is_some_and(|ps1| ps1.has_lightgun()) {
+            render_crosshair(ctx);
+        }
         
         (action, any_menu_open)
     }
@@ -36,6 +66,48 @@ impl Gui {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     *any_menu_open = true;
+                    ui.menu_button("Save State", |ui| {
+                        for slot in 0..crate::savestate::SLOTS {
+                            if ui.button(format!("Slot {} (Shift+F{})", slot + 1, slot + 1)).clicked() {
+                                *action = Some(GuiAction::SaveState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("Load State", |ui| {
+                        for slot in 0..crate::savestate::SLOTS {
+                            if ui.button(format!("Slot {} (F{})", slot + 1, slot + 1)).clicked() {
+                                *action = Some(GuiAction::LoadState(slot));
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    ui.menu_button("Movie", |ui| {
+                        if ui.button("Record from power on").clicked() {
+                            *action = Some(GuiAction::RecordMovie(true));
+                            ui.close_menu();
+                        }
+                        if ui.button("Record from now").clicked() {
+                            *action = Some(GuiAction::RecordMovie(false));
+                            ui.close_menu();
+                        }
+                        if ui.button("Play (read-only)").clicked() {
+                            *action = Some(GuiAction::PlayMovie(true));
+                            ui.close_menu();
+                        }
+                        if ui.button("Play (read-write)").clicked() {
+                            *action = Some(GuiAction::PlayMovie(false));
+                            ui.close_menu();
+                        }
+                        if ui.button("Stop").clicked() {
+                            *action = Some(GuiAction::StopMovie);
+                            ui.close_menu();
+                        }
+                    });
+                    if ui.button("Save States...").clicked() {
+                        self.show_save_state_panel = true;
+                        ui.close_menu();
+                    }
                     ui.separator();
                     if ui.button("Exit").clicked() {
                         *action = Some(GuiAction::Exit);
@@ -55,6 +127,35 @@ impl Gui {
                         *action = Some(GuiAction::TogglePause);
                         ui.close_menu();
                     }
+                    if ui.add_enabled(paused, egui::Button::new("Step Instruction")).clicked() {
+                        *action = Some(GuiAction::StepCycles(1));
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(paused, egui::Button::new("Step Frame")).clicked() {
+                        *action = Some(GuiAction::StepFrames(1));
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Tools", |ui| {
+                    *any_menu_open = true;
+                    if ui.button("Memory Cards...").clicked() {
+                        self.show_memory_card_panel = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("TTY Console...").clicked() {
+                        self.show_tty_panel = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Kernel Call Trace...").clicked() {
+                        self.show_kernel_trace_panel = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Disc Browser...").clicked() {
+                        self.show_disc_browser_panel = true;
+                        ui.close_menu();
+                    }
+                    ui.checkbox(&mut self.show_crosshair, "Lightgun crosshair");
                 });
             });
         });
@@ -125,10 +226,37 @@ impl Gui {
         self.status_message = Some(status_message);
     }
 
+    pub(crate) fn save_states_changed(&mut self) {
+        self.save_state_manager.refresh();
+    }
+
+    pub(crate) fn append_tty(&mut self, output: &[u8]) {
+        self.tty_console.append(output);
+    }
+
+    pub(crate) fn append_kernel_calls(&mut self, calls: &[crate::bios::calls::KernelCall]) {
+        self.kernel_trace.append(calls);
+    }
+
     fn render_debug_panels(&mut self, ctx: &Context, ps1: Option<&crate::psx::PS1>, action: &mut Option<GuiAction>) {
         if self.show_breakpoint_panel {
             self.render_breakpoint_panel(ctx, action, ps1);
         }
+        if self.show_memory_card_panel {
+            self.memory_card_manager.render(ctx, action, ps1, &mut self.show_memory_card_panel);
+        }
+        if self.show_tty_panel {
+            self.tty_console.render(ctx, &mut self.show_tty_panel);
+        }
+        if self.show_kernel_trace_panel {
+            self.kernel_trace.render(ctx, &mut self.show_kernel_trace_panel);
+        }
+        if self.show_save_state_panel {
+            self.save_state_manager.render(ctx, action, ps1, &mut self.show_save_state_panel);
+        }
+        if self.show_disc_browser_panel {
+            self.disc_browser.render(ctx, ps1, &mut self.show_disc_browser_panel);
+        }
     }
 
     fn render_breakpoint_panel(&mut self, ctx: &Context, action: &mut Option<GuiAction>, ps1: Option<&crate::psx::PS1>) {
@@ -162,15 +290,12 @@ impl Gui {
                     ui.label("Active Breakpoints:");
                     ui.separator();
 
+                    // Already in address order
                     let breakpoints: Vec<u32> = ps1.get_breakpoints().iter().cloned().collect();
                     if breakpoints.is_empty() {
                         ui.label("No breakpoints set");
                     } else {
-                        // Sort breakpoints for consistent display
-                        let mut sorted_breakpoints = breakpoints.clone();
-                        sorted_breakpoints.sort();
-
-                        for &address in &sorted_breakpoints {
+                        for &address in &breakpoints {
                             ui.horizontal(|ui| {
                                 ui.monospace(format!("{:04X}", address));
                                 if ui.small_button("✕").clicked() {
@@ -197,3 +322,16 @@ impl Gui {
             });
     }
 }
+
+// Drawn over everything at the mouse, which is where lightguns aim
+fn render_crosshair(ctx: &Context) {
+    const RADIUS: f32 = 10.0;
+    let Some(position) = ctx.pointer_hover_pos() else {
+        return;
+    };
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("crosshair")));
+    let stroke = egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 48, 48));
+    painter.circle_stroke(position, RADIUS, stroke);
+    painter.line_segment([position - egui::vec2(RADIUS * 1.5, 0.0), position + egui::vec2(RADIUS * 1.5, 0.0)], stroke);
+    painter.line_segment([position - egui::vec2(0.0, RADIUS * 1.5), position + egui::vec2(0.0, RADIUS * 1.5)], stroke);
+}
@@ -0,0 +1,145 @@
+use egui::{Context, TextureHandle, TextureOptions};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::actions::GuiAction;
+use crate::memcard::directory;
+
+// The BIOS advances multi-frame icons every 16 vblanks
+const ICON_FRAME_SECONDS: f64 = 16.0 / 60.0;
+const ICON_SIZE: f32 = 32.0;
+
+// Decoded RGBA frames and the textures built from them
+struct Icon {
+    frames: Vec<Vec<u8>>,
+    textures: Vec<TextureHandle>,
+}
+
+pub(crate) struct MemoryCardManager {
+    path_input: String,
+    // Keyed by slot and first block
+    icons: HashMap<(usize, usize), Icon>,
+}
+
+impl MemoryCardManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            path_input: String::new(),
+            icons: HashMap::new(),
+        }
+    }
+
+    fn icon_textures(&mut self, ctx: &Context, slot: usize, save: &directory::Save) -> &[TextureHandle] {
+        let key = (slot, save.first_block());
+        let stale = self.icons.get(&key).is_none_or(|icon| icon.frames != save.icon_frames);
+        if stale {
+            let textures = save
+                .icon_frames
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| {
+                    let image = egui::ColorImage::from_rgba_unmultiplied([16, 16], frame);
+                    ctx.load_texture(format!("memcard-{}-{}-{}", slot, key.1, index), image, TextureOptions::NEAREST)
+                })
+                .collect();
+            self.icons.insert(key, Icon { frames: save.icon_frames.clone(), textures });
+        }
+        &self.icons[&key].textures
+    }
+
+    pub(crate) fn render(&mut self, ctx: &Context, action: &mut Option<GuiAction>, ps1: Option<&crate::psx::PS1>, open: &mut bool) {
+        egui::Window::new("Memory Card Manager")
+            .open(open)
+            .default_width(520.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("File:");
+                    ui.add(egui::TextEdit::singleline(&mut self.path_input)
+                        .desired_width(360.0)
+                        .hint_text("saves: .mcs .psx .psv, cards: .mcr .gme .vmp"));
+                });
+                ui.separator();
+
+                let Some(ps1) = ps1 else {
+                    ui.label("PS1 not available");
+                    return;
+                };
+
+                let path = PathBuf::from(self.path_input.trim());
+                let has_path = !self.path_input.trim().is_empty();
+                let frame_time = ctx.input(|input| input.time);
+
+                let slots = ps1.memory_card_slots();
+                for &slot in &slots {
+                    // Cards a save can be copied onto
+                    let others: Vec<usize> = slots
+                        .iter()
+                        .copied()
+                        .filter(|&other| other != slot && ps1.memory_card(other).is_some())
+                        .collect();
+                    ui.heading(format!("Slot {}", ps1.memory_card_slot_name(slot)));
+
+                    let Some(card) = ps1.memory_card(slot) else {
+                        ui.label("No memory card inserted");
+                        ui.separator();
+                        continue;
+                    };
+
+                    if let Some(card_path) = card.path() {
+                        ui.small(card_path.display().to_string());
+                    }
+                    let saves = directory::list_saves(card.data());
+                    ui.label(format!("{} saves, {} of {} blocks free", saves.len(), directory::free_blocks(card.data()), directory::SAVE_BLOCKS));
+
+                    egui::Grid::new(format!("memcard_saves_{}", slot)).striped(true).show(ui, |ui| {
+                        for save in &saves {
+                            let textures = self.icon_textures(ctx, slot, save);
+                            if textures.is_empty() {
+                                ui.label("");
+                            } else {
+                                let frame = (frame_time / ICON_FRAME_SECONDS) as usize % textures.len();
+                                ui.image((textures[frame].id(), egui::vec2(ICON_SIZE, ICON_SIZE)));
+                            }
+
+                            ui.vertical(|ui| {
+                                ui.label(&save.title);
+                                ui.small(&save.filename);
+                            });
+                            ui.label(format!("{} blocks", save.blocks.len()));
+
+                            ui.horizontal(|ui| {
+                                for &other in &others {
+                                    let text = format!("Copy to {}", ps1.memory_card_slot_name(other));
+                                    if ui.button(text).clicked() {
+                                        *action = Some(GuiAction::CopySave(slot, save.first_block(), other));
+                                    }
+                                }
+                                if ui.add_enabled(has_path, egui::Button::new("Export")).clicked() {
+                                    *action = Some(GuiAction::ExportSave(slot, save.first_block(), path.clone()));
+                                }
+                                if ui.button("Delete").clicked() {
+                                    *action = Some(GuiAction::DeleteSave(slot, save.first_block()));
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(has_path, egui::Button::new("Import save")).clicked() {
+                            *action = Some(GuiAction::ImportSave(slot, path.clone()));
+                        }
+                        if ui.add_enabled(has_path, egui::Button::new("Import card")).clicked() {
+                            *action = Some(GuiAction::ImportMemoryCard(slot, path.clone()));
+                        }
+                        if ui.add_enabled(has_path, egui::Button::new("Export card")).clicked() {
+                            *action = Some(GuiAction::ExportMemoryCard(slot, path.clone()));
+                        }
+                    });
+                    ui.separator();
+                }
+
+                ui.small("Importing a card replaces everything on it. PSP and PS3 files are written unsigned.");
+            });
+    }
+}
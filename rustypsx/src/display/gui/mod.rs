@@ -1,6 +1,11 @@
 mod actions;
+mod disc_browser_ui;
 mod framework;
+mod kernel_trace_ui;
 mod main_ui;
+mod memory_card_ui;
+mod save_state_ui;
+mod tty_ui;
 
 pub use actions::GuiAction;
 pub(crate) use framework::Framework;
@@ -0,0 +1,185 @@
+use egui::{Context, TextureHandle, TextureOptions};
+use std::path::PathBuf;
+
+use super::actions::GuiAction;
+use crate::savestate;
+
+const THUMBNAIL_SCALE: f32 = 0.5;
+
+// What's in a slot's file, read from its header
+enum Slot {
+    Empty,
+    Saved(savestate::Header, TextureHandle),
+    // Unreadable, or from another format version, shown with why
+    Unusable(String),
+}
+
+/// The current game's save state slots
+pub(crate) struct SaveStateManager {
+    state_dir: PathBuf,
+    export_input: String,
+    // Game the slots were listed for
+    game_id: String,
+    slots: Vec<Slot>,
+    // Cleared whenever a slot's file may have changed
+    listed: bool,
+    message: Option<String>,
+}
+
+impl SaveStateManager {
+    pub(crate) fn new(state_dir: PathBuf) -> Self {
+        Self {
+            state_dir,
+            export_input: String::new(),
+            game_id: String::new(),
+            slots: Vec::new(),
+            listed: false,
+            message: None,
+        }
+    }
+
+    fn slot_path(&self, slot: usize) -> PathBuf {
+        savestate::slot_path(&self.state_dir, &self.game_id, slot)
+    }
+
+    /// Read the slots again next time the window shows, after a state was saved outside it
+    pub(crate) fn refresh(&mut self) {
+        self.listed = false;
+    }
+
+    fn list(&mut self, ctx: &Context, game_id: &str) {
+        self.game_id = game_id.to_string();
+        self.listed = true;
+        self.slots = (0..savestate::SLOTS)
+            .map(|slot| {
+                let path = self.slot_path(slot);
+                if !path.exists() {
+                    return Slot::Empty;
+                }
+                match savestate::read_header(&path) {
+                    Ok(header) if header.thumbnail.len() != savestate::THUMBNAIL_WIDTH * savestate::THUMBNAIL_HEIGHT * 4 => {
+                        Slot::Unusable("Save state thumbnail is corrupt".to_string())
+                    }
+                    Ok(header) => {
+                        let image = egui::ColorImage::from_rgba_unmultiplied(
+                            [savestate::THUMBNAIL_WIDTH, savestate::THUMBNAIL_HEIGHT],
+                            &header.thumbnail,
+                        );
+                        let texture = ctx.load_texture(format!("savestate-{}", slot), image, TextureOptions::LINEAR);
+                        Slot::Saved(header, texture)
+                    }
+                    Err(e) => Slot::Unusable(e),
+                }
+            })
+            .collect();
+    }
+
+    fn delete(&self, slot: usize) -> String {
+        let path = self.slot_path(slot);
+        match std::fs::remove_file(&path) {
+            Ok(()) => format!("Deleted slot {}", slot + 1),
+            Err(e) => format!("Failed to delete {}: {}", path.display(), e),
+        }
+    }
+
+    fn export(&self, slot: usize) -> String {
+        let path = self.slot_path(slot);
+        let mut output = PathBuf::from(self.export_input.trim());
+        if output.is_dir() {
+            output = output.join(path.file_name().unwrap_or_default());
+        }
+        match std::fs::copy(&path, &output) {
+            Ok(_) => format!("Exported slot {} to {}", slot + 1, output.display()),
+            Err(e) => format!("Failed to export to {}: {}", output.display(), e),
+        }
+    }
+
+    pub(crate) fn render(&mut self, ctx: &Context, action: &mut Option<GuiAction>, ps1: Option<&crate::psx::PS1>, open: &mut bool) {
+        let Some(ps1) = ps1 else {
+            return;
+        };
+        if !self.listed || self.game_id != ps1.game_id() {
+            self.list(ctx, ps1.game_id());
+        }
+
+        let mut changed = false;
+        egui::Window::new("Save States")
+            .open(open)
+            .default_width(560.0)
+            .default_height(480.0)
+            .show(ctx, |ui| {
+                ui.label(format!("{} in {}", self.game_id, self.state_dir.display()));
+                ui.horizontal(|ui| {
+                    ui.label("Export to:");
+                    ui.add(egui::TextEdit::singleline(&mut self.export_input)
+                        .desired_width(320.0)
+                        .hint_text("file or directory"));
+                });
+                if let Some(message) = &self.message {
+                    ui.small(message);
+                }
+                ui.separator();
+
+                let can_export = !self.export_input.trim().is_empty();
+                let mut message = None;
+                egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| {
+                    egui::Grid::new("save_state_slots").striped(true).show(ui, |ui| {
+                        for (slot, contents) in self.slots.iter().enumerate() {
+                            ui.strong(format!("{}", slot + 1));
+                            let loadable = match contents {
+                                Slot::Empty => {
+                                    ui.label("");
+                                    ui.label("Empty");
+                                    false
+                                }
+                                Slot::Saved(header, texture) => {
+                                    let size = egui::vec2(savestate::THUMBNAIL_WIDTH as f32, savestate::THUMBNAIL_HEIGHT as f32) * THUMBNAIL_SCALE;
+                                    ui.image((texture.id(), size));
+                                    let refused = header.validate(ps1).err();
+                                    ui.vertical(|ui| {
+                                        ui.label(header.saved_at());
+                                        ui.small(format!("Played {}, version {}", header.play_time(), header.emulator_version));
+                                        if let Some(reason) = &refused {
+                                            ui.colored_label(egui::Color32::from_rgb(255, 96, 96), reason);
+                                        }
+                                    });
+                                    refused.is_none()
+                                }
+                                Slot::Unusable(reason) => {
+                                    ui.label("");
+                                    ui.colored_label(egui::Color32::from_rgb(255, 96, 96), reason);
+                                    false
+                                }
+                            };
+                            let saved = !matches!(contents, Slot::Empty);
+
+                            ui.horizontal(|ui| {
+                                if ui.add_enabled(loadable, egui::Button::new("Load")).clicked() {
+                                    *action = Some(GuiAction::LoadState(slot));
+                                }
+                                let save_text = if saved { "Save over" } else { "Save" };
+                                if ui.button(save_text).clicked() {
+                                    *action = Some(GuiAction::SaveState(slot));
+                                }
+                                if ui.add_enabled(saved, egui::Button::new("Delete")).clicked() {
+                                    message = Some(self.delete(slot));
+                                    changed = true;
+                                }
+                                if ui.add_enabled(saved && can_export, egui::Button::new("Export")).clicked() {
+                                    message = Some(self.export(slot));
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+                });
+                if message.is_some() {
+                    self.message = message;
+                }
+            });
+
+        if changed {
+            self.refresh();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use egui::Context;
+
+// Oldest text is dropped past this many bytes
+const MAX_TEXT_LENGTH: usize = 256 * 1024;
+
+/// Text the emulated machine printed to its debug TTY
+pub(crate) struct TtyConsole {
+    text: String,
+}
+
+impl TtyConsole {
+    pub(crate) fn new() -> Self {
+        Self { text: String::new() }
+    }
+
+    pub(crate) fn append(&mut self, output: &[u8]) {
+        self.text.push_str(&String::from_utf8_lossy(output).replace('\r', ""));
+        if self.text.len() > MAX_TEXT_LENGTH {
+            let mut cut = self.text.len() - MAX_TEXT_LENGTH;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.text.drain(..cut);
+        }
+    }
+
+    pub(crate) fn render(&mut self, ctx: &Context, open: &mut bool) {
+        egui::Window::new("TTY Console")
+            .open(open)
+            .default_width(480.0)
+            .default_height(300.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Clear").clicked() {
+                        self.text.clear();
+                    }
+                    ui.small(format!("{} bytes", self.text.len()));
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .auto_shrink([false, false])
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        ui.add(egui::Label::new(egui::RichText::new(&self.text).monospace()).wrap(true));
+                    });
+            });
+    }
+}
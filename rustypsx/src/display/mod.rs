@@ -1,4 +1,6 @@
 mod pixels;
+mod player;
 mod gui;
 
 pub use pixels::*;
+pub use player::*;
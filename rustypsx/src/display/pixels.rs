@@ -1,20 +1,32 @@
 use crate::config;
+use crate::input::{self, PointerInput};
 use crate::display::gui::{Framework, GuiAction};
+use crate::memcard::{self, directory, formats};
+use crate::movie;
 use crate::psx;
+use crate::savestate;
 
+use std::io::Write;
+use std::path::Path;
 use std::time::{Duration, Instant};
 use winit::dpi::LogicalSize;
-use winit::event::{Event,WindowEvent};
+use winit::event::{Event, MouseButton, WindowEvent};
 use winit::event_loop::EventLoop;
 use winit::keyboard::KeyCode;
-use winit::window::WindowBuilder;
+use winit::window::{CursorGrabMode, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
 use pixels::{Error, Pixels, SurfaceTexture};
 
-const WIDTH: u32 = 640;
-const HEIGHT: u32 = 480;
+pub const WIDTH: u32 = 640;
+pub const HEIGHT: u32 = 480;
 pub const FB_SIZE: usize = (WIDTH * HEIGHT * 4) as usize;
 
+// Save state slots, load with the key and save with Shift held
+const STATE_KEYS: [KeyCode; savestate::SLOTS] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5,
+    KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10,
+];
+
 pub fn run_with_gui(ps1: psx::PS1, config: &config::CleanConfig) -> Result<(), Error> {
     let event_loop = EventLoop::new().unwrap();
     let window = {
@@ -38,6 +50,7 @@ pub fn run_with_gui(ps1: psx::PS1, config: &config::CleanConfig) -> Result<(), E
             window_size.height,
             scale_factor,
             &pixels,
+            config.state_dir.clone(),
         );
 
         (pixels, framework)
@@ -51,10 +64,21 @@ fn run_gui_loop(
     mut pixels: Pixels,
     mut framework: Framework,
     ps1: psx::PS1,
-    _config: &config::CleanConfig,
+    config: &config::CleanConfig,
 ) -> Result<(), Error> {
     let mut input = WinitInputHelper::new();
-    let mut world = World::new(ps1);
+    let mut gamepads = input::gamepad::Gamepads::open();
+    let rewind = savestate::rewind::Rewind::new(config.rewind_interval, config.rewind_budget);
+    let run_ahead = savestate::run_ahead::RunAhead::new(config.run_ahead, config.run_ahead_mode);
+    let mut world = World::new(ps1, rewind, run_ahead, movie::Settings::from_config(config));
+    world.log_state_hash = config.log_state_hash;
+    if config.play_movie
+        && let Some(path) = &config.movie
+    {
+        match world.play_movie(path, true) {
+            Ok(message) | Err(message) => println!("{}", message),
+        }
+    }
     
     let mut manually_paused = false;
     let mut user_paused = false; // Track user-initiated pause separate from debug pause
@@ -68,6 +92,8 @@ fn run_gui_loop(
     let mut n_key_processed_initial = false;
     let mut f_last_repeat_time: Option<Instant> = None;
     let mut n_last_repeat_time: Option<Instant> = None;
+    // Relative motion only reaches an emulated mouse while the host cursor is captured
+    let mut mouse_captured = false;
 
     let res = event_loop.run(|event, elwt| {
         if input.update(&event) {
@@ -140,12 +166,80 @@ fn run_gui_loop(
                 n_last_repeat_time = None;
             }
 
+            if input.key_pressed(KeyCode::F12) {
+                mouse_captured = !mouse_captured;
+                set_mouse_capture(window, mouse_captured);
+                framework.set_status(if mouse_captured {
+                    "Mouse captured, press F12 to release".to_string()
+                } else {
+                    "Mouse released".to_string()
+                });
+            }
+
+            // Rewinds for as long as it's held
+            let rewinding = input.key_held(KeyCode::Backspace) && !framework.wants_keyboard_input();
+            if rewinding != world.rewinding {
+                world.set_rewinding(rewinding);
+            }
+
+            for (slot, key) in STATE_KEYS.iter().enumerate() {
+                if input.key_pressed(*key) {
+                    if input.held_shift() {
+                        save_state(&world, &mut framework, pixels.frame(), &config.state_dir, slot);
+                    } else if load_state(&mut world, &mut framework, &config.state_dir, slot) {
+                        manually_paused = user_paused;
+                    }
+                }
+            }
+
+            let pointer = sample_pointer(&input, &pixels, &framework, mouse_captured);
+            let pad_inputs = config
+                .input
+                .pads
+                .iter()
+                .map(|bindings| {
+                    let keyboard = bindings.sample(&input);
+                    let sampled = match bindings.gamepad.and_then(|gamepad| gamepads.sample(gamepad)) {
+                        Some(gamepad) => input::gamepad::merge(keyboard, gamepad),
+                        None => keyboard,
+                    };
+                    input::PadInput { pointer, ..sampled }
+                })
+                .collect();
+            world.set_host_input(pad_inputs);
+
             if let Some(scale_factor) = input.scale_factor() {
                 framework.scale_factor(scale_factor);
             }
 
             // Update internal state and request a redraw (only if not resizing)
             world.update();
+            // Motors stop while the game isn't running
+            let running = !world.is_paused && world.error_state.is_none();
+            for (pad, bindings) in config.input.pads.iter().enumerate() {
+                if let Some(gamepad) = bindings.gamepad {
+                    let (small, large) = world.ps1.get_rumble(pad).filter(|_| running).unwrap_or((false, 0));
+                    gamepads.set_rumble(gamepad, small, large);
+                }
+            }
+            let tty_output = world.ps1.take_tty_output();
+            if !tty_output.is_empty() {
+                if config.tty {
+                    let mut stdout = std::io::stdout();
+                    let _ = stdout.write_all(&tty_output).and_then(|_| stdout.flush());
+                }
+                framework.append_tty(&tty_output);
+            }
+            let kernel_calls = world.ps1.take_kernel_calls();
+            if config.trace_kernel {
+                for call in &kernel_calls {
+                    println!("{}", call);
+                }
+            }
+            framework.append_kernel_calls(&kernel_calls);
+            if let Some(message) = world.status.take() {
+                framework.set_status(message);
+            }
             window.request_redraw();
         }
 
@@ -219,6 +313,49 @@ fn run_gui_loop(
                         framework.set_status(format!("Breakpoint removed from ${:04X}", address));
                         window.request_redraw();
                     }
+                    Some(action @ (GuiAction::DeleteSave(..)
+                        | GuiAction::CopySave(..)
+                        | GuiAction::ExportSave(..)
+                        | GuiAction::ImportSave(..)
+                        | GuiAction::ImportMemoryCard(..)
+                        | GuiAction::ExportMemoryCard(..))) => {
+                        match world.manage_memory_cards(action) {
+                            Ok(message) | Err(message) => framework.set_status(message),
+                        }
+                    }
+                    Some(GuiAction::RecordMovie(from_power_on)) => {
+                        let result = match &config.movie {
+                            Some(path) => world.record_movie(path, from_power_on, pixels.frame()),
+                            None => Err("No movie file, pass one with --movie".to_string()),
+                        };
+                        match result {
+                            Ok(message) | Err(message) => framework.set_status(message),
+                        }
+                    }
+                    Some(GuiAction::PlayMovie(read_only)) => {
+                        let result = match &config.movie {
+                            Some(path) => world.play_movie(path, read_only),
+                            None => Err("No movie file, pass one with --movie".to_string()),
+                        };
+                        match result {
+                            Ok(message) | Err(message) => framework.set_status(message),
+                        }
+                        window.request_redraw();
+                    }
+                    Some(GuiAction::StopMovie) => {
+                        let message = world.stop_movie().unwrap_or_else(|| "No movie running".to_string());
+                        framework.set_status(message);
+                    }
+                    Some(GuiAction::SaveState(slot)) => {
+                        save_state(&world, &mut framework, pixels.frame(), &config.state_dir, slot);
+                    }
+                    Some(GuiAction::LoadState(slot)) => {
+                        if load_state(&mut world, &mut framework, &config.state_dir, slot) {
+                            // Keep user pause state, as when restarting
+                            manually_paused = user_paused;
+                        }
+                        window.request_redraw();
+                    }
                     None => {}
                 }
 
@@ -268,9 +405,85 @@ fn run_gui_loop(
             _ => (),
         }
     });
+    // Don't lose a recording or a save by quitting
+    if let Some(message) = world.stop_movie() {
+        println!("{}", message);
+    }
+    if let Err(e) = world.ps1.flush_memory_cards() {
+        println!("{}", e);
+    }
     res.map_err(|e| Error::UserDefined(Box::new(e)))
 }
 
+// Shared by the slot keys and the File menu, the thumbnail comes from the frame on screen
+fn save_state(world: &World, framework: &mut Framework, frame: &[u8], directory: &Path, slot: usize) {
+    let path = savestate::slot_path(directory, world.ps1.game_id(), slot);
+    match savestate::save(&path, &world.ps1, frame) {
+        Ok(()) => framework.set_status(format!("Saved state to slot {}", slot + 1)),
+        Err(e) => framework.set_status(format!("Failed to save state: {}", e)),
+    }
+    framework.save_states_changed();
+}
+
+// Whether the state loaded, which clears any crash
+fn load_state(world: &mut World, framework: &mut Framework, directory: &Path, slot: usize) -> bool {
+    let path = savestate::slot_path(directory, world.ps1.game_id(), slot);
+    match world.load_state(&path) {
+        Ok(()) => {
+            framework.clear_error();
+            framework.set_status(format!("Loaded state from slot {}", slot + 1));
+            true
+        }
+        Err(e) => {
+            framework.set_status(format!("Failed to load state: {}", e));
+            false
+        }
+    }
+}
+
+// Frames emulated again after rewinding get the movie's input for them, when there is one
+fn replay_movie_input(movie: Option<&movie::Movie>, ps1: &mut psx::PS1) {
+    let Some(inputs) = movie.and_then(|movie| movie.recorded_input(ps1)) else {
+        return;
+    };
+    for (pad, input) in inputs.iter().enumerate() {
+        ps1.set_pad_input(pad, *input);
+    }
+}
+
+// Mouse position as a fraction of the emulated screen, ignoring clicks meant for the GUI
+fn sample_pointer(input: &WinitInputHelper, pixels: &Pixels, framework: &Framework, captured: bool) -> PointerInput {
+    if framework.wants_pointer_input() && !captured {
+        return PointerInput::default();
+    }
+    let position = input
+        .cursor()
+        .and_then(|cursor| pixels.window_pos_to_pixel(cursor).ok())
+        .map(|(x, y)| (x as f32 / WIDTH as f32, y as f32 / HEIGHT as f32));
+    PointerInput {
+        position,
+        delta: if captured { input.mouse_diff() } else { (0.0, 0.0) },
+        left: input.mouse_held(MouseButton::Left),
+        right: input.mouse_held(MouseButton::Right),
+        middle: input.mouse_held(MouseButton::Middle),
+    }
+}
+
+fn set_mouse_capture(window: &winit::window::Window, captured: bool) {
+    let result = if captured {
+        // Not every platform can lock the cursor in place, confining it is the fallback
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(err) = result {
+        println!("Failed to change mouse capture: {}", err);
+    }
+    window.set_cursor_visible(!captured);
+}
+
 struct World {
     ps1: psx::PS1,
     error_state: Option<String>,
@@ -287,10 +500,22 @@ struct World {
     last_frame_time: Instant,
     // Breakpoint status
     breakpoint_hit: bool,
+    rewind: savestate::rewind::Rewind,
+    // Whether the rewind key is held
+    rewinding: bool,
+    run_ahead: savestate::run_ahead::RunAhead,
+    movie: Option<movie::Movie>,
+    movie_settings: movie::Settings,
+    // What the host's controls are doing, which a playing movie overrides
+    host_input: Vec<input::PadInput>,
+    // Messages from inside a frame for the status bar
+    status: Option<String>,
+    // Print the state hash after every frame, to compare runs
+    log_state_hash: bool,
 }
 
 impl World {
-    fn new(ps1: psx::PS1) -> Self {
+    fn new(ps1: psx::PS1, rewind: savestate::rewind::Rewind, run_ahead: savestate::run_ahead::RunAhead, movie_settings: movie::Settings) -> Self {
         let now = Instant::now();
         Self {
             ps1,
@@ -305,6 +530,14 @@ impl World {
             last_frame_time: now,
             breakpoint_hit: false,
             frame: None,
+            rewind,
+            rewinding: false,
+            run_ahead,
+            movie: None,
+            movie_settings,
+            host_input: Vec::new(),
+            status: None,
+            log_state_hash: false,
         }
     }
 
@@ -323,6 +556,7 @@ impl World {
     fn restart(&mut self) {
         // Reset the Game Boy to its initial state
         self.ps1.reset();
+        self.forget_history();
         
         // Clear any error state
         self.error_state = None;
@@ -337,6 +571,142 @@ impl World {
         self.error_state = None;
     }
 
+    fn load_state(&mut self, path: &Path) -> Result<(), String> {
+        savestate::load(path, &mut self.ps1)?;
+        self.forget_history();
+        if let Some(movie) = self.movie.as_mut() {
+            movie.state_loaded();
+        }
+        // A crash before loading no longer applies
+        self.error_state = None;
+        self.frame = None;
+        Ok(())
+    }
+
+    // After the machine jumps somewhere else, rewinding and running ahead start over
+    fn forget_history(&mut self) {
+        self.rewind.clear();
+        self.run_ahead.clear();
+    }
+
+    // Pads get the host's input straight away, unless a movie is running, which gives it to them each frame
+    fn set_host_input(&mut self, inputs: Vec<input::PadInput>) {
+        if self.movie.is_none() {
+            for (pad, input) in inputs.iter().enumerate() {
+                self.ps1.set_pad_input(pad, *input);
+            }
+        }
+        self.host_input = inputs;
+    }
+
+    // Before each frame, feed the pads from the movie or record the host's input into it
+    fn apply_movie_input(&mut self) {
+        let Some(movie) = self.movie.as_mut() else {
+            return;
+        };
+        match movie.input(&self.ps1, &self.host_input) {
+            Some(inputs) => {
+                for (pad, input) in inputs.into_iter().enumerate() {
+                    self.ps1.set_pad_input(pad, input);
+                }
+            }
+            None => {
+                self.status = self.stop_movie();
+            }
+        }
+    }
+
+    fn record_movie(&mut self, path: &Path, from_power_on: bool, frame: &[u8]) -> Result<String, String> {
+        self.stop_movie();
+        let movie = movie::Movie::record(path, &mut self.ps1, self.movie_settings.clone(), from_power_on, frame)?;
+        if from_power_on {
+            self.forget_history();
+            self.error_state = None;
+            self.frame = None;
+        }
+        self.movie = Some(movie);
+        Ok(format!("Recording movie to {}, memory cards stay in memory until restarting the emulator", path.display()))
+    }
+
+    fn play_movie(&mut self, path: &Path, read_only: bool) -> Result<String, String> {
+        self.stop_movie();
+        let mut movie = movie::Movie::open(path)?;
+        movie.start_playback(&mut self.ps1, &self.movie_settings, read_only)?;
+        self.forget_history();
+        self.error_state = None;
+        self.frame = None;
+        let message = format!(
+            "Playing movie {}, {} frames, {} rerecords, memory cards stay in memory until restarting the emulator",
+            path.display(),
+            movie.frame_count(),
+            movie.header.rerecords
+        );
+        self.movie = Some(movie);
+        Ok(message)
+    }
+
+    // What happened to the movie, saved if it was recording
+    fn stop_movie(&mut self) -> Option<String> {
+        let movie = self.movie.take()?;
+        if movie.mode() != movie::Mode::Recording {
+            return Some(format!("Movie stopped, {}", movie.describe(&self.ps1)));
+        }
+        Some(match movie.save() {
+            Ok(()) => format!("Movie saved, {} frames and {} rerecords", movie.frame_count(), movie.header.rerecords),
+            Err(e) => format!("Failed to save movie: {}", e),
+        })
+    }
+
+    fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+        if rewinding || !self.rewind.is_rewinding() {
+            return;
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.rewind.finish(&mut self.ps1, |ps1| replay_movie_input(self.movie.as_ref(), ps1));
+        }));
+        if result.is_err() {
+            self.error_state = Some("Emulator panic while catching up after rewinding".to_string());
+        }
+        // Going back branches a movie just like loading a state
+        if let Some(movie) = self.movie.as_mut() {
+            movie.state_loaded();
+        }
+    }
+
+    fn rewind_frame(&mut self) {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.rewind.step_back(&mut self.ps1, |ps1| replay_movie_input(self.movie.as_ref(), ps1))
+        }));
+        match result {
+            Ok(Some(frame)) => self.frame = Some(frame),
+            // Out of history, the oldest frame stays on screen
+            Ok(None) => {}
+            Err(_) => {
+                self.error_state = Some("Emulator panic while rewinding".to_string());
+                self.frame = None;
+            }
+        }
+    }
+
+    // With run-ahead on, the frame just run is swapped for one from the future
+    fn show_frame(&mut self, frame_data: Box<[u8]>) {
+        if !self.run_ahead.enabled() {
+            self.frame = Some(frame_data);
+            return;
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.run_ahead.future_frame(&self.ps1)
+        }));
+        match result {
+            Ok(frame) => self.frame = Some(frame),
+            Err(_) => {
+                self.error_state = Some("Emulator panic while running ahead".to_string());
+                self.frame = None;
+            }
+        }
+    }
+
     fn draw(&mut self, frame: &mut [u8]) {
         if let Some(rgba_frame) = &self.frame {
             frame.copy_from_slice(rgba_frame);
@@ -344,13 +714,29 @@ impl World {
         }
     }
 
+    // After each frame run forwards
+    fn finish_frame(&mut self) {
+        self.print_state_hash();
+        if let Err(e) = self.ps1.end_frame_memory_cards() {
+            self.status = Some(e);
+        }
+    }
+
+    fn print_state_hash(&self) {
+        if self.log_state_hash {
+            println!("frame {} {:08X}", self.ps1.frames(), savestate::state_hash(&self.ps1));
+        }
+    }
+
     fn run_until_frame(&mut self) -> Result<Box<[u8]>, String> {
+        self.apply_movie_input();
         let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             self.ps1.run_until_frame(true)
         }));
 
         match result {
             Ok((frame_data, _breakpoint_hit)) => {
+                self.finish_frame();
                 Ok(frame_data)
             },
             Err(panic_info) => {
@@ -369,6 +755,7 @@ impl World {
     }
 
     fn run_until_frame_with_breakpoints(&mut self) -> (Result<Box<[u8]>, String>, bool) {
+        self.apply_movie_input();
         let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
             // Collect audio when running frames
             self.ps1.run_until_frame(true)
@@ -376,6 +763,7 @@ impl World {
 
         match result {
             Ok((frame_data, breakpoint_hit)) => {
+                self.finish_frame();
                 (Ok(frame_data), breakpoint_hit)
             },
             Err(panic_info) => {
@@ -549,12 +937,18 @@ impl World {
         
         self.last_frame_time = Instant::now();
 
+        if self.rewinding {
+            self.rewind_frame();
+            return;
+        }
+
         // Use breakpoint-aware version if we have any breakpoints set
         if self.ps1.get_breakpoints().is_empty() {
             // No breakpoints - use regular version for better performance
             match self.run_until_frame() {
                 Ok(frame_data) => {
-                    self.frame = Some(frame_data);
+                    self.rewind.record(&self.ps1);
+                    self.show_frame(frame_data);
                     self.update_performance_metrics();
                 }
                 Err(err) => {
@@ -568,7 +962,8 @@ impl World {
             let (frame_result, breakpoint_hit) = self.run_until_frame_with_breakpoints();
             match frame_result {
                 Ok(frame_data) => {
-                    self.frame = Some(frame_data);
+                    self.rewind.record(&self.ps1);
+                    self.show_frame(frame_data);
                     self.update_performance_metrics();
                     
                     // If a breakpoint was hit, pause emulation
@@ -620,13 +1015,16 @@ impl World {
         if now.duration_since(self.last_title_update).as_millis() >= 500 {
             let fps = self.get_fps();
             
-            let title = if self.error_state.is_some() {
+            let mut title = if self.error_state.is_some() {
                 format!("RustyPSX - ERROR | {:.1} FPS", fps)
             } else if is_paused {
                 format!("RustyPSX - PAUSED | {:.1} FPS", fps)
             } else {
                 format!("RustyPSX | {:.1} FPS", fps)
             };
+            if let Some(movie) = &self.movie {
+                title = format!("{} | {}", title, movie.describe(&self.ps1));
+            }
             
             window.set_title(&title);
             self.last_title_update = now;
@@ -642,6 +1040,59 @@ impl World {
         self.ps1.remove_breakpoint(address);
     }
 
+    fn memory_card(&self, slot: usize) -> Result<&memcard::MemoryCard, String> {
+        let name = self.ps1.memory_card_slot_name(slot);
+        self.ps1.memory_card(slot).ok_or_else(|| format!("No memory card in slot {}", name))
+    }
+
+    fn memory_card_mut(&mut self, slot: usize) -> Result<&mut memcard::MemoryCard, String> {
+        let name = self.ps1.memory_card_slot_name(slot);
+        self.ps1.memory_card_mut(slot).ok_or_else(|| format!("No memory card in slot {}", name))
+    }
+
+    // Memory card manager actions, changes are written straight back to the card image
+    fn manage_memory_cards(&mut self, action: GuiAction) -> Result<String, String> {
+        match action {
+            GuiAction::DeleteSave(slot, block) => {
+                let card = self.memory_card_mut(slot)?;
+                directory::delete_save(card.data_mut(), block)?;
+                card.flush()?;
+                Ok(format!("Deleted save from slot {}", self.ps1.memory_card_slot_name(slot)))
+            }
+            GuiAction::CopySave(from, block, to) => {
+                let save = directory::export_save(self.memory_card(from)?.data(), block)?;
+                let card = self.memory_card_mut(to)?;
+                directory::import_save(card.data_mut(), &save)?;
+                card.flush()?;
+                Ok(format!("Copied {} to slot {}", save.filename(), self.ps1.memory_card_slot_name(to)))
+            }
+            GuiAction::ExportSave(slot, block, path) => {
+                let save = directory::export_save(self.memory_card(slot)?.data(), block)?;
+                formats::write_save(&path, &save)?;
+                Ok(format!("Exported {} to {}", save.filename(), path.display()))
+            }
+            GuiAction::ImportSave(slot, path) => {
+                let save = formats::read_save(&path)?;
+                let card = self.memory_card_mut(slot)?;
+                directory::import_save(card.data_mut(), &save)?;
+                card.flush()?;
+                Ok(format!("Imported {} into slot {}", save.filename(), self.ps1.memory_card_slot_name(slot)))
+            }
+            GuiAction::ImportMemoryCard(slot, path) => {
+                let data = formats::read_card(&path)?;
+                let card = self.memory_card_mut(slot)?;
+                card.replace_data(data);
+                card.flush()?;
+                Ok(format!("Loaded {} into slot {}", path.display(), self.ps1.memory_card_slot_name(slot)))
+            }
+            GuiAction::ExportMemoryCard(slot, path) => {
+                formats::write_card(&path, self.memory_card(slot)?.data())?;
+                Ok(format!("Exported slot {} to {}", self.ps1.memory_card_slot_name(slot), path.display()))
+            }
+            _ => Err("Not a memory card action".to_string()),
+        }
+    }
+
     fn check_and_clear_breakpoint_hit(&mut self) -> bool {
         let hit = self.breakpoint_hit;
         self.breakpoint_hit = false;
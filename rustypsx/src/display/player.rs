@@ -0,0 +1,108 @@
+use crate::player::{self, StrPlayer};
+
+use std::time::{Duration, Instant};
+use winit::dpi::LogicalSize;
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::KeyCode;
+use winit::window::WindowBuilder;
+use winit_input_helper::WinitInputHelper;
+use pixels::{Error, Pixels, SurfaceTexture};
+
+// Used until the first frame gives the movie's size
+const DEFAULT_WIDTH: u32 = 320;
+const DEFAULT_HEIGHT: u32 = 240;
+
+/// Show a movie's frames in a window at the rate they were streamed, Space pauses
+pub fn run_player(mut str_player: StrPlayer, scale: u8) -> Result<(), Error> {
+    let event_loop = EventLoop::new().unwrap();
+    let window = {
+        let size = LogicalSize::new((DEFAULT_WIDTH * scale as u32) as f64, (DEFAULT_HEIGHT * scale as u32) as f64);
+        WindowBuilder::new()
+            .with_title("RustyPSX - STR player")
+            .with_inner_size(size)
+            .build(&event_loop)
+            .unwrap()
+    };
+    let mut pixels = {
+        let window_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
+        Pixels::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, surface_texture)?
+    };
+
+    let mut input = WinitInputHelper::new();
+    let mut paused = false;
+    let mut finished = false;
+    let mut buffer_size = (DEFAULT_WIDTH, DEFAULT_HEIGHT);
+    // Playback clock, as a sector count from the first sector
+    let mut clock_start = Instant::now();
+    let mut paused_at_sector = 0.0;
+    let mut next_frame = str_player.next_frame();
+
+    let res = event_loop.run(|event, elwt| {
+        if input.update(&event) {
+            if input.key_pressed(KeyCode::Escape) || input.close_requested() {
+                elwt.exit();
+                return;
+            }
+            if input.key_pressed(KeyCode::Space) {
+                paused = !paused;
+                if paused {
+                    paused_at_sector = clock_start.elapsed().as_secs_f64() * player::SECTORS_PER_SECOND as f64;
+                } else {
+                    clock_start = Instant::now() - Duration::from_secs_f64(paused_at_sector / player::SECTORS_PER_SECOND as f64);
+                }
+            }
+        }
+
+        match event {
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                    println!("Failed to resize surface during window event: {}", err);
+                    elwt.exit();
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
+                if let Err(err) = pixels.render() {
+                    println!("Render error: {}", err);
+                }
+            }
+            Event::AboutToWait if !paused && !finished => {
+                let position = clock_start.elapsed().as_secs_f64() * player::SECTORS_PER_SECOND as f64;
+                while let Some(frame) = &next_frame {
+                    let due = match frame {
+                        Ok(frame) => (frame.sector - str_player.first_sector()) as f64,
+                        Err(_) => 0.0,
+                    };
+                    if due > position {
+                        break;
+                    }
+                    match next_frame.take() {
+                        Some(Ok(frame)) => {
+                            if buffer_size != (frame.width, frame.height) {
+                                buffer_size = (frame.width, frame.height);
+                                if let Err(err) = pixels.resize_buffer(frame.width, frame.height) {
+                                    println!("Failed to resize frame buffer: {}", err);
+                                }
+                            }
+                            pixels.frame_mut().copy_from_slice(&frame.rgba);
+                            window.set_title(&format!("RustyPSX - STR player | frame {}", frame.number));
+                            window.request_redraw();
+                        }
+                        Some(Err(err)) => println!("{}", err),
+                        None => {}
+                    }
+                    next_frame = str_player.next_frame();
+                }
+                if next_frame.is_none() {
+                    finished = true;
+                    window.set_title("RustyPSX - STR player | finished");
+                }
+                elwt.set_control_flow(ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(5)));
+            }
+            Event::AboutToWait => elwt.set_control_flow(ControlFlow::Wait),
+            _ => (),
+        }
+    });
+    res.map_err(|e| Error::UserDefined(Box::new(e)))
+}
@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+
+pub const DMA_START: u32 = 0x1F801080;
+pub const DMA_END: u32 = 0x1F8010FF;
+
+const DPCR: u32 = 0x70;
+const DICR: u32 = 0x74;
+
+pub const CHANNEL_MDEC_IN: usize = 0;
+pub const CHANNEL_MDEC_OUT: usize = 1;
+const CHANNEL_COUNT: usize = 7;
+
+const CHCR_BACKWARDS: u32 = 1 << 1;
+const CHCR_START: u32 = 1 << 24;
+const CHCR_TRIGGER: u32 = 1 << 28;
+
+const DICR_FORCE_IRQ: u32 = 1 << 15;
+const DICR_MASTER_ENABLE: u32 = 1 << 23;
+const DICR_MASTER_FLAG: u32 = 1 << 31;
+// Bits 0-5 and 15-23 are plain read/write, 24-30 are write 1 to clear, 31 is read-only
+const DICR_WRITABLE: u32 = 0x00FF803F;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SyncMode {
+    // Everything at once, started by the trigger bit
+    Manual,
+    // BCR block count blocks of BCR block size words, paced by the device's DMA request
+    Request,
+    LinkedList,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Channel {
+    base_address: u32,
+    block_control: u32,
+    channel_control: u32,
+}
+
+impl Channel {
+    pub fn is_active(&self) -> bool {
+        let started = self.channel_control & CHCR_START != 0;
+        match self.sync_mode() {
+            SyncMode::Manual => started && self.channel_control & CHCR_TRIGGER != 0,
+            _ => started,
+        }
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        match (self.channel_control >> 9) & 3 {
+            0 => SyncMode::Manual,
+            1 => SyncMode::Request,
+            _ => SyncMode::LinkedList,
+        }
+    }
+
+    pub fn address(&self) -> u32 {
+        self.base_address & 0x1FFFFC
+    }
+
+    /// Words moved per block, manual mode moves its whole transfer as one block
+    pub fn block_size(&self) -> u32 {
+        match self.block_control & 0xFFFF {
+            0 => 0x10000,
+            size => size,
+        }
+    }
+
+    fn remaining_blocks(&self) -> u32 {
+        match self.sync_mode() {
+            SyncMode::Manual => 1,
+            _ => (self.block_control >> 16).max(1),
+        }
+    }
+
+    /// Address of the `index`th word of the current block
+    pub fn word_address(&self, index: u32) -> u32 {
+        let offset = index.wrapping_mul(4);
+        let address = if self.channel_control & CHCR_BACKWARDS != 0 {
+            self.address().wrapping_sub(offset)
+        } else {
+            self.address().wrapping_add(offset)
+        };
+        address & 0x1FFFFC
+    }
+
+    /// Advance past a finished block, returns true once the whole transfer is done
+    pub fn complete_block(&mut self) -> bool {
+        let size = self.block_size();
+        let next = self.word_address(size);
+        self.base_address = next;
+        let remaining = self.remaining_blocks() - 1;
+        if self.sync_mode() == SyncMode::Request {
+            self.block_control = (self.block_control & 0xFFFF) | (remaining << 16);
+        }
+        if remaining == 0 || self.sync_mode() == SyncMode::Manual {
+            self.channel_control &= !(CHCR_START | CHCR_TRIGGER);
+            return true;
+        }
+        false
+    }
+}
+
+/// DMA controller registers; the transfers themselves are run by the bus, which owns RAM and the devices
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Dma {
+    pub channels: [Channel; CHANNEL_COUNT],
+    control: u32,
+    interrupt: u32,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            channels: Default::default(),
+            control: 0x07654321,
+            interrupt: 0,
+        }
+    }
+
+    pub fn channel_enabled(&self, channel: usize) -> bool {
+        self.control & (1 << (channel * 4 + 3)) != 0
+    }
+
+    /// Whether `channel` has a transfer to run
+    pub fn is_pending(&self, channel: usize) -> bool {
+        self.channel_enabled(channel) && self.channels[channel].is_active()
+    }
+
+    fn master_flag(&self) -> bool {
+        let enabled = (self.interrupt >> 16) & 0x7F;
+        let flags = (self.interrupt >> 24) & 0x7F;
+        self.interrupt & DICR_FORCE_IRQ != 0
+            || (self.interrupt & DICR_MASTER_ENABLE != 0 && enabled & flags != 0)
+    }
+
+    /// Flag a finished transfer, returns true when that raises IRQ3
+    pub fn finish(&mut self, channel: usize) -> bool {
+        let was_set = self.master_flag();
+        if self.interrupt & (1 << (16 + channel)) != 0 {
+            self.interrupt |= 1 << (24 + channel);
+        }
+        !was_set && self.master_flag()
+    }
+
+    fn register(&self, offset: u32) -> u32 {
+        match offset {
+            DPCR => self.control,
+            DICR => {
+                let flag = if self.master_flag() { DICR_MASTER_FLAG } else { 0 };
+                self.interrupt & !DICR_MASTER_FLAG | flag
+            }
+            0x00..=0x6F => {
+                let channel = &self.channels[(offset >> 4) as usize];
+                match offset & 0xF {
+                    0x0 => channel.base_address,
+                    0x4 => channel.block_control,
+                    0x8 => channel.channel_control,
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn read(&self, addr: u32) -> u8 {
+        let offset = addr - DMA_START;
+        (self.register(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Returns true when IRQ3 should be raised
+    pub fn write(&mut self, addr: u32, value: u8) -> bool {
+        let offset = addr - DMA_START;
+        let register = offset & !3;
+        let shift = (offset & 3) * 8;
+        let mask = 0xFF << shift;
+        let value = (value as u32) << shift;
+        match register {
+            DPCR => self.control = self.control & !mask | value,
+            DICR => {
+                let was_set = self.master_flag();
+                let acknowledged = value & mask & 0x7F00_0000;
+                self.interrupt = (self.interrupt & !(mask & DICR_WRITABLE) | value & DICR_WRITABLE) & !acknowledged;
+                return !was_set && self.master_flag();
+            }
+            0x00..=0x6F => {
+                let channel = &mut self.channels[(register >> 4) as usize];
+                match register & 0xF {
+                    0x0 => channel.base_address = (channel.base_address & !mask | value) & 0xFFFFFF,
+                    0x4 => channel.block_control = channel.block_control & !mask | value,
+                    0x8 => channel.channel_control = channel.channel_control & !mask | value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// The DUART sits 0x20 bytes into Expansion 2, each of its registers is a byte wide
+pub const DUART_OFFSET: u32 = 0x20;
+pub const DUART_SIZE: u32 = 0x10;
+
+// Channel A's registers, channel B has the same ones 8 bytes on
+const MR_A: u32 = 0x0;
+const SR_CSR_A: u32 = 0x1;
+const CR_A: u32 = 0x2;
+const RHR_THR_A: u32 = 0x3;
+const CHANNEL_B: u32 = 0x8;
+const MR_B: u32 = MR_A + CHANNEL_B;
+const SR_CSR_B: u32 = SR_CSR_A + CHANNEL_B;
+const CR_B: u32 = CR_A + CHANNEL_B;
+const RHR_THR_B: u32 = RHR_THR_A + CHANNEL_B;
+const IPCR_ACR: u32 = 0x4;
+const ISR_IMR: u32 = 0x5;
+const CTU: u32 = 0x6;
+const CTL: u32 = 0x7;
+const IVR: u32 = 0xC;
+const IP_OPCR: u32 = 0xD;
+const SET_OUTPUT: u32 = 0xE;
+const RESET_OUTPUT: u32 = 0xF;
+
+const SR_RX_READY: u8 = 1 << 0;
+const SR_TX_READY: u8 = 1 << 2;
+const SR_TX_EMPTY: u8 = 1 << 3;
+const ISR_TX_READY_A: u8 = 1 << 0;
+const ISR_TX_READY_B: u8 = 1 << 4;
+
+const CR_RX_ENABLE: u8 = 1 << 0;
+const CR_RX_DISABLE: u8 = 1 << 1;
+const CR_TX_ENABLE: u8 = 1 << 2;
+const CR_TX_DISABLE: u8 = 1 << 3;
+const CR_COMMAND_RESET_MR_POINTER: u8 = 1;
+const CR_COMMAND_RESET_RX: u8 = 2;
+
+// Bytes sent kept until the TTY takes them, the oldest are dropped past this
+const MAX_TRANSMITTED: usize = 0x10000;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Channel {
+    mode: [u8; 2],
+    // MR1 is written first, then every access goes to MR2 until a pointer reset
+    mode_pointer: usize,
+    clock_select: u8,
+    rx_enabled: bool,
+    tx_enabled: bool,
+    rx: VecDeque<u8>,
+    // Sent but not yet picked up by the TTY, which is output rather than machine state
+    #[serde(skip)]
+    tx: VecDeque<u8>,
+}
+
+impl Channel {
+    fn status(&self) -> u8 {
+        // Bytes go out instantly, so the transmitter is always ready
+        let mut status = SR_TX_READY | SR_TX_EMPTY;
+        if !self.rx.is_empty() {
+            status |= SR_RX_READY;
+        }
+        status
+    }
+
+    fn command(&mut self, value: u8) {
+        if value & CR_RX_ENABLE != 0 {
+            self.rx_enabled = true;
+        }
+        if value & CR_RX_DISABLE != 0 {
+            self.rx_enabled = false;
+        }
+        if value & CR_TX_ENABLE != 0 {
+            self.tx_enabled = true;
+        }
+        if value & CR_TX_DISABLE != 0 {
+            self.tx_enabled = false;
+        }
+        match (value >> 4) & 7 {
+            CR_COMMAND_RESET_MR_POINTER => self.mode_pointer = 0,
+            CR_COMMAND_RESET_RX => self.rx.clear(),
+            _ => {}
+        }
+    }
+}
+
+/// SCN2681 dual UART fitted to development boards, where SDKs send their debug text
+///
+/// Only the parts software needs to print are emulated: mode, status and command registers and
+/// the holding registers. There is no baud timing and the interrupt output is not connected.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Duart {
+    channels: [Channel; 2],
+    aux_control: u8,
+    interrupt_mask: u8,
+    counter: u16,
+    interrupt_vector: u8,
+    output_config: u8,
+    output_port: u8,
+}
+
+impl Duart {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes sent on channel A since the last call, which is the TTY
+    pub fn take_transmitted(&mut self) -> VecDeque<u8> {
+        std::mem::take(&mut self.channels[0].tx)
+    }
+
+    /// Read at an offset from the first DUART register
+    pub fn read(&mut self, offset: u32) -> u8 {
+        let channel = &mut self.channels[(offset / CHANNEL_B) as usize];
+        match offset {
+            MR_A | MR_B => {
+                let value = channel.mode[channel.mode_pointer];
+                channel.mode_pointer = 1;
+                value
+            }
+            SR_CSR_A | SR_CSR_B => channel.status(),
+            RHR_THR_A | RHR_THR_B => channel.rx.pop_front().unwrap_or(0),
+            ISR_IMR => ISR_TX_READY_A | ISR_TX_READY_B,
+            CTU => (self.counter >> 8) as u8,
+            CTL => self.counter as u8,
+            IVR => self.interrupt_vector,
+            // Nothing drives the input pins
+            IP_OPCR => 0xFF,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, value: u8) {
+        let channel = &mut self.channels[(offset / CHANNEL_B) as usize];
+        match offset {
+            MR_A | MR_B => {
+                channel.mode[channel.mode_pointer] = value;
+                channel.mode_pointer = 1;
+            }
+            SR_CSR_A | SR_CSR_B => channel.clock_select = value,
+            CR_A | CR_B => channel.command(value),
+            // Everything written is kept, the TTY shouldn't lose text to an unset enable bit
+            RHR_THR_A => {
+                if channel.tx.len() == MAX_TRANSMITTED {
+                    channel.tx.pop_front();
+                }
+                channel.tx.push_back(value);
+            }
+            // Nothing is wired to channel B, so its bytes go nowhere
+            RHR_THR_B => {}
+            IPCR_ACR => self.aux_control = value,
+            ISR_IMR => self.interrupt_mask = value,
+            CTU => self.counter = self.counter & 0x00FF | (value as u16) << 8,
+            CTL => self.counter = self.counter & 0xFF00 | value as u16,
+            IVR => self.interrupt_vector = value,
+            IP_OPCR => self.output_config = value,
+            SET_OUTPUT => self.output_port |= value,
+            RESET_OUTPUT => self.output_port &= !value,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+
+// Cheat cartridges decode their ROM in the first 128 KB and their PC link registers above it
+pub const EXPANSION_1_ROM_SIZE: usize = 0x20000;
+
+const DATA_IN: u32 = 0x20000;
+const SWITCH: u32 = 0x20010;
+const HANDSHAKE: u32 = 0x20018;
+const DATA_OUT: u32 = 0x40000;
+
+const EMPTY_BYTE: u8 = 0xFF;
+
+// The BIOS jumps to the cartridge at these offsets when the ID text follows them
+const PRE_BOOT_ENTRY: usize = 0x80;
+const MID_BOOT_ENTRY: usize = 0x00;
+const LICENSE_TEXT: &[u8] = b"Licensed by Sony Computer Entertainment Inc.";
+
+/// Cartridge on the parallel port, in the Action Replay and Caetla mould
+///
+/// A ROM image the BIOS can hook into at boot, the switch on top of the cartridge, and a byte-wide
+/// link to a PC for debugging tools.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Expansion1 {
+    // Loaded from a file like the BIOS, so not part of the machine's state
+    #[serde(skip)]
+    rom: Vec<u8>,
+    pub switch: bool,
+    // Bytes from the PC waiting to be read
+    data_in: VecDeque<u8>,
+    // Bytes written for the PC
+    data_out: VecDeque<u8>,
+}
+
+impl Expansion1 {
+    /// An empty port, where the BIOS finds nothing to boot
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let rom = std::fs::read(path).map_err(|e| format!("Failed to read expansion ROM {}: {}", path.display(), e))?;
+        if rom.len() > EXPANSION_1_ROM_SIZE {
+            return Err(format!(
+                "Expansion ROM {} is {} bytes, at most {} fit",
+                path.display(),
+                rom.len(),
+                EXPANSION_1_ROM_SIZE
+            ));
+        }
+        Ok(Expansion1 { rom, ..Self::new() })
+    }
+
+    /// Keep the ROM of the cartridge this one's state replaces
+    pub fn take_rom(&mut self, replaced: &mut Expansion1) {
+        self.rom = std::mem::take(&mut replaced.rom);
+    }
+
+    /// Take on `other`'s state, keeping this cartridge's ROM
+    pub fn copy_state_from(&mut self, other: &Expansion1) {
+        let Expansion1 { rom: _, switch, data_in, data_out } = other;
+        self.switch = *switch;
+        self.data_in.clone_from(data_in);
+        self.data_out.clone_from(data_out);
+    }
+
+    fn has_hook(&self, entry: usize) -> bool {
+        self.rom.get(entry + 4..entry + 4 + LICENSE_TEXT.len()) == Some(LICENSE_TEXT)
+    }
+
+    /// Whether the BIOS will call into the ROM before it sets itself up
+    pub fn has_pre_boot_hook(&self) -> bool {
+        self.has_hook(PRE_BOOT_ENTRY)
+    }
+
+    /// Whether the BIOS will call into the ROM once the kernel is up, before the shell
+    pub fn has_mid_boot_hook(&self) -> bool {
+        self.has_hook(MID_BOOT_ENTRY)
+    }
+
+    /// A byte arriving from the PC
+    pub fn receive(&mut self, value: u8) {
+        self.data_in.push_back(value);
+    }
+
+    /// Bytes written for the PC since the last call
+    pub fn take_transmitted(&mut self) -> VecDeque<u8> {
+        std::mem::take(&mut self.data_out)
+    }
+
+    /// Read at an offset from the start of the region
+    pub fn read(&mut self, offset: u32) -> u8 {
+        match offset {
+            DATA_IN => self.data_in.pop_front().unwrap_or(0),
+            SWITCH => self.switch as u8,
+            HANDSHAKE => !self.data_in.is_empty() as u8,
+            _ => self.rom.get(offset as usize).copied().unwrap_or(EMPTY_BYTE),
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, value: u8) {
+        if offset == DATA_OUT {
+            self.data_out.push_back(value);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod duart;
+pub mod exp1;
+
+pub use duart::*;
+pub use exp1::*;
@@ -0,0 +1,118 @@
+use crate::input::{Button, PadInput, STICK_CENTER};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Keys that push an analog stick to its limits
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StickBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+}
+
+impl StickBindings {
+    fn sample(&self, input: &WinitInputHelper) -> (u8, u8) {
+        let axis = |negative: KeyCode, positive: KeyCode| {
+            match (input.key_held(negative), input.key_held(positive)) {
+                (true, false) => 0x00,
+                (false, true) => 0xFF,
+                _ => STICK_CENTER,
+            }
+        };
+        (axis(self.left, self.right), axis(self.up, self.down))
+    }
+}
+
+/// Keyboard bindings for a single pad
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PadBindings {
+    pub buttons: BTreeMap<Button, KeyCode>,
+    pub left_stick: Option<StickBindings>,
+    pub right_stick: Option<StickBindings>,
+    /// DualShock Analog button, toggles between digital and analog mode
+    pub analog: Option<KeyCode>,
+    /// Host gamepad that also drives this pad, numbered in the order they were found
+    pub gamepad: Option<usize>,
+}
+
+impl PadBindings {
+    pub fn keyboard_default() -> Self {
+        PadBindings {
+            buttons: BTreeMap::from([
+                (Button::Up, KeyCode::ArrowUp),
+                (Button::Down, KeyCode::ArrowDown),
+                (Button::Left, KeyCode::ArrowLeft),
+                (Button::Right, KeyCode::ArrowRight),
+                (Button::Cross, KeyCode::KeyX),
+                (Button::Circle, KeyCode::KeyC),
+                (Button::Square, KeyCode::KeyZ),
+                (Button::Triangle, KeyCode::KeyS),
+                (Button::L1, KeyCode::KeyQ),
+                (Button::R1, KeyCode::KeyE),
+                (Button::L2, KeyCode::Digit1),
+                (Button::R2, KeyCode::Digit3),
+                (Button::L3, KeyCode::KeyU),
+                (Button::R3, KeyCode::KeyO),
+                (Button::Start, KeyCode::Enter),
+                (Button::Select, KeyCode::ShiftRight),
+            ]),
+            left_stick: Some(StickBindings {
+                up: KeyCode::KeyI,
+                down: KeyCode::KeyK,
+                left: KeyCode::KeyJ,
+                right: KeyCode::KeyL,
+            }),
+            right_stick: Some(StickBindings {
+                up: KeyCode::Numpad8,
+                down: KeyCode::Numpad5,
+                left: KeyCode::Numpad4,
+                right: KeyCode::Numpad6,
+            }),
+            analog: Some(KeyCode::KeyM),
+            gamepad: Some(0),
+        }
+    }
+
+    pub fn sample(&self, input: &WinitInputHelper) -> PadInput {
+        let mut pad = PadInput {
+            buttons: self
+                .buttons
+                .iter()
+                .filter(|(_, key)| input.key_held(**key))
+                .fold(0, |mask, (button, _)| mask | button.mask()),
+            ..PadInput::default()
+        };
+        if let Some(stick) = &self.left_stick {
+            pad.left_stick = stick.sample(input);
+        }
+        if let Some(stick) = &self.right_stick {
+            pad.right_stick = stick.sample(input);
+        }
+        if let Some(key) = self.analog {
+            pad.analog_button = input.key_held(key);
+        }
+        pad
+    }
+}
+
+pub const MAX_PADS: usize = 8;
+
+/// Keyboard and gamepad bindings for every pad, loaded from the `--keymap` JSON file
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Indexed by pad number: port 1 then port 2, each counting four pads if it has a multitap
+    pub pads: Vec<PadBindings>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        let mut pads: Vec<_> = (0..MAX_PADS).map(|pad| PadBindings { gamepad: Some(pad), ..PadBindings::default() }).collect();
+        pads[0] = PadBindings::keyboard_default();
+        InputConfig { pads }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::input::digital_pad::DigitalPad;
+use crate::input::dual_shock::DualShock;
+use crate::input::lightgun::{BeamPosition, DisplayArea, GunCon, Justifier};
+use crate::input::mouse::Mouse;
+use crate::input::multitap::{MULTITAP_SLOTS, Multitap};
+use serde::{Deserialize, Serialize};
+
+/// Pad buttons, numbered by their bit in the 16-bit button report
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Button {
+    Select = 0,
+    L3 = 1,
+    R3 = 2,
+    Start = 3,
+    Up = 4,
+    Right = 5,
+    Down = 6,
+    Left = 7,
+    L2 = 8,
+    R2 = 9,
+    L1 = 10,
+    R1 = 11,
+    Triangle = 12,
+    Circle = 13,
+    Cross = 14,
+    Square = 15,
+}
+
+impl Button {
+    pub fn mask(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+pub const STICK_CENTER: u8 = 0x80;
+
+/// Host mouse state, with the position as a fraction of the emulated screen
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+pub struct PointerInput {
+    /// None while the cursor is off the screen
+    pub position: Option<(f32, f32)>,
+    /// Raw movement since the last sample, only while the mouse is captured
+    pub delta: (f32, f32),
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// Host-side state of a pad, sampled on every GUI update
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct PadInput {
+    /// Held buttons, one bit per `Button`, set when pressed
+    pub buttons: u16,
+    /// X/Y of each stick, 0x00 is left/up and 0xFF is right/down
+    pub left_stick: (u8, u8),
+    pub right_stick: (u8, u8),
+    /// The Analog mode button in the middle of a DualShock
+    pub analog_button: bool,
+    /// Host mouse, for lightguns and the PlayStation Mouse
+    pub pointer: PointerInput,
+}
+
+impl Default for PadInput {
+    fn default() -> Self {
+        PadInput {
+            buttons: 0,
+            left_stick: (STICK_CENTER, STICK_CENTER),
+            right_stick: (STICK_CENTER, STICK_CENTER),
+            analog_button: false,
+            pointer: PointerInput::default(),
+        }
+    }
+}
+
+/// Peripheral type that can be plugged into a controller port
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControllerType {
+    None,
+    Digital,
+    Analog,
+    #[value(name = "guncon")]
+    GunCon,
+    Justifier,
+    Mouse,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Controller {
+    Disconnected,
+    Digital(DigitalPad),
+    Analog(DualShock),
+    GunCon(GunCon),
+    Justifier(Justifier),
+    Mouse(Mouse),
+    Multitap(Box<Multitap>),
+}
+
+impl Controller {
+    pub fn new(controller_type: ControllerType) -> Self {
+        match controller_type {
+            ControllerType::None => Controller::Disconnected,
+            ControllerType::Digital => Controller::Digital(DigitalPad::new()),
+            ControllerType::Analog => Controller::Analog(DualShock::new()),
+            ControllerType::GunCon => Controller::GunCon(GunCon::new()),
+            ControllerType::Justifier => Controller::Justifier(Justifier::new()),
+            ControllerType::Mouse => Controller::Mouse(Mouse::new()),
+        }
+    }
+
+    /// A multitap with a controller of the given type in slots A to D
+    pub fn multitap(controller_types: [ControllerType; MULTITAP_SLOTS]) -> Self {
+        let pads = controller_types.map(Controller::new);
+        Controller::Multitap(Box::new(Multitap::new(pads)))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !matches!(self, Controller::Disconnected)
+    }
+
+    /// Exchange one byte with the controller, returning the reply and whether it pulls /ACK low
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        match self {
+            Controller::Disconnected => (0xFF, false),
+            Controller::Digital(pad) => pad.transfer(value),
+            Controller::Analog(pad) => pad.transfer(value),
+            Controller::GunCon(gun) => gun.transfer(value),
+            Controller::Justifier(gun) => gun.transfer(value),
+            Controller::Mouse(mouse) => mouse.transfer(value),
+            Controller::Multitap(tap) => tap.transfer(value),
+        }
+    }
+
+    /// Called when /JOYn goes high, aborting any command in progress
+    pub fn deselect(&mut self) {
+        match self {
+            Controller::Disconnected => {}
+            Controller::Digital(pad) => pad.deselect(),
+            Controller::Analog(pad) => pad.deselect(),
+            Controller::GunCon(gun) => gun.deselect(),
+            Controller::Justifier(gun) => gun.deselect(),
+            Controller::Mouse(mouse) => mouse.deselect(),
+            Controller::Multitap(tap) => tap.deselect(),
+        }
+    }
+
+    /// `display` maps the pointer onto the screen for lightguns
+    pub fn set_input(&mut self, input: PadInput, display: &DisplayArea) {
+        match self {
+            // The pads behind a multitap are addressed individually
+            Controller::Disconnected | Controller::Multitap(_) => {}
+            Controller::Digital(pad) => pad.buttons = input.buttons,
+            Controller::Analog(pad) => pad.set_input(input),
+            Controller::GunCon(gun) => gun.set_input(&input, display),
+            Controller::Justifier(gun) => gun.set_input(&input, display),
+            Controller::Mouse(mouse) => mouse.set_input(&input),
+        }
+    }
+
+    pub fn is_lightgun(&self) -> bool {
+        matches!(self, Controller::GunCon(_) | Controller::Justifier(_))
+    }
+
+    /// Where a Justifier wants the lightgun IRQ raised
+    pub fn lightgun_irq_position(&self) -> Option<BeamPosition> {
+        match self {
+            Controller::Justifier(gun) => gun.aim,
+            _ => None,
+        }
+    }
+
+    /// Small motor on/off and large motor strength, for controllers that rumble
+    pub fn rumble(&self) -> Option<(bool, u8)> {
+        match self {
+            Controller::Analog(pad) => Some((pad.small_motor, pad.large_motor)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+const PAD_ID: u16 = 0x5A41;
+
+/// SCPH-1080 digital controller
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DigitalPad {
+    /// Held buttons, set bits are pressed (the wire format is active low)
+    pub buttons: u16,
+    // Position within the current command, 0 being the address byte
+    step: u8,
+}
+
+impl DigitalPad {
+    pub fn new() -> Self {
+        DigitalPad {
+            buttons: 0,
+            step: 0,
+        }
+    }
+
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        match step {
+            // Address byte 0x01, already matched by the port
+            0 => (0xFF, true),
+            // Only 0x42 (read buttons) is supported, anything else ends the command
+            1 if value == 0x42 => (PAD_ID as u8, true),
+            2 => ((PAD_ID >> 8) as u8, true),
+            3 => (!self.buttons as u8, true),
+            // The last byte is not acknowledged
+            4 => (!(self.buttons >> 8) as u8, false),
+            _ => (0xFF, false),
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.step = 0;
+    }
+}
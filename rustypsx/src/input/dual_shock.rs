@@ -0,0 +1,256 @@
+use crate::input::{Button, PadInput};
+use serde::{Deserialize, Serialize};
+
+const DIGITAL_ID: u8 = 0x41;
+const ANALOG_ID: u8 = 0x73;
+const CONFIG_ID: u8 = 0xF3;
+
+// 0x4D entries that route a 0x42 payload byte to a motor
+const RUMBLE_SMALL_MOTOR: u8 = 0x00;
+const RUMBLE_LARGE_MOTOR: u8 = 0x01;
+const RUMBLE_UNMAPPED: u8 = 0xFF;
+
+/// SCPH-1200 DualShock analog controller
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DualShock {
+    input: PadInput,
+    analog_mode: bool,
+    // Set by 0x44, stops the Analog button from switching modes
+    analog_locked: bool,
+    config_mode: bool,
+    rumble_config: [u8; 6],
+    // Motor state as the game last set it, for the host gamepad to play
+    pub small_motor: bool,
+    pub large_motor: u8,
+    // Position within the current command, 0 being the address byte
+    step: u8,
+    command: u8,
+    // Number of bytes following the 0x5A marker for the current command
+    payload_length: u8,
+    response: [u8; 6],
+}
+
+impl DualShock {
+    pub fn new() -> Self {
+        DualShock {
+            input: PadInput::default(),
+            analog_mode: false,
+            analog_locked: false,
+            config_mode: false,
+            rumble_config: [RUMBLE_UNMAPPED; 6],
+            small_motor: false,
+            large_motor: 0,
+            step: 0,
+            command: 0,
+            payload_length: 0,
+            response: [0; 6],
+        }
+    }
+
+    pub fn set_input(&mut self, input: PadInput) {
+        if input.analog_button && !self.input.analog_button && !self.analog_locked {
+            self.analog_mode = !self.analog_mode;
+        }
+        self.input = input;
+    }
+
+    fn id(&self) -> u8 {
+        if self.config_mode {
+            CONFIG_ID
+        } else if self.analog_mode {
+            ANALOG_ID
+        } else {
+            DIGITAL_ID
+        }
+    }
+
+    fn poll_response(&self) -> [u8; 6] {
+        let mut buttons = self.input.buttons;
+        if !self.analog_mode {
+            // L3 and R3 only exist in analog mode
+            buttons &= !(Button::L3.mask() | Button::R3.mask());
+        }
+        let buttons = !buttons;
+        let (lx, ly) = self.input.left_stick;
+        let (rx, ry) = self.input.right_stick;
+        [buttons as u8, (buttons >> 8) as u8, rx, ry, lx, ly]
+    }
+
+    fn begin_command(&mut self, command: u8) -> u8 {
+        let id = self.id();
+        self.command = command;
+        self.payload_length = (id & 0x0F) * 2;
+        self.response = match (self.config_mode, command) {
+            (_, 0x42) | (false, 0x43) => self.poll_response(),
+            (true, 0x45) => [0x01, 0x02, self.analog_mode as u8, 0x02, 0x01, 0x00],
+            (true, 0x47) => [0x00, 0x00, 0x02, 0x00, 0x01, 0x00],
+            (true, 0x4D) => self.rumble_config,
+            // 0x46 and 0x4C fill their replies in once the parameter byte has arrived
+            _ => [0; 6],
+        };
+        id
+    }
+
+    fn receive_payload(&mut self, index: usize, value: u8) {
+        match (self.config_mode, self.command) {
+            (_, 0x42) => {
+                if self.rumble_config[index] == RUMBLE_SMALL_MOTOR {
+                    self.small_motor = value & 0x01 != 0;
+                } else if self.rumble_config[index] == RUMBLE_LARGE_MOTOR {
+                    self.large_motor = value;
+                }
+            }
+            (_, 0x43) if index == 0 => self.config_mode = value == 0x01,
+            (true, 0x44) if index == 0 => self.analog_mode = value == 0x01,
+            (true, 0x44) if index == 1 => self.analog_locked = value == 0x03,
+            (true, 0x46) if index == 0 => {
+                self.response = match value {
+                    0x00 => [0x00, 0x00, 0x01, 0x02, 0x00, 0x0A],
+                    0x01 => [0x00, 0x00, 0x01, 0x01, 0x01, 0x14],
+                    _ => [0; 6],
+                };
+            }
+            (true, 0x4C) if index == 0 => {
+                self.response = match value {
+                    0x00 => [0x00, 0x00, 0x00, 0x04, 0x00, 0x00],
+                    0x01 => [0x00, 0x00, 0x00, 0x07, 0x00, 0x00],
+                    _ => [0; 6],
+                };
+            }
+            (true, 0x4D) => {
+                self.rumble_config[index] = value;
+                if !self.rumble_config.contains(&RUMBLE_SMALL_MOTOR) {
+                    self.small_motor = false;
+                }
+                if !self.rumble_config.contains(&RUMBLE_LARGE_MOTOR) {
+                    self.large_motor = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        match step {
+            // Address byte 0x01, already matched by the port
+            0 => (0xFF, true),
+            1 => match value {
+                0x42 | 0x43 => (self.begin_command(value), true),
+                0x40..=0x4F if self.config_mode => (self.begin_command(value), true),
+                _ => (0xFF, false),
+            },
+            2 => (0x5A, true),
+            _ => {
+                let index = (step - 3) as usize;
+                if index >= self.payload_length as usize {
+                    return (0xFF, false);
+                }
+                let reply = self.response[index];
+                self.receive_payload(index, value);
+                (reply, index + 1 < self.payload_length as usize)
+            }
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.step = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a whole command from the address byte, returns the replies after it
+    fn command(pad: &mut DualShock, bytes: &[u8]) -> Vec<u8> {
+        pad.deselect();
+        assert_eq!(pad.transfer(0x01), (0xFF, true));
+        let replies: Vec<_> = bytes.iter().map(|&value| pad.transfer(value)).collect();
+        let acks: Vec<_> = replies.iter().map(|&(_, ack)| ack).collect();
+        // Every byte but the last is acknowledged
+        assert_eq!(acks.iter().filter(|&&ack| !ack).count(), 1, "{:02X?}", replies);
+        assert!(!acks[acks.len() - 1]);
+        replies.into_iter().map(|(reply, _)| reply).collect()
+    }
+
+    fn config_command(pad: &mut DualShock, command_byte: u8, parameters: [u8; 6]) -> Vec<u8> {
+        let mut bytes = vec![command_byte, 0x00];
+        bytes.extend(parameters);
+        command(pad, &bytes)
+    }
+
+    fn enter_config(pad: &mut DualShock) {
+        command(pad, &[0x43, 0x00, 0x01, 0x00]);
+        assert!(pad.config_mode);
+    }
+
+    #[test]
+    fn digital_mode_polls_like_a_digital_pad() {
+        let mut pad = DualShock::new();
+        pad.set_input(PadInput { buttons: Button::Start.mask() | Button::L3.mask(), ..PadInput::default() });
+        assert_eq!(command(&mut pad, &[0x42, 0x00, 0x00, 0x00]), [0x41, 0x5A, 0xF7, 0xFF]);
+    }
+
+    #[test]
+    fn the_analog_button_switches_to_analog_reports() {
+        let mut pad = DualShock::new();
+        pad.set_input(PadInput { analog_button: true, left_stick: (0x10, 0x20), right_stick: (0x30, 0x40), ..PadInput::default() });
+        assert_eq!(command(&mut pad, &[0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), [0x73, 0x5A, 0xFF, 0xFF, 0x30, 0x40, 0x10, 0x20]);
+    }
+
+    #[test]
+    fn config_commands_need_config_mode() {
+        let mut pad = DualShock::new();
+        pad.deselect();
+        pad.transfer(0x01);
+        assert_eq!(pad.transfer(0x45), (0xFF, false));
+        enter_config(&mut pad);
+        assert_eq!(config_command(&mut pad, 0x45, [0; 6])[0], CONFIG_ID);
+        command(&mut pad, &[0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(!pad.config_mode);
+    }
+
+    #[test]
+    fn set_mode_switches_and_locks_analog_mode() {
+        let mut pad = DualShock::new();
+        enter_config(&mut pad);
+        config_command(&mut pad, 0x44, [0x01, 0x03, 0, 0, 0, 0]);
+        assert_eq!(config_command(&mut pad, 0x45, [0; 6])[2..], [0x01, 0x02, 0x01, 0x02, 0x01, 0x00]);
+        command(&mut pad, &[0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        // Locked, so the Analog button is ignored
+        pad.set_input(PadInput { analog_button: true, ..PadInput::default() });
+        assert_eq!(pad.id(), ANALOG_ID);
+    }
+
+    #[test]
+    fn status_and_constant_queries_answer_fixed_tables() {
+        let mut pad = DualShock::new();
+        enter_config(&mut pad);
+        assert_eq!(config_command(&mut pad, 0x46, [0x00, 0, 0, 0, 0, 0])[2..], [0x00, 0x00, 0x01, 0x02, 0x00, 0x0A]);
+        assert_eq!(config_command(&mut pad, 0x46, [0x01, 0, 0, 0, 0, 0])[2..], [0x00, 0x00, 0x01, 0x01, 0x01, 0x14]);
+        assert_eq!(config_command(&mut pad, 0x47, [0; 6])[2..], [0x00, 0x00, 0x02, 0x00, 0x01, 0x00]);
+        assert_eq!(config_command(&mut pad, 0x4C, [0x00, 0, 0, 0, 0, 0])[2..], [0x00, 0x00, 0x00, 0x04, 0x00, 0x00]);
+        assert_eq!(config_command(&mut pad, 0x4C, [0x01, 0, 0, 0, 0, 0])[2..], [0x00, 0x00, 0x00, 0x07, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn rumble_mapping_routes_poll_bytes_to_the_motors() {
+        let mut pad = DualShock::new();
+        enter_config(&mut pad);
+        // 0x4D replies with the mapping it replaces
+        assert_eq!(config_command(&mut pad, 0x4D, [0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF])[2..], [0xFF; 6]);
+        assert_eq!(config_command(&mut pad, 0x4D, [0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF])[2..], [0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]);
+        command(&mut pad, &[0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        command(&mut pad, &[0x42, 0x00, 0x01, 0xC0]);
+        assert!(pad.small_motor);
+        assert_eq!(pad.large_motor, 0xC0);
+        // Unmapping a motor stops it
+        enter_config(&mut pad);
+        config_command(&mut pad, 0x4D, [0xFF, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(!pad.small_motor);
+        assert_eq!(pad.large_motor, 0xC0);
+    }
+}
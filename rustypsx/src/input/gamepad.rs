@@ -0,0 +1,248 @@
+//! Host gamepads, read through Linux evdev and laid out like a PlayStation pad.
+//!
+//! Pads are found once at startup and numbered in the order the kernel lists them. Only pads the user
+//! can open are used, which usually means being in the `input` group. Other hosts have none.
+
+use crate::input::{Button, PadInput, STICK_CENTER};
+
+// Half way down, for analog triggers standing in for L2 and R2
+const TRIGGER_THRESHOLD: u8 = 0x80;
+
+/// Every gamepad found at startup
+pub struct Gamepads {
+    pads: Vec<Gamepad>,
+}
+
+impl Gamepads {
+    pub fn open() -> Self {
+        let pads = find_gamepads();
+        for pad in &pads {
+            println!("Found gamepad {}", pad.name());
+        }
+        Gamepads { pads }
+    }
+
+    /// What gamepad `index` is doing, None if there's no such pad or it has been unplugged
+    pub fn sample(&mut self, index: usize) -> Option<PadInput> {
+        self.pads.get_mut(index)?.sample()
+    }
+
+    /// Run the motors as the game has set them, small motor on or off and large motor strength
+    pub fn set_rumble(&mut self, index: usize, small: bool, large: u8) {
+        if let Some(pad) = self.pads.get_mut(index) {
+            pad.set_rumble(small, large);
+        }
+    }
+}
+
+/// The keyboard's input with a gamepad's on top, sticks come from whichever is off center
+pub fn merge(keyboard: PadInput, gamepad: PadInput) -> PadInput {
+    let stick = |keyboard: (u8, u8), gamepad: (u8, u8)| {
+        if keyboard == (STICK_CENTER, STICK_CENTER) { gamepad } else { keyboard }
+    };
+    PadInput {
+        buttons: keyboard.buttons | gamepad.buttons,
+        left_stick: stick(keyboard.left_stick, gamepad.left_stick),
+        right_stick: stick(keyboard.right_stick, gamepad.right_stick),
+        analog_button: keyboard.analog_button || gamepad.analog_button,
+        pointer: keyboard.pointer,
+    }
+}
+
+/// An axis between `minimum` and `maximum` as a stick position, 0x00 being left/up
+fn scale_axis(value: i32, minimum: i32, maximum: i32) -> u8 {
+    if maximum <= minimum {
+        return STICK_CENTER;
+    }
+    let offset = (value.clamp(minimum, maximum) - minimum) as i64;
+    (offset * 0xFF / (maximum - minimum) as i64) as u8
+}
+
+#[cfg(target_os = "linux")]
+use linux::{Gamepad, find_gamepads};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use evdev::{AbsoluteAxisType, Device, FFEffect, FFEffectData, FFEffectKind, FFEffectType, FFReplay, FFTrigger, Key};
+
+    // The kernel's standard gamepad layout, by position rather than label
+    const BUTTONS: [(Key, Button); 16] = [
+        (Key::BTN_SOUTH, Button::Cross),
+        (Key::BTN_EAST, Button::Circle),
+        (Key::BTN_NORTH, Button::Triangle),
+        (Key::BTN_WEST, Button::Square),
+        (Key::BTN_TL, Button::L1),
+        (Key::BTN_TR, Button::R1),
+        (Key::BTN_TL2, Button::L2),
+        (Key::BTN_TR2, Button::R2),
+        (Key::BTN_SELECT, Button::Select),
+        (Key::BTN_START, Button::Start),
+        (Key::BTN_THUMBL, Button::L3),
+        (Key::BTN_THUMBR, Button::R3),
+        (Key::BTN_DPAD_UP, Button::Up),
+        (Key::BTN_DPAD_DOWN, Button::Down),
+        (Key::BTN_DPAD_LEFT, Button::Left),
+        (Key::BTN_DPAD_RIGHT, Button::Right),
+    ];
+
+    pub struct Gamepad {
+        device: Device,
+        // Uploaded the first time the game runs a motor, None if the pad can't rumble or rumbling failed
+        effect: Option<FFEffect>,
+        can_rumble: bool,
+        motors: (bool, u8),
+    }
+
+    pub fn find_gamepads() -> Vec<Gamepad> {
+        evdev::enumerate()
+            .map(|(_, device)| device)
+            .filter(|device| device.supported_keys().is_some_and(|keys| keys.contains(Key::BTN_SOUTH)))
+            .map(|device| Gamepad {
+                can_rumble: device.supported_ff().is_some_and(|effects| effects.contains(FFEffectType::FF_RUMBLE)),
+                device,
+                effect: None,
+                motors: (false, 0),
+            })
+            .collect()
+    }
+
+    impl Gamepad {
+        pub fn name(&self) -> &str {
+            self.device.name().unwrap_or("(unnamed)")
+        }
+
+        /// Reads the current state rather than events, so nothing queues up between frames
+        pub fn sample(&mut self) -> Option<PadInput> {
+            let keys = self.device.get_key_state().ok()?;
+            let axes = self.device.get_abs_state().ok()?;
+            let supported = self.device.supported_absolute_axes();
+            let axis = |axis: AbsoluteAxisType| {
+                let info = &axes[axis.0 as usize];
+                supported
+                    .is_some_and(|supported| supported.contains(axis))
+                    .then(|| scale_axis(info.value, info.minimum, info.maximum))
+            };
+
+            let mut buttons = BUTTONS.iter().filter(|(key, _)| keys.contains(*key)).fold(0, |mask, (_, button)| mask | button.mask());
+            // D-pads reported as a hat
+            let hat = |axis: AbsoluteAxisType| axes[axis.0 as usize].value.signum();
+            for (axis, negative, positive) in [(AbsoluteAxisType::ABS_HAT0X, Button::Left, Button::Right), (AbsoluteAxisType::ABS_HAT0Y, Button::Up, Button::Down)] {
+                match hat(axis) {
+                    -1 => buttons |= negative.mask(),
+                    1 => buttons |= positive.mask(),
+                    _ => {}
+                }
+            }
+
+            let center = |value: Option<u8>| value.unwrap_or(STICK_CENTER);
+            let left_stick = (center(axis(AbsoluteAxisType::ABS_X)), center(axis(AbsoluteAxisType::ABS_Y)));
+            // Pads with RX/RY have analog triggers on Z/RZ, simpler ones put the right stick there
+            let right_stick = if axis(AbsoluteAxisType::ABS_RX).is_some() {
+                for (trigger, button) in [(AbsoluteAxisType::ABS_Z, Button::L2), (AbsoluteAxisType::ABS_RZ, Button::R2)] {
+                    if axis(trigger).is_some_and(|value| value >= TRIGGER_THRESHOLD) {
+                        buttons |= button.mask();
+                    }
+                }
+                (center(axis(AbsoluteAxisType::ABS_RX)), center(axis(AbsoluteAxisType::ABS_RY)))
+            } else {
+                (center(axis(AbsoluteAxisType::ABS_Z)), center(axis(AbsoluteAxisType::ABS_RZ)))
+            };
+
+            Some(PadInput {
+                buttons,
+                left_stick,
+                right_stick,
+                analog_button: keys.contains(Key::BTN_MODE),
+                ..PadInput::default()
+            })
+        }
+
+        pub fn set_rumble(&mut self, small: bool, large: u8) {
+            if !self.can_rumble || self.motors == (small, large) {
+                return;
+            }
+            self.motors = (small, large);
+            if let Err(e) = self.play_rumble(small, large) {
+                println!("Gamepad {}: rumble failed, turning it off: {}", self.name(), e);
+                self.can_rumble = false;
+                self.effect = None;
+            }
+        }
+
+        fn play_rumble(&mut self, small: bool, large: u8) -> std::io::Result<()> {
+            let data = FFEffectData {
+                direction: 0,
+                trigger: FFTrigger::default(),
+                // No length plays it until it's stopped
+                replay: FFReplay { length: 0, delay: 0 },
+                kind: FFEffectKind::Rumble {
+                    strong_magnitude: large as u16 * 0x101,
+                    weak_magnitude: if small { u16::MAX } else { 0 },
+                },
+            };
+            let effect = match self.effect.as_mut() {
+                Some(effect) => {
+                    effect.update(data)?;
+                    effect
+                }
+                None => self.effect.insert(self.device.upload_ff_effect(data)?),
+            };
+            if small || large > 0 { effect.play(1) } else { effect.stop() }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+enum Gamepad {}
+
+#[cfg(not(target_os = "linux"))]
+fn find_gamepads() -> Vec<Gamepad> {
+    Vec::new()
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Gamepad {
+    fn name(&self) -> &str {
+        match *self {}
+    }
+
+    fn sample(&mut self) -> Option<PadInput> {
+        match *self {}
+    }
+
+    fn set_rumble(&mut self, _small: bool, _large: u8) {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn axes_scale_to_the_stick_range() {
+        assert_eq!(scale_axis(-32768, -32768, 32767), 0x00);
+        assert_eq!(scale_axis(0, -32768, 32767), 0x7F);
+        assert_eq!(scale_axis(32767, -32768, 32767), 0xFF);
+        assert_eq!(scale_axis(300, 0, 255), 0xFF);
+        assert_eq!(scale_axis(5, 0, 0), STICK_CENTER);
+    }
+
+    #[test]
+    fn merged_sticks_come_from_whichever_is_pushed() {
+        let keyboard = PadInput { buttons: Button::Start.mask(), left_stick: (0x00, STICK_CENTER), ..PadInput::default() };
+        let gamepad = PadInput {
+            buttons: Button::Cross.mask(),
+            left_stick: (0xFF, 0xFF),
+            right_stick: (0x20, 0x30),
+            analog_button: true,
+            ..PadInput::default()
+        };
+        let merged = merge(keyboard, gamepad);
+        assert_eq!(merged.buttons, Button::Start.mask() | Button::Cross.mask());
+        assert_eq!(merged.left_stick, (0x00, STICK_CENTER));
+        assert_eq!(merged.right_stick, (0x20, 0x30));
+        assert!(merged.analog_button);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::input::{Button, PadInput};
+use serde::{Deserialize, Serialize};
+
+const GUNCON_ID: u16 = 0x5A63;
+const JUSTIFIER_ID: u16 = 0x5A31;
+
+// NTSC video timing, in GPU video clock ticks (11/7 of the CPU clock) and scanlines
+const TICKS_PER_LINE: u32 = 3413;
+const LINES_PER_FRAME: u32 = 263;
+const VIDEO_CLOCK_HZ: u32 = 53_693_175;
+// The GunCon counts X in 8 MHz units from the start of the line
+const GUNCON_CLOCK_HZ: u32 = 8_000_000;
+// What the GunCon reports when it sees no light
+const GUNCON_NO_LIGHT: (u16, u16) = (0x0001, 0x000A);
+
+/// Visible part of the frame, in video clock ticks and scanlines like the GPU's display range registers
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct DisplayArea {
+    pub x1: u16,
+    pub x2: u16,
+    pub y1: u16,
+    pub y2: u16,
+}
+
+impl Default for DisplayArea {
+    // The range the BIOS sets up for NTSC
+    fn default() -> Self {
+        DisplayArea {
+            x1: 0x260,
+            x2: 0xC60,
+            y1: 0x10,
+            y2: 0x100,
+        }
+    }
+}
+
+impl DisplayArea {
+    /// Beam position for a point given as a fraction of the display's width and height
+    pub fn beam_position(&self, (x, y): (f32, f32)) -> BeamPosition {
+        let lerp = |start: u16, end: u16, fraction: f32| start + ((end - start) as f32 * fraction.clamp(0.0, 1.0)) as u16;
+        BeamPosition {
+            tick: lerp(self.x1, self.x2, x),
+            line: lerp(self.y1, self.y2, y),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct BeamPosition {
+    pub tick: u16,
+    pub line: u16,
+}
+
+// Both guns report trigger, A/Aux and B/Start in the pad's Circle, Cross and Start bits
+fn gun_buttons(input: &PadInput) -> u16 {
+    let pointer = &input.pointer;
+    let mut buttons = input.buttons & (Button::Circle.mask() | Button::Cross.mask() | Button::Start.mask());
+    if pointer.left {
+        buttons |= Button::Circle.mask();
+    }
+    if pointer.right {
+        buttons |= Button::Cross.mask();
+    }
+    if pointer.middle {
+        buttons |= Button::Start.mask();
+    }
+    buttons
+}
+
+/// Namco GunCon, which times the beam itself and reports its X/Y counters
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GunCon {
+    buttons: u16,
+    aim: Option<BeamPosition>,
+    step: u8,
+}
+
+impl GunCon {
+    pub fn new() -> Self {
+        GunCon {
+            buttons: 0,
+            aim: None,
+            step: 0,
+        }
+    }
+
+    pub fn set_input(&mut self, input: &PadInput, display: &DisplayArea) {
+        self.buttons = gun_buttons(input);
+        self.aim = input.pointer.position.map(|position| display.beam_position(position));
+    }
+
+    fn counters(&self) -> (u16, u16) {
+        match self.aim {
+            Some(aim) => ((aim.tick as u32 * GUNCON_CLOCK_HZ / VIDEO_CLOCK_HZ) as u16, aim.line),
+            None => GUNCON_NO_LIGHT,
+        }
+    }
+
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        let (x, y) = self.counters();
+        match step {
+            0 => (0xFF, true),
+            1 if value == 0x42 => (GUNCON_ID as u8, true),
+            2 => ((GUNCON_ID >> 8) as u8, true),
+            3 => (!self.buttons as u8, true),
+            4 => (!(self.buttons >> 8) as u8, true),
+            5 => (x as u8, true),
+            6 => ((x >> 8) as u8, true),
+            7 => (y as u8, true),
+            8 => ((y >> 8) as u8, false),
+            _ => (0xFF, false),
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.step = 0;
+    }
+}
+
+/// Konami Justifier, which raises the lightgun IRQ when the beam passes its aim for the game to time
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Justifier {
+    buttons: u16,
+    pub aim: Option<BeamPosition>,
+    step: u8,
+}
+
+impl Justifier {
+    pub fn new() -> Self {
+        Justifier {
+            buttons: 0,
+            aim: None,
+            step: 0,
+        }
+    }
+
+    pub fn set_input(&mut self, input: &PadInput, display: &DisplayArea) {
+        self.buttons = gun_buttons(input);
+        self.aim = input.pointer.position.map(|position| display.beam_position(position));
+    }
+
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        match step {
+            0 => (0xFF, true),
+            1 if value == 0x42 => (JUSTIFIER_ID as u8, true),
+            2 => ((JUSTIFIER_ID >> 8) as u8, true),
+            3 => (!self.buttons as u8, true),
+            4 => (!(self.buttons >> 8) as u8, false),
+            _ => (0xFF, false),
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.step = 0;
+    }
+}
+
+/// Tracks the raster beam so the Justifier's IRQ fires at the right time
+///
+/// Runs on NTSC timing from CPU cycles, standing in for the GPU's own video timing.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Beam {
+    // Video clock ticks since the start of the frame, times 7 to keep the 11/7 ratio exact
+    ticks_x7: u64,
+    fired: bool,
+}
+
+impl Beam {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn position(&self) -> BeamPosition {
+        let ticks = (self.ticks_x7 / 7) as u32;
+        BeamPosition {
+            tick: (ticks % TICKS_PER_LINE) as u16,
+            line: (ticks / TICKS_PER_LINE) as u16,
+        }
+    }
+
+    /// Advance by CPU cycles, returns true when the beam crosses `target` this frame
+    pub fn step(&mut self, cycles: u32, target: Option<BeamPosition>) -> bool {
+        self.ticks_x7 += cycles as u64 * 11;
+        let frame_x7 = (TICKS_PER_LINE * LINES_PER_FRAME) as u64 * 7;
+        if self.ticks_x7 >= frame_x7 {
+            self.ticks_x7 -= frame_x7;
+            self.fired = false;
+        }
+
+        let Some(target) = target else {
+            return false;
+        };
+        let beam = self.position();
+        if !self.fired && (beam.line, beam.tick) >= (target.line, target.tick) {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+}
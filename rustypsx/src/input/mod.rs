@@ -0,0 +1,10 @@
+pub mod bindings;
+pub mod controller;
+pub mod digital_pad;
+pub mod dual_shock;
+pub mod gamepad;
+pub mod lightgun;
+pub mod mouse;
+pub mod multitap;
+
+pub use controller::*;
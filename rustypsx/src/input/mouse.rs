@@ -0,0 +1,74 @@
+use crate::input::PadInput;
+use serde::{Deserialize, Serialize};
+
+const MOUSE_ID: u16 = 0x5A12;
+
+const BUTTON_RIGHT: u16 = 1 << 10;
+const BUTTON_LEFT: u16 = 1 << 11;
+
+/// SCPH-1030 mouse, reporting movement since the last poll
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Mouse {
+    buttons: u16,
+    // Host movement not yet reported, beyond what one poll can carry
+    delta_x: i32,
+    delta_y: i32,
+    // Deltas latched for the poll in progress
+    report: (i8, i8),
+    step: u8,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Mouse {
+            buttons: 0,
+            delta_x: 0,
+            delta_y: 0,
+            report: (0, 0),
+            step: 0,
+        }
+    }
+
+    pub fn set_input(&mut self, input: &PadInput) {
+        let pointer = &input.pointer;
+        self.buttons = 0;
+        if pointer.left {
+            self.buttons |= BUTTON_LEFT;
+        }
+        if pointer.right {
+            self.buttons |= BUTTON_RIGHT;
+        }
+        self.delta_x += pointer.delta.0.round() as i32;
+        self.delta_y += pointer.delta.1.round() as i32;
+    }
+
+    fn latch_report(&mut self) {
+        let x = self.delta_x.clamp(i8::MIN as i32, i8::MAX as i32);
+        let y = self.delta_y.clamp(i8::MIN as i32, i8::MAX as i32);
+        self.delta_x -= x;
+        self.delta_y -= y;
+        self.report = (x as i8, y as i8);
+    }
+
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        match step {
+            0 => (0xFF, true),
+            1 if value == 0x42 => {
+                self.latch_report();
+                (MOUSE_ID as u8, true)
+            }
+            2 => ((MOUSE_ID >> 8) as u8, true),
+            3 => (!self.buttons as u8, true),
+            4 => (!(self.buttons >> 8) as u8, true),
+            5 => (self.report.0 as u8, true),
+            6 => (self.report.1 as u8, false),
+            _ => (0xFF, false),
+        }
+    }
+
+    pub fn deselect(&mut self) {
+        self.step = 0;
+    }
+}
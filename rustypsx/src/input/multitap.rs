@@ -0,0 +1,219 @@
+use crate::input::Controller;
+use crate::memcard::MemoryCard;
+use serde::{Deserialize, Serialize};
+
+pub const MULTITAP_SLOTS: usize = 4;
+
+const MULTITAP_ID: u8 = 0x80;
+// Each slot gets the pad's ID and 0x5A followed by up to six bytes of data
+const SLOT_BYTES: u8 = 8;
+const ALL_SLOTS_END: u8 = 3 + SLOT_BYTES * MULTITAP_SLOTS as u8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum Target {
+    Pad(usize),
+    MemoryCard(usize),
+    // 0x42 poll of all four pads at once
+    AllPads,
+}
+
+/// SCPH-1070 multitap, fanning one port out to four pads and four memory cards
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Multitap {
+    pub pads: [Controller; MULTITAP_SLOTS],
+    pub memory_cards: [Option<MemoryCard>; MULTITAP_SLOTS],
+    // Latched from the TAP byte of a 0x42 command, selects the all-pads poll for the next one
+    multi_mode: bool,
+    step: u8,
+    target: Target,
+    command: u8,
+    // Pads that have ended their part of an all-pads poll
+    finished: [bool; MULTITAP_SLOTS],
+}
+
+impl Multitap {
+    pub fn new(pads: [Controller; MULTITAP_SLOTS]) -> Self {
+        Multitap {
+            pads,
+            memory_cards: [None, None, None, None],
+            multi_mode: false,
+            step: 0,
+            target: Target::Pad(0),
+            command: 0,
+            finished: [false; MULTITAP_SLOTS],
+        }
+    }
+
+    fn latch_tap_byte(&mut self, value: u8) {
+        if self.command == 0x42 {
+            self.multi_mode = value == 0x01;
+        }
+    }
+
+    fn transfer_all_pads(&mut self, step: u8, value: u8) -> (u8, bool) {
+        match step {
+            1 => {
+                self.command = value;
+                (MULTITAP_ID, true)
+            }
+            2 => {
+                self.latch_tap_byte(value);
+                (0x5A, true)
+            }
+            3..ALL_SLOTS_END => {
+                let slot = ((step - 3) / SLOT_BYTES) as usize;
+                let byte = (step - 3) % SLOT_BYTES;
+                let pad = &mut self.pads[slot];
+                if byte == 0 {
+                    // The multitap addresses each pad itself
+                    pad.deselect();
+                    self.finished[slot] = !pad.transfer(0x01).1;
+                }
+                let reply = if self.finished[slot] {
+                    0xFF
+                } else {
+                    let (reply, ack) = pad.transfer(value);
+                    self.finished[slot] = !ack;
+                    reply
+                };
+                if byte == SLOT_BYTES - 1 {
+                    pad.deselect();
+                }
+                (reply, step + 1 < ALL_SLOTS_END)
+            }
+            _ => (0xFF, false),
+        }
+    }
+
+    /// Exchange one byte, returning the reply and whether /ACK is pulled low
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+
+        if step == 0 {
+            self.target = match value {
+                0x01 if self.multi_mode => Target::AllPads,
+                0x01..=0x04 => Target::Pad((value - 0x01) as usize),
+                0x81..=0x84 => Target::MemoryCard((value - 0x81) as usize),
+                _ => return (0xFF, false),
+            };
+        }
+
+        match self.target {
+            Target::AllPads => {
+                if step == 0 {
+                    return (0xFF, true);
+                }
+                self.transfer_all_pads(step, value)
+            }
+            Target::Pad(slot) => {
+                match step {
+                    1 => self.command = value,
+                    2 if slot == 0 => self.latch_tap_byte(value),
+                    _ => {}
+                }
+                let address = if step == 0 { 0x01 } else { value };
+                self.pads[slot].transfer(address)
+            }
+            Target::MemoryCard(slot) => {
+                let address = if step == 0 { 0x81 } else { value };
+                match self.memory_cards[slot].as_mut() {
+                    Some(card) => card.transfer(address),
+                    None => (0xFF, false),
+                }
+            }
+        }
+    }
+
+    /// Called when /JOYn goes high, aborting any command in progress
+    pub fn deselect(&mut self) {
+        self.step = 0;
+        self.finished = [false; MULTITAP_SLOTS];
+        for pad in &mut self.pads {
+            pad.deselect();
+        }
+        for card in self.memory_cards.iter_mut().flatten() {
+            card.deselect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::digital_pad::DigitalPad;
+
+    fn pad(buttons: u16) -> Controller {
+        let mut pad = DigitalPad::new();
+        pad.buttons = buttons;
+        Controller::Digital(pad)
+    }
+
+    /// Run a whole command from the address byte, returns each reply and whether it was acknowledged
+    fn command(tap: &mut Multitap, address: u8, bytes: &[u8]) -> Vec<(u8, bool)> {
+        tap.deselect();
+        [address].iter().chain(bytes).map(|&value| tap.transfer(value)).collect()
+    }
+
+    #[test]
+    fn pad_addresses_reach_their_slot() {
+        let mut tap = Multitap::new([pad(0), pad(0x0001), Controller::Disconnected, pad(0x8000)]);
+        assert_eq!(command(&mut tap, 0x02, &[0x42, 0x00, 0x00, 0x00]), [(0xFF, true), (0x41, true), (0x5A, true), (0xFE, true), (0xFF, false)]);
+        assert_eq!(command(&mut tap, 0x04, &[0x42, 0x00, 0x00, 0x00])[4], (0x7F, false));
+        assert_eq!(command(&mut tap, 0x03, &[]), [(0xFF, false)]);
+    }
+
+    #[test]
+    fn card_addresses_reach_their_slot() {
+        for slot in 0..MULTITAP_SLOTS {
+            let mut tap = Multitap::new([pad(0), pad(0), pad(0), pad(0)]);
+            tap.memory_cards[slot] = Some(MemoryCard::new());
+            for address in 0x81..=0x84 {
+                let replies = command(&mut tap, address, &[0x53, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+                if (address - 0x81) as usize == slot {
+                    assert_eq!(replies[2..5], [(0x5A, true), (0x5D, true), (0x5C, true)]);
+                    assert_eq!(replies[9], (0x80, false));
+                } else {
+                    assert_eq!(replies[0], (0xFF, false), "slot {} answered {:02X}", slot, address);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_addresses_are_not_acknowledged() {
+        let mut tap = Multitap::new([pad(0), pad(0), pad(0), pad(0)]);
+        tap.memory_cards[0] = Some(MemoryCard::new());
+        for address in [0x00, 0x05, 0x80, 0x85] {
+            assert_eq!(tap.transfer(address), (0xFF, false));
+            tap.deselect();
+        }
+    }
+
+    #[test]
+    fn the_tap_byte_switches_to_polling_all_pads() {
+        let mut tap = Multitap::new([pad(0x0001), pad(0), Controller::Disconnected, pad(0x8000)]);
+        command(&mut tap, 0x01, &[0x42, 0x01, 0x00, 0x00]);
+        assert!(tap.multi_mode);
+
+        let slot_bytes = [0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut bytes = vec![0x42, 0x01];
+        for _ in 0..MULTITAP_SLOTS {
+            bytes.extend(slot_bytes);
+        }
+        let replies = command(&mut tap, 0x01, &bytes);
+        let replies: Vec<u8> = replies.into_iter().map(|(reply, _)| reply).collect();
+        assert_eq!(replies[..3], [0xFF, MULTITAP_ID, 0x5A]);
+        let slots: Vec<&[u8]> = replies[3..].chunks(SLOT_BYTES as usize).collect();
+        assert_eq!(slots[0], [0x41, 0x5A, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(slots[1], [0x41, 0x5A, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(slots[2], [0xFF; 8]);
+        assert_eq!(slots[3], [0x41, 0x5A, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(tap.transfer(0x00), (0xFF, false));
+
+        // A poll with the tap byte clear goes back to addressing one pad
+        command(&mut tap, 0x01, &[0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert!(!tap.multi_mode);
+        assert_eq!(command(&mut tap, 0x01, &[0x42])[1], (0x41, true));
+    }
+}
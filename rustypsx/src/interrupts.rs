@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+pub const I_STAT_START: u32 = 0x1F801070;
+pub const I_MASK_START: u32 = 0x1F801074;
+pub const INTERRUPTS_END: u32 = 0x1F801077;
+
+// I_STAT bits, less the GPU, CD-ROM and SPU ones until those devices are emulated
+#[derive(Clone, Copy, Debug)]
+pub enum Interrupt {
+    VBlank = 0,
+    Dma = 3,
+    Timer0 = 4,
+    Timer1 = 5,
+    Timer2 = 6,
+    ControllerAndMemoryCard = 7,
+    Sio = 8,
+    Lightpen = 10,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InterruptController {
+    status: u16,
+    mask: u16,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        InterruptController {
+            status: 0,
+            mask: 0,
+        }
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.status |= 1 << interrupt as u16;
+    }
+
+    /// Clear requests, as writing 0 to their I_STAT bits does
+    pub fn acknowledge(&mut self, mask: u16) {
+        self.status &= !mask;
+    }
+
+    /// Requests that are unmasked
+    pub fn active(&self) -> u16 {
+        self.status & self.mask
+    }
+
+    /// Whether any unmasked interrupt is waiting to be serviced (COP0 cause bit 10)
+    pub fn pending(&self) -> bool {
+        self.status & self.mask != 0
+    }
+
+    pub fn read(&self, addr: u32) -> u8 {
+        let value = if addr < I_MASK_START { self.status } else { self.mask };
+        let shift = (addr & 3) * 8;
+        (value as u32 >> shift) as u8
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let shift = (addr & 3) * 8;
+        if shift >= 16 {
+            // Upper halves of both registers are unused
+            return;
+        }
+        if addr < I_MASK_START {
+            // Writing 0 to a status bit acknowledges it, writing 1 leaves it untouched
+            let keep = (value as u16) << shift | !(0xFF << shift);
+            self.status &= keep;
+        } else {
+            self.mask = self.mask & !(0xFF << shift) | (value as u16) << shift;
+        }
+    }
+}
@@ -1,17 +1,99 @@
 #![warn(clippy::all)]
 #![forbid(unsafe_code)]
 
+mod bios;
 mod config;
+mod cdrom;
 mod cpu;
+mod determinism;
 mod display;
+mod dma;
+mod expansion;
+mod input;
+mod interrupts;
+mod mdec;
+mod memcard;
 mod memory;
+mod movie;
+mod player;
 mod psx;
+mod savestate;
+mod sio;
 
 use clap::Parser;
 
 fn main() -> Result<(), pixels::Error> {
     let config = config::RawConfig::parse().clean();
-    let ps1 = psx::PS1::new();
+    if let Some(player_config) = &config.player {
+        return play_str(player_config, config.scale);
+    }
 
+    if let Some(frames) = config.check_determinism {
+        let settings = movie::Settings::from_config(&config);
+        let diverged = determinism::check(|| build_ps1(&config), config.movie.as_deref(), &settings, frames, config.log_state_hash)
+            .unwrap_or_else(|e| panic!("{}", e));
+        if diverged.is_some() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut ps1 = build_ps1(&config);
+    if let Some(path) = &config.expansion_pty {
+        let link = sio::link::Link::open(&sio::link::LinkTarget::Pty(path.clone())).unwrap_or_else(|e| panic!("{}", e));
+        ps1.connect_expansion_link(link);
+    }
+    if let Some(target) = &config.sio1_link {
+        let link = sio::link::Link::open(target).unwrap_or_else(|e| panic!("{}", e));
+        ps1.connect_link(link);
+    }
     display::run_with_gui(ps1, &config)
 }
+
+// Everything but the links, which talk to the outside world, reset and ready to run
+fn build_ps1(config: &config::CleanConfig) -> psx::PS1 {
+    let mut ps1 = psx::PS1::new();
+    match &config.bios {
+        Some(path) => ps1.load_bios(path).unwrap_or_else(|e| panic!("{}", e)),
+        None => ps1.use_hle_bios(),
+    }
+    if let Some(path) = &config.disc {
+        let disc = cdrom::Disc::open(path).unwrap_or_else(|e| panic!("{}", e));
+        ps1.insert_disc(disc);
+    }
+    if let Some(path) = &config.exe {
+        ps1.side_load_exe(path.clone());
+    }
+    ps1.set_fast_boot(config.fast_boot);
+    ps1.set_seed(config.seed);
+    for (port, controller_type) in config.controllers.iter().enumerate() {
+        match config.multitaps[port] {
+            Some(pads) => ps1.connect_multitap(port, pads),
+            None => ps1.connect_controller(port, *controller_type),
+        }
+    }
+    for (slot, path) in config.memory_cards.iter().enumerate() {
+        if let Some(path) = path {
+            let card = memcard::MemoryCard::open(path).unwrap_or_else(|e| panic!("{}", e));
+            ps1.insert_memory_card(slot, Some(card));
+        }
+    }
+
+    if let Some(path) = &config.expansion_rom {
+        let mut cartridge = expansion::Expansion1::open(path).unwrap_or_else(|e| panic!("{}", e));
+        cartridge.switch = config.expansion_switch;
+        ps1.insert_expansion_1(cartridge);
+    }
+
+    ps1.reset();
+    ps1
+}
+
+fn play_str(config: &player::PlayerConfig, scale: u8) -> Result<(), pixels::Error> {
+    if let Some(directory) = &config.output {
+        player::export(config, directory).unwrap_or_else(|e| panic!("{}", e));
+        return Ok(());
+    }
+    let str_player = player::StrPlayer::open(config).unwrap_or_else(|e| panic!("{}", e));
+    display::run_player(str_player, scale)
+}
@@ -0,0 +1,253 @@
+//! Decompression of the BS bitstream that STR video frames are stored in.
+//!
+//! Games turn it back into MDEC run-length codes on the CPU before feeding the MDEC,
+//! the player does the same. Versions 2 and 3 are supported.
+
+const BS_MAGIC: u16 = 0x3800;
+const HEADER_SIZE: usize = 8;
+const END_OF_BLOCK: u16 = 0xFE00;
+
+// Code length, code, run of zeros and level, from the MPEG-1 dct_coeff_next table
+const AC_CODES: [(u8, u16, u8, u8); 111] = [
+    (2, 0b11, 0, 1),
+    (3, 0b011, 1, 1),
+    (4, 0b0100, 0, 2),
+    (4, 0b0101, 2, 1),
+    (5, 0b00101, 0, 3),
+    (5, 0b00111, 3, 1),
+    (5, 0b00110, 4, 1),
+    (6, 0b000110, 1, 2),
+    (6, 0b000111, 5, 1),
+    (6, 0b000101, 6, 1),
+    (6, 0b000100, 7, 1),
+    (7, 0b0000110, 0, 4),
+    (7, 0b0000100, 2, 2),
+    (7, 0b0000111, 8, 1),
+    (7, 0b0000101, 9, 1),
+    (8, 0b00100110, 0, 5),
+    (8, 0b00100001, 0, 6),
+    (8, 0b00100101, 1, 3),
+    (8, 0b00100100, 3, 2),
+    (8, 0b00100111, 10, 1),
+    (8, 0b00100011, 11, 1),
+    (8, 0b00100010, 12, 1),
+    (8, 0b00100000, 13, 1),
+    (10, 0b0000001010, 0, 7),
+    (10, 0b0000001100, 1, 4),
+    (10, 0b0000001011, 2, 3),
+    (10, 0b0000001111, 4, 2),
+    (10, 0b0000001001, 5, 2),
+    (10, 0b0000001110, 14, 1),
+    (10, 0b0000001101, 15, 1),
+    (10, 0b0000001000, 16, 1),
+    (12, 0b000000011101, 0, 8),
+    (12, 0b000000011000, 0, 9),
+    (12, 0b000000010011, 0, 10),
+    (12, 0b000000010000, 0, 11),
+    (12, 0b000000011011, 1, 5),
+    (12, 0b000000010100, 2, 4),
+    (12, 0b000000011100, 3, 3),
+    (12, 0b000000010010, 4, 3),
+    (12, 0b000000011110, 6, 2),
+    (12, 0b000000010101, 7, 2),
+    (12, 0b000000010001, 8, 2),
+    (12, 0b000000011111, 17, 1),
+    (12, 0b000000011010, 18, 1),
+    (12, 0b000000011001, 19, 1),
+    (12, 0b000000010111, 20, 1),
+    (12, 0b000000010110, 21, 1),
+    (13, 0b0000000011010, 0, 12),
+    (13, 0b0000000011001, 0, 13),
+    (13, 0b0000000011000, 0, 14),
+    (13, 0b0000000010111, 0, 15),
+    (13, 0b0000000010110, 1, 6),
+    (13, 0b0000000010101, 1, 7),
+    (13, 0b0000000010100, 2, 5),
+    (13, 0b0000000010011, 3, 4),
+    (13, 0b0000000010010, 5, 3),
+    (13, 0b0000000010001, 9, 2),
+    (13, 0b0000000010000, 10, 2),
+    (13, 0b0000000011111, 22, 1),
+    (13, 0b0000000011110, 23, 1),
+    (13, 0b0000000011101, 24, 1),
+    (13, 0b0000000011100, 25, 1),
+    (13, 0b0000000011011, 26, 1),
+    (14, 0b00000000011111, 0, 16),
+    (14, 0b00000000011110, 0, 17),
+    (14, 0b00000000011101, 0, 18),
+    (14, 0b00000000011100, 0, 19),
+    (14, 0b00000000011011, 0, 20),
+    (14, 0b00000000011010, 0, 21),
+    (14, 0b00000000011001, 0, 22),
+    (14, 0b00000000011000, 0, 23),
+    (14, 0b00000000010111, 0, 24),
+    (14, 0b00000000010110, 0, 25),
+    (14, 0b00000000010101, 0, 26),
+    (14, 0b00000000010100, 0, 27),
+    (14, 0b00000000010011, 0, 28),
+    (14, 0b00000000010010, 0, 29),
+    (14, 0b00000000010001, 0, 30),
+    (14, 0b00000000010000, 0, 31),
+    (15, 0b000000000011000, 0, 32),
+    (15, 0b000000000010111, 0, 33),
+    (15, 0b000000000010110, 0, 34),
+    (15, 0b000000000010101, 0, 35),
+    (15, 0b000000000010100, 0, 36),
+    (15, 0b000000000010011, 0, 37),
+    (15, 0b000000000010010, 0, 38),
+    (15, 0b000000000010001, 0, 39),
+    (15, 0b000000000010000, 0, 40),
+    (15, 0b000000000011111, 1, 8),
+    (15, 0b000000000011110, 1, 9),
+    (15, 0b000000000011101, 1, 10),
+    (15, 0b000000000011100, 1, 11),
+    (15, 0b000000000011011, 1, 12),
+    (15, 0b000000000011010, 1, 13),
+    (15, 0b000000000011001, 1, 14),
+    (16, 0b0000000000010011, 1, 15),
+    (16, 0b0000000000010010, 1, 16),
+    (16, 0b0000000000010001, 1, 17),
+    (16, 0b0000000000010000, 1, 18),
+    (16, 0b0000000000010100, 6, 3),
+    (16, 0b0000000000011010, 11, 2),
+    (16, 0b0000000000011001, 12, 2),
+    (16, 0b0000000000011000, 13, 2),
+    (16, 0b0000000000010111, 14, 2),
+    (16, 0b0000000000010110, 15, 2),
+    (16, 0b0000000000010101, 16, 2),
+    (16, 0b0000000000011111, 27, 1),
+    (16, 0b0000000000011110, 28, 1),
+    (16, 0b0000000000011101, 29, 1),
+    (16, 0b0000000000011100, 30, 1),
+    (16, 0b0000000000011011, 31, 1),
+];
+
+// Code length and code for each DC difference size, from the MPEG-1 tables
+const LUMA_DC_SIZES: [(u8, u16); 9] = [
+    (3, 0b100), (2, 0b00), (2, 0b01), (3, 0b101), (3, 0b110),
+    (4, 0b1110), (5, 0b11110), (6, 0b111110), (7, 0b1111110),
+];
+const CHROMA_DC_SIZES: [(u8, u16); 9] = [
+    (2, 0b00), (2, 0b01), (2, 0b10), (3, 0b110), (4, 0b1110),
+    (5, 0b11110), (6, 0b111110), (7, 0b1111110), (8, 0b11111110),
+];
+
+/// Reads bits MSB first out of little-endian halfwords
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn halfword(&self, index: usize) -> u32 {
+        match self.data.get(index * 2..index * 2 + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            None => 0,
+        }
+    }
+
+    fn peek(&self, bits: u8) -> u32 {
+        let index = self.position / 16;
+        let window = self.halfword(index) << 16 | self.halfword(index + 1);
+        (window << (self.position % 16)) >> (32 - bits as u32)
+    }
+
+    fn read(&mut self, bits: u8) -> Result<u32, String> {
+        if self.position + bits as usize > self.data.len() * 8 {
+            return Err("Frame bitstream ended early".to_string());
+        }
+        let value = self.peek(bits);
+        self.position += bits as usize;
+        Ok(value)
+    }
+}
+
+fn read_ac(reader: &mut BitReader) -> Result<Option<u16>, String> {
+    if reader.peek(2) == 0b10 {
+        reader.read(2)?;
+        return Ok(None);
+    }
+    if reader.peek(6) == 0b000001 {
+        reader.read(6)?;
+        let run = reader.read(6)? as u16;
+        let level = reader.read(10)? as u16;
+        return Ok(Some(run << 10 | level));
+    }
+    for &(length, code, run, level) in AC_CODES.iter() {
+        if reader.peek(length) == code as u32 {
+            reader.read(length)?;
+            let negative = reader.read(1)? != 0;
+            let level = if negative { -(level as i16) } else { level as i16 };
+            return Ok(Some((run as u16) << 10 | (level as u16 & 0x3FF)));
+        }
+    }
+    Err(format!("Invalid AC code at bit {}", reader.position))
+}
+
+fn read_dc_difference(reader: &mut BitReader, sizes: &[(u8, u16); 9]) -> Result<i32, String> {
+    let size = sizes
+        .iter()
+        .position(|&(length, code)| reader.peek(length) == code as u32)
+        .ok_or_else(|| format!("Invalid DC size code at bit {}", reader.position))?;
+    reader.read(sizes[size].0)?;
+    if size == 0 {
+        return Ok(0);
+    }
+    let bits = reader.read(size as u8)? as i32;
+    // A clear top bit marks a negative difference
+    if bits & (1 << (size - 1)) == 0 {
+        Ok(bits - (1 << size) + 1)
+    } else {
+        Ok(bits)
+    }
+}
+
+/// Decompress one BS frame into MDEC run-length halfwords for its macroblocks
+pub fn decode_frame(data: &[u8], width: u16, height: u16) -> Result<Vec<u16>, String> {
+    if data.len() < HEADER_SIZE {
+        return Err("Frame is too short for a BS header".to_string());
+    }
+    let field = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+    if field(1) != BS_MAGIC {
+        return Err(format!("Bad BS magic {:04X}", field(1)));
+    }
+    let q_scale = field(2) & 0x3F;
+    let version = field(3);
+    if version != 2 && version != 3 {
+        return Err(format!("Unsupported BS version {}", version));
+    }
+
+    let macroblocks = width.div_ceil(16) as usize * height.div_ceil(16) as usize;
+    let mut reader = BitReader { data: &data[HEADER_SIZE..], position: 0 };
+    let mut output = Vec::with_capacity(field(0) as usize * 2);
+    // Version 3 codes DC as a difference from the previous Cr, Cb or Y block
+    let mut predictors = [0i32; 3];
+
+    for _ in 0..macroblocks {
+        for block in 0..6 {
+            let dc = if version == 2 {
+                reader.read(10)? as u16
+            } else {
+                let (predictor, sizes) = match block {
+                    0 => (&mut predictors[0], &CHROMA_DC_SIZES),
+                    1 => (&mut predictors[1], &CHROMA_DC_SIZES),
+                    _ => (&mut predictors[2], &LUMA_DC_SIZES),
+                };
+                *predictor += read_dc_difference(&mut reader, sizes)? * 4;
+                (*predictor & 0x3FF) as u16
+            };
+            output.push(q_scale << 10 | dc);
+
+            while let Some(code) = read_ac(&mut reader)? {
+                output.push(code);
+            }
+            output.push(END_OF_BLOCK);
+        }
+    }
+
+    // The MDEC takes whole words
+    if output.len() % 2 != 0 {
+        output.push(END_OF_BLOCK);
+    }
+    Ok(output)
+}
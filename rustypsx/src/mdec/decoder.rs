@@ -0,0 +1,329 @@
+//! Run-length decoding, IDCT and colour conversion for MDEC macroblocks.
+//!
+//! Rounding follows hardware exactly, games compare decoded output against it.
+
+use serde::{Deserialize, Serialize};
+
+const END_OF_BLOCK: u16 = 0xFE00;
+
+/// Raster position of each coefficient in zigzag order
+const ZAGZIG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10,
+    17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Quantization table uploaded by the Sony libraries, in zigzag order, for luma and chroma alike
+pub const STANDARD_QUANT_TABLE: [u8; 64] = [
+    0x02, 0x10, 0x10, 0x13, 0x10, 0x13, 0x16, 0x16,
+    0x16, 0x16, 0x16, 0x16, 0x1A, 0x18, 0x1A, 0x1B,
+    0x1B, 0x1B, 0x1A, 0x1A, 0x1A, 0x1A, 0x1B, 0x1B,
+    0x1B, 0x1D, 0x1D, 0x1D, 0x22, 0x22, 0x22, 0x1D,
+    0x1D, 0x1D, 0x1B, 0x1B, 0x1D, 0x1D, 0x20, 0x20,
+    0x22, 0x22, 0x25, 0x26, 0x25, 0x23, 0x23, 0x22,
+    0x23, 0x26, 0x26, 0x28, 0x28, 0x28, 0x30, 0x30,
+    0x2E, 0x2E, 0x38, 0x38, 0x3A, 0x45, 0x45, 0x53,
+];
+
+/// IDCT scale table uploaded by the Sony libraries
+pub const STANDARD_SCALE_TABLE: [i16; 64] = {
+    const RAW: [u16; 64] = [
+        0x5A82, 0x5A82, 0x5A82, 0x5A82, 0x5A82, 0x5A82, 0x5A82, 0x5A82,
+        0x7D8A, 0x6A6D, 0x471C, 0x18F8, 0xE707, 0xB8E3, 0x9592, 0x8275,
+        0x7641, 0x30FB, 0xCF04, 0x89BE, 0x89BE, 0xCF04, 0x30FB, 0x7641,
+        0x6A6D, 0xE707, 0x8275, 0xB8E3, 0x471C, 0x7D8A, 0x18F8, 0x9592,
+        0x5A82, 0xA57D, 0xA57D, 0x5A82, 0x5A82, 0xA57D, 0xA57D, 0x5A82,
+        0x471C, 0x8275, 0x18F8, 0x6A6D, 0x9592, 0xE707, 0x7D8A, 0xB8E3,
+        0x30FB, 0x89BE, 0x7641, 0xCF04, 0xCF04, 0x7641, 0x89BE, 0x30FB,
+        0x18F8, 0xB8E3, 0x6A6D, 0x8275, 0x7D8A, 0x9592, 0x471C, 0xE707,
+    ];
+    let mut table = [0i16; 64];
+    let mut i = 0;
+    while i < 64 {
+        table[i] = RAW[i] as i16;
+        i += 1;
+    }
+    table
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Depth {
+    Bits4,
+    Bits8,
+    Bits24,
+    Bits15,
+}
+
+impl Depth {
+    pub fn from_bits(bits: u32) -> Self {
+        match bits & 3 {
+            0 => Depth::Bits4,
+            1 => Depth::Bits8,
+            2 => Depth::Bits24,
+            _ => Depth::Bits15,
+        }
+    }
+
+    pub fn is_monochrome(self) -> bool {
+        matches!(self, Depth::Bits4 | Depth::Bits8)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct OutputFormat {
+    pub depth: Depth,
+    /// Output -128..127 instead of 0..255
+    pub signed: bool,
+    /// Value of bit 15 in 15bpp output
+    pub set_bit15: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Tables {
+    // Vecs rather than arrays, serde only derives arrays of up to 32 elements
+    pub luma_quant: Vec<u8>,
+    pub chroma_quant: Vec<u8>,
+    pub scale: Vec<i16>,
+}
+
+impl Tables {
+    pub fn new() -> Self {
+        Tables {
+            luma_quant: vec![0; 64],
+            chroma_quant: vec![0; 64],
+            scale: vec![0; 64],
+        }
+    }
+}
+
+fn sign_extend_10(value: u16) -> i32 {
+    ((value << 6) as i16 >> 6) as i32
+}
+
+fn sign_extend_9(value: i64) -> i32 {
+    ((value as i32) << 23) >> 23
+}
+
+/// Run-length decode one block starting at `input[0]`
+///
+/// Returns the number of halfwords used and the dequantized coefficients in raster
+/// order, or None if the block runs past the end of `input`.
+fn rl_decode_block(input: &[u16], quant: &[u8]) -> Option<(usize, [i16; 64])> {
+    let mut block = [0i16; 64];
+    let mut position = 0;
+
+    // Padding between blocks
+    while *input.get(position)? == END_OF_BLOCK {
+        position += 1;
+    }
+
+    let mut n = input[position];
+    position += 1;
+    let q_scale = (n >> 10) as i32 & 0x3F;
+    let mut k = 0usize;
+    let mut value = sign_extend_10(n) * quant[0] as i32;
+
+    loop {
+        if q_scale == 0 {
+            value = sign_extend_10(n) * 2;
+        }
+        let value_clamped = value.clamp(-0x400, 0x3FF) as i16;
+        if q_scale > 0 {
+            block[ZAGZIG[k]] = value_clamped;
+        } else {
+            // Without a scale the coefficients are stored unzigzagged
+            block[k] = value_clamped;
+        }
+
+        n = *input.get(position)?;
+        position += 1;
+        k += ((n >> 10) & 0x3F) as usize + 1;
+        if k > 63 {
+            break;
+        }
+        value = (sign_extend_10(n) * quant[k] as i32 * q_scale + 4) / 8;
+    }
+
+    Some((position, block))
+}
+
+/// Two pass matrix IDCT, leaving each sample clamped to -128..127
+fn idct(block: &mut [i16; 64], scale: &[i16]) {
+    let mut temp = [0i64; 64];
+    for x in 0..8 {
+        for y in 0..8 {
+            let mut sum = 0i64;
+            for u in 0..8 {
+                sum += block[u * 8 + x] as i64 * scale[u * 8 + y] as i64;
+            }
+            temp[x + y * 8] = sum;
+        }
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            let mut sum = 0i64;
+            for u in 0..8 {
+                sum += temp[u + y * 8] * scale[u * 8 + x] as i64;
+            }
+            let rounded = (sum >> 32) + ((sum >> 31) & 1);
+            block[x + y * 8] = sign_extend_9(rounded).clamp(-128, 127) as i16;
+        }
+    }
+}
+
+/// Run-length decode and IDCT one block starting at `input[0]`
+///
+/// Returns the number of halfwords used and the samples, or None if `input` does not
+/// hold the whole block yet.
+pub fn decode_block(input: &[u16], quant: &[u8], scale: &[i16]) -> Option<(usize, [i16; 64])> {
+    let (used, mut block) = rl_decode_block(input, quant)?;
+    idct(&mut block, scale);
+    Some((used, block))
+}
+
+/// Blocks making up one macroblock of the given depth
+pub fn blocks_per_macroblock(depth: Depth) -> usize {
+    if depth.is_monochrome() { 1 } else { 6 }
+}
+
+/// Quantization table for the `index`th block of a macroblock
+pub fn block_quant(tables: &Tables, depth: Depth, index: usize) -> &[u8] {
+    if !depth.is_monochrome() && index < 2 {
+        &tables.chroma_quant
+    } else {
+        &tables.luma_quant
+    }
+}
+
+fn output_sample(value: i32, signed: bool) -> u8 {
+    let value = value.clamp(-128, 127) as u8;
+    if signed { value } else { value ^ 0x80 }
+}
+
+/// Convert one 8x8 quadrant of a colour macroblock to RGB
+fn yuv_to_rgb(rgb: &mut [[u8; 3]; 256], luma: &[i16], cr: &[i16], cb: &[i16], xx: usize, yy: usize, signed: bool) {
+    for y in 0..8 {
+        for x in 0..8 {
+            let chroma = (x + xx) / 2 + (y + yy) / 2 * 8;
+            let r = cr[chroma] as i32;
+            let b = cb[chroma] as i32;
+            let g = ((-88 * b) & !0x1F) + ((-183 * r) & !0x07);
+            let luma = luma[x + y * 8] as i32;
+
+            rgb[(x + xx) + (y + yy) * 16] = [
+                output_sample(((359 * r + 0x80) >> 8) + luma, signed),
+                output_sample(((g + 0x80) >> 8) + luma, signed),
+                output_sample(((454 * b + 0x80) >> 8) + luma, signed),
+            ];
+        }
+    }
+}
+
+fn pack_bytes(bytes: &[u8], output: &mut Vec<u32>) {
+    output.extend(bytes.chunks_exact(4).map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])));
+}
+
+/// Convert decoded blocks, 64 samples each, to output words in raster order
+///
+/// Colour macroblocks are Cr, Cb and four Y blocks making a 16x16 image, monochrome
+/// ones a lone 8x8 Y block.
+pub fn output_macroblock(blocks: &[i16], format: OutputFormat, output: &mut Vec<u32>) {
+    if format.depth.is_monochrome() {
+        let samples: Vec<u8> = blocks[..64].iter().map(|&y| output_sample(y as i32, format.signed)).collect();
+        if format.depth == Depth::Bits4 {
+            for chunk in samples.chunks_exact(8) {
+                let word = chunk.iter().enumerate().fold(0u32, |word, (index, &sample)| {
+                    word | ((sample >> 4) as u32) << (index * 4)
+                });
+                output.push(word);
+            }
+        } else {
+            pack_bytes(&samples, output);
+        }
+        return;
+    }
+
+    let mut rgb = [[0u8; 3]; 256];
+    let (cr, cb) = (&blocks[..64], &blocks[64..128]);
+    for (luma, (xx, yy)) in blocks[128..].chunks_exact(64).zip([(0, 0), (8, 0), (0, 8), (8, 8)]) {
+        yuv_to_rgb(&mut rgb, luma, cr, cb, xx, yy, format.signed);
+    }
+
+    if format.depth == Depth::Bits24 {
+        let bytes: Vec<u8> = rgb.iter().flatten().copied().collect();
+        pack_bytes(&bytes, output);
+    } else {
+        let bit15 = if format.set_bit15 { 0x8000 } else { 0 };
+        let pixels: Vec<u32> = rgb
+            .iter()
+            .map(|[r, g, b]| (*r as u32 >> 3) | (*g as u32 >> 3) << 5 | (*b as u32 >> 3) << 10 | bit15)
+            .collect();
+        output.extend(pixels.chunks_exact(2).map(|pair| pair[0] | pair[1] << 16));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quantizer scale 1 and a 10 bit DC value
+    fn dc(value: i16) -> u16 {
+        1 << 10 | (value as u16 & 0x3FF)
+    }
+
+    fn decode(input: &[u16]) -> Option<(usize, [i16; 64])> {
+        decode_block(input, &STANDARD_QUANT_TABLE, &STANDARD_SCALE_TABLE)
+    }
+
+    #[test]
+    fn dc_only_blocks_are_flat() {
+        // The DC value is doubled by the quantizer then divided by 8 by the IDCT
+        let (used, block) = decode(&[dc(32), END_OF_BLOCK]).unwrap();
+        assert_eq!(used, 2);
+        assert!(block.iter().all(|&sample| sample == 8), "{:?}", block);
+
+        let (_, block) = decode(&[dc(-20), END_OF_BLOCK]).unwrap();
+        assert!(block.iter().all(|&sample| sample == -5), "{:?}", block);
+    }
+
+    #[test]
+    fn samples_are_clamped() {
+        let (_, block) = decode(&[dc(511), END_OF_BLOCK]).unwrap();
+        assert!(block.iter().all(|&sample| sample == 127));
+        let (_, block) = decode(&[dc(-512), END_OF_BLOCK]).unwrap();
+        assert!(block.iter().all(|&sample| sample == -128));
+    }
+
+    #[test]
+    fn first_ac_coefficient_varies_across_columns() {
+        // Run 0 puts it at zigzag position 1, the first horizontal frequency
+        let (used, block) = decode(&[dc(0), 64, END_OF_BLOCK]).unwrap();
+        assert_eq!(used, 3);
+        for row in block.chunks_exact(8) {
+            assert_eq!(row, &block[..8]);
+        }
+        // A half cosine, falling from left to right
+        assert!(block[..8].windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", &block[..8]);
+        assert!(block[0] > 0 && block[7] < 0);
+        assert_eq!(block[0], -block[7]);
+    }
+
+    #[test]
+    fn padding_before_a_block_is_skipped() {
+        let (used, block) = decode(&[END_OF_BLOCK, END_OF_BLOCK, dc(32), END_OF_BLOCK]).unwrap();
+        assert_eq!(used, 4);
+        assert_eq!(block[0], 8);
+    }
+
+    #[test]
+    fn incomplete_blocks_wait_for_more_input() {
+        assert!(decode(&[]).is_none());
+        assert!(decode(&[END_OF_BLOCK]).is_none());
+        assert!(decode(&[dc(32), 64]).is_none());
+    }
+}
@@ -0,0 +1,264 @@
+pub mod bitstream;
+pub mod decoder;
+
+use decoder::{Depth, OutputFormat, Tables};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const MDEC_START: u32 = 0x1F801820;
+pub const MDEC_END: u32 = 0x1F801827;
+
+// Command/parameter writes and data reads at +0, control writes and status reads at +4
+const MDEC_DATA: u32 = 0x0;
+const MDEC_CONTROL: u32 = 0x4;
+
+const CONTROL_RESET: u32 = 1 << 31;
+const CONTROL_DATA_IN_DMA: u32 = 1 << 30;
+const CONTROL_DATA_OUT_DMA: u32 = 1 << 29;
+
+const STATUS_OUT_EMPTY: u32 = 1 << 31;
+const STATUS_IN_FULL: u32 = 1 << 30;
+const STATUS_BUSY: u32 = 1 << 29;
+const STATUS_DATA_IN_REQUEST: u32 = 1 << 28;
+const STATUS_DATA_OUT_REQUEST: u32 = 1 << 27;
+
+// Depth, signed and bit 15 sit in command bits 25-28 and status bits 23-26
+const COMMAND_FORMAT_MASK: u32 = 0x1E00_0000;
+const SCALE_TABLE_WORDS: u16 = 32;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum Command {
+    Idle,
+    DecodeMacroblocks,
+    // With or without the chroma table
+    SetQuantTables(bool),
+    SetScaleTable,
+}
+
+/// Motion decoder, turning run-length encoded macroblocks into pixels
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Mdec {
+    tables: Tables,
+    control: u32,
+    command: Command,
+    command_word: u32,
+    // Parameter words still expected by the current command
+    remaining: u16,
+    // Undecoded halfwords and the finished blocks of the macroblock in progress, 64 samples each
+    input: Vec<u16>,
+    blocks: Vec<i16>,
+    params: Vec<u32>,
+    output: VecDeque<u32>,
+    write_latch: u32,
+    control_latch: u32,
+    read_latch: u32,
+}
+
+impl Mdec {
+    pub fn new() -> Self {
+        Mdec {
+            tables: Tables::new(),
+            control: 0,
+            command: Command::Idle,
+            command_word: 0,
+            remaining: 0,
+            input: Vec::new(),
+            blocks: Vec::new(),
+            params: Vec::new(),
+            output: VecDeque::new(),
+            write_latch: 0,
+            control_latch: 0,
+            read_latch: 0,
+        }
+    }
+
+    /// Abort the current command and clear the FIFOs, keeping the uploaded tables
+    pub fn reset(&mut self) {
+        *self = Mdec {
+            tables: self.tables.clone(),
+            ..Mdec::new()
+        };
+    }
+
+    fn format(&self) -> OutputFormat {
+        OutputFormat {
+            depth: Depth::from_bits(self.command_word >> 27),
+            signed: self.command_word & (1 << 26) != 0,
+            set_bit15: self.command_word & (1 << 25) != 0,
+        }
+    }
+
+    /// Whether DMA0 may feed another word
+    pub fn data_in_request(&self) -> bool {
+        self.control & CONTROL_DATA_IN_DMA != 0 && self.command != Command::Idle
+    }
+
+    pub fn data_out_request(&self) -> bool {
+        self.control & CONTROL_DATA_OUT_DMA != 0 && !self.output.is_empty()
+    }
+
+    /// Decoded words waiting for DMA1 or the CPU
+    pub fn output_len(&self) -> usize {
+        self.output.len()
+    }
+
+    pub fn pop_word(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+
+    fn current_block(&self) -> u32 {
+        if self.command != Command::DecodeMacroblocks || self.format().depth.is_monochrome() {
+            return 4;
+        }
+        // Blocks are sent Cr, Cb, Y1-Y4 but numbered Y1-Y4 = 0-3, Cr = 4, Cb = 5
+        match self.blocks.len() / 64 {
+            0 => 4,
+            1 => 5,
+            index => index as u32 - 2,
+        }
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = (self.command_word & COMMAND_FORMAT_MASK) >> 2;
+        status |= self.current_block() << 16;
+        status |= self.remaining.wrapping_sub(1) as u32;
+        if self.output.is_empty() {
+            status |= STATUS_OUT_EMPTY;
+        }
+        if self.command != Command::Idle || !self.output.is_empty() {
+            status |= STATUS_BUSY;
+        }
+        if self.data_in_request() {
+            status |= STATUS_DATA_IN_REQUEST;
+        }
+        if self.data_out_request() {
+            status |= STATUS_DATA_OUT_REQUEST;
+        }
+        status & !STATUS_IN_FULL
+    }
+
+    fn start_command(&mut self, value: u32) {
+        self.command_word = value;
+        self.params.clear();
+        (self.command, self.remaining) = match value >> 29 {
+            1 => (Command::DecodeMacroblocks, value as u16),
+            2 if value & 1 != 0 => (Command::SetQuantTables(true), 32),
+            2 => (Command::SetQuantTables(false), 16),
+            3 => (Command::SetScaleTable, SCALE_TABLE_WORDS),
+            _ => {
+                // Unknown commands take no parameters and are ignored
+                self.remaining = 0;
+                return;
+            }
+        };
+        if self.remaining == 0 {
+            self.finish_command();
+        }
+    }
+
+    fn finish_command(&mut self) {
+        match self.command {
+            Command::SetQuantTables(with_chroma) => {
+                let bytes: Vec<u8> = self.params.iter().flat_map(|word| word.to_le_bytes()).collect();
+                self.tables.luma_quant.copy_from_slice(&bytes[..64]);
+                if with_chroma {
+                    self.tables.chroma_quant.copy_from_slice(&bytes[64..]);
+                }
+            }
+            Command::SetScaleTable => {
+                for (index, word) in self.params.iter().enumerate() {
+                    self.tables.scale[index * 2] = *word as i16;
+                    self.tables.scale[index * 2 + 1] = (*word >> 16) as i16;
+                }
+            }
+            Command::DecodeMacroblocks | Command::Idle => {}
+        }
+        // Halfwords left over from an incomplete macroblock are dropped
+        self.input.clear();
+        self.blocks.clear();
+        self.command = Command::Idle;
+    }
+
+    fn decode_pending(&mut self) {
+        let format = self.format();
+        let mut position = 0;
+        while let Some((used, block)) = decoder::decode_block(
+            &self.input[position..],
+            decoder::block_quant(&self.tables, format.depth, self.blocks.len() / 64),
+            &self.tables.scale,
+        ) {
+            position += used;
+            self.blocks.extend_from_slice(&block);
+            if self.blocks.len() == decoder::blocks_per_macroblock(format.depth) * 64 {
+                let mut words = Vec::new();
+                decoder::output_macroblock(&self.blocks, format, &mut words);
+                self.output.extend(words);
+                self.blocks.clear();
+            }
+        }
+        self.input.drain(..position);
+    }
+
+    /// Accept a command or parameter word, from the CPU or DMA0
+    pub fn push_word(&mut self, value: u32) {
+        if self.command == Command::Idle {
+            self.start_command(value);
+            return;
+        }
+
+        match self.command {
+            Command::DecodeMacroblocks => {
+                self.input.push(value as u16);
+                self.input.push((value >> 16) as u16);
+                self.decode_pending();
+            }
+            _ => self.params.push(value),
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.finish_command();
+        }
+    }
+
+    fn write_control(&mut self, value: u32) {
+        if value & CONTROL_RESET != 0 {
+            self.reset();
+        }
+        self.control = value & (CONTROL_DATA_IN_DMA | CONTROL_DATA_OUT_DMA);
+    }
+
+    pub fn read(&mut self, addr: u32) -> u8 {
+        let offset = addr - MDEC_START;
+        let shift = (offset & 3) * 8;
+        match offset & !3 {
+            MDEC_DATA => {
+                if shift == 0 {
+                    self.read_latch = self.pop_word();
+                }
+                (self.read_latch >> shift) as u8
+            }
+            _ => (self.status() >> shift) as u8,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let offset = addr - MDEC_START;
+        let shift = (offset & 3) * 8;
+        let merge = |latch: u32| latch & !(0xFF << shift) | (value as u32) << shift;
+        match offset & !3 {
+            MDEC_DATA => {
+                self.write_latch = merge(self.write_latch);
+                if shift == 24 {
+                    self.push_word(self.write_latch);
+                }
+            }
+            MDEC_CONTROL => {
+                self.control_latch = merge(self.control_latch);
+                if shift == 24 {
+                    self.write_control(self.control_latch);
+                }
+            }
+            _ => {}
+        }
+    }
+}
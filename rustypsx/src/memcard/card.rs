@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const FRAME_SIZE: usize = 128;
+pub const FRAME_COUNT: usize = 1024;
+pub const BLOCK_FRAMES: usize = 64;
+pub const CARD_SIZE: usize = FRAME_SIZE * FRAME_COUNT;
+
+// FLAG byte: set until the first successful write after power-on/insertion, and on a failed write
+const FLAG_NOT_WRITTEN: u8 = 1 << 3;
+const FLAG_ERROR: u8 = 1 << 2;
+
+const STATUS_GOOD: u8 = 0x47;
+const STATUS_BAD_CHECKSUM: u8 = 0x4E;
+const STATUS_BAD_SECTOR: u8 = 0xFF;
+
+// Frames without a write before a written card goes back to its file, so a save is one file write
+const FLUSH_IDLE_FRAMES: u32 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum Command {
+    None,
+    Read,
+    Write,
+    GetId,
+}
+
+/// 128 KiB memory card, optionally backed by a raw `.mcr`/`.mcd` image on disk
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemoryCard {
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    // Where this host keeps the image, not part of the machine
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    flag: u8,
+    // Position within the current command, 0 being the address byte
+    step: u16,
+    command: Command,
+    sector: u16,
+    checksum: u8,
+    // Writes echo the previously received byte
+    last_byte: u8,
+    #[serde(with = "serde_bytes")]
+    write_buffer: Vec<u8>,
+    // Written since the image file was last brought up to date, and frames since that write
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    idle_frames: u32,
+}
+
+impl MemoryCard {
+    /// A freshly formatted card that is not saved anywhere
+    pub fn new() -> Self {
+        let mut card = MemoryCard {
+            data: vec![0; CARD_SIZE],
+            path: None,
+            flag: FLAG_NOT_WRITTEN,
+            step: 0,
+            command: Command::None,
+            sector: 0,
+            checksum: 0,
+            last_byte: 0,
+            write_buffer: vec![0; FRAME_SIZE],
+            dirty: false,
+            idle_frames: 0,
+        };
+        card.format();
+        // Nothing to write back until something changes it
+        card.dirty = false;
+        card
+    }
+
+    /// Open the card image at `path`, creating a formatted one if it does not exist yet
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut card = Self::new();
+        card.path = Some(path.to_path_buf());
+        match std::fs::read(path) {
+            Ok(data) => {
+                if data.len() != CARD_SIZE {
+                    return Err(format!("{} is {} bytes, expected a raw {} byte card image", path.display(), data.len(), CARD_SIZE));
+                }
+                card.data = data;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => card.flush()?,
+            Err(e) => return Err(format!("Failed to read memory card {}: {}", path.display(), e)),
+        }
+        Ok(card)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The card's contents, which are written back to its file like a game's writes
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        self.mark_dirty();
+        &mut self.data
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.idle_frames = 0;
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Keep writes in memory from now on, leaving the image file alone
+    pub fn detach(&mut self) {
+        self.path = None;
+    }
+
+    /// Write to the file `inserted` is backed by, bringing it up to date with this card's contents
+    pub fn take_file(&mut self, inserted: &MemoryCard) {
+        self.path = inserted.path.clone();
+        if self.path.is_some() {
+            self.mark_dirty();
+        }
+    }
+
+    /// Swap in a whole new card image, which the game sees as a card change
+    pub fn replace_data(&mut self, data: Vec<u8>) {
+        assert_eq!(data.len(), CARD_SIZE);
+        self.data = data;
+        self.flag = FLAG_NOT_WRITTEN;
+    }
+
+    /// Write the whole card back to its image file, if it has one
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.idle_frames = 0;
+        if let Some(path) = &self.path {
+            std::fs::write(path, &self.data).map_err(|e| format!("Failed to write memory card {}: {}", path.display(), e))?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Write the card back if the game has written to it since the last flush
+    pub fn flush_if_dirty(&mut self) -> Result<(), String> {
+        if self.dirty { self.flush() } else { Ok(()) }
+    }
+
+    /// Call once a frame, writes the card back once the game has stopped writing to it for a while
+    pub fn end_frame(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.idle_frames += 1;
+        if self.idle_frames < FLUSH_IDLE_FRAMES {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    /// Lay down an empty filesystem: header, free directory entries and an empty broken sector list
+    pub fn format(&mut self) {
+        self.mark_dirty();
+        self.data.fill(0);
+        self.data[0] = b'M';
+        self.data[1] = b'C';
+        for frame in 1..16 {
+            let entry = &mut self.data[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE];
+            entry[0] = 0xA0;
+            entry[8] = 0xFF;
+            entry[9] = 0xFF;
+        }
+        for frame in 16..36 {
+            let entry = &mut self.data[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE];
+            entry[0..4].fill(0xFF);
+            entry[8] = 0xFF;
+            entry[9] = 0xFF;
+        }
+        for frame in 0..36 {
+            update_frame_checksum(&mut self.data[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE]);
+        }
+        // The write test frame mirrors the header
+        self.data.copy_within(0..FRAME_SIZE, 63 * FRAME_SIZE);
+    }
+
+    fn frame(&self, sector: u16) -> &[u8] {
+        let start = sector as usize * FRAME_SIZE;
+        &self.data[start..start + FRAME_SIZE]
+    }
+
+    fn commit_write(&mut self) -> u8 {
+        if self.sector as usize >= FRAME_COUNT {
+            self.flag |= FLAG_ERROR;
+            return STATUS_BAD_SECTOR;
+        }
+        let checksum = self.write_buffer.iter().fold((self.sector >> 8) as u8 ^ self.sector as u8, |sum, byte| sum ^ byte);
+        if checksum != self.checksum {
+            self.flag |= FLAG_ERROR;
+            return STATUS_BAD_CHECKSUM;
+        }
+
+        let start = self.sector as usize * FRAME_SIZE;
+        self.data[start..start + FRAME_SIZE].copy_from_slice(&self.write_buffer);
+        self.flag = 0;
+        self.mark_dirty();
+        STATUS_GOOD
+    }
+
+    /// Exchange one byte with the card, returning the reply and whether it pulls /ACK low
+    pub fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let step = self.step;
+        self.step = self.step.saturating_add(1);
+        match (step, self.command) {
+            // Address byte 0x81, already matched by the port
+            (0, _) => (0xFF, true),
+            (1, _) => {
+                self.command = match value {
+                    0x52 => Command::Read,
+                    0x57 => Command::Write,
+                    0x53 => Command::GetId,
+                    _ => Command::None,
+                };
+                (self.flag, self.command != Command::None)
+            }
+            (2, _) => (0x5A, true),
+            (3, _) => (0x5D, true),
+
+            (4..=9, Command::GetId) => {
+                const ID: [u8; 6] = [0x5C, 0x5D, 0x04, 0x00, 0x00, 0x80];
+                (ID[step as usize - 4], step < 9)
+            }
+
+            (4, Command::Read | Command::Write) => {
+                self.sector = (value as u16) << 8;
+                self.last_byte = value;
+                (0x00, true)
+            }
+            (5, Command::Read | Command::Write) => {
+                self.sector |= value as u16;
+                let reply = self.last_byte;
+                self.last_byte = value;
+                self.checksum = (self.sector >> 8) as u8 ^ self.sector as u8;
+                (reply, true)
+            }
+
+            (6, Command::Read) => (0x5C, true),
+            (7, Command::Read) => (0x5D, true),
+            (8, Command::Read) => {
+                if self.sector as usize >= FRAME_COUNT {
+                    (0xFF, true)
+                } else {
+                    ((self.sector >> 8) as u8, true)
+                }
+            }
+            // An out of range sector ends the command after the confirmed address
+            (9, Command::Read) => {
+                if self.sector as usize >= FRAME_COUNT {
+                    (0xFF, false)
+                } else {
+                    (self.sector as u8, true)
+                }
+            }
+            (10..=137, Command::Read) => {
+                let byte = self.frame(self.sector)[step as usize - 10];
+                self.checksum ^= byte;
+                (byte, true)
+            }
+            (138, Command::Read) => (self.checksum, true),
+            (139, Command::Read) => (STATUS_GOOD, false),
+
+            (6..=133, Command::Write) => {
+                self.write_buffer[step as usize - 6] = value;
+                let reply = self.last_byte;
+                self.last_byte = value;
+                (reply, true)
+            }
+            (134, Command::Write) => {
+                self.checksum = value;
+                (self.last_byte, true)
+            }
+            (135, Command::Write) => (0x5C, true),
+            (136, Command::Write) => (0x5D, true),
+            (137, Command::Write) => (self.commit_write(), false),
+
+            _ => (0xFF, false),
+        }
+    }
+
+    /// Called when /JOYn goes high, aborting any command in progress
+    pub fn deselect(&mut self) {
+        self.step = 0;
+        self.command = Command::None;
+    }
+}
+
+/// Store the XOR of the first 127 bytes of a frame in its last byte
+pub fn update_frame_checksum(frame: &mut [u8]) {
+    frame[FRAME_SIZE - 1] = frame[..FRAME_SIZE - 1].iter().fold(0, |sum, byte| sum ^ byte);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a whole command from the address byte, returns the replies and whether the last was acknowledged
+    fn command(card: &mut MemoryCard, bytes: &[u8]) -> (Vec<u8>, bool) {
+        card.deselect();
+        assert_eq!(card.transfer(0x81), (0xFF, true));
+        let mut replies = Vec::new();
+        let mut ack = true;
+        for &value in bytes {
+            assert!(ack, "byte {} sent after the card stopped acknowledging", replies.len());
+            let (reply, acknowledged) = card.transfer(value);
+            replies.push(reply);
+            ack = acknowledged;
+        }
+        (replies, ack)
+    }
+
+    fn read_command(sector: u16) -> Vec<u8> {
+        let mut bytes = vec![0x52, 0x00, 0x00, (sector >> 8) as u8, sector as u8];
+        bytes.resize(bytes.len() + 4 + FRAME_SIZE + 2, 0x00);
+        bytes
+    }
+
+    fn write_command(sector: u16, data: &[u8], checksum: u8) -> Vec<u8> {
+        let mut bytes = vec![0x57, 0x00, 0x00, (sector >> 8) as u8, sector as u8];
+        bytes.extend(data);
+        bytes.extend([checksum, 0x00, 0x00, 0x00]);
+        bytes
+    }
+
+    fn pattern() -> Vec<u8> {
+        (0..FRAME_SIZE as u8).map(|byte| byte.wrapping_mul(3)).collect()
+    }
+
+    fn checksum(sector: u16, data: &[u8]) -> u8 {
+        data.iter().fold((sector >> 8) as u8 ^ sector as u8, |sum, byte| sum ^ byte)
+    }
+
+    #[test]
+    fn get_id_reports_the_card_size() {
+        let mut card = MemoryCard::new();
+        let (replies, ack) = command(&mut card, &[0x53, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(replies, [FLAG_NOT_WRITTEN, 0x5A, 0x5D, 0x5C, 0x5D, 0x04, 0x00, 0x00, 0x80]);
+        assert!(!ack);
+    }
+
+    #[test]
+    fn unknown_commands_only_return_the_flag() {
+        let mut card = MemoryCard::new();
+        assert_eq!(command(&mut card, &[0x00]), (vec![FLAG_NOT_WRITTEN], false));
+    }
+
+    #[test]
+    fn reads_return_the_frame_with_its_checksum() {
+        let mut card = MemoryCard::new();
+        let data = pattern();
+        card.data[0x123 * FRAME_SIZE..0x124 * FRAME_SIZE].copy_from_slice(&data);
+        let (replies, ack) = command(&mut card, &read_command(0x123));
+        assert_eq!(replies[..9], [FLAG_NOT_WRITTEN, 0x5A, 0x5D, 0x00, 0x01, 0x5C, 0x5D, 0x01, 0x23]);
+        assert_eq!(replies[9..9 + FRAME_SIZE], data);
+        assert_eq!(replies[9 + FRAME_SIZE..], [checksum(0x123, &data), STATUS_GOOD]);
+        assert!(!ack);
+    }
+
+    #[test]
+    fn reads_past_the_end_stop_after_the_address() {
+        let mut card = MemoryCard::new();
+        let (replies, ack) = command(&mut card, &read_command(FRAME_COUNT as u16)[..9]);
+        assert_eq!(replies[7..], [0xFF, 0xFF]);
+        assert!(!ack);
+    }
+
+    #[test]
+    fn writes_store_the_frame_and_clear_the_flag() {
+        let mut card = MemoryCard::new();
+        let data = pattern();
+        let (replies, ack) = command(&mut card, &write_command(0x40, &data, checksum(0x40, &data)));
+        // Each data byte echoes the one before it
+        assert_eq!(replies[..5], [FLAG_NOT_WRITTEN, 0x5A, 0x5D, 0x00, 0x00]);
+        assert_eq!(replies[5], 0x40);
+        assert_eq!(replies[6..5 + FRAME_SIZE], data[..FRAME_SIZE - 1]);
+        assert_eq!(replies[5 + FRAME_SIZE..], [data[FRAME_SIZE - 1], 0x5C, 0x5D, STATUS_GOOD]);
+        assert!(!ack);
+        assert_eq!(card.frame(0x40), data);
+        assert!(card.dirty);
+        assert_eq!(command(&mut card, &[0x53]).0, [0x00]);
+    }
+
+    #[test]
+    fn bad_writes_are_refused_and_set_the_error_flag() {
+        let mut card = MemoryCard::new();
+        let data = pattern();
+        let before = card.frame(0x40).to_vec();
+        let (replies, _) = command(&mut card, &write_command(0x40, &data, checksum(0x40, &data) ^ 1));
+        assert_eq!(replies.last(), Some(&STATUS_BAD_CHECKSUM));
+        assert_eq!(card.frame(0x40), before);
+        let (replies, _) = command(&mut card, &write_command(FRAME_COUNT as u16, &data, checksum(FRAME_COUNT as u16, &data)));
+        assert_eq!(replies.last(), Some(&STATUS_BAD_SECTOR));
+        assert_eq!(command(&mut card, &[0x53]).0, [FLAG_NOT_WRITTEN | FLAG_ERROR]);
+    }
+
+    #[test]
+    fn deselecting_abandons_a_write() {
+        let mut card = MemoryCard::new();
+        let data = pattern();
+        let before = card.frame(0x40).to_vec();
+        command(&mut card, &write_command(0x40, &data, checksum(0x40, &data))[..100]);
+        card.deselect();
+        assert_eq!(card.transfer(0x81), (0xFF, true));
+        assert_eq!(card.transfer(0x53), (FLAG_NOT_WRITTEN, true));
+        assert_eq!(card.frame(0x40), before);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::memcard::{update_frame_checksum, BLOCK_FRAMES, FRAME_SIZE};
+
+pub const BLOCK_SIZE: usize = FRAME_SIZE * BLOCK_FRAMES;
+pub const SAVE_BLOCKS: usize = 15;
+
+const FILENAME_OFFSET: usize = 0x0A;
+const FILENAME_LENGTH: usize = 20;
+const NO_NEXT_BLOCK: u16 = 0xFFFF;
+
+const STATE_FREE: u8 = 0xA0;
+const STATE_FIRST: u8 = 0x51;
+const STATE_MIDDLE: u8 = 0x52;
+const STATE_LAST: u8 = 0x53;
+// Deleting a save only flips the in-use states to these, which can later be reused
+const STATE_DELETED_FIRST: u8 = 0xA1;
+const STATE_DELETED_MIDDLE: u8 = 0xA2;
+const STATE_DELETED_LAST: u8 = 0xA3;
+
+const ICON_PIXELS: usize = 16 * 16;
+
+/// A save as listed in the card directory
+pub struct Save {
+    /// Card blocks in chain order, 1-15
+    pub blocks: Vec<usize>,
+    /// Product code and game specific name, e.g. BASCUS-94426...
+    pub filename: String,
+    pub title: String,
+    /// 16x16 RGBA icon frames, one to three of them
+    pub icon_frames: Vec<Vec<u8>>,
+}
+
+impl Save {
+    pub fn first_block(&self) -> usize {
+        self.blocks[0]
+    }
+}
+
+/// A save lifted off a card: its directory entry and the contents of its blocks
+pub struct SaveFile {
+    pub directory_entry: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(filename: &str, data: Vec<u8>) -> Self {
+        let mut directory_entry = vec![0; FRAME_SIZE];
+        directory_entry[0] = STATE_FIRST;
+        directory_entry[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        directory_entry[8..10].copy_from_slice(&NO_NEXT_BLOCK.to_le_bytes());
+        let name = filename.as_bytes();
+        let length = name.len().min(FILENAME_LENGTH);
+        directory_entry[FILENAME_OFFSET..FILENAME_OFFSET + length].copy_from_slice(&name[..length]);
+        update_frame_checksum(&mut directory_entry);
+        SaveFile { directory_entry, data }
+    }
+
+    pub fn filename(&self) -> String {
+        read_filename(&self.directory_entry)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.data.len() / BLOCK_SIZE
+    }
+}
+
+fn directory_entry(card: &[u8], block: usize) -> &[u8] {
+    &card[block * FRAME_SIZE..(block + 1) * FRAME_SIZE]
+}
+
+fn directory_entry_mut(card: &mut [u8], block: usize) -> &mut [u8] {
+    &mut card[block * FRAME_SIZE..(block + 1) * FRAME_SIZE]
+}
+
+fn block_data(card: &[u8], block: usize) -> &[u8] {
+    &card[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE]
+}
+
+fn read_filename(entry: &[u8]) -> String {
+    let name = &entry[FILENAME_OFFSET..FILENAME_OFFSET + FILENAME_LENGTH];
+    let length = name.iter().position(|&byte| byte == 0).unwrap_or(FILENAME_LENGTH);
+    String::from_utf8_lossy(&name[..length]).into_owned()
+}
+
+fn next_block(entry: &[u8]) -> Option<usize> {
+    match u16::from_le_bytes([entry[8], entry[9]]) {
+        next @ 0..=14 => Some(next as usize + 1),
+        _ => None,
+    }
+}
+
+/// Follow the directory chain starting at `first_block`
+fn block_chain(card: &[u8], first_block: usize) -> Vec<usize> {
+    let mut blocks = vec![first_block];
+    let mut block = first_block;
+    while let Some(next) = next_block(directory_entry(card, block)) {
+        // Stop on corrupted chains rather than looping forever
+        if blocks.contains(&next) || blocks.len() >= SAVE_BLOCKS {
+            break;
+        }
+        blocks.push(next);
+        block = next;
+    }
+    blocks
+}
+
+/// Decode the Shift-JIS title from a save's title frame
+pub fn decode_title(raw: &[u8]) -> String {
+    let length = raw.iter().position(|&byte| byte == 0).unwrap_or(raw.len());
+    let (title, _, _) = encoding_rs::SHIFT_JIS.decode(&raw[..length]);
+    // Titles are mostly written in full-width ASCII, which the GUI font cannot show
+    title
+        .chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap_or(c),
+            '\u{3000}' => ' ',
+            _ => c,
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+fn bgr555_to_rgba(color: u16) -> [u8; 4] {
+    let expand = |value: u16| ((value & 0x1F) << 3 | (value & 0x1F) >> 2) as u8;
+    let alpha = if color == 0 { 0 } else { 0xFF };
+    [expand(color), expand(color >> 5), expand(color >> 10), alpha]
+}
+
+/// Convert the 4bpp icon frames that follow the title frame into RGBA images
+fn decode_icon_frames(block: &[u8]) -> Vec<Vec<u8>> {
+    let frame_count = match block[2] {
+        0x12 => 2,
+        0x13 => 3,
+        _ => 1,
+    };
+    let palette: Vec<[u8; 4]> = block[0x60..0x80]
+        .chunks_exact(2)
+        .map(|color| bgr555_to_rgba(u16::from_le_bytes([color[0], color[1]])))
+        .collect();
+
+    (1..=frame_count)
+        .map(|frame| {
+            let pixels = &block[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE];
+            let mut rgba = Vec::with_capacity(ICON_PIXELS * 4);
+            for byte in pixels {
+                rgba.extend_from_slice(&palette[(byte & 0x0F) as usize]);
+                rgba.extend_from_slice(&palette[(byte >> 4) as usize]);
+            }
+            rgba
+        })
+        .collect()
+}
+
+/// List every save on a raw card image
+pub fn list_saves(card: &[u8]) -> Vec<Save> {
+    (1..=SAVE_BLOCKS)
+        .filter(|&block| directory_entry(card, block)[0] == STATE_FIRST)
+        .map(|first_block| {
+            let entry = directory_entry(card, first_block);
+            let block = block_data(card, first_block);
+            let has_title_frame = &block[0..2] == b"SC";
+            Save {
+                blocks: block_chain(card, first_block),
+                filename: read_filename(entry),
+                title: if has_title_frame { decode_title(&block[4..0x44]) } else { String::new() },
+                icon_frames: if has_title_frame { decode_icon_frames(block) } else { Vec::new() },
+            }
+        })
+        .collect()
+}
+
+pub fn free_blocks(card: &[u8]) -> usize {
+    (1..=SAVE_BLOCKS)
+        .filter(|&block| is_free(directory_entry(card, block)[0]))
+        .count()
+}
+
+fn is_free(state: u8) -> bool {
+    matches!(state, STATE_FREE | STATE_DELETED_FIRST | STATE_DELETED_MIDDLE | STATE_DELETED_LAST)
+}
+
+/// Mark every block of the save starting at `first_block` as deleted
+pub fn delete_save(card: &mut [u8], first_block: usize) -> Result<(), String> {
+    if directory_entry(card, first_block)[0] != STATE_FIRST {
+        return Err(format!("Block {} does not start a save", first_block));
+    }
+    for block in block_chain(card, first_block) {
+        let entry = directory_entry_mut(card, block);
+        entry[0] = match entry[0] {
+            STATE_FIRST => STATE_DELETED_FIRST,
+            STATE_MIDDLE => STATE_DELETED_MIDDLE,
+            _ => STATE_DELETED_LAST,
+        };
+        update_frame_checksum(entry);
+    }
+    Ok(())
+}
+
+pub fn export_save(card: &[u8], first_block: usize) -> Result<SaveFile, String> {
+    if directory_entry(card, first_block)[0] != STATE_FIRST {
+        return Err(format!("Block {} does not start a save", first_block));
+    }
+    let blocks = block_chain(card, first_block);
+    let mut directory_entry = directory_entry(card, first_block).to_vec();
+    directory_entry[8..10].copy_from_slice(&NO_NEXT_BLOCK.to_le_bytes());
+    update_frame_checksum(&mut directory_entry);
+    let data = blocks.iter().flat_map(|&block| block_data(card, block).iter().copied()).collect();
+    Ok(SaveFile { directory_entry, data })
+}
+
+/// Write a save into free blocks, linking up a new directory chain for it
+pub fn import_save(card: &mut [u8], save: &SaveFile) -> Result<(), String> {
+    let block_count = save.block_count();
+    if block_count == 0 || !save.data.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(format!("Save data is {} bytes, not a whole number of blocks", save.data.len()));
+    }
+    let filename = save.filename();
+    if list_saves(card).iter().any(|existing| existing.filename == filename) {
+        return Err(format!("A save named {} is already on the card", filename));
+    }
+    let free: Vec<usize> = (1..=SAVE_BLOCKS)
+        .filter(|&block| is_free(directory_entry(card, block)[0]))
+        .take(block_count)
+        .collect();
+    if free.len() < block_count {
+        return Err(format!("Save needs {} blocks but only {} are free", block_count, free.len()));
+    }
+
+    for (index, &block) in free.iter().enumerate() {
+        let entry = directory_entry_mut(card, block);
+        if index == 0 {
+            entry.copy_from_slice(&save.directory_entry);
+            entry[0] = STATE_FIRST;
+            entry[4..8].copy_from_slice(&(save.data.len() as u32).to_le_bytes());
+        } else {
+            entry.fill(0);
+            entry[0] = if index + 1 == block_count { STATE_LAST } else { STATE_MIDDLE };
+        }
+        let next = free.get(index + 1).map_or(NO_NEXT_BLOCK, |&next| (next - 1) as u16);
+        entry[8..10].copy_from_slice(&next.to_le_bytes());
+        update_frame_checksum(entry);
+
+        let data = &save.data[index * BLOCK_SIZE..(index + 1) * BLOCK_SIZE];
+        card[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].copy_from_slice(data);
+    }
+    Ok(())
+}
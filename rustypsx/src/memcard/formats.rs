@@ -0,0 +1,317 @@
+//! Memory card and single save file formats used by other emulators and consoles.
+//!
+//! The PSP (.vmp) and PS3 (.psv) formats carry a signature over their contents. It is
+//! ignored on import and left blank on export, so those consoles need the file resigned.
+
+use crate::memcard::directory::{SaveFile, BLOCK_SIZE};
+use crate::memcard::{FRAME_SIZE, CARD_SIZE};
+use std::path::Path;
+
+const GME_MAGIC: &[u8] = b"123-456-STD";
+// Includes a 256 byte comment per save from 0x40 onwards, left empty on export
+const GME_HEADER_SIZE: usize = 3904;
+
+const VMP_MAGIC: &[u8] = b"\0PMV";
+const VMP_HEADER_SIZE: usize = 0x80;
+
+const PSV_MAGIC: &[u8] = b"\0VSP";
+const PSV_HEADER_SIZE: usize = 0x84;
+const PSV_FILENAME_OFFSET: usize = 0x64;
+
+const ACTION_REPLAY_HEADER_SIZE: usize = 54;
+const ACTION_REPLAY_TITLE_OFFSET: usize = 21;
+
+/// Whole card images
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CardFormat {
+    /// .mcr/.mcd/.mem, a plain 128 KiB dump
+    Raw,
+    /// .gme, InterAct DexDrive
+    Gme,
+    /// .vmp, PSP virtual memory card
+    Vmp,
+}
+
+/// Files holding a single save
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SaveFormat {
+    /// .mcs, directory frame followed by the save blocks
+    Mcs,
+    /// .psx, Action Replay/Xplorer
+    ActionReplay,
+    /// .psv, PS3 exported save
+    Psv,
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+impl CardFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match extension(path).as_str() {
+            "mcr" | "mcd" | "mem" | "srm" => Some(CardFormat::Raw),
+            "gme" => Some(CardFormat::Gme),
+            "vmp" => Some(CardFormat::Vmp),
+            _ => None,
+        }
+    }
+}
+
+impl SaveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match extension(path).as_str() {
+            "mcs" => Some(SaveFormat::Mcs),
+            "psx" => Some(SaveFormat::ActionReplay),
+            "psv" => Some(SaveFormat::Psv),
+            _ => None,
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    std::fs::write(path, data).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Load a card image, picking the format from the file extension
+pub fn read_card(path: &Path) -> Result<Vec<u8>, String> {
+    let format = CardFormat::from_path(path)
+        .ok_or_else(|| format!("{} is not a known memory card format", path.display()))?;
+    let file = read_file(path)?;
+    let header_size = match format {
+        CardFormat::Raw => 0,
+        CardFormat::Gme => {
+            if !file.starts_with(GME_MAGIC) {
+                return Err(format!("{} is not a DexDrive image", path.display()));
+            }
+            GME_HEADER_SIZE
+        }
+        CardFormat::Vmp => {
+            if !file.starts_with(VMP_MAGIC) {
+                return Err(format!("{} is not a PSP memory card image", path.display()));
+            }
+            VMP_HEADER_SIZE
+        }
+    };
+    if file.len() != header_size + CARD_SIZE {
+        return Err(format!("{} is {} bytes, expected {}", path.display(), file.len(), header_size + CARD_SIZE));
+    }
+    Ok(file[header_size..].to_vec())
+}
+
+pub fn write_card(path: &Path, card: &[u8]) -> Result<(), String> {
+    let format = CardFormat::from_path(path)
+        .ok_or_else(|| format!("{} is not a known memory card format", path.display()))?;
+    let mut file = match format {
+        CardFormat::Raw => Vec::new(),
+        CardFormat::Gme => {
+            let mut header = vec![0; GME_HEADER_SIZE];
+            header[..GME_MAGIC.len()].copy_from_slice(GME_MAGIC);
+            header[18] = 0x01;
+            header[20] = 0x01;
+            header[21] = b'M';
+            // DexDrive keeps a copy of each directory entry's state and next block bytes
+            for entry in 0..15 {
+                let frame = &card[(entry + 1) * FRAME_SIZE..(entry + 2) * FRAME_SIZE];
+                header[22 + entry] = frame[0];
+                header[38 + entry] = frame[8];
+            }
+            header
+        }
+        CardFormat::Vmp => {
+            let mut header = vec![0; VMP_HEADER_SIZE];
+            header[..VMP_MAGIC.len()].copy_from_slice(VMP_MAGIC);
+            header[4..8].copy_from_slice(&(VMP_HEADER_SIZE as u32).to_le_bytes());
+            header
+        }
+    };
+    file.extend_from_slice(card);
+    write_file(path, &file)
+}
+
+pub fn read_save(path: &Path) -> Result<SaveFile, String> {
+    let format = SaveFormat::from_path(path)
+        .ok_or_else(|| format!("{} is not a known save format", path.display()))?;
+    let file = read_file(path)?;
+    let save = match format {
+        SaveFormat::Mcs => {
+            if file.len() < FRAME_SIZE {
+                return Err(format!("{} is too short for a save", path.display()));
+            }
+            SaveFile {
+                directory_entry: file[..FRAME_SIZE].to_vec(),
+                data: file[FRAME_SIZE..].to_vec(),
+            }
+        }
+        SaveFormat::ActionReplay => {
+            if file.len() < ACTION_REPLAY_HEADER_SIZE {
+                return Err(format!("{} is too short for a save", path.display()));
+            }
+            let filename = c_string(&file[..ACTION_REPLAY_TITLE_OFFSET]);
+            SaveFile::new(&filename, file[ACTION_REPLAY_HEADER_SIZE..].to_vec())
+        }
+        SaveFormat::Psv => {
+            if !file.starts_with(PSV_MAGIC) || file.len() < PSV_HEADER_SIZE {
+                return Err(format!("{} is not a PS3 save", path.display()));
+            }
+            if u32::from_le_bytes([file[0x3C], file[0x3D], file[0x3E], file[0x3F]]) != 1 {
+                return Err(format!("{} is a PS2 save, not a PS1 one", path.display()));
+            }
+            let filename = c_string(&file[PSV_FILENAME_OFFSET..PSV_FILENAME_OFFSET + 20]);
+            SaveFile::new(&filename, file[PSV_HEADER_SIZE..].to_vec())
+        }
+    };
+    if save.data.is_empty() || !save.data.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(format!("{} holds {} bytes of save data, not a whole number of blocks", path.display(), save.data.len()));
+    }
+    Ok(save)
+}
+
+pub fn write_save(path: &Path, save: &SaveFile) -> Result<(), String> {
+    let format = SaveFormat::from_path(path)
+        .ok_or_else(|| format!("{} is not a known save format", path.display()))?;
+    let filename = save.filename();
+    let mut file = match format {
+        SaveFormat::Mcs => save.directory_entry.clone(),
+        SaveFormat::ActionReplay => {
+            let mut header = vec![0; ACTION_REPLAY_HEADER_SIZE];
+            let name = filename.as_bytes();
+            let length = name.len().min(ACTION_REPLAY_TITLE_OFFSET - 1);
+            header[..length].copy_from_slice(&name[..length]);
+            header
+        }
+        SaveFormat::Psv => {
+            let mut header = vec![0; PSV_HEADER_SIZE];
+            header[..PSV_MAGIC.len()].copy_from_slice(PSV_MAGIC);
+            header[0x38..0x3C].copy_from_slice(&0x14u32.to_le_bytes());
+            header[0x3C..0x40].copy_from_slice(&1u32.to_le_bytes());
+            header[0x40..0x44].copy_from_slice(&(save.data.len() as u32).to_le_bytes());
+            header[0x44..0x48].copy_from_slice(&(PSV_HEADER_SIZE as u32).to_le_bytes());
+            header[0x48..0x4C].copy_from_slice(&0x200u32.to_le_bytes());
+            header[0x5C..0x60].copy_from_slice(&0x2000u32.to_le_bytes());
+            header[0x60..0x64].copy_from_slice(&0x9003u32.to_le_bytes());
+            let name = filename.as_bytes();
+            let length = name.len().min(20);
+            header[PSV_FILENAME_OFFSET..PSV_FILENAME_OFFSET + length].copy_from_slice(&name[..length]);
+            header
+        }
+    };
+    file.extend_from_slice(&save.data);
+    write_file(path, &file)
+}
+
+fn c_string(raw: &[u8]) -> String {
+    let length = raw.iter().position(|&byte| byte == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..length]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memcard::MemoryCard;
+    use crate::memcard::directory::{export_save, import_save, list_saves};
+    use std::path::PathBuf;
+
+    const FILENAME: &str = "BASLUS-00000SAVE";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustypsx-{}-{}", std::process::id(), name))
+    }
+
+    fn formatted_card() -> Vec<u8> {
+        let mut card = MemoryCard::new();
+        card.format();
+        card.data().to_vec()
+    }
+
+    // Two blocks, each filled with its own byte
+    fn save_file() -> SaveFile {
+        let mut data = vec![0x11; BLOCK_SIZE];
+        data.extend(vec![0x22; BLOCK_SIZE]);
+        SaveFile::new(FILENAME, data)
+    }
+
+    #[test]
+    fn card_formats_round_trip() {
+        let mut card = formatted_card();
+        import_save(&mut card, &save_file()).unwrap();
+        for extension in ["mcr", "gme", "vmp"] {
+            let path = temp_path(&format!("card.{}", extension));
+            write_card(&path, &card).unwrap();
+            let read = read_card(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(read.unwrap() == card, "{} changed the card", extension);
+        }
+    }
+
+    #[test]
+    fn dexdrive_header_mirrors_the_directory() {
+        let mut card = formatted_card();
+        import_save(&mut card, &save_file()).unwrap();
+        let path = temp_path("mirror.gme");
+        write_card(&path, &card).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.starts_with(GME_MAGIC));
+        assert_eq!(file[22], card[FRAME_SIZE]);
+        assert_eq!(file[38], card[FRAME_SIZE + 8]);
+    }
+
+    #[test]
+    fn bad_card_images_are_refused() {
+        let short = temp_path("short.mcr");
+        std::fs::write(&short, vec![0; CARD_SIZE - 1]).unwrap();
+        let unsigned = temp_path("unsigned.vmp");
+        std::fs::write(&unsigned, vec![0; VMP_HEADER_SIZE + CARD_SIZE]).unwrap();
+        let results = [read_card(&short), read_card(&unsigned), read_card(Path::new("card.bin"))];
+        std::fs::remove_file(short).unwrap();
+        std::fs::remove_file(unsigned).unwrap();
+        assert!(results.iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn save_formats_round_trip() {
+        let save = save_file();
+        for extension in ["mcs", "psx", "psv"] {
+            let path = temp_path(&format!("save.{}", extension));
+            write_save(&path, &save).unwrap();
+            let read = read_save(&path);
+            std::fs::remove_file(&path).unwrap();
+            let read = read.unwrap();
+            assert_eq!(read.filename(), FILENAME, "{} lost the filename", extension);
+            assert!(read.data == save.data, "{} changed the save data", extension);
+        }
+    }
+
+    #[test]
+    fn partial_blocks_are_refused() {
+        let path = temp_path("partial.mcs");
+        let mut file = save_file().directory_entry;
+        file.extend(vec![0; BLOCK_SIZE / 2]);
+        std::fs::write(&path, file).unwrap();
+        let read = read_save(&path);
+        std::fs::remove_file(path).unwrap();
+        assert!(read.is_err());
+    }
+
+    #[test]
+    fn saves_import_and_export_through_a_card() {
+        let mut card = formatted_card();
+        import_save(&mut card, &save_file()).unwrap();
+        let saves = list_saves(&card);
+        assert_eq!(saves.len(), 1);
+        assert_eq!(saves[0].filename, FILENAME);
+        assert_eq!(saves[0].blocks, [1, 2]);
+
+        let exported = export_save(&card, saves[0].first_block()).unwrap();
+        assert!(exported.data == save_file().data);
+        assert!(import_save(&mut card, &exported).unwrap_err().contains("already on the card"));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod card;
+pub mod directory;
+pub mod formats;
+
+pub use card::*;
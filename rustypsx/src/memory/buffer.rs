@@ -2,8 +2,9 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Memory<const START: u32, const SIZE: usize> {
+    // Heap allocated, RAM and VRAM are too large for the stack
     #[serde(with = "serde_bytes")]
-    data: [u8; SIZE],
+    data: Vec<u8>,
 }
 
 impl<const START: u32, const SIZE: usize> Default for Memory<START, SIZE> {
@@ -15,22 +16,36 @@ impl<const START: u32, const SIZE: usize> Default for Memory<START, SIZE> {
 impl<const START: u32, const SIZE: usize> Memory<START, SIZE> {
     pub fn new() -> Self {
         Memory {
-            data: [0; SIZE],
+            data: vec![0; SIZE],
         }
     }
 
+    /// Read without needing `&mut`, for debugging tools
+    pub fn peek(&self, addr: u32) -> u8 {
+        self.data[Self::normalize_addr(addr) as usize]
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take on `other`'s contents without a new allocation
+    pub fn copy_from(&mut self, other: &Self) {
+        self.data.copy_from_slice(&other.data);
+    }
+
     fn normalize_addr(addr: u32) -> u32 {
         addr - START
     }
 }
 
 pub trait Addressable {
-    fn read(&self, addr: u32) -> u8;
+    fn read(&mut self, addr: u32) -> u8;
     fn write(&mut self, addr: u32, value: u8);
 }
 
 impl<const START: u32, const SIZE: usize> Addressable for Memory<START, SIZE> {
-    fn read(&self, addr: u32) -> u8 {
+    fn read(&mut self, addr: u32) -> u8 {
         let offset = Self::normalize_addr(addr);
         self.data[offset as usize]
     }
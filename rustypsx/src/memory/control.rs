@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+pub const MEMORY_CONTROL_START: u32 = 0x1F801000;
+pub const MEMORY_CONTROL_END: u32 = 0x1F801023;
+pub const RAM_SIZE_START: u32 = 0x1F801060;
+pub const RAM_SIZE_END: u32 = 0x1F801063;
+// In KSEG2, so it is matched on the virtual address
+pub const CACHE_CONTROL_START: u32 = 0xFFFE0130;
+pub const CACHE_CONTROL_END: u32 = 0xFFFE0133;
+
+const EXPANSION_1_BASE: u32 = 0x00;
+const EXPANSION_2_BASE: u32 = 0x04;
+const COM_DELAY: u32 = 0x20;
+// Delay/size registers between the two base addresses and COM_DELAY
+const DELAY_SIZE_START: u32 = 0x08;
+const DELAY_SIZE_COUNT: usize = 6;
+
+const DELAY_USE_COM0: u32 = 1 << 8;
+const DELAY_USE_COM2: u32 = 1 << 10;
+const DELAY_USE_COM3: u32 = 1 << 11;
+const DELAY_BUS_16BIT: u32 = 1 << 12;
+
+const CACHE_TAG_TEST: u32 = 1 << 2;
+const CACHE_SCRATCHPAD_ENABLE_1: u32 = 1 << 3;
+const CACHE_SCRATCHPAD_ENABLE_2: u32 = 1 << 7;
+const CACHE_CODE_ENABLE: u32 = 1 << 11;
+
+// What the BIOS writes during init, used from reset so nothing changes before it runs
+const DEFAULT_RAM_SIZE: u32 = 0x00000B88;
+const DEFAULT_DELAY_SIZE: [u32; DELAY_SIZE_COUNT] = [0x0013243F, 0x00003022, 0x0013243F, 0x200931E1, 0x00020843, 0x00070777];
+const DEFAULT_COM_DELAY: u32 = 0x00031125;
+
+/// Devices with their own delay/size register, in register order
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Expansion1 = 0,
+    Expansion3 = 1,
+    Bios = 2,
+    Spu = 3,
+    CdRom = 4,
+    Expansion2 = 5,
+}
+
+/// What part of the 8 MB RAM window an address falls in, as set by RAM_SIZE
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RamAccess {
+    // Offset into the 2 MB of RAM
+    Ram(u32),
+    // Nothing drives the bus
+    HighZ,
+    // Bus error on hardware
+    Locked,
+}
+
+/// Memory control registers, which set up the bus for everything outside RAM, and the cache control register
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MemoryControl {
+    expansion_1_base: u32,
+    expansion_2_base: u32,
+    delay_size: [u32; DELAY_SIZE_COUNT],
+    com_delay: u32,
+    ram_size: u32,
+    cache_control: u32,
+}
+
+impl MemoryControl {
+    pub fn new() -> Self {
+        MemoryControl {
+            expansion_1_base: 0x1F000000,
+            expansion_2_base: 0x1F802000,
+            delay_size: DEFAULT_DELAY_SIZE,
+            com_delay: DEFAULT_COM_DELAY,
+            ram_size: DEFAULT_RAM_SIZE,
+            cache_control: 0,
+        }
+    }
+
+    pub fn expansion_1_base(&self) -> u32 {
+        self.expansion_1_base & 0x00FFFFFF | 0x1F000000
+    }
+
+    pub fn expansion_2_base(&self) -> u32 {
+        self.expansion_2_base & 0x00FFFFFF | 0x1F000000
+    }
+
+    /// Bytes of the region that respond, accesses past this see an empty bus
+    pub fn window_size(&self, region: Region) -> u32 {
+        1 << ((self.delay_size[region as usize] >> 16) & 0x1F)
+    }
+
+    /// Cycles for a read or write of `width` bytes to the region
+    ///
+    /// The first access pays the full delay, each further access needed to cover the width on an
+    /// 8-bit or 16-bit bus pays the shorter sequential delay.
+    pub fn access_cycles(&self, region: Region, width: u32, write: bool) -> u32 {
+        let delay_size = self.delay_size[region as usize];
+        let access_time = if write { delay_size & 0xF } else { (delay_size >> 4) & 0xF } as i32;
+        let com = |index: u32| ((self.com_delay >> (index * 4)) & 0xF) as i32;
+
+        let (mut first, mut sequential, mut minimum) = (0, 0, 0);
+        if delay_size & DELAY_USE_COM0 != 0 {
+            first += com(0) - 1;
+            sequential += com(0) - 1;
+        }
+        if delay_size & DELAY_USE_COM2 != 0 {
+            first += com(2);
+            sequential += com(2);
+        }
+        if delay_size & DELAY_USE_COM3 != 0 {
+            minimum = com(3);
+        }
+        if first < 6 {
+            first += 1;
+        }
+        let first = (first + access_time + 2).max(minimum + 6) as u32;
+        let sequential = (sequential + access_time + 2).max(minimum + 2) as u32;
+
+        let bus_width = if delay_size & DELAY_BUS_16BIT != 0 { 2 } else { 1 };
+        let accesses = width.div_ceil(bus_width).max(1);
+        first + sequential * (accesses - 1)
+    }
+
+    /// Where an address below 8 MB goes with the current RAM window
+    pub fn ram_access(&self, addr: u32) -> RamAccess {
+        const MB: u32 = 0x100000;
+        let (memory, high_z) = match (self.ram_size >> 9) & 7 {
+            0 => (MB, 0),
+            1 => (4 * MB, 0),
+            2 => (MB, MB),
+            3 => (4 * MB, 4 * MB),
+            4 => (2 * MB, 0),
+            6 => (2 * MB, 2 * MB),
+            _ => (8 * MB, 0),
+        };
+        if addr < memory {
+            // Only 2 MB is fitted, larger windows mirror it and smaller ones cut it off
+            RamAccess::Ram(addr & (memory.min(2 * MB) - 1))
+        } else if addr < memory + high_z {
+            RamAccess::HighZ
+        } else {
+            RamAccess::Locked
+        }
+    }
+
+    pub fn tag_test_mode(&self) -> bool {
+        self.cache_control & CACHE_TAG_TEST != 0
+    }
+
+    pub fn scratchpad_enabled(&self) -> bool {
+        self.cache_control & (CACHE_SCRATCHPAD_ENABLE_1 | CACHE_SCRATCHPAD_ENABLE_2) == CACHE_SCRATCHPAD_ENABLE_1 | CACHE_SCRATCHPAD_ENABLE_2
+    }
+
+    pub fn code_cache_enabled(&self) -> bool {
+        self.cache_control & CACHE_CODE_ENABLE != 0
+    }
+
+    fn register_mut(&mut self, addr: u32) -> Option<&mut u32> {
+        match addr & !3 {
+            CACHE_CONTROL_START => return Some(&mut self.cache_control),
+            RAM_SIZE_START => return Some(&mut self.ram_size),
+            _ => {}
+        }
+        match (addr & !3) - MEMORY_CONTROL_START {
+            EXPANSION_1_BASE => Some(&mut self.expansion_1_base),
+            EXPANSION_2_BASE => Some(&mut self.expansion_2_base),
+            COM_DELAY => Some(&mut self.com_delay),
+            offset => self.delay_size.get_mut(((offset - DELAY_SIZE_START) / 4) as usize),
+        }
+    }
+
+    pub fn read(&mut self, addr: u32) -> u8 {
+        let shift = (addr & 3) * 8;
+        self.register_mut(addr).map_or(0, |register| (*register >> shift) as u8)
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let shift = (addr & 3) * 8;
+        if let Some(register) = self.register_mut(addr) {
+            *register = *register & !(0xFF << shift) | (value as u32) << shift;
+        }
+    }
+}
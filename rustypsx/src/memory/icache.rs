@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+pub const ICACHE_SIZE: usize = 0x1000;
+const LINE_SIZE: u32 = 16;
+const LINE_COUNT: usize = ICACHE_SIZE / LINE_SIZE as usize;
+
+/// One cache line's tag and a valid bit for each of its four words
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+struct Line {
+    tag: u32,
+    valid: u8,
+}
+
+/// The R3000A's 4 KB direct-mapped instruction cache, 256 lines of four words
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InstructionCache {
+    lines: Vec<Line>,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+fn line_index(addr: u32) -> usize {
+    ((addr >> 4) as usize) % LINE_COUNT
+}
+
+fn tag(addr: u32) -> u32 {
+    addr & 0xFFFFF000
+}
+
+impl InstructionCache {
+    pub fn new() -> Self {
+        InstructionCache {
+            lines: vec![Line::default(); LINE_COUNT],
+            data: vec![0; ICACHE_SIZE],
+        }
+    }
+
+    /// The cached word for a physical address, if its line holds it
+    pub fn lookup(&self, addr: u32) -> Option<u32> {
+        let line = self.lines[line_index(addr)];
+        let word = (addr >> 2) & 3;
+        if line.tag != tag(addr) || line.valid & (1 << word) == 0 {
+            return None;
+        }
+        let offset = (addr & !3) as usize % ICACHE_SIZE;
+        Some(u32::from_le_bytes(self.data[offset..offset + 4].try_into().unwrap()))
+    }
+
+    /// Refill a line after a miss with the words from the missed one to the end of the line
+    ///
+    /// Words before the missed one are not fetched, so they are left invalid.
+    pub fn fill(&mut self, addr: u32, words: &[u32]) {
+        let first = (addr >> 2) & 3;
+        let line_start = (addr & !(LINE_SIZE - 1)) as usize % ICACHE_SIZE;
+        for (index, word) in words.iter().enumerate() {
+            let offset = line_start + (first as usize + index) * 4;
+            self.data[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        self.lines[line_index(addr)] = Line {
+            tag: tag(addr),
+            valid: (0xF << first) & 0xF,
+        };
+    }
+
+    /// Drop every line, as the BIOS's FlushCache does
+    pub fn invalidate_all(&mut self) {
+        self.lines.fill(Line::default());
+    }
+
+    /// Store made while the cache is isolated from memory
+    ///
+    /// In tag test mode the store invalidates the line and gives it the store's tag, which is how the
+    /// BIOS flushes the cache. Otherwise it lands in the cached data.
+    pub fn write_isolated(&mut self, addr: u32, value: u8, tag_test: bool) {
+        if tag_test {
+            self.lines[line_index(addr)] = Line { tag: tag(addr), valid: 0 };
+        } else {
+            self.data[addr as usize % ICACHE_SIZE] = value;
+        }
+    }
+
+    /// Load made while the cache is isolated from memory
+    pub fn read_isolated(&self, addr: u32) -> u8 {
+        self.data[addr as usize % ICACHE_SIZE]
+    }
+}
@@ -1,4 +1,5 @@
-use crate::memory;
+use crate::memory::Addressable;
+use crate::{dma, expansion, input, interrupts, mdec, memory, sio};
 use serde::{Deserialize, Serialize};
 
 const EMPTY_BYTE: u8 = 0xFF;
@@ -19,37 +20,512 @@ const VIRTUAL_MEMORY_START: u32 = 0xC0000000;
 const VIRTUAL_MEMORY_LENGTH: usize = 0x40000000; // 1 GB
 const VIRTUAL_MEMORY_END: u32 = VIRTUAL_MEMORY_START - 1 + VIRTUAL_MEMORY_LENGTH as u32;
 
+// KUSEG, KSEG0 and KSEG1 all map onto the same 512 MB physical address space
+const PHYSICAL_MEMORY_END: u32 = 0x1FFFFFFF;
+const UNMAPPED_USER_MEMORY_START: u32 = PHYSICAL_MEMORY_END + 1;
+
+// 2 MB of RAM in an 8 MB window, mirrored as RAM_SIZE sets up
+const RAM_SIZE: usize = 0x200000;
+const RAM_MASK: u32 = RAM_SIZE as u32 - 1;
+const RAM_MIRROR_END: u32 = 0x7FFFFF;
+
+const EXPANSION_3_START: u32 = 0x1FA00000;
+const BIOS_START: u32 = 0x1FC00000;
+const BIOS_END: u32 = 0x1FC7FFFF;
+pub const BIOS_SIZE: usize = 0x80000;
+const CDROM_START: u32 = 0x1F801800;
+const CDROM_END: u32 = 0x1F801803;
+const SPU_START: u32 = 0x1F801C00;
+const SPU_END: u32 = 0x1F801FFF;
+
+const SCRATCHPAD_START: u32 = 0x1F800000;
+const SCRATCHPAD_SIZE: usize = 0x400;
+const SCRATCHPAD_END: u32 = SCRATCHPAD_START + SCRATCHPAD_SIZE as u32 - 1;
+
+const RAM_ACCESS_CYCLES: u32 = 5;
+const RAM_WRITE_CYCLES: u32 = 1;
+// Each word after the first in a cache line fill streams in on the next cycle
+const RAM_BURST_CYCLES: u32 = 1;
+const SCRATCHPAD_ACCESS_CYCLES: u32 = 1;
+const CACHE_CONTROL_ACCESS_CYCLES: u32 = 1;
+// Bus time DMA takes from the CPU per word moved, and to set up each block
+const DMA_WORD_CYCLES: u32 = 1;
+const DMA_BLOCK_CYCLES: u32 = 4;
+const IO_ACCESS_CYCLES: u32 = 2;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Mmio {
+    pub ram: memory::Memory<0, RAM_SIZE>,
+    // The firmware ROM, loaded from a file so not part of the machine's state
+    #[serde(skip, default)]
+    bios: Option<memory::Memory<BIOS_START, BIOS_SIZE>>,
+    pub memory_control: memory::control::MemoryControl,
+    pub icache: memory::icache::InstructionCache,
+    // Data cache turned into fast RAM, only reachable through KUSEG and KSEG0
+    pub scratchpad: memory::Memory<SCRATCHPAD_START, SCRATCHPAD_SIZE>,
+    // Mirrors COP0 SR bit 16, set by the CPU before each instruction
+    pub cache_isolated: bool,
+    write_queue: memory::write_queue::WriteQueue,
+    // Bus cycles DMA has taken that the CPU hasn't been charged for yet
+    dma_cycles: u32,
+    pub interrupts: interrupts::InterruptController,
+    pub dma: dma::Dma,
+    pub mdec: mdec::Mdec,
+    pub sio0: sio::Sio0,
+    pub sio1: sio::Sio1,
+    pub expansion1: expansion::Expansion1,
+    pub duart: expansion::Duart,
+    // Video timing for lightguns until the GPU provides it
+    pub display_area: input::lightgun::DisplayArea,
+    beam: input::lightgun::Beam,
 }
 
 impl Mmio {
     pub fn new() -> Self {
         Mmio {
+            ram: memory::Memory::new(),
+            bios: None,
+            memory_control: memory::control::MemoryControl::new(),
+            icache: memory::icache::InstructionCache::new(),
+            scratchpad: memory::Memory::new(),
+            cache_isolated: false,
+            write_queue: memory::write_queue::WriteQueue::new(),
+            dma_cycles: 0,
+            interrupts: interrupts::InterruptController::new(),
+            dma: dma::Dma::new(),
+            mdec: mdec::Mdec::new(),
+            sio0: sio::Sio0::new(),
+            sio1: sio::Sio1::new(),
+            expansion1: expansion::Expansion1::new(),
+            duart: expansion::Duart::new(),
+            display_area: input::lightgun::DisplayArea::default(),
+            beam: input::lightgun::Beam::new(),
         }
     }
 
+    /// Reset all devices, leaving plugged-in peripherals connected
     pub fn reset(&mut self) {
-        *self = Self::new();
+        self.memory_control = memory::control::MemoryControl::new();
+        self.cache_isolated = false;
+        self.write_queue = memory::write_queue::WriteQueue::new();
+        self.dma_cycles = 0;
+        self.interrupts = interrupts::InterruptController::new();
+        self.dma = dma::Dma::new();
+        self.mdec = mdec::Mdec::new();
+        self.sio0.reset();
+        self.sio1.reset();
+        self.duart = expansion::Duart::new();
+        self.display_area = input::lightgun::DisplayArea::default();
+        self.beam = input::lightgun::Beam::new();
+    }
+
+    /// Map a firmware image into the BIOS ROM area
+    pub fn load_bios(&mut self, image: &[u8]) -> Result<(), String> {
+        if image.len() != BIOS_SIZE {
+            return Err(format!("BIOS image is {} bytes, expected {}", image.len(), BIOS_SIZE));
+        }
+        let mut bios = memory::Memory::new();
+        for (offset, &value) in image.iter().enumerate() {
+            bios.write(BIOS_START + offset as u32, value);
+        }
+        self.bios = Some(bios);
+        Ok(())
+    }
+
+    /// CRC32 of the BIOS image, 0 without one
+    pub fn bios_hash(&self) -> u32 {
+        self.bios.as_ref().map_or(0, |bios| crc32fast::hash(bios.data()))
+    }
+
+    /// Take on a loaded state, keeping the BIOS image it doesn't carry
+    pub fn restore_state(&mut self, mut state: Mmio) {
+        state.bios = self.bios.take();
+        state.expansion1.take_rom(&mut self.expansion1);
+        self.keep_card_files(&mut state.sio0);
+        *self = state;
+    }
+
+    /// Take on another machine's state as `restore_state` does, copying into the buffers already here
+    pub fn copy_state_from(&mut self, other: &Mmio) {
+        // Every field is named, so a new one doesn't compile until it is copied or skipped here
+        let Mmio {
+            ram,
+            bios: _,
+            memory_control,
+            icache,
+            scratchpad,
+            cache_isolated,
+            write_queue,
+            dma_cycles,
+            interrupts,
+            dma,
+            mdec,
+            sio0,
+            sio1,
+            expansion1,
+            duart,
+            display_area,
+            beam,
+        } = other;
+        self.ram.copy_from(ram);
+        self.memory_control.clone_from(memory_control);
+        self.icache.clone_from(icache);
+        self.scratchpad.copy_from(scratchpad);
+        self.cache_isolated = *cache_isolated;
+        self.write_queue.clone_from(write_queue);
+        self.dma_cycles = *dma_cycles;
+        self.interrupts.clone_from(interrupts);
+        self.dma.clone_from(dma);
+        self.mdec.clone_from(mdec);
+        let mut sio0 = sio0.clone();
+        self.keep_card_files(&mut sio0);
+        self.sio0 = sio0;
+        self.sio1.clone_from(sio1);
+        self.expansion1.copy_state_from(expansion1);
+        self.duart.clone_from(duart);
+        self.display_area.clone_from(display_area);
+        self.beam.clone_from(beam);
+    }
+
+    /// Card images stay where they are, and take on the contents of the cards in `state`
+    fn keep_card_files(&self, state: &mut sio::Sio0) {
+        for slot in self.sio0.memory_card_slots() {
+            if let (Some(inserted), Some(card)) = (self.sio0.memory_card(slot), state.memory_card_mut(slot)) {
+                card.take_file(inserted);
+            }
+        }
+    }
+
+    /// Advance device timers by the given number of CPU cycles
+    pub fn step(&mut self, cycles: u32) {
+        self.write_queue.advance(cycles);
+        if self.sio0.step(cycles) {
+            self.interrupts.request(interrupts::Interrupt::ControllerAndMemoryCard);
+        }
+        if self.sio1.step(cycles) {
+            self.interrupts.request(interrupts::Interrupt::Sio);
+        }
+        if self.beam.step(cycles, self.sio0.lightgun_irq_position()) {
+            self.interrupts.request(interrupts::Interrupt::Lightpen);
+        }
+        self.run_dma();
+    }
+
+    fn read_ram_word(&mut self, addr: u32) -> u32 {
+        u32::from_le_bytes([0, 1, 2, 3].map(|byte| self.ram.read((addr + byte) & RAM_MASK)))
+    }
+
+    fn write_ram_word(&mut self, addr: u32, value: u32) {
+        for (byte, value) in value.to_le_bytes().into_iter().enumerate() {
+            self.ram.write((addr + byte as u32) & RAM_MASK, value);
+        }
+    }
+
+    fn finish_dma_block(&mut self, channel: usize) {
+        self.dma_cycles += DMA_BLOCK_CYCLES + self.dma.channels[channel].block_size() * DMA_WORD_CYCLES;
+        if self.dma.channels[channel].complete_block() && self.dma.finish(channel) {
+            self.interrupts.request(interrupts::Interrupt::Dma);
+        }
+    }
+
+    /// Bus cycles DMA has held the bus for since the last call, which the CPU spends stalled
+    pub fn take_dma_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.dma_cycles)
+    }
+
+    /// Move whole blocks for every channel whose device is requesting data
+    ///
+    /// Blocks move at once, the time they took is charged to the CPU's next instruction.
+    fn run_dma(&mut self) {
+        while self.dma.is_pending(dma::CHANNEL_MDEC_IN) && self.mdec.data_in_request() {
+            let channel = self.dma.channels[dma::CHANNEL_MDEC_IN].clone();
+            for index in 0..channel.block_size() {
+                let word = self.read_ram_word(channel.word_address(index));
+                self.mdec.push_word(word);
+            }
+            self.finish_dma_block(dma::CHANNEL_MDEC_IN);
+        }
+
+        while self.dma.is_pending(dma::CHANNEL_MDEC_OUT) && self.mdec.data_out_request() {
+            let channel = self.dma.channels[dma::CHANNEL_MDEC_OUT].clone();
+            // Wait until the decoder has produced a whole block
+            if self.mdec.output_len() < channel.block_size() as usize {
+                break;
+            }
+            for index in 0..channel.block_size() {
+                let word = self.mdec.pop_word();
+                self.write_ram_word(channel.word_address(index), word);
+            }
+            self.finish_dma_block(dma::CHANNEL_MDEC_OUT);
+        }
+    }
+
+    /// Cycles a CPU read or write of `width` bytes at a physical address takes on the bus
+    pub fn access_cycles(&self, addr: u32, width: u32, write: bool) -> u32 {
+        use memory::control::Region;
+        let control = &self.memory_control;
+        let expansion_1 = control.expansion_1_base();
+        let expansion_2 = control.expansion_2_base();
+        let region = match addr {
+            0..=RAM_MIRROR_END => return if write { RAM_WRITE_CYCLES } else { RAM_ACCESS_CYCLES },
+            SCRATCHPAD_START..=SCRATCHPAD_END => return SCRATCHPAD_ACCESS_CYCLES,
+            BIOS_START..=BIOS_END => Region::Bios,
+            SPU_START..=SPU_END => Region::Spu,
+            CDROM_START..=CDROM_END => Region::CdRom,
+            _ if (expansion_1..expansion_1 + control.window_size(Region::Expansion1)).contains(&addr) => Region::Expansion1,
+            _ if (expansion_2..expansion_2 + control.window_size(Region::Expansion2)).contains(&addr) => Region::Expansion2,
+            _ if (EXPANSION_3_START..EXPANSION_3_START + control.window_size(Region::Expansion3)).contains(&addr) => Region::Expansion3,
+            _ => return IO_ACCESS_CYCLES,
+        };
+        control.access_cycles(region, width, write)
+    }
+
+    /// Read RAM or the scratchpad without touching any device, for debugging tools
+    pub fn peek(&self, addr: u32) -> Option<u8> {
+        if let Some(offset) = self.scratchpad_offset(addr) {
+            return Some(self.scratchpad.peek(offset));
+        }
+        if addr >= VIRTUAL_MEMORY_START {
+            return None;
+        }
+        match self.memory_control.ram_access(addr & PHYSICAL_MEMORY_END) {
+            memory::control::RamAccess::Ram(offset) if addr & PHYSICAL_MEMORY_END <= RAM_MIRROR_END => Some(self.ram.peek(offset)),
+            _ => None,
+        }
+    }
+
+    /// CPU data load of `width` bytes, returns the value and the cycles the CPU waits for it
+    ///
+    /// There is no data cache, so anything but the scratchpad waits for queued stores and then the bus.
+    #[allow(dead_code)] // For the load and store instructions, which the CPU core doesn't decode yet
+    pub fn load(&mut self, addr: u32, width: u32) -> (u32, u32) {
+        let mut value = 0;
+        for byte in 0..width {
+            value |= (self.read(addr.wrapping_add(byte)) as u32) << (byte * 8);
+        }
+        let cycles = if self.cache_isolated || self.scratchpad_offset(addr).is_some() {
+            0
+        } else if addr >= VIRTUAL_MEMORY_START {
+            CACHE_CONTROL_ACCESS_CYCLES
+        } else {
+            self.write_queue.drain() + self.access_cycles(addr & PHYSICAL_MEMORY_END, width, false)
+        };
+        (value, cycles)
+    }
+
+    /// CPU data store of `width` bytes, returns the cycles the CPU stalls on a full write queue
+    #[allow(dead_code)]
+    pub fn store(&mut self, addr: u32, value: u32, width: u32) -> u32 {
+        for byte in 0..width {
+            self.write(addr.wrapping_add(byte), (value >> (byte * 8)) as u8);
+        }
+        if self.cache_isolated || self.scratchpad_offset(addr).is_some() || addr >= VIRTUAL_MEMORY_START {
+            return 0;
+        }
+        let cycles = self.access_cycles(addr & PHYSICAL_MEMORY_END, width, true);
+        self.write_queue.push(cycles)
+    }
+
+    fn read_physical_word(&mut self, addr: u32) -> u32 {
+        u32::from_le_bytes([0, 1, 2, 3].map(|byte| self.read_physical(addr + byte)))
+    }
+
+    /// Fetch the instruction at a virtual address, returns it and the cycles spent waiting on the bus
+    ///
+    /// KUSEG and KSEG0 fetches go through the instruction cache when it is enabled. A hit costs nothing
+    /// beyond the instruction's own cycle, a miss refills the rest of the line from memory.
+    pub fn fetch_instruction(&mut self, addr: u32) -> (u32, u32) {
+        let physical = addr & PHYSICAL_MEMORY_END & !3;
+        let cached = addr < UNCACHED_KERNEL_MEMORY_START && self.memory_control.code_cache_enabled();
+        if !cached {
+            let cycles = self.write_queue.drain() + self.access_cycles(physical, 4, false);
+            return (self.read_physical_word(physical), cycles);
+        }
+        if let Some(word) = self.icache.lookup(physical) {
+            return (word, 0);
+        }
+        // Queued stores finish before the refill gets the bus
+        let queued = self.write_queue.drain();
+
+        let line_end = (physical | 0xF) + 1;
+        let words: Vec<u32> = (physical..line_end).step_by(4).map(|word| self.read_physical_word(word)).collect();
+        self.icache.fill(physical, &words);
+        let extra_words = words.len() as u32 - 1;
+        let cycles = match physical {
+            0..=RAM_MIRROR_END => RAM_ACCESS_CYCLES + extra_words * RAM_BURST_CYCLES,
+            _ => self.access_cycles(physical, 4, false) * words.len() as u32,
+        };
+        (words[0], queued + cycles)
+    }
+
+    pub fn set_controller(&mut self, port: usize, controller: input::Controller) {
+        self.sio0.set_controller(port, controller);
+    }
+
+    /// Offset into Expansion 1 for an address in the window memory control gives it
+    fn expansion_1_offset(&self, addr: u32) -> Option<u32> {
+        let base = self.memory_control.expansion_1_base();
+        let size = self.memory_control.window_size(memory::control::Region::Expansion1);
+        (base..base + size).contains(&addr).then(|| addr - base)
+    }
+
+    /// Offset of a DUART register in Expansion 2, wherever memory control puts it
+    fn duart_offset(&self, addr: u32) -> Option<u32> {
+        let base = self.memory_control.expansion_2_base() + expansion::DUART_OFFSET;
+        let size = expansion::DUART_SIZE.min(self.memory_control.window_size(memory::control::Region::Expansion2));
+        (base..base + size).contains(&addr).then(|| addr - base)
+    }
+
+    fn read_physical(&mut self, addr: u32) -> u8 {
+        match addr {
+            0..=RAM_MIRROR_END => match self.memory_control.ram_access(addr) {
+                memory::control::RamAccess::Ram(offset) => self.ram.read(offset),
+                _ => EMPTY_BYTE,
+            },
+            BIOS_START..=BIOS_END => self.bios.as_mut().map_or(EMPTY_BYTE, |bios| bios.read(addr)),
+            memory::control::MEMORY_CONTROL_START..=memory::control::MEMORY_CONTROL_END
+            | memory::control::RAM_SIZE_START..=memory::control::RAM_SIZE_END => self.memory_control.read(addr),
+            sio::SIO0_START..=sio::SIO0_END => self.sio0.read(addr),
+            sio::SIO1_START..=sio::SIO1_END => self.sio1.read(addr),
+            interrupts::I_STAT_START..=interrupts::INTERRUPTS_END => self.interrupts.read(addr),
+            dma::DMA_START..=dma::DMA_END => self.dma.read(addr),
+            mdec::MDEC_START..=mdec::MDEC_END => self.mdec.read(addr),
+            _ => {
+                if let Some(offset) = self.expansion_1_offset(addr) {
+                    self.expansion1.read(offset)
+                } else if let Some(offset) = self.duart_offset(addr) {
+                    self.duart.read(offset)
+                } else {
+                    EMPTY_BYTE
+                }
+            }
+        }
+    }
+
+    fn write_physical(&mut self, addr: u32, value: u8) {
+        match addr {
+            0..=RAM_MIRROR_END => {
+                if let memory::control::RamAccess::Ram(offset) = self.memory_control.ram_access(addr) {
+                    self.ram.write(offset, value);
+                }
+            }
+            memory::control::MEMORY_CONTROL_START..=memory::control::MEMORY_CONTROL_END
+            | memory::control::RAM_SIZE_START..=memory::control::RAM_SIZE_END => self.memory_control.write(addr, value),
+            sio::SIO0_START..=sio::SIO0_END => self.sio0.write(addr, value),
+            sio::SIO1_START..=sio::SIO1_END => self.sio1.write(addr, value),
+            interrupts::I_STAT_START..=interrupts::INTERRUPTS_END => self.interrupts.write(addr, value),
+            dma::DMA_START..=dma::DMA_END => {
+                if self.dma.write(addr, value) {
+                    self.interrupts.request(interrupts::Interrupt::Dma);
+                }
+                self.run_dma();
+            }
+            mdec::MDEC_START..=mdec::MDEC_END => {
+                self.mdec.write(addr, value);
+                self.run_dma();
+            }
+            _ => {
+                if let Some(offset) = self.expansion_1_offset(addr) {
+                    self.expansion1.write(offset, value);
+                } else if let Some(offset) = self.duart_offset(addr) {
+                    self.duart.write(offset, value);
+                }
+            }
+        }
+    }
+}
+
+impl Mmio {
+    /// The scratchpad address for a virtual address in it, KSEG1 can't reach it
+    fn scratchpad_offset(&self, addr: u32) -> Option<u32> {
+        if addr >= UNCACHED_KERNEL_MEMORY_START || !self.memory_control.scratchpad_enabled() {
+            return None;
+        }
+        let physical = addr & PHYSICAL_MEMORY_END;
+        (SCRATCHPAD_START..=SCRATCHPAD_END).contains(&physical).then_some(physical)
     }
 }
 
 impl memory::Addressable for Mmio {
-    fn read(&self, addr: u32) -> u8 {
+    fn read(&mut self, addr: u32) -> u8 {
+        // KSEG1 is uncached, so isolating the cache doesn't touch it
+        if self.cache_isolated && addr < UNCACHED_KERNEL_MEMORY_START {
+            return self.icache.read_isolated(addr & PHYSICAL_MEMORY_END);
+        }
+        if let Some(offset) = self.scratchpad_offset(addr) {
+            return self.scratchpad.read(offset);
+        }
         match addr {
-            USER_MEMORY_START..=USER_MEMORY_END => EMPTY_BYTE,
-            CACHED_KERNEL_MEMORY_START..=CACHED_KERNEL_MEMORY_END => EMPTY_BYTE,
-            UNCACHED_KERNEL_MEMORY_START..=UNCACHED_KERNEL_MEMORY_END => EMPTY_BYTE,
+            memory::control::CACHE_CONTROL_START..=memory::control::CACHE_CONTROL_END => self.memory_control.read(addr),
+            USER_MEMORY_START..=PHYSICAL_MEMORY_END => self.read_physical(addr),
+            UNMAPPED_USER_MEMORY_START..=USER_MEMORY_END => EMPTY_BYTE,
+            CACHED_KERNEL_MEMORY_START..=CACHED_KERNEL_MEMORY_END => self.read_physical(addr - CACHED_KERNEL_MEMORY_START),
+            UNCACHED_KERNEL_MEMORY_START..=UNCACHED_KERNEL_MEMORY_END => self.read_physical(addr - UNCACHED_KERNEL_MEMORY_START),
             VIRTUAL_MEMORY_START..=VIRTUAL_MEMORY_END => EMPTY_BYTE,
         }
     }
 
-    fn write(&mut self, addr: u32, _value: u8) {
+    fn write(&mut self, addr: u32, value: u8) {
+        // The BIOS isolates the cache to flush it, its cached stores must not reach memory
+        if self.cache_isolated && addr < UNCACHED_KERNEL_MEMORY_START {
+            self.icache.write_isolated(addr & PHYSICAL_MEMORY_END, value, self.memory_control.tag_test_mode());
+            return;
+        }
+        if let Some(offset) = self.scratchpad_offset(addr) {
+            self.scratchpad.write(offset, value);
+            return;
+        }
         match addr {
-            USER_MEMORY_START..=USER_MEMORY_END => {}
-            CACHED_KERNEL_MEMORY_START..=CACHED_KERNEL_MEMORY_END => {}
-            UNCACHED_KERNEL_MEMORY_START..=UNCACHED_KERNEL_MEMORY_END => {}
+            memory::control::CACHE_CONTROL_START..=memory::control::CACHE_CONTROL_END => self.memory_control.write(addr, value),
+            USER_MEMORY_START..=PHYSICAL_MEMORY_END => self.write_physical(addr, value),
+            UNMAPPED_USER_MEMORY_START..=USER_MEMORY_END => {}
+            CACHED_KERNEL_MEMORY_START..=CACHED_KERNEL_MEMORY_END => self.write_physical(addr - CACHED_KERNEL_MEMORY_START, value),
+            UNCACHED_KERNEL_MEMORY_START..=UNCACHED_KERNEL_MEMORY_END => self.write_physical(addr - UNCACHED_KERNEL_MEMORY_START, value),
             VIRTUAL_MEMORY_START..=VIRTUAL_MEMORY_END => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_return_what_stores_wrote() {
+        let mut mmio = Mmio::new();
+        mmio.store(0x80001000, 0x12345678, 4);
+        assert_eq!(mmio.load(0x80001000, 4).0, 0x12345678);
+        assert_eq!(mmio.load(0x80001001, 2).0, 0x3456);
+    }
+
+    #[test]
+    fn loads_wait_for_queued_stores() {
+        let mut mmio = Mmio::new();
+        for index in 0..4 {
+            assert_eq!(mmio.store(0x80001000 + index * 4, 0, 4), 0);
+        }
+        let stall = mmio.store(0x80001010, 0, 4);
+        assert_eq!(stall, RAM_WRITE_CYCLES);
+        // The store's own cycle is the only bus time the stall doesn't already cover
+        mmio.step(1 + stall);
+        assert_eq!(mmio.load(0x80002000, 4).1, 3 * RAM_WRITE_CYCLES + RAM_ACCESS_CYCLES);
+        assert_eq!(mmio.load(0x80002000, 4).1, RAM_ACCESS_CYCLES);
+    }
+
+    #[test]
+    fn copied_state_matches_a_restored_one_and_keeps_the_bios() {
+        let mut state = Mmio::new();
+        state.write(0x80000010, 0x5A);
+        state.store(0x80000020, 0x1234, 4);
+        state.sio0.set_memory_card(0, Some(crate::memcard::MemoryCard::new()));
+        let mut copied = Mmio::new();
+        copied.load_bios(&vec![1; BIOS_SIZE]).unwrap();
+        let mut restored = copied.clone();
+
+        copied.copy_state_from(&state);
+        restored.restore_state(state.clone());
+        assert_eq!(copied.read(0x80000010), 0x5A);
+        assert_eq!(bincode::serialize(&copied).unwrap(), bincode::serialize(&restored).unwrap());
+        assert_eq!(copied.bios_hash(), crc32fast::hash(&vec![1; BIOS_SIZE]));
+    }
+}
@@ -1,4 +1,7 @@
 pub mod buffer;
+pub mod control;
+pub mod icache;
 pub mod mmio;
+pub mod write_queue;
 
 pub use buffer::*;
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// The R3000A's write buffer holds four stores
+const WRITE_QUEUE_DEPTH: usize = 4;
+
+/// Timing of the CPU's write buffer, stores land straight away but hold the bus until they drain
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct WriteQueue {
+    // Bus cycles each queued store still needs, oldest first
+    pending: VecDeque<u32>,
+    // Stalls already run through by push and drain, which the next advance doesn't count again
+    ahead: u32,
+}
+
+impl WriteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let the bus work through queued stores while the CPU gets on with other things
+    ///
+    /// `cycles` is everything the CPU took, including stalls push and drain returned.
+    pub fn advance(&mut self, cycles: u32) {
+        let credited = cycles.min(self.ahead);
+        self.ahead -= credited;
+        self.run(cycles - credited);
+    }
+
+    /// Queue a store taking `cycles` on the bus, returns how long the CPU stalls for a free slot
+    pub fn push(&mut self, cycles: u32) -> u32 {
+        let stall = if self.pending.len() == WRITE_QUEUE_DEPTH { self.pending[0] } else { 0 };
+        self.run(stall);
+        self.ahead += stall;
+        self.pending.push_back(cycles);
+        stall
+    }
+
+    /// Loads wait for every queued store to finish, returns the cycles that takes
+    pub fn drain(&mut self) -> u32 {
+        let cycles = self.pending.iter().sum();
+        self.pending.clear();
+        self.ahead += cycles;
+        cycles
+    }
+
+    fn run(&mut self, mut cycles: u32) {
+        while let Some(front) = self.pending.front_mut() {
+            if *front > cycles {
+                *front -= cycles;
+                return;
+            }
+            cycles -= *front;
+            self.pending.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_only_stall_once_the_queue_is_full() {
+        let mut queue = WriteQueue::new();
+        for cycles in [3, 1, 1, 1] {
+            assert_eq!(queue.push(cycles), 0);
+        }
+        assert_eq!(queue.push(1), 3);
+        assert_eq!(queue.pending, [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn stalls_are_not_counted_again_when_the_cpu_advances() {
+        let mut queue = WriteQueue::new();
+        for _ in 0..4 {
+            queue.push(2);
+        }
+        let stall = queue.push(2);
+        assert_eq!(stall, 2);
+        // The instruction took its own cycle plus the stall, only that cycle is new bus time
+        queue.advance(1 + stall);
+        assert_eq!(queue.pending, [1, 2, 2, 2]);
+        queue.advance(1);
+        assert_eq!(queue.pending, [2, 2, 2]);
+    }
+
+    #[test]
+    fn draining_empties_the_queue_and_covers_stores_queued_after() {
+        let mut queue = WriteQueue::new();
+        queue.push(2);
+        queue.push(3);
+        let drained = queue.drain();
+        assert_eq!(drained, 5);
+        assert!(queue.pending.is_empty());
+        queue.push(4);
+        queue.advance(1 + drained);
+        assert_eq!(queue.pending, [3]);
+    }
+}
@@ -0,0 +1,234 @@
+//! Input movies: the state of every pad for each frame from a known starting point.
+//!
+//! A movie starts at power on or from a save state embedded in it. Played back on the same
+//! game, BIOS and settings it drives the machine exactly as when it was recorded. Loading a
+//! state while recording, or during read-write playback, branches the movie there and
+//! counts a rerecord, and so does rewinding.
+//!
+//! Memory cards are part of where a movie starts, so their contents are checked on playback and
+//! cards stop writing to their files while a movie runs.
+
+use crate::config;
+use crate::input::multitap::MULTITAP_SLOTS;
+use crate::input::{ControllerType, PadInput};
+use crate::psx::PS1;
+use crate::savestate;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"RPSXMOVI";
+// Bumped whenever a released header or pad input changes shape
+pub const FORMAT_VERSION: u32 = 1;
+// Magic and format version
+const PREFIX_SIZE: usize = 12;
+
+/// Settings that change how a run plays out, which must match for a movie to play back
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub controllers: [ControllerType; 2],
+    pub multitaps: [Option<[ControllerType; MULTITAP_SLOTS]>; 2],
+    pub fast_boot: bool,
+    pub seed: u32,
+    // Pads given input each frame
+    pub pads: usize,
+}
+
+impl Settings {
+    pub fn from_config(config: &config::CleanConfig) -> Self {
+        Settings {
+            controllers: config.controllers,
+            multitaps: config.multitaps,
+            fast_boot: config.fast_boot,
+            seed: config.seed,
+            pads: config.input.pads.len(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Header {
+    pub game_id: String,
+    // CRC32 of the BIOS image, 0 for the HLE BIOS
+    pub bios_hash: u32,
+    pub settings: Settings,
+    // Times a state was loaded while recording
+    pub rerecords: u32,
+    // PS1::frames at the start, when the first frame's input is applied
+    pub start_frame: u64,
+    // PS1::memory_cards_hash at the start
+    pub memory_cards_hash: u32,
+    pub emulator_version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Start {
+    PowerOn,
+    // A save state file's contents
+    SaveState(#[serde(with = "serde_bytes")] Vec<u8>),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Recording,
+    // Read-only playback ignores loaded states, read-write playback records from them
+    Playing { read_only: bool },
+}
+
+pub struct Movie {
+    pub header: Header,
+    start: Start,
+    // Every pad's input, one entry per frame
+    inputs: Vec<Vec<PadInput>>,
+    mode: Mode,
+    path: PathBuf,
+}
+
+impl Movie {
+    /// Start recording to `path`, resetting first or starting from the machine as it is now
+    pub fn record(path: &Path, ps1: &mut PS1, settings: Settings, from_power_on: bool, frame: &[u8]) -> Result<Self, String> {
+        let start = if from_power_on {
+            ps1.reset();
+            Start::PowerOn
+        } else {
+            Start::SaveState(savestate::to_bytes(ps1, frame)?)
+        };
+        ps1.detach_memory_cards();
+        Ok(Movie {
+            header: Header {
+                game_id: ps1.game_id().to_string(),
+                bios_hash: ps1.bios_hash(),
+                settings,
+                rerecords: 0,
+                start_frame: ps1.frames(),
+                memory_cards_hash: ps1.memory_cards_hash(),
+                emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            start,
+            inputs: Vec::new(),
+            mode: Mode::Recording,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if file.len() < PREFIX_SIZE || &file[..8] != MAGIC {
+            return Err(format!("{} is not a movie", path.display()));
+        }
+        let version = u32::from_le_bytes(file[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(format!("Movie is format version {}, this emulator reads version {}", version, FORMAT_VERSION));
+        }
+        let mut body = Vec::new();
+        ZlibDecoder::new(&file[PREFIX_SIZE..])
+            .read_to_end(&mut body)
+            .map_err(|e| format!("Failed to decompress movie: {}", e))?;
+        let (header, start, inputs) = bincode::deserialize(&body).map_err(|e| format!("Bad movie: {}", e))?;
+        Ok(Movie {
+            header,
+            start,
+            inputs,
+            mode: Mode::Playing { read_only: true },
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let body = bincode::serialize(&(&self.header, &self.start, &self.inputs)).map_err(|e| format!("Failed to encode movie: {}", e))?;
+        let mut file = Vec::with_capacity(PREFIX_SIZE + body.len() / 4);
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        let mut encoder = ZlibEncoder::new(file, Compression::default());
+        encoder.write_all(&body).map_err(|e| format!("Failed to compress movie: {}", e))?;
+        let file = encoder.finish().map_err(|e| format!("Failed to compress movie: {}", e))?;
+        std::fs::write(&self.path, file).map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    /// Check the movie was made on this game, BIOS and settings, then put the machine at its start
+    pub fn start_playback(&mut self, ps1: &mut PS1, settings: &Settings, read_only: bool) -> Result<(), String> {
+        if self.header.game_id != ps1.game_id() {
+            return Err(format!("Movie is for {}, not {}", self.header.game_id, ps1.game_id()));
+        }
+        if self.header.bios_hash != ps1.bios_hash() {
+            return Err(format!("Movie was recorded with BIOS {:08X}, this is {:08X}", self.header.bios_hash, ps1.bios_hash()));
+        }
+        if &self.header.settings != settings {
+            return Err(format!("Movie was recorded with {:?}, not {:?}", self.header.settings, settings));
+        }
+        match &self.start {
+            Start::PowerOn => ps1.reset(),
+            Start::SaveState(state) => {
+                savestate::load_bytes(state, ps1)?;
+            }
+        }
+        ps1.detach_memory_cards();
+        if ps1.memory_cards_hash() != self.header.memory_cards_hash {
+            return Err("Movie was recorded with different memory card contents".to_string());
+        }
+        self.mode = Mode::Playing { read_only };
+        Ok(())
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // Index into the inputs of the frame `ps1` runs next, None before the movie starts
+    fn position(&self, ps1: &PS1) -> Option<usize> {
+        ps1.frames().checked_sub(self.header.start_frame).map(|position| position as usize)
+    }
+
+    /// The input for the frame `ps1` is about to run, recording `host` when recording.
+    /// None once playback runs out or the machine is from before the movie started.
+    pub fn input(&mut self, ps1: &PS1, host: &[PadInput]) -> Option<Vec<PadInput>> {
+        let position = self.position(ps1)?;
+        match self.mode {
+            Mode::Recording => {
+                // Anything past here was recorded before going back, by a loaded state or rewinding
+                self.inputs.truncate(position);
+                self.inputs.resize(position, vec![PadInput::default(); self.header.settings.pads]);
+                self.inputs.push(host.to_vec());
+                Some(host.to_vec())
+            }
+            Mode::Playing { .. } => self.inputs.get(position).cloned(),
+        }
+    }
+
+    /// The input the movie has for the frame `ps1` is about to run, without recording anything
+    pub fn recorded_input(&self, ps1: &PS1) -> Option<&[PadInput]> {
+        self.inputs.get(self.position(ps1)?).map(Vec::as_slice)
+    }
+
+    /// Call after loading a state or rewinding, which branches the movie unless it's playing read-only
+    pub fn state_loaded(&mut self) {
+        match self.mode {
+            Mode::Playing { read_only: true } => {}
+            Mode::Recording | Mode::Playing { read_only: false } => {
+                self.mode = Mode::Recording;
+                self.header.rerecords += 1;
+            }
+        }
+    }
+
+    /// As in "Recording frame 120" or "Playing frame 120 of 3600"
+    pub fn describe(&self, ps1: &PS1) -> String {
+        let position = self.position(ps1).unwrap_or(0);
+        match self.mode {
+            Mode::Recording => format!("Recording frame {}, {} rerecords", position, self.header.rerecords),
+            Mode::Playing { read_only } => format!(
+                "Playing frame {} of {}{}",
+                position,
+                self.inputs.len(),
+                if read_only { "" } else { ", read-write" }
+            ),
+        }
+    }
+}
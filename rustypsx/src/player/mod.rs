@@ -0,0 +1,289 @@
+//! Standalone STR/XA movie player, for looking at FMVs without booting the game.
+
+use crate::cdrom::{self, xa};
+use crate::mdec::{self, bitstream, decoder};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// STR movies are streamed at double speed
+pub const SECTORS_PER_SECOND: u32 = 150;
+
+const VIDEO_CHUNK_MAGIC: [u8; 4] = [0x60, 0x01, 0x01, 0x80];
+const VIDEO_CHUNK_HEADER: usize = 32;
+const MACROBLOCK_WORDS: usize = 16 * 16 * 3 / 4;
+
+pub struct PlayerConfig {
+    // .str file or disc image
+    pub path: PathBuf,
+    // First and last sector to demux, defaults to the whole file
+    pub sectors: Option<(u32, u32)>,
+    // XA channel to play, defaults to the first one found
+    pub xa_channel: Option<u8>,
+    // Directory to write PNG frames and a WAV file to instead of showing a window
+    pub output: Option<PathBuf>,
+}
+
+pub struct Frame {
+    pub number: u32,
+    // Sector the last chunk of the frame was read from
+    pub sector: u32,
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Audio decoded from every XA sector of the selected channel
+pub struct AudioTrack {
+    pub sample_rate: u32,
+    pub stereo: bool,
+    pub samples: Vec<i16>,
+}
+
+// Chunks of the frame currently being demuxed
+struct PendingFrame {
+    number: u32,
+    width: u16,
+    height: u16,
+    size: usize,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+pub struct StrPlayer {
+    disc: cdrom::Disc,
+    first_sector: u32,
+    next_sector: u32,
+    last_sector: u32,
+    pending: Option<PendingFrame>,
+    mdec: mdec::Mdec,
+    xa: xa::XaDecoder,
+    xa_channel: Option<u8>,
+    pub audio: Option<AudioTrack>,
+}
+
+impl StrPlayer {
+    pub fn open(config: &PlayerConfig) -> Result<Self, String> {
+        let disc = cdrom::Disc::open(&config.path)?;
+        let (first_sector, last_sector) = config.sectors.unwrap_or((0, disc.sector_count().saturating_sub(1)));
+        if first_sector > last_sector || last_sector >= disc.sector_count() {
+            return Err(format!("Sectors {}-{} are outside {} ({} sectors)", first_sector, last_sector, disc.path().display(), disc.sector_count()));
+        }
+
+        let mut player = StrPlayer {
+            disc,
+            first_sector,
+            next_sector: first_sector,
+            last_sector,
+            pending: None,
+            mdec: mdec::Mdec::new(),
+            xa: xa::XaDecoder::new(),
+            xa_channel: config.xa_channel,
+            audio: None,
+        };
+        player.upload_tables();
+        Ok(player)
+    }
+
+    pub fn first_sector(&self) -> u32 {
+        self.first_sector
+    }
+
+    // The tables the Sony libraries give the MDEC before playing a movie
+    fn upload_tables(&mut self) {
+        self.mdec.push_word(0x4000_0001);
+        for chunk in decoder::STANDARD_QUANT_TABLE.repeat(2).chunks_exact(4) {
+            self.mdec.push_word(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        }
+        self.mdec.push_word(0x6000_0000);
+        for pair in decoder::STANDARD_SCALE_TABLE.chunks_exact(2) {
+            self.mdec.push_word(pair[0] as u16 as u32 | (pair[1] as u16 as u32) << 16);
+        }
+    }
+
+    fn decode_audio(&mut self, sector: &cdrom::Sector) {
+        let Some(subheader) = sector.subheader() else {
+            return;
+        };
+        let channel = *self.xa_channel.get_or_insert(subheader.channel);
+        if subheader.channel != channel {
+            return;
+        }
+        let Some(decoded) = self.xa.decode_sector(sector) else {
+            return;
+        };
+        let audio = self.audio.get_or_insert(AudioTrack {
+            sample_rate: decoded.sample_rate,
+            stereo: decoded.stereo,
+            samples: Vec::new(),
+        });
+        if audio.sample_rate != decoded.sample_rate || audio.stereo != decoded.stereo {
+            println!("Skipping XA sector {} with a different format than the rest of the track", self.next_sector - 1);
+            return;
+        }
+        audio.samples.extend(decoded.samples);
+    }
+
+    // Add a video chunk, returning the frame once all of its chunks are in
+    fn add_video_chunk(&mut self, data: &[u8]) -> Option<PendingFrame> {
+        let field16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let field32 = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let chunk = field16(4) as usize;
+        let chunk_count = field16(6) as usize;
+        let number = field32(8);
+        if chunk >= chunk_count {
+            return None;
+        }
+
+        if self.pending.as_ref().is_none_or(|pending| pending.number != number) {
+            if let Some(pending) = &self.pending {
+                println!("Dropping incomplete frame {}", pending.number);
+            }
+            self.pending = Some(PendingFrame {
+                number,
+                width: field16(16),
+                height: field16(18),
+                size: field32(12) as usize,
+                chunks: vec![None; chunk_count],
+            });
+        }
+        let pending = self.pending.as_mut()?;
+        if let Some(slot) = pending.chunks.get_mut(chunk) {
+            *slot = Some(data[VIDEO_CHUNK_HEADER..].to_vec());
+        }
+        if pending.chunks.iter().all(Option::is_some) {
+            return self.pending.take();
+        }
+        None
+    }
+
+    fn decode_frame(&mut self, pending: PendingFrame) -> Result<Frame, String> {
+        let mut data: Vec<u8> = pending.chunks.into_iter().flatten().flatten().collect();
+        data.truncate(pending.size);
+        let codes = bitstream::decode_frame(&data, pending.width, pending.height)
+            .map_err(|e| format!("Frame {}: {}", pending.number, e))?;
+
+        // 24bpp unsigned output, like games use for movies
+        self.mdec.push_word(0x3000_0000 | (codes.len() / 2) as u32);
+        for pair in codes.chunks_exact(2) {
+            self.mdec.push_word(pair[0] as u32 | (pair[1] as u32) << 16);
+        }
+
+        let (width, height) = (pending.width as usize, pending.height as usize);
+        let mut rgba = vec![0xFF; width * height * 4];
+        let mut macroblock = Vec::with_capacity(MACROBLOCK_WORDS * 4);
+        // Macroblocks run down each 16 pixel column before moving right
+        for x in (0..width).step_by(16) {
+            for y in (0..height).step_by(16) {
+                if self.mdec.output_len() < MACROBLOCK_WORDS {
+                    return Err(format!("Frame {}: MDEC produced too few macroblocks", pending.number));
+                }
+                macroblock.clear();
+                for _ in 0..MACROBLOCK_WORDS {
+                    macroblock.extend(self.mdec.pop_word().to_le_bytes());
+                }
+                for (row, pixels) in macroblock.chunks_exact(16 * 3).enumerate().take(height - y) {
+                    for (column, rgb) in pixels.chunks_exact(3).enumerate().take(width - x) {
+                        let offset = ((y + row) * width + x + column) * 4;
+                        rgba[offset..offset + 3].copy_from_slice(rgb);
+                    }
+                }
+            }
+        }
+        // Words left over from padding
+        while self.mdec.output_len() > 0 {
+            self.mdec.pop_word();
+        }
+
+        Ok(Frame {
+            number: pending.number,
+            sector: self.next_sector - 1,
+            width: width as u32,
+            height: height as u32,
+            rgba,
+        })
+    }
+
+    /// Demux sectors until the next frame is complete, decoding audio along the way
+    ///
+    /// Returns None at the last sector. Frames that fail to decode come back as errors
+    /// so the caller can carry on with the next one.
+    pub fn next_frame(&mut self) -> Option<Result<Frame, String>> {
+        while self.next_sector <= self.last_sector {
+            let sector = match self.disc.read_sector(self.next_sector) {
+                Ok(sector) => sector,
+                Err(e) => return Some(Err(e)),
+            };
+            self.next_sector += 1;
+
+            if sector.subheader().is_some_and(|subheader| subheader.submode & cdrom::SUBMODE_AUDIO != 0) {
+                self.decode_audio(&sector);
+                continue;
+            }
+            if sector.data().starts_with(&VIDEO_CHUNK_MAGIC)
+                && let Some(pending) = self.add_video_chunk(sector.data())
+            {
+                return Some(self.decode_frame(pending));
+            }
+        }
+        None
+    }
+}
+
+fn write_png(path: &Path, frame: &Frame) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&frame.rgba))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn write_wav(path: &Path, audio: &AudioTrack) -> Result<(), String> {
+    let channels: u16 = if audio.stereo { 2 } else { 1 };
+    let data_size = (audio.samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+    bytes.extend(b"RIFF");
+    bytes.extend((36 + data_size).to_le_bytes());
+    bytes.extend(b"WAVEfmt ");
+    bytes.extend(16u32.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes()); // PCM
+    bytes.extend(channels.to_le_bytes());
+    bytes.extend(audio.sample_rate.to_le_bytes());
+    bytes.extend((audio.sample_rate * channels as u32 * 2).to_le_bytes());
+    bytes.extend((channels * 2).to_le_bytes());
+    bytes.extend(16u16.to_le_bytes());
+    bytes.extend(b"data");
+    bytes.extend(data_size.to_le_bytes());
+    for sample in &audio.samples {
+        bytes.extend(sample.to_le_bytes());
+    }
+    File::create(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Write every frame as frame_NNNNN.png and the audio as audio.wav
+pub fn export(config: &PlayerConfig, directory: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+    let mut player = StrPlayer::open(config)?;
+    let mut frames = 0;
+    while let Some(frame) = player.next_frame() {
+        match frame {
+            Ok(frame) => {
+                write_png(&directory.join(format!("frame_{:05}.png", frame.number)), &frame)?;
+                frames += 1;
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+    println!("Wrote {} frames to {}", frames, directory.display());
+
+    if let Some(audio) = &player.audio {
+        let path = directory.join("audio.wav");
+        write_wav(&path, audio)?;
+        println!("Wrote {} Hz {} audio to {}", audio.sample_rate, if audio.stereo { "stereo" } else { "mono" }, path.display());
+    }
+    Ok(())
+}
@@ -1,25 +1,80 @@
+use crate::bios;
+use crate::cdrom;
 use crate::cpu;
+use crate::expansion;
 use crate::display;
+use crate::input;
+use crate::memcard;
 use crate::memory;
+use crate::sio;
 
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize)]
 pub struct PS1 {
     cpu: cpu::R3000A,
     mmio: memory::mmio::Mmio,
+    // Built-in kernel used in place of a BIOS image
+    hle: Option<bios::hle::HleBios>,
     #[serde(skip, default)]
-    breakpoints: HashSet<u32>,
+    disc: Option<cdrom::Disc>,
+    // Executable booted instead of the disc
+    #[serde(skip, default)]
+    exe_path: Option<PathBuf>,
+    // Skip the real BIOS's shell and logo, loading the boot executable when it gets there
+    #[serde(skip, default)]
+    fast_boot: bool,
+    // Names save states, from the boot executable
+    #[serde(skip, default)]
+    game_id: String,
+    // Where the HLE BIOS's rand() starts, so runs differ only when asked to
+    #[serde(skip, default)]
+    seed: u32,
+    fast_boot_pending: bool,
+    // Frames run since power on, which save states show as play time
+    frames: u64,
+    #[serde(skip, default)]
+    // Ordered so nothing that lists them depends on hashing
+    breakpoints: BTreeSet<u32>,
+    // Host end of the SIO1 link cable, not part of the machine's state
+    #[serde(skip, default)]
+    link: Option<sio::link::Link>,
+    // Host end of the parallel port cartridge's PC link
+    #[serde(skip, default)]
+    expansion_link: Option<sio::link::Link>,
+    #[serde(skip, default)]
+    link_cycles: u32,
 }
 
+// How often the link cable is serviced, well under one byte time at the usual baud rates
+const LINK_POLL_CYCLES: u32 = 2048;
+// Where the CPU starts after reset, the start of the BIOS ROM
+const RESET_VECTOR: u32 = 0xBFC00000;
+// The BIOS jumps here once the kernel is up, to the shell that shows the logo and boots the disc
+const SHELL_ENTRY: u32 = 0x80030000;
+// Time still passes for devices once the program has exited
+const STOPPED_CYCLES: u32 = 1;
+
 impl Clone for PS1 {
     fn clone(&self) -> Self {
         PS1 {
             cpu: self.cpu.clone(),
             mmio: self.mmio.clone(),
+            hle: self.hle.clone(),
+            disc: self.disc.clone(),
+            exe_path: self.exe_path.clone(),
+            fast_boot: self.fast_boot,
+            game_id: self.game_id.clone(),
+            seed: self.seed,
+            fast_boot_pending: self.fast_boot_pending,
+            frames: self.frames,
             breakpoints: self.breakpoints.clone(),
+            link: None,
+            expansion_link: None,
+            link_cycles: 0,
         }
     }
 }
@@ -29,7 +84,18 @@ impl PS1 {
         PS1 {
             cpu: cpu::R3000A::new(),
             mmio: memory::mmio::Mmio::new(),
-            breakpoints: HashSet::new(),
+            hle: None,
+            disc: None,
+            exe_path: None,
+            fast_boot: false,
+            game_id: String::new(),
+            seed: 0,
+            fast_boot_pending: false,
+            frames: 0,
+            breakpoints: BTreeSet::new(),
+            link: None,
+            expansion_link: None,
+            link_cycles: 0,
         }
     }
 
@@ -38,29 +104,261 @@ impl PS1 {
         self.cpu.halted = false;
         self.cpu.stopped = false;
         self.cpu.registers.reset();
+        self.cpu.cop0 = cpu::cop0::Cop0::new();
+        self.cpu.muldiv = cpu::muldiv::MulDivUnit::new();
+        self.frames = 0;
+        self.boot();
+    }
+
+    /// Start the BIOS, or with the HLE BIOS go straight to the executable or the disc's boot file
+    fn boot(&mut self) {
+        let Some(hle) = self.hle.as_mut() else {
+            self.cpu.registers.pc = RESET_VECTOR;
+            // A side-loaded executable can only go in once the kernel is up
+            self.fast_boot_pending = self.fast_boot || self.exe_path.is_some();
+            return;
+        };
+        let executable = self.exe_path.as_deref().map(bios::exe::Executable::open).transpose();
+        let booted = executable.and_then(|executable| hle.boot(&mut self.cpu, &mut self.mmio, self.disc.as_mut(), executable.as_ref()));
+        if let Err(e) = booted {
+            println!("Boot failed: {}", e);
+            self.cpu.stopped = true;
+        }
+        hle.seed_rand(self.seed);
+    }
+
+    /// Load the boot executable in place of the shell, with SYSTEM.CNF's settings
+    fn fast_boot(&mut self) -> Result<(), String> {
+        let (executable, cnf) = match &self.exe_path {
+            Some(path) => (bios::exe::Executable::open(path)?, bios::system_cnf::SystemCnf::default()),
+            None => {
+                let disc = self.disc.as_mut().ok_or("no disc inserted")?;
+                let cnf = bios::system_cnf::SystemCnf::from_disc(disc)?;
+                (bios::exe::Executable::from_disc(disc, &cnf)?, cnf)
+            }
+        };
+        executable.load_with_set_conf(&mut self.cpu, &mut self.mmio, &cnf);
+        println!("Fast boot: {} with TCB={} EVENT={} STACK={:08X}", cnf.boot, cnf.tcb, cnf.event, cnf.stack);
+        Ok(())
+    }
+
+    /// Takes effect on the next reset
+    pub fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+    }
+
+    pub fn set_fast_boot(&mut self, fast_boot: bool) {
+        self.fast_boot = fast_boot;
+    }
+
+    pub fn load_bios(&mut self, path: &Path) -> Result<(), String> {
+        let image = std::fs::read(path).map_err(|e| format!("Failed to read BIOS {}: {}", path.display(), e))?;
+        self.mmio.load_bios(&image)?;
+        self.hle = None;
+        Ok(())
+    }
+
+    /// Use the built-in kernel instead of a BIOS image, takes effect on the next reset
+    pub fn use_hle_bios(&mut self) {
+        self.hle = Some(bios::hle::HleBios::new());
+    }
+
+    pub fn insert_disc(&mut self, mut disc: cdrom::Disc) {
+        // Discs without a SYSTEM.CNF boot PSX.EXE, which says nothing about the game
+        self.game_id = bios::system_cnf::SystemCnf::from_disc(&mut disc)
+            .ok()
+            .filter(|cnf| cnf.boot != bios::system_cnf::SystemCnf::default().boot)
+            .map(|cnf| boot_file_name(&cnf.boot))
+            .unwrap_or_else(|| file_stem(disc.path()));
+        self.disc = Some(disc);
+    }
+
+    pub fn disc_path(&self) -> Option<&Path> {
+        self.disc.as_ref().map(|disc| disc.path())
+    }
+
+    /// Boot this PS-X EXE instead of the disc, a real BIOS loads it when it reaches the shell
+    pub fn side_load_exe(&mut self, path: PathBuf) {
+        self.game_id = file_stem(&path);
+        self.exe_path = Some(path);
+    }
+
+    /// The boot file's name, as in SLUS_012.34, or the image's name without one
+    pub fn game_id(&self) -> &str {
+        if self.game_id.is_empty() { "BIOS" } else { &self.game_id }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn bios_hash(&self) -> u32 {
+        self.mmio.bios_hash()
+    }
+
+    /// Take on a loaded state's machine, keeping the disc, BIOS image, links and breakpoints the state doesn't carry
+    pub fn restore_state(&mut self, state: PS1) {
+        self.cpu = state.cpu;
+        self.mmio.restore_state(state.mmio);
+        self.hle = state.hle;
+        self.fast_boot_pending = state.fast_boot_pending;
+        self.frames = state.frames;
+    }
+
+    /// Take on another machine's state as `restore_state` does, without going through a save state
+    ///
+    /// RAM and the other large buffers are copied into this machine's, and the BIOS image isn't copied.
+    pub fn copy_state_from(&mut self, other: &PS1) {
+        // Every field is named, so a new one doesn't compile until it is copied or skipped here
+        let PS1 {
+            cpu,
+            mmio,
+            hle,
+            disc: _,
+            exe_path: _,
+            fast_boot: _,
+            game_id: _,
+            seed: _,
+            fast_boot_pending,
+            frames,
+            breakpoints: _,
+            link: _,
+            expansion_link: _,
+            link_cycles: _,
+        } = other;
+        self.cpu.clone_from(cpu);
+        self.mmio.copy_state_from(mmio);
+        self.hle.clone_from(hle);
+        self.fast_boot_pending = *fast_boot_pending;
+        self.frames = *frames;
     }
 
     pub fn get_current_frame(&mut self) -> Box<[u8]> {
         vec![0; display::FB_SIZE].into_boxed_slice()
     }
 
-    pub fn step_instruction(&mut self, _collect_audio: bool) -> (bool, u8) {
+    pub fn step_instruction(&mut self, _collect_audio: bool) -> (bool, u32) {
         let pc = self.cpu.registers.pc;
         if self.breakpoints.contains(&pc) {
             return (true, 0);
         }
 
-        let cycles = self.cpu.step(&mut self.mmio);
+        if self.fast_boot_pending && pc == SHELL_ENTRY {
+            self.fast_boot_pending = false;
+            // Without anything to load the shell runs as usual
+            if let Err(e) = self.fast_boot() {
+                println!("Fast boot skipped: {}", e);
+            }
+        }
+
+        let cycles = if self.cpu.stopped {
+            STOPPED_CYCLES
+        } else if let Some(hle) = self.hle.as_mut().filter(|hle| hle.intercepts(pc)) {
+            // An interrupt can still come in before a kernel call starts
+            if self.cpu.take_interrupt(&self.mmio) {
+                1
+            } else {
+                hle.step(&mut self.cpu, &mut self.mmio, self.disc.as_mut())
+            }
+        } else {
+            self.cpu.step(&mut self.mmio)
+        };
+        self.mmio.step(cycles);
+        self.link_cycles += cycles;
+        if self.link_cycles >= LINK_POLL_CYCLES {
+            self.link_cycles = 0;
+            self.service_link();
+            self.service_expansion_link();
+        }
         (false, cycles)
     }
 
+    /// Swap bytes and handshake lines between SIO1 and the other end of the link
+    fn service_link(&mut self) {
+        let Some(link) = self.link.as_mut() else {
+            return;
+        };
+        let sio1 = &mut self.mmio.sio1;
+        for value in sio1.take_transmitted() {
+            link.send_data(value);
+        }
+        link.send_lines(sio1.output_lines());
+        for event in link.poll() {
+            match event {
+                sio::link::LinkEvent::Data(value) => sio1.receive(value),
+                sio::link::LinkEvent::Lines(dtr, rts) => sio1.set_remote_lines(dtr, rts),
+            }
+        }
+    }
+
+    /// Swap bytes between the parallel port cartridge and the PC side tool
+    fn service_expansion_link(&mut self) {
+        let Some(link) = self.expansion_link.as_mut() else {
+            return;
+        };
+        let cartridge = &mut self.mmio.expansion1;
+        for value in cartridge.take_transmitted() {
+            link.send_data(value);
+        }
+        for event in link.poll() {
+            if let sio::link::LinkEvent::Data(value) = event {
+                cartridge.receive(value);
+            }
+        }
+    }
+
+    /// Text sent to the debug TTY or through std_out_putchar since the last call
+    pub fn take_tty_output(&mut self) -> Vec<u8> {
+        let mut output: Vec<u8> = self.mmio.duart.take_transmitted().into();
+        output.extend(self.cpu.tty_output.drain(..));
+        output
+    }
+
+    /// Calls into the kernel's A0/B0/C0 tables since the last call
+    pub fn take_kernel_calls(&mut self) -> Vec<bios::calls::KernelCall> {
+        std::mem::take(&mut self.cpu.kernel_calls).into()
+    }
+
+    pub fn connect_link(&mut self, link: sio::link::Link) {
+        self.link = Some(link);
+    }
+
+    pub fn connect_expansion_link(&mut self, link: sio::link::Link) {
+        self.expansion_link = Some(link);
+    }
+
+    pub fn insert_expansion_1(&mut self, cartridge: expansion::Expansion1) {
+        if cartridge.has_pre_boot_hook() {
+            println!("Expansion ROM has a pre-boot hook");
+        }
+        if cartridge.has_mid_boot_hook() {
+            println!("Expansion ROM has a mid-boot hook");
+        }
+        self.mmio.expansion1 = cartridge;
+    }
+
+    /// Run a frame that already happened once, as after rewinding. The links are left alone and
+    /// what it prints or sends is dropped, so none of it is seen twice.
+    pub fn rerun_frame(&mut self) -> Box<[u8]> {
+        let link = self.link.take();
+        let expansion_link = self.expansion_link.take();
+        let frame = self.run_until_frame(false).0;
+        self.link = link;
+        self.expansion_link = expansion_link;
+        self.mmio.sio1.take_transmitted();
+        self.mmio.expansion1.take_transmitted();
+        self.take_tty_output();
+        self.take_kernel_calls();
+        frame
+    }
+
     pub fn run_until_frame(&mut self, collect_audio: bool) -> (Box<[u8]>, bool) {
         let mut cpu_cycles_this_frame = 0u32;
         const MAX_CYCLES_PER_FRAME: u32 = 1000; // Placeholder value
         let frame = vec![0; display::FB_SIZE].into_boxed_slice();
         loop {
             let (breakpoint_hit, cycles) = self.step_instruction(collect_audio);
-            cpu_cycles_this_frame += cycles as u32;
+            cpu_cycles_this_frame += cycles;
             
             if breakpoint_hit {
                 // Breakpoint hit - return current frame and indicate breakpoint hit
@@ -72,11 +370,100 @@ impl PS1 {
             }
             
             if cpu_cycles_this_frame >= MAX_CYCLES_PER_FRAME {
+                self.frames += 1;
                 return (frame, false);
             }
         }
     }
 
+    pub fn connect_controller(&mut self, port: usize, controller_type: input::ControllerType) {
+        self.mmio.set_controller(port, input::Controller::new(controller_type));
+    }
+
+    pub fn connect_multitap(&mut self, port: usize, controller_types: [input::ControllerType; input::multitap::MULTITAP_SLOTS]) {
+        self.mmio.set_controller(port, input::Controller::multitap(controller_types));
+    }
+
+    /// Card slots are numbered as `sio0` numbers them, four to a port, false if `slot` isn't there
+    pub fn insert_memory_card(&mut self, slot: usize, card: Option<memcard::MemoryCard>) -> bool {
+        self.mmio.sio0.set_memory_card(slot, card)
+    }
+
+    pub fn memory_card(&self, slot: usize) -> Option<&memcard::MemoryCard> {
+        self.mmio.sio0.memory_card(slot)
+    }
+
+    pub fn memory_card_mut(&mut self, slot: usize) -> Option<&mut memcard::MemoryCard> {
+        self.mmio.sio0.memory_card_mut(slot)
+    }
+
+    pub fn memory_card_slots(&self) -> Vec<usize> {
+        self.mmio.sio0.memory_card_slots()
+    }
+
+    pub fn memory_card_slot_name(&self, slot: usize) -> String {
+        self.mmio.sio0.memory_card_slot_name(slot)
+    }
+
+    /// CRC32 of the cards in every slot, which runs must start with to play out the same
+    pub fn memory_cards_hash(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        for slot in self.memory_card_slots() {
+            match self.memory_card(slot) {
+                Some(card) => {
+                    hasher.update(&[1]);
+                    hasher.update(card.data());
+                }
+                None => hasher.update(&[0]),
+            }
+        }
+        hasher.finalize()
+    }
+
+    /// Call once a frame, writes back memory cards the game has finished writing to
+    pub fn end_frame_memory_cards(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for card in self.mmio.sio0.memory_cards_mut() {
+            if let Err(e) = card.end_frame() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Write back every memory card with writes still waiting, before quitting
+    pub fn flush_memory_cards(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for card in self.mmio.sio0.memory_cards_mut() {
+            if let Err(e) = card.flush_if_dirty() {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Stop memory cards writing to their files, for copies whose frames never really happen
+    pub fn detach_memory_cards(&mut self) {
+        for card in self.mmio.sio0.memory_cards_mut() {
+            card.detach();
+        }
+    }
+
+    /// Pads are numbered across both ports, counting each multitap slot
+    pub fn set_pad_input(&mut self, pad: usize, input: input::PadInput) {
+        if let Some(controller) = self.mmio.sio0.pad_mut(pad) {
+            controller.set_input(input, &self.mmio.display_area);
+        }
+    }
+
+    pub fn has_lightgun(&self) -> bool {
+        self.mmio.sio0.ports.iter().any(|port| port.controller.is_lightgun())
+    }
+
+    pub fn get_rumble(&mut self, pad: usize) -> Option<(bool, u8)> {
+        self.mmio.sio0.pad_mut(pad).and_then(|controller| controller.rumble())
+    }
+
     pub fn get_cpu_registers(&self) -> &cpu::registers::Registers {
         &self.cpu.registers
     }
@@ -89,7 +476,17 @@ impl PS1 {
         self.breakpoints.remove(&address);
     }
 
-    pub fn get_breakpoints(&self) -> &HashSet<u32> {
+    pub fn get_breakpoints(&self) -> &BTreeSet<u32> {
         &self.breakpoints
     }
-}
\ No newline at end of file
+}
+
+// cdrom:\SLUS_012.34;1 to SLUS_012.34
+fn boot_file_name(boot: &str) -> String {
+    let name = boot.rsplit(['\\', '/', ':']).next().unwrap_or(boot);
+    name.split(';').next().unwrap_or_default().to_string()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().to_string())
+}
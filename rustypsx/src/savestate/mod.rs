@@ -0,0 +1,299 @@
+//! Save states: a small uncompressed header followed by the deflated machine state.
+//!
+//! The header carries what's needed to list and check a state without decoding it, the
+//! game it belongs to, the BIOS it ran on and a thumbnail of the screen. Both are bincode,
+//! whose fixed width integers keep fields at the same offsets from one state to the next.
+
+pub mod rewind;
+pub mod run_ahead;
+
+use crate::display;
+use crate::psx::PS1;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAGIC: &[u8; 8] = b"RPSXSTAT";
+// Bumped whenever a released header or serialized type changes shape
+pub const FORMAT_VERSION: u32 = 1;
+// Magic, format version and header length
+const PREFIX_SIZE: usize = 16;
+// Well past any real header, so a corrupt length can't ask for gigabytes
+const MAX_HEADER_SIZE: usize = 1 << 20;
+// Over ten times a machine with every card in, so a corrupt stream can't inflate without end
+const MAX_STATE_SIZE: usize = 1 << 25;
+
+pub const SLOTS: usize = 10;
+// Play time is counted in NTSC frames
+const FRAMES_PER_SECOND: u64 = 60;
+// A quarter of the screen in each direction
+pub const THUMBNAIL_WIDTH: usize = display::WIDTH as usize / 4;
+pub const THUMBNAIL_HEIGHT: usize = display::HEIGHT as usize / 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Header {
+    pub game_id: String,
+    // CRC32 of the BIOS image, 0 for the HLE BIOS
+    pub bios_hash: u32,
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub emulator_version: String,
+    // Frames run since power on
+    pub frames: u64,
+    // THUMBNAIL_WIDTH by THUMBNAIL_HEIGHT RGBA
+    #[serde(with = "serde_bytes")]
+    pub thumbnail: Vec<u8>,
+}
+
+impl Header {
+    fn new(ps1: &PS1, frame: &[u8]) -> Self {
+        Header {
+            game_id: ps1.game_id().to_string(),
+            bios_hash: ps1.bios_hash(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            frames: ps1.frames(),
+            thumbnail: thumbnail(frame),
+        }
+    }
+
+    /// Whether the state can go into this machine, same game and same BIOS
+    pub fn validate(&self, ps1: &PS1) -> Result<(), String> {
+        if self.game_id != ps1.game_id() {
+            return Err(format!("State is for {}, not {}", self.game_id, ps1.game_id()));
+        }
+        if self.bios_hash != ps1.bios_hash() {
+            return Err(format!("State was saved with BIOS {:08X}, this is {:08X}", self.bios_hash, ps1.bios_hash()));
+        }
+        Ok(())
+    }
+
+    /// As in 1:02:03
+    pub fn play_time(&self) -> String {
+        let seconds = self.frames / FRAMES_PER_SECOND;
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+
+    /// As in 2024-03-01 17:45 UTC
+    pub fn saved_at(&self) -> String {
+        let days = (self.timestamp / 86400) as i64;
+        let minutes = self.timestamp % 86400 / 60;
+        let (year, month, day) = civil_from_days(days);
+        format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+    }
+}
+
+// Days since 1970-01-01 to a Gregorian date, after Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Every 4th pixel of every 4th line of an RGBA frame
+fn thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let offset = (y * 4 * display::WIDTH as usize + x * 4) * 4;
+            thumbnail.extend_from_slice(frame.get(offset..offset + 4).unwrap_or(&[0, 0, 0, 0xFF]));
+        }
+    }
+    thumbnail
+}
+
+/// CRC32 of the encoded machine, cheap enough to log every frame
+///
+/// Equal states always hash the same and different ones almost never do. Files the machine was
+/// built from, the BIOS, expansion ROM and card image paths, are not part of it.
+pub fn state_hash(ps1: &PS1) -> u32 {
+    let mut writer = HashWriter(crc32fast::Hasher::new());
+    bincode::serialize_into(&mut writer, ps1).expect("Machine state failed to encode");
+    writer.0.finalize()
+}
+
+/// Hashes what is written to it, so the machine is hashed as it encodes without a copy of the state
+struct HashWriter(crc32fast::Hasher);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where a slot's state lives, one file per game and slot
+pub fn slot_path(directory: &Path, game_id: &str, slot: usize) -> PathBuf {
+    directory.join(format!("{}.{}.state", game_id, slot + 1))
+}
+
+/// A state file's contents, which movies also embed
+pub fn to_bytes(ps1: &PS1, frame: &[u8]) -> Result<Vec<u8>, String> {
+    let header = bincode::serialize(&Header::new(ps1, frame)).map_err(|e| format!("Failed to encode header: {}", e))?;
+    let state = bincode::serialize(ps1).map_err(|e| format!("Failed to encode state: {}", e))?;
+
+    let mut file = Vec::with_capacity(PREFIX_SIZE + header.len() + state.len() / 4);
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    file.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    file.extend_from_slice(&header);
+    let mut encoder = ZlibEncoder::new(file, Compression::default());
+    encoder.write_all(&state).map_err(|e| format!("Failed to compress state: {}", e))?;
+    encoder.finish().map_err(|e| format!("Failed to compress state: {}", e))
+}
+
+pub fn save(path: &Path, ps1: &PS1, frame: &[u8]) -> Result<(), String> {
+    let file = to_bytes(ps1, frame)?;
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+    }
+    std::fs::write(path, file).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// The header's length, once the prefix shows the header and state are in a shape this build reads
+fn check_prefix(prefix: &[u8]) -> Result<usize, String> {
+    if prefix.len() < PREFIX_SIZE || &prefix[..8] != MAGIC {
+        return Err("Not a save state".to_string());
+    }
+    let version = u32::from_le_bytes(prefix[8..12].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(format!("State is format version {}, this emulator reads version {}", version, FORMAT_VERSION));
+    }
+    let header_length = u32::from_le_bytes(prefix[12..16].try_into().unwrap()) as usize;
+    if header_length > MAX_HEADER_SIZE {
+        return Err("Save state header is corrupt".to_string());
+    }
+    Ok(header_length)
+}
+
+fn decode_header(header: &[u8]) -> Result<Header, String> {
+    bincode::deserialize(header).map_err(|e| format!("Bad save state header: {}", e))
+}
+
+// The header and the compressed state after it
+fn split(file: &[u8]) -> Result<(Header, &[u8]), String> {
+    let header_length = check_prefix(file)?;
+    let header = file
+        .get(PREFIX_SIZE..PREFIX_SIZE + header_length)
+        .ok_or("Save state header is truncated")?;
+    Ok((decode_header(header)?, &file[PREFIX_SIZE + header_length..]))
+}
+
+/// Just the header, without reading the state after it
+pub fn read_header(path: &Path) -> Result<Header, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+    let mut file = std::fs::File::open(path).map_err(read_error)?;
+    let mut prefix = [0; PREFIX_SIZE];
+    file.read_exact(&mut prefix).map_err(|_| "Not a save state".to_string())?;
+    let mut header = vec![0; check_prefix(&prefix)?];
+    file.read_exact(&mut header).map_err(|_| "Save state header is truncated".to_string())?;
+    decode_header(&header)
+}
+
+/// Load a state into `ps1`, which keeps its disc, BIOS and links
+pub fn load(path: &Path, ps1: &mut PS1) -> Result<Header, String> {
+    let file = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    load_bytes(&file, ps1)
+}
+
+/// Load a state from a state file's contents
+pub fn load_bytes(file: &[u8], ps1: &mut PS1) -> Result<Header, String> {
+    let (header, compressed) = split(file)?;
+    header.validate(ps1)?;
+
+    let mut state = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(MAX_STATE_SIZE as u64)
+        .read_to_end(&mut state)
+        .map_err(|e| format!("Failed to decompress state: {}", e))?;
+    if state.len() == MAX_STATE_SIZE {
+        return Err("Save state is too large".to_string());
+    }
+    let loaded = bincode::deserialize::<PS1>(&state).map_err(|e| format!("Bad save state: {}", e))?;
+    ps1.restore_state(loaded);
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memcard::MemoryCard;
+
+    fn machine() -> PS1 {
+        let mut ps1 = PS1::new();
+        ps1.insert_memory_card(0, Some(MemoryCard::new()));
+        ps1
+    }
+
+    fn saved_file() -> (PS1, Vec<u8>) {
+        let mut ps1 = machine();
+        ps1.memory_card_mut(0).unwrap().data_mut()[0x80] = 0x5A;
+        let file = to_bytes(&ps1, &vec![0x40; display::FB_SIZE]).unwrap();
+        (ps1, file)
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let (saved, file) = saved_file();
+        let mut loaded = machine();
+        let header = load_bytes(&file, &mut loaded).unwrap();
+
+        assert_eq!(header.thumbnail.len(), THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+        assert_eq!(loaded.memory_card(0).unwrap().data()[0x80], 0x5A);
+        assert_eq!(state_hash(&loaded), state_hash(&saved));
+    }
+
+    #[test]
+    fn state_hash_covers_the_encoded_machine() {
+        let (ps1, _) = saved_file();
+        assert_eq!(state_hash(&ps1), crc32fast::hash(&bincode::serialize(&ps1).unwrap()));
+    }
+
+    #[test]
+    fn truncated_state_is_refused() {
+        let (_, file) = saved_file();
+        let header_end = PREFIX_SIZE + u32::from_le_bytes(file[12..16].try_into().unwrap()) as usize;
+        for length in [0, 8, PREFIX_SIZE, header_end - 1, header_end, file.len() / 2, file.len() - 1] {
+            assert!(load_bytes(&file[..length], &mut machine()).is_err(), "{} of {} bytes loaded", length, file.len());
+        }
+    }
+
+    #[test]
+    fn other_format_versions_are_refused() {
+        let (_, mut file) = saved_file();
+        file[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(load_bytes(&file, &mut machine()).unwrap_err().contains("format version"));
+    }
+
+    #[test]
+    fn huge_header_lengths_are_refused() {
+        let (_, mut file) = saved_file();
+        file[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(load_bytes(&file, &mut machine()).unwrap_err(), "Save state header is corrupt");
+    }
+
+    #[test]
+    fn oversized_states_are_refused() {
+        let (_, file) = saved_file();
+        let header_end = PREFIX_SIZE + u32::from_le_bytes(file[12..16].try_into().unwrap()) as usize;
+        let mut encoder = ZlibEncoder::new(file[..header_end].to_vec(), Compression::default());
+        encoder.write_all(&vec![0; MAX_STATE_SIZE]).unwrap();
+        let file = encoder.finish().unwrap();
+        assert_eq!(load_bytes(&file, &mut machine()).unwrap_err(), "Save state is too large");
+    }
+}
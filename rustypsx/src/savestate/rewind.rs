@@ -0,0 +1,215 @@
+//! Rewinding through a ring buffer of snapshots taken every few frames.
+//!
+//! Snapshots are the encoded machine state. Every few of them is a deflated keyframe and
+//! the rest are deflated XORs against the keyframe before them, which are mostly zeroes.
+//! Playing backwards restores a snapshot, emulates forward to the frame being left and
+//! then shows those frames in reverse, so each frame shown costs one frame of emulation.
+//! The frames in between run with the input held when the snapshot was taken, unless the
+//! caller sets the input for each of them, as a running movie does.
+
+use crate::psx::PS1;
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+// Snapshots per keyframe, more makes deltas larger and evictions coarser
+const KEYFRAME_SNAPSHOTS: usize = 8;
+
+struct Snapshot {
+    // PS1::frames when taken
+    frame: u64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+// The snapshot being rewound through and the frames emulated after it, newest last
+struct Playback {
+    frame: u64,
+    state: Vec<u8>,
+    frames: Vec<Box<[u8]>>,
+}
+
+pub struct Rewind {
+    // Frames between snapshots, 0 when rewinding is off
+    interval: u64,
+    // Bytes kept before the oldest snapshots are dropped, counting what's held uncompressed too
+    budget: usize,
+    snapshots: VecDeque<Snapshot>,
+    // Bytes of compressed snapshots
+    used: usize,
+    // Uncompressed state of the newest keyframe, what deltas are taken against
+    keyframe: Option<Vec<u8>>,
+    since_keyframe: usize,
+    playback: Option<Playback>,
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).expect("Writing to a Vec can't fail");
+    encoder.finish().expect("Writing to a Vec can't fail")
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ZlibDecoder::new(data)
+        .read_to_end(&mut output)
+        .expect("Rewind snapshot is corrupt");
+    output
+}
+
+// Also undoes itself, a state's length is kept and the keyframe is treated as zero past its end
+fn xor(data: &[u8], keyframe: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ keyframe.get(index).copied().unwrap_or(0))
+        .collect()
+}
+
+fn restore(ps1: &mut PS1, state: &[u8]) {
+    let state = bincode::deserialize::<PS1>(state).expect("Rewind snapshot failed to decode");
+    ps1.restore_state(state);
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Self {
+            interval: interval as u64,
+            budget,
+            snapshots: VecDeque::new(),
+            used: 0,
+            keyframe: None,
+            since_keyframe: 0,
+            playback: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval > 0
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Forget everything, after a reset or a loaded state breaks the timeline
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.used = 0;
+        self.keyframe = None;
+        self.playback = None;
+    }
+
+    /// Call after each frame run forwards, snapshots the frames that fall on the interval
+    pub fn record(&mut self, ps1: &PS1) {
+        if !self.enabled() || self.playback.is_some() || !ps1.frames().is_multiple_of(self.interval) {
+            return;
+        }
+        let state = bincode::serialize(ps1).expect("Machine state failed to encode");
+        self.push(ps1.frames(), state);
+    }
+
+    fn push(&mut self, frame: u64, state: Vec<u8>) {
+        let data = match &self.keyframe {
+            Some(keyframe) if self.since_keyframe < KEYFRAME_SNAPSHOTS => compress(&xor(&state, keyframe)),
+            _ => {
+                let data = compress(&state);
+                self.keyframe = Some(state);
+                self.since_keyframe = 0;
+                data
+            }
+        };
+        let keyframe = self.since_keyframe == 0;
+        self.since_keyframe += 1;
+        self.used += data.len();
+        self.snapshots.push_back(Snapshot { frame, keyframe, data });
+        self.evict();
+    }
+
+    // Bytes held outside the snapshots, the keyframe deltas are taken against and what's being played back
+    fn working_set(&self) -> usize {
+        let keyframe = self.keyframe.as_ref().map_or(0, Vec::len);
+        let playback = self.playback.as_ref().map_or(0, |playback| {
+            playback.state.len() + playback.frames.iter().map(|frame| frame.len()).sum::<usize>()
+        });
+        keyframe + playback
+    }
+
+    // Drop the oldest keyframe and its deltas until under budget, always keeping the newest group
+    fn evict(&mut self) {
+        while self.used + self.working_set() > self.budget {
+            let group = 1 + self.snapshots.iter().skip(1).take_while(|snapshot| !snapshot.keyframe).count();
+            if group >= self.snapshots.len() {
+                break;
+            }
+            for snapshot in self.snapshots.drain(..group) {
+                self.used -= snapshot.data.len();
+            }
+        }
+    }
+
+    fn decode(&self, index: usize) -> Vec<u8> {
+        let snapshot = &self.snapshots[index];
+        if snapshot.keyframe {
+            return decompress(&snapshot.data);
+        }
+        let keyframe = (0..index)
+            .rev()
+            .find(|&earlier| self.snapshots[earlier].keyframe)
+            .expect("Rewind deltas always follow a keyframe");
+        xor(&decompress(&snapshot.data), &decompress(&self.snapshots[keyframe].data))
+    }
+
+    fn drop_newest(&mut self) {
+        if let Some(snapshot) = self.snapshots.pop_back() {
+            self.used -= snapshot.data.len();
+        }
+        // The keyframe may be gone, so start a new one
+        self.keyframe = None;
+    }
+
+    /// The frame before the one on screen, None once there's no more history.
+    /// `before_frame` is called ahead of each frame emulated again.
+    pub fn step_back(&mut self, ps1: &mut PS1, mut before_frame: impl FnMut(&mut PS1)) -> Option<Box<[u8]>> {
+        if !self.enabled() {
+            return None;
+        }
+        if self.playback.as_ref().is_none_or(|playback| playback.frames.is_empty()) {
+            // The first frame still to show, the one after the previous snapshot's, or the one on screen
+            let target = self.playback.as_ref().map_or(ps1.frames(), |playback| playback.frame + 1);
+            while self.snapshots.back().is_some_and(|snapshot| snapshot.frame + 1 >= target) {
+                self.drop_newest();
+            }
+            let frame = self.snapshots.back()?.frame;
+            let state = self.decode(self.snapshots.len() - 1);
+            self.drop_newest();
+
+            restore(ps1, &state);
+            let frames = (frame + 1..target)
+                .map(|_| {
+                    before_frame(ps1);
+                    ps1.rerun_frame()
+                })
+                .collect();
+            self.playback = Some(Playback { frame, state, frames });
+            self.evict();
+        }
+        self.playback.as_mut()?.frames.pop()
+    }
+
+    /// Bring the machine to the frame on screen and carry on recording from there
+    pub fn finish(&mut self, ps1: &mut PS1, mut before_frame: impl FnMut(&mut PS1)) {
+        let Some(playback) = self.playback.take() else {
+            return;
+        };
+        // Frames left unshown come after the one on screen, which is the next one up
+        let shown = playback.frame + 1 + playback.frames.len() as u64;
+        restore(ps1, &playback.state);
+        for _ in playback.frame..shown {
+            before_frame(ps1);
+            ps1.rerun_frame();
+        }
+        self.push(playback.frame, playback.state);
+    }
+}
@@ -0,0 +1,61 @@
+//! Run-ahead: showing the frame a few frames in the future to hide games' input lag.
+//!
+//! The machine the player hears and controls never runs ahead. A copy of it runs the extra
+//! frames with the input held now and only its last frame is kept.
+
+use crate::psx::PS1;
+
+/// How the copy that runs ahead is made each frame
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunAheadMode {
+    /// Clone the whole machine every frame, BIOS image included
+    Clone,
+    /// Copy the machine's state into a copy kept from frame to frame
+    State,
+}
+
+pub struct RunAhead {
+    // Frames to run ahead, 0 when off
+    frames: u32,
+    mode: RunAheadMode,
+    // The copy the State mode copies into, made on first use
+    copy: Option<PS1>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32, mode: RunAheadMode) -> Self {
+        Self { frames, mode, copy: None }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.frames > 0
+    }
+
+    /// Forget the copy, after a reset or a loaded state changes more than the state carries
+    pub fn clear(&mut self) {
+        self.copy = None;
+    }
+
+    /// The frame `frames` ahead of `ps1`, without audio and leaving `ps1` as it was
+    pub fn future_frame(&mut self, ps1: &PS1) -> Box<[u8]> {
+        let copy = match self.mode {
+            RunAheadMode::Clone => self.copy.insert(ps1.clone()),
+            RunAheadMode::State => match self.copy.as_mut() {
+                Some(copy) => {
+                    copy.copy_state_from(ps1);
+                    copy
+                }
+                None => self.copy.insert(ps1.clone()),
+            },
+        };
+        copy.detach_memory_cards();
+        let mut frame = copy.get_current_frame();
+        for _ in 0..self.frames {
+            frame = copy.run_until_frame(false).0;
+        }
+        // Output from frames that never happen
+        copy.take_tty_output();
+        copy.take_kernel_calls();
+        frame
+    }
+}
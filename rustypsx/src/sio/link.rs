@@ -0,0 +1,181 @@
+//! Host side of the SIO1 link cable: a TCP connection to another instance, or a pseudo-terminal.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+// Two byte messages over TCP, a kind and its value
+const MESSAGE_DATA: u8 = 0x00;
+const MESSAGE_LINES: u8 = 0x01;
+const LINE_DTR: u8 = 1 << 0;
+const LINE_RTS: u8 = 1 << 1;
+
+#[derive(Clone, Debug)]
+pub enum LinkTarget {
+    // Wait for the other instance to connect to this address
+    Listen(String),
+    Connect(String),
+    // Existing pseudo-terminal, e.g. one end of a socat pty pair
+    Pty(PathBuf),
+}
+
+pub enum LinkEvent {
+    Data(u8),
+    // The other end's DTR and RTS
+    Lines(bool, bool),
+}
+
+enum Writer {
+    Tcp(TcpStream),
+    Pty(File),
+}
+
+pub struct Link {
+    writer: Option<Writer>,
+    incoming: Option<Receiver<LinkEvent>>,
+    // Set while listening for the other instance
+    pending: Option<Receiver<TcpStream>>,
+    lines: (bool, bool),
+}
+
+fn spawn_tcp_reader(mut stream: TcpStream) -> Receiver<LinkEvent> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut message = [0u8; 2];
+        while stream.read_exact(&mut message).is_ok() {
+            let event = match message[0] {
+                MESSAGE_DATA => LinkEvent::Data(message[1]),
+                MESSAGE_LINES => LinkEvent::Lines(message[1] & LINE_DTR != 0, message[1] & LINE_RTS != 0),
+                _ => continue,
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        // The cable was pulled
+        let _ = sender.send(LinkEvent::Lines(false, false));
+    });
+    receiver
+}
+
+fn spawn_pty_reader(mut file: File) -> Receiver<LinkEvent> {
+    let (sender, receiver) = mpsc::channel();
+    // A terminal has no handshake lines, so it is always ready
+    let _ = sender.send(LinkEvent::Lines(true, true));
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; 256];
+        while let Ok(count) = file.read(&mut buffer) {
+            if count == 0 || buffer[..count].iter().any(|&byte| sender.send(LinkEvent::Data(byte)).is_err()) {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+impl Link {
+    pub fn open(target: &LinkTarget) -> Result<Self, String> {
+        let mut link = Link {
+            writer: None,
+            incoming: None,
+            pending: None,
+            lines: (false, false),
+        };
+        match target {
+            LinkTarget::Listen(address) => {
+                let listener = TcpListener::bind(address).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+                println!("SIO1 waiting for a link on {}", address);
+                let (sender, receiver) = mpsc::channel();
+                std::thread::spawn(move || {
+                    if let Ok((stream, peer)) = listener.accept() {
+                        println!("SIO1 linked with {}", peer);
+                        let _ = sender.send(stream);
+                    }
+                });
+                link.pending = Some(receiver);
+            }
+            LinkTarget::Connect(address) => {
+                let stream = TcpStream::connect(address).map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+                link.attach_tcp(stream)?;
+            }
+            LinkTarget::Pty(path) => {
+                let file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(path)
+                    .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+                let reader = file.try_clone().map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+                link.incoming = Some(spawn_pty_reader(reader));
+                link.writer = Some(Writer::Pty(file));
+            }
+        }
+        Ok(link)
+    }
+
+    fn attach_tcp(&mut self, stream: TcpStream) -> Result<(), String> {
+        // Link cable games exchange single bytes, don't let them sit in Nagle's buffer
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+        self.incoming = Some(spawn_tcp_reader(reader));
+        self.writer = Some(Writer::Tcp(stream));
+        // Tell the new peer where our lines are
+        let lines = self.lines;
+        self.lines = (!lines.0, !lines.1);
+        self.send_lines(lines);
+        Ok(())
+    }
+
+    fn write(&mut self, message: &[u8]) {
+        let result = match &mut self.writer {
+            Some(Writer::Tcp(stream)) => stream.write_all(message),
+            Some(Writer::Pty(file)) => file.write_all(&message[1..]),
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            println!("SIO1 link closed: {}", err);
+            self.writer = None;
+        }
+    }
+
+    pub fn send_data(&mut self, value: u8) {
+        self.write(&[MESSAGE_DATA, value]);
+    }
+
+    /// Pass on our DTR and RTS if they changed
+    pub fn send_lines(&mut self, lines: (bool, bool)) {
+        if lines == self.lines {
+            return;
+        }
+        self.lines = lines;
+        if matches!(self.writer, Some(Writer::Tcp(_))) {
+            let value = if lines.0 { LINE_DTR } else { 0 } | if lines.1 { LINE_RTS } else { 0 };
+            self.write(&[MESSAGE_LINES, value]);
+        }
+    }
+
+    /// Everything received since the last poll
+    pub fn poll(&mut self) -> Vec<LinkEvent> {
+        if let Some(pending) = &self.pending {
+            match pending.try_recv() {
+                Ok(stream) => {
+                    self.pending = None;
+                    if let Err(err) = self.attach_tcp(stream) {
+                        println!("SIO1 link failed: {}", err);
+                    }
+                }
+                Err(TryRecvError::Disconnected) => self.pending = None,
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+
+        let mut events = Vec::new();
+        if let Some(incoming) = &self.incoming {
+            while let Ok(event) = incoming.try_recv() {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
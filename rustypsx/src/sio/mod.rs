@@ -0,0 +1,6 @@
+pub mod link;
+pub mod sio0;
+pub mod sio1;
+
+pub use sio0::*;
+pub use sio1::*;
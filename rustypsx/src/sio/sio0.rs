@@ -0,0 +1,516 @@
+use crate::input::multitap::MULTITAP_SLOTS;
+use crate::{input, memcard};
+use serde::{Deserialize, Serialize};
+
+pub const SIO0_START: u32 = 0x1F801040;
+pub const SIO0_END: u32 = 0x1F80104F;
+
+const JOY_DATA: u32 = 0x0;
+const JOY_STAT: u32 = 0x4;
+const JOY_MODE: u32 = 0x8;
+const JOY_CTRL: u32 = 0xA;
+const JOY_BAUD: u32 = 0xE;
+
+const STAT_TX_READY: u32 = 1 << 0;
+const STAT_RX_NOT_EMPTY: u32 = 1 << 1;
+const STAT_TX_FINISHED: u32 = 1 << 2;
+const STAT_ACK_LOW: u32 = 1 << 7;
+const STAT_IRQ: u32 = 1 << 9;
+
+const CTRL_TX_ENABLE: u16 = 1 << 0;
+const CTRL_SELECT: u16 = 1 << 1;
+const CTRL_ACKNOWLEDGE: u16 = 1 << 4;
+const CTRL_RESET: u16 = 1 << 6;
+const CTRL_ACK_IRQ_ENABLE: u16 = 1 << 12;
+const CTRL_PORT_2: u16 = 1 << 13;
+
+// Delay between the end of a byte and the device pulling /ACK low, and how long it stays low
+const CONTROLLER_ACK_DELAY: u32 = 450;
+const MEMORY_CARD_ACK_DELAY: u32 = 170;
+const ACK_PULSE_LENGTH: u32 = 96;
+
+pub const PORT_COUNT: usize = 2;
+// Card slots are numbered across both ports, with room for all of a multitap's on each
+pub const MEMORY_CARD_SLOTS: usize = PORT_COUNT * MULTITAP_SLOTS;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum ActiveDevice {
+    None,
+    Controller,
+    MemoryCard,
+    // Nothing answered the address byte, or the device ended the command
+    Finished,
+}
+
+/// One of the two controller/memory card connectors
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Port {
+    pub controller: input::Controller,
+    // Unused while a multitap is plugged in, which has its own card slots
+    memory_card: Option<memcard::MemoryCard>,
+    active: ActiveDevice,
+}
+
+impl Port {
+    fn new() -> Self {
+        Port {
+            controller: input::Controller::Disconnected,
+            memory_card: None,
+            active: ActiveDevice::None,
+        }
+    }
+
+    /// The card in this port, or in slot A of its multitap
+    pub fn memory_card(&self) -> Option<&memcard::MemoryCard> {
+        self.cards()[0].as_ref()
+    }
+
+    pub fn memory_card_mut(&mut self) -> Option<&mut memcard::MemoryCard> {
+        self.cards_mut()[0].as_mut()
+    }
+
+    /// Card slots on this port, four of them on a multitap
+    fn cards(&self) -> &[Option<memcard::MemoryCard>] {
+        match &self.controller {
+            input::Controller::Multitap(tap) => &tap.memory_cards,
+            _ => std::slice::from_ref(&self.memory_card),
+        }
+    }
+
+    fn cards_mut(&mut self) -> &mut [Option<memcard::MemoryCard>] {
+        match &mut self.controller {
+            input::Controller::Multitap(tap) => &mut tap.memory_cards,
+            _ => std::slice::from_mut(&mut self.memory_card),
+        }
+    }
+
+    /// Pads reachable through this port, four of them behind a multitap
+    fn pads_mut(&mut self) -> &mut [input::Controller] {
+        match &mut self.controller {
+            input::Controller::Multitap(tap) => &mut tap.pads,
+            controller => std::slice::from_mut(controller),
+        }
+    }
+
+    /// Returns the reply byte and the /ACK delay in cycles, if the device acknowledged
+    fn transfer(&mut self, value: u8) -> (u8, Option<u32>) {
+        if self.active == ActiveDevice::None {
+            let multitap = matches!(self.controller, input::Controller::Multitap(_));
+            self.active = match value {
+                0x01 if self.controller.is_connected() => ActiveDevice::Controller,
+                0x02..=0x04 if multitap => ActiveDevice::Controller,
+                0x81..=0x84 if multitap => ActiveDevice::MemoryCard,
+                0x81 if self.memory_card.is_some() => ActiveDevice::MemoryCard,
+                _ => ActiveDevice::Finished,
+            };
+        }
+
+        let (reply, ack, ack_delay) = match (self.active, &mut self.controller, self.memory_card.as_mut()) {
+            (ActiveDevice::Controller, controller, _) => {
+                let (reply, ack) = controller.transfer(value);
+                (reply, ack, CONTROLLER_ACK_DELAY)
+            }
+            // Memory card accesses are passed through by the multitap
+            (ActiveDevice::MemoryCard, input::Controller::Multitap(tap), _) => {
+                let (reply, ack) = tap.transfer(value);
+                (reply, ack, MEMORY_CARD_ACK_DELAY)
+            }
+            (ActiveDevice::MemoryCard, _, Some(card)) => {
+                let (reply, ack) = card.transfer(value);
+                (reply, ack, MEMORY_CARD_ACK_DELAY)
+            }
+            _ => return (0xFF, None),
+        };
+        if !ack {
+            self.active = ActiveDevice::Finished;
+        }
+        (reply, ack.then_some(ack_delay))
+    }
+
+    fn deselect(&mut self) {
+        self.active = ActiveDevice::None;
+        self.controller.deselect();
+        if let Some(card) = self.memory_card.as_mut() {
+            card.deselect();
+        }
+    }
+}
+
+/// Serial interface shared by the controllers and memory cards
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sio0 {
+    pub ports: [Port; PORT_COUNT],
+    mode: u16,
+    ctrl: u16,
+    baud: u16,
+    rx_data: u8,
+    rx_not_empty: bool,
+    irq: bool,
+    ack_low: bool,
+    // Byte being shifted out and the cycles left until it completes
+    transfer: Option<(u8, u32)>,
+    ack_delay: u32,
+    ack_pulse: u32,
+}
+
+impl Sio0 {
+    pub fn new() -> Self {
+        Sio0 {
+            ports: [Port::new(), Port::new()],
+            mode: 0,
+            ctrl: 0,
+            baud: 0,
+            rx_data: 0xFF,
+            rx_not_empty: false,
+            irq: false,
+            ack_low: false,
+            transfer: None,
+            ack_delay: 0,
+            ack_pulse: 0,
+        }
+    }
+
+    /// Reset the interface, keeping whatever is plugged into the ports
+    pub fn reset(&mut self) {
+        let mut ports = std::mem::replace(&mut self.ports, [Port::new(), Port::new()]);
+        for port in &mut ports {
+            port.deselect();
+        }
+        *self = Sio0 { ports, ..Self::new() };
+    }
+
+    fn selected_port(&self) -> Option<usize> {
+        if self.ctrl & CTRL_SELECT == 0 {
+            return None;
+        }
+        Some(if self.ctrl & CTRL_PORT_2 != 0 { 1 } else { 0 })
+    }
+
+    fn cycles_per_byte(&self) -> u32 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        (self.baud as u32 * factor).max(1) * 8
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        if self.transfer.is_none() {
+            status |= STAT_TX_READY | STAT_TX_FINISHED;
+        }
+        if self.rx_not_empty {
+            status |= STAT_RX_NOT_EMPTY;
+        }
+        if self.ack_low {
+            status |= STAT_ACK_LOW;
+        }
+        if self.irq {
+            status |= STAT_IRQ;
+        }
+        if let Some((_, remaining)) = self.transfer {
+            status |= (remaining & 0x1FFFFF) << 11;
+        }
+        status
+    }
+
+    fn write_ctrl(&mut self, value: u16) {
+        self.ctrl = value;
+        if value & CTRL_RESET != 0 {
+            self.reset();
+            return;
+        }
+        if value & CTRL_ACKNOWLEDGE != 0 {
+            self.irq = false;
+            self.ctrl &= !CTRL_ACKNOWLEDGE;
+        }
+
+        // Ports that are not selected see /JOYn high and abandon their command
+        let selected = self.selected_port();
+        for (index, port) in self.ports.iter_mut().enumerate() {
+            if selected != Some(index) {
+                port.deselect();
+            }
+        }
+        if selected.is_none() {
+            self.ack_delay = 0;
+        }
+    }
+
+    /// Advance transfers and /ACK timing, returns true when IRQ7 should be raised
+    pub fn step(&mut self, mut cycles: u32) -> bool {
+        let mut raise_irq = false;
+
+        if let Some((value, remaining)) = self.transfer {
+            if remaining > cycles {
+                self.transfer = Some((value, remaining - cycles));
+            } else {
+                // Only what's left after the byte counts towards its /ACK
+                cycles -= remaining;
+                self.transfer = None;
+                let (reply, ack_delay) = match self.selected_port() {
+                    Some(port) => self.ports[port].transfer(value),
+                    None => (0xFF, None),
+                };
+                self.rx_data = reply;
+                self.rx_not_empty = true;
+                self.ack_delay = ack_delay.unwrap_or(0);
+            }
+        }
+
+        if self.ack_delay > 0 {
+            if self.ack_delay > cycles {
+                self.ack_delay -= cycles;
+            } else {
+                self.ack_delay = 0;
+                self.ack_low = true;
+                self.ack_pulse = ACK_PULSE_LENGTH;
+                if self.ctrl & CTRL_ACK_IRQ_ENABLE != 0 && !self.irq {
+                    self.irq = true;
+                    raise_irq = true;
+                }
+            }
+        } else if self.ack_pulse > 0 {
+            self.ack_pulse = self.ack_pulse.saturating_sub(cycles);
+            if self.ack_pulse == 0 {
+                self.ack_low = false;
+            }
+        }
+
+        raise_irq
+    }
+
+    pub fn set_controller(&mut self, port: usize, controller: input::Controller) {
+        // Cards keep their slots when a multitap is plugged in or pulled out in front of them,
+        // those in slots B-D are dropped when it's pulled
+        let port = &mut self.ports[port];
+        let cards: Vec<_> = port.cards_mut().iter_mut().map(Option::take).collect();
+        port.controller = controller;
+        for (slot, card) in port.cards_mut().iter_mut().zip(cards) {
+            *slot = card;
+        }
+        port.deselect();
+    }
+
+    /// Insert or pull the card in `slot`, false if it's a multitap slot and there's no multitap
+    pub fn set_memory_card(&mut self, slot: usize, card: Option<memcard::MemoryCard>) -> bool {
+        let port = &mut self.ports[slot / MULTITAP_SLOTS];
+        let Some(cards_slot) = port.cards_mut().get_mut(slot % MULTITAP_SLOTS) else {
+            return false;
+        };
+        *cards_slot = card;
+        port.deselect();
+        true
+    }
+
+    pub fn memory_card(&self, slot: usize) -> Option<&memcard::MemoryCard> {
+        self.ports.get(slot / MULTITAP_SLOTS)?.cards().get(slot % MULTITAP_SLOTS)?.as_ref()
+    }
+
+    pub fn memory_card_mut(&mut self, slot: usize) -> Option<&mut memcard::MemoryCard> {
+        self.ports.get_mut(slot / MULTITAP_SLOTS)?.cards_mut().get_mut(slot % MULTITAP_SLOTS)?.as_mut()
+    }
+
+    /// Slots that can take a card with what's plugged in now
+    pub fn memory_card_slots(&self) -> Vec<usize> {
+        (0..MEMORY_CARD_SLOTS)
+            .filter(|slot| slot % MULTITAP_SLOTS < self.ports[slot / MULTITAP_SLOTS].cards().len())
+            .collect()
+    }
+
+    /// As in "1", or "1B" for slot B of a multitap in port 1
+    pub fn memory_card_slot_name(&self, slot: usize) -> String {
+        let port = slot / MULTITAP_SLOTS;
+        match self.ports[port].controller {
+            input::Controller::Multitap(_) => format!("{}{}", port + 1, (b'A' + (slot % MULTITAP_SLOTS) as u8) as char),
+            _ => format!("{}", port + 1),
+        }
+    }
+
+    pub fn memory_cards_mut(&mut self) -> impl Iterator<Item = &mut memcard::MemoryCard> {
+        self.ports.iter_mut().flat_map(|port| port.cards_mut().iter_mut().flatten())
+    }
+
+    /// Pads are numbered across both ports, with all four slots of a multitap in turn
+    pub fn pad_mut(&mut self, pad: usize) -> Option<&mut input::Controller> {
+        self.ports.iter_mut().flat_map(|port| port.pads_mut().iter_mut()).nth(pad)
+    }
+
+    /// Beam position a Justifier wants the lightgun IRQ at, it only works plugged straight into a port
+    pub fn lightgun_irq_position(&self) -> Option<input::lightgun::BeamPosition> {
+        self.ports.iter().find_map(|port| port.controller.lightgun_irq_position())
+    }
+
+    pub fn read(&mut self, addr: u32) -> u8 {
+        let offset = addr - SIO0_START;
+        let shift = (offset & 1) * 8;
+        match offset {
+            JOY_DATA => {
+                self.rx_not_empty = false;
+                self.rx_data
+            }
+            // Wider reads peek at the (single entry) receive FIFO
+            0x1..=0x3 => self.rx_data,
+            0x4..=0x7 => (self.status() >> ((offset - JOY_STAT) * 8)) as u8,
+            0x8..=0x9 => (self.mode >> shift) as u8,
+            0xA..=0xB => (self.ctrl >> shift) as u8,
+            0xE..=0xF => (self.baud >> shift) as u8,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let offset = addr - SIO0_START;
+        let shift = (offset & 1) * 8;
+        let merge = |register: u16| register & !(0xFF << shift) | (value as u16) << shift;
+        match offset {
+            JOY_DATA if self.ctrl & CTRL_TX_ENABLE != 0 => {
+                self.transfer = Some((value, self.cycles_per_byte()));
+            }
+            JOY_MODE | 0x9 => self.mode = merge(self.mode),
+            JOY_CTRL | 0xB => {
+                let ctrl = merge(self.ctrl);
+                self.write_ctrl(ctrl);
+            }
+            JOY_BAUD | 0xF => self.baud = merge(self.baud),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAUD: u16 = 0x88;
+    const CTRL_ENABLED: u16 = CTRL_TX_ENABLE | CTRL_SELECT | CTRL_ACK_IRQ_ENABLE;
+
+    fn write16(sio: &mut Sio0, offset: u32, value: u16) {
+        sio.write(SIO0_START + offset, value as u8);
+        sio.write(SIO0_START + offset + 1, (value >> 8) as u8);
+    }
+
+    fn status(sio: &mut Sio0) -> u32 {
+        u32::from_le_bytes([0, 1, 2, 3].map(|byte| sio.read(SIO0_START + JOY_STAT + byte)))
+    }
+
+    /// A selected interface with `controller` in port 1
+    fn setup(controller: input::Controller) -> Sio0 {
+        let mut sio = Sio0::new();
+        sio.set_controller(0, controller);
+        write16(&mut sio, JOY_BAUD, BAUD);
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED);
+        sio
+    }
+
+    /// Send a byte and wait out any /ACK, returns the reply and whether the device acknowledged
+    fn exchange(sio: &mut Sio0, value: u8) -> (u8, bool) {
+        sio.write(SIO0_START + JOY_DATA, value);
+        sio.step(sio.cycles_per_byte());
+        let reply = sio.read(SIO0_START + JOY_DATA);
+        let acknowledged = sio.step(CONTROLLER_ACK_DELAY);
+        sio.step(ACK_PULSE_LENGTH);
+        write16(sio, JOY_CTRL, sio.ctrl | CTRL_ACKNOWLEDGE);
+        (reply, acknowledged)
+    }
+
+    fn pad(buttons: u16) -> input::Controller {
+        let mut pad = input::digital_pad::DigitalPad::new();
+        pad.buttons = buttons;
+        input::Controller::Digital(pad)
+    }
+
+    #[test]
+    fn bytes_take_eight_baud_periods_to_shift_out() {
+        let mut sio = setup(input::Controller::Disconnected);
+        assert_ne!(status(&mut sio) & STAT_TX_READY, 0);
+        sio.write(SIO0_START + JOY_DATA, 0x01);
+        assert_eq!(status(&mut sio) & (STAT_TX_READY | STAT_TX_FINISHED), 0);
+        sio.step(BAUD as u32 * 8 - 1);
+        assert_eq!(status(&mut sio) & STAT_RX_NOT_EMPTY, 0);
+        sio.step(1);
+        assert_eq!(status(&mut sio) & (STAT_TX_READY | STAT_RX_NOT_EMPTY), STAT_TX_READY | STAT_RX_NOT_EMPTY);
+        assert_eq!(sio.read(SIO0_START + JOY_DATA), 0xFF);
+        assert_eq!(status(&mut sio) & STAT_RX_NOT_EMPTY, 0);
+    }
+
+    #[test]
+    fn acknowledged_bytes_pulse_ack_and_raise_the_irq() {
+        let mut sio = setup(pad(0));
+        sio.write(SIO0_START + JOY_DATA, 0x01);
+        sio.step(sio.cycles_per_byte());
+        assert!(!sio.step(CONTROLLER_ACK_DELAY - 1));
+        assert_eq!(status(&mut sio) & (STAT_ACK_LOW | STAT_IRQ), 0);
+        assert!(sio.step(1));
+        assert_eq!(status(&mut sio) & (STAT_ACK_LOW | STAT_IRQ), STAT_ACK_LOW | STAT_IRQ);
+        sio.step(ACK_PULSE_LENGTH);
+        assert_eq!(status(&mut sio) & (STAT_ACK_LOW | STAT_IRQ), STAT_IRQ);
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED | CTRL_ACKNOWLEDGE);
+        assert_eq!(status(&mut sio) & STAT_IRQ, 0);
+        assert_eq!(sio.ctrl, CTRL_ENABLED);
+    }
+
+    #[test]
+    fn pads_answer_a_button_poll() {
+        let mut sio = setup(pad(input::Button::Cross.mask()));
+        let replies: Vec<_> = [0x01, 0x42, 0x00, 0x00, 0x00].map(|value| exchange(&mut sio, value)).into();
+        assert_eq!(replies, [(0xFF, true), (0x41, true), (0x5A, true), (0xFF, true), (0xBF, false)]);
+    }
+
+    #[test]
+    fn empty_ports_and_unknown_addresses_are_not_acknowledged() {
+        let mut sio = setup(input::Controller::Disconnected);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, false));
+
+        let mut sio = setup(pad(0));
+        assert_eq!(exchange(&mut sio, 0x81), (0xFF, false));
+        // Once the address goes unanswered the rest of the command is ignored
+        assert_eq!(exchange(&mut sio, 0x42), (0xFF, false));
+    }
+
+    #[test]
+    fn deselecting_aborts_the_command() {
+        let mut sio = setup(pad(0));
+        exchange(&mut sio, 0x01);
+        exchange(&mut sio, 0x42);
+        write16(&mut sio, JOY_CTRL, CTRL_TX_ENABLE);
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED);
+        assert_eq!(exchange(&mut sio, 0x42), (0xFF, false));
+        write16(&mut sio, JOY_CTRL, CTRL_TX_ENABLE);
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, true));
+        assert_eq!(exchange(&mut sio, 0x42), (0x41, true));
+    }
+
+    #[test]
+    fn port_2_is_selected_by_its_ctrl_bit() {
+        let mut sio = setup(input::Controller::Disconnected);
+        sio.set_controller(1, pad(0));
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, false));
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED | CTRL_PORT_2);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, true));
+    }
+
+    #[test]
+    fn memory_cards_answer_their_own_address() {
+        let mut sio = setup(pad(0));
+        sio.set_memory_card(0, Some(memcard::MemoryCard::new()));
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED);
+        assert_eq!(exchange(&mut sio, 0x81), (0xFF, true));
+        // A card not written since it went in replies with its FLAG byte
+        assert_eq!(exchange(&mut sio, 0x53), (0x08, true));
+    }
+
+    #[test]
+    fn reset_keeps_what_is_plugged_in() {
+        let mut sio = setup(pad(0));
+        exchange(&mut sio, 0x01);
+        write16(&mut sio, JOY_CTRL, CTRL_RESET);
+        assert_eq!(sio.ctrl, 0);
+        assert_eq!(sio.baud, 0);
+        write16(&mut sio, JOY_BAUD, BAUD);
+        write16(&mut sio, JOY_CTRL, CTRL_ENABLED);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, true));
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const SIO1_START: u32 = 0x1F801050;
+pub const SIO1_END: u32 = 0x1F80105F;
+
+const SIO_DATA: u32 = 0x0;
+const SIO_STAT: u32 = 0x4;
+const SIO_MODE: u32 = 0x8;
+const SIO_CTRL: u32 = 0xA;
+const SIO_MISC: u32 = 0xC;
+const SIO_BAUD: u32 = 0xE;
+
+const STAT_TX_READY: u32 = 1 << 0;
+const STAT_RX_NOT_EMPTY: u32 = 1 << 1;
+const STAT_TX_FINISHED: u32 = 1 << 2;
+const STAT_RX_OVERRUN: u32 = 1 << 4;
+const STAT_DSR: u32 = 1 << 7;
+const STAT_CTS: u32 = 1 << 8;
+const STAT_IRQ: u32 = 1 << 9;
+
+const CTRL_TX_ENABLE: u16 = 1 << 0;
+const CTRL_DTR: u16 = 1 << 1;
+const CTRL_RX_ENABLE: u16 = 1 << 2;
+const CTRL_ACKNOWLEDGE: u16 = 1 << 4;
+const CTRL_RTS: u16 = 1 << 5;
+const CTRL_RESET: u16 = 1 << 6;
+const CTRL_TX_IRQ_ENABLE: u16 = 1 << 10;
+const CTRL_RX_IRQ_ENABLE: u16 = 1 << 11;
+const CTRL_DSR_IRQ_ENABLE: u16 = 1 << 12;
+
+const RX_FIFO_SIZE: usize = 8;
+
+/// Serial port on the back of the console, used by the link cable
+///
+/// Bytes on the wire are queued for the host side of the link to carry, which also
+/// delivers received bytes and the other end's DTR/RTS lines.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sio1 {
+    mode: u16,
+    ctrl: u16,
+    misc: u16,
+    baud: u16,
+    rx_fifo: VecDeque<u8>,
+    rx_overrun: bool,
+    // Byte waiting behind the one being shifted out
+    tx_data: Option<u8>,
+    // Byte being shifted out and the cycles until it is on the wire
+    tx_shift: Option<(u8, u32)>,
+    transmitted: VecDeque<u8>,
+    // The other end's DTR and RTS, wired to our DSR and CTS
+    dsr: bool,
+    cts: bool,
+    irq: bool,
+    // Raised outside of step(), by the link delivering data
+    irq_edge: bool,
+}
+
+impl Sio1 {
+    pub fn new() -> Self {
+        Sio1 {
+            mode: 0,
+            ctrl: 0,
+            misc: 0,
+            baud: 0,
+            rx_fifo: VecDeque::new(),
+            rx_overrun: false,
+            tx_data: None,
+            tx_shift: None,
+            transmitted: VecDeque::new(),
+            dsr: false,
+            cts: false,
+            irq: false,
+            irq_edge: false,
+        }
+    }
+
+    /// Reset registers and FIFOs, leaving the other end's lines as they are
+    pub fn reset(&mut self) {
+        *self = Sio1 {
+            dsr: self.dsr,
+            cts: self.cts,
+            ..Sio1::new()
+        };
+    }
+
+    fn cycles_per_byte(&self) -> u32 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        let data_bits = 5 + ((self.mode >> 2) & 3) as u32;
+        let parity_bits = ((self.mode >> 4) & 1) as u32;
+        let stop_bits = if (self.mode >> 6) & 3 >= 2 { 2 } else { 1 };
+        (self.baud as u32 * factor).max(1) * (1 + data_bits + parity_bits + stop_bits)
+    }
+
+    fn raise_irq(&mut self, enable: u16) {
+        if self.ctrl & enable != 0 && !self.irq {
+            self.irq = true;
+            self.irq_edge = true;
+        }
+    }
+
+    fn rx_irq_threshold(&self) -> usize {
+        1 << ((self.ctrl >> 8) & 3)
+    }
+
+    /// A byte arriving from the other end of the link
+    pub fn receive(&mut self, value: u8) {
+        if self.ctrl & CTRL_RX_ENABLE == 0 {
+            return;
+        }
+        if self.rx_fifo.len() == RX_FIFO_SIZE {
+            self.rx_overrun = true;
+            return;
+        }
+        self.rx_fifo.push_back(value);
+        if self.rx_fifo.len() >= self.rx_irq_threshold() {
+            self.raise_irq(CTRL_RX_IRQ_ENABLE);
+        }
+    }
+
+    /// The other end's DTR and RTS outputs
+    pub fn set_remote_lines(&mut self, dtr: bool, rts: bool) {
+        let dsr_rising = dtr && !self.dsr;
+        self.dsr = dtr;
+        self.cts = rts;
+        if dsr_rising {
+            self.raise_irq(CTRL_DSR_IRQ_ENABLE);
+        }
+    }
+
+    /// Our DTR and RTS outputs, for the link to pass on
+    pub fn output_lines(&self) -> (bool, bool) {
+        (self.ctrl & CTRL_DTR != 0, self.ctrl & CTRL_RTS != 0)
+    }
+
+    /// Bytes that have finished going out on the wire since the last call
+    pub fn take_transmitted(&mut self) -> VecDeque<u8> {
+        std::mem::take(&mut self.transmitted)
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        if self.tx_data.is_none() {
+            status |= STAT_TX_READY;
+            if self.tx_shift.is_none() {
+                status |= STAT_TX_FINISHED;
+            }
+        }
+        if !self.rx_fifo.is_empty() {
+            status |= STAT_RX_NOT_EMPTY;
+        }
+        if self.rx_overrun {
+            status |= STAT_RX_OVERRUN;
+        }
+        if self.dsr {
+            status |= STAT_DSR;
+        }
+        if self.cts {
+            status |= STAT_CTS;
+        }
+        if self.irq {
+            status |= STAT_IRQ;
+        }
+        if let Some((_, remaining)) = self.tx_shift {
+            status |= (remaining & 0x1FFFFF) << 11;
+        }
+        status
+    }
+
+    fn write_ctrl(&mut self, value: u16) {
+        if value & CTRL_RESET != 0 {
+            self.reset();
+            return;
+        }
+        self.ctrl = value & !CTRL_ACKNOWLEDGE;
+        if value & CTRL_ACKNOWLEDGE != 0 {
+            self.irq = false;
+            self.rx_overrun = false;
+        }
+        if value & CTRL_RX_ENABLE == 0 {
+            self.rx_fifo.clear();
+        }
+    }
+
+    fn start_transmit(&mut self) {
+        if self.tx_shift.is_none()
+            && self.ctrl & CTRL_TX_ENABLE != 0
+            && let Some(value) = self.tx_data.take()
+        {
+            self.tx_shift = Some((value, self.cycles_per_byte()));
+        }
+    }
+
+    /// Advance the transmitter, returns true when IRQ8 should be raised
+    pub fn step(&mut self, cycles: u32) -> bool {
+        if let Some((value, remaining)) = self.tx_shift {
+            if remaining > cycles {
+                self.tx_shift = Some((value, remaining - cycles));
+            } else {
+                self.tx_shift = None;
+                self.transmitted.push_back(value);
+                self.start_transmit();
+                if self.tx_data.is_none() {
+                    self.raise_irq(CTRL_TX_IRQ_ENABLE);
+                }
+            }
+        }
+        std::mem::take(&mut self.irq_edge)
+    }
+
+    pub fn read(&mut self, addr: u32) -> u8 {
+        let offset = addr - SIO1_START;
+        let shift = (offset & 1) * 8;
+        match offset {
+            SIO_DATA => self.rx_fifo.pop_front().unwrap_or(0),
+            // Wider reads peek at the front of the FIFO
+            0x1..=0x3 => self.rx_fifo.front().copied().unwrap_or(0),
+            0x4..=0x7 => (self.status() >> ((offset - SIO_STAT) * 8)) as u8,
+            0x8..=0x9 => (self.mode >> shift) as u8,
+            0xA..=0xB => (self.ctrl >> shift) as u8,
+            0xC..=0xD => (self.misc >> shift) as u8,
+            0xE..=0xF => (self.baud >> shift) as u8,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u32, value: u8) {
+        let offset = addr - SIO1_START;
+        let shift = (offset & 1) * 8;
+        let merge = |register: u16| register & !(0xFF << shift) | (value as u16) << shift;
+        match offset {
+            SIO_DATA => {
+                self.tx_data = Some(value);
+                self.start_transmit();
+            }
+            SIO_MODE | 0x9 => self.mode = merge(self.mode),
+            SIO_CTRL | 0xB => {
+                let ctrl = merge(self.ctrl);
+                self.write_ctrl(ctrl);
+                self.start_transmit();
+            }
+            SIO_MISC | 0xD => self.misc = merge(self.misc),
+            SIO_BAUD | 0xF => self.baud = merge(self.baud),
+            _ => {}
+        }
+    }
+}